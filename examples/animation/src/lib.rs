@@ -19,6 +19,7 @@ struct AnimationExample {
     /// The application data
     animated_objects: Vec<AnimatedObject>,
     _directional_light_handle: Option<DirectionalLightHandle>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 
 fn update(renderer: &rend3::Renderer, delta: f32, animated_object: &mut AnimatedObject) {
@@ -47,8 +48,9 @@ impl rend3_framework::App for AnimationExample {
         _window: &winit::window::Window,
         renderer: &Arc<rend3::Renderer>,
         _routines: &Arc<rend3_framework::DefaultRoutines>,
-        _surface_format: rend3::types::TextureFormat,
+        surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         let view_location = glam::Vec3::new(0.0, -1.5, 5.0);
         let view = glam::Mat4::from_euler(glam::EulerRot::XYZ, 0.0, 0.0, 0.0);
         let view = view * glam::Mat4::from_translation(view_location);
@@ -81,7 +83,9 @@ impl rend3_framework::App for AnimationExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
+            caster_margin: 0.0,
             resolution: 2048,
+            cast_shadows: true,
         });
 
         self._directional_light_handle = Some(directional_light_handle);
@@ -149,12 +153,26 @@ impl rend3_framework::App for AnimationExample {
             // Render!
             rend3_framework::Event::RedrawRequested(_) => {
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
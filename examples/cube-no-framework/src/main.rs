@@ -106,8 +106,8 @@ fn main() {
     // Create the base rendergraph.
     let base_rendergraph = rend3_routine::base::BaseRenderGraph::new(&renderer, &spp);
 
-    let mut data_core = renderer.data_core.lock();
-    let pbr_routine =
+    let mut data_core = renderer.data_core.write();
+    let mut pbr_routine =
         rend3_routine::pbr::PbrRoutine::new(&renderer, &mut data_core, &spp, &base_rendergraph.interfaces);
     drop(data_core);
     let tonemapping_routine = rend3_routine::tonemapping::TonemappingRoutine::new(
@@ -138,6 +138,12 @@ fn main() {
         mesh_kind: rend3::types::ObjectMeshKind::Static(mesh_handle),
         material: material_handle,
         transform: glam::Mat4::IDENTITY,
+        overlay: false,
+        layers: u32::MAX,
+        cast_shadows: true,
+        receive_shadows: true,
+        tint: glam::Vec4::ONE,
+        clip: rend3::types::ObjectClip::default(),
     };
     // Creating an object will hold onto both the mesh and the material
     // even if they are deleted.
@@ -164,7 +170,9 @@ fn main() {
         // Direction will be normalized
         direction: glam::Vec3::new(-1.0, -4.0, 2.0),
         distance: 400.0,
+        caster_margin: 0.0,
         resolution: 2048,
+        cast_shadows: true,
     });
 
     let mut resolution = glam::UVec2::new(window_size.width, window_size.height);
@@ -197,12 +205,31 @@ fn main() {
         // Render!
         winit::event::Event::MainEventsCleared => {
             // Get a frame
-            let frame = surface.get_current_texture().unwrap();
+            let frame = match rend3::get_surface_texture(
+                &surface,
+                &renderer.device,
+                preferred_format,
+                resolution,
+                rend3::types::PresentMode::Fifo,
+            ) {
+                Ok(frame) => frame,
+                // Try again next frame.
+                Err(rend3::SurfaceAcquireError::Skip) => return,
+                // A real application would recreate the renderer here, see the docs of Renderer.
+                Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+            };
 
             // Swap the instruction buffers so that our frame's changes can be processed.
             renderer.swap_instruction_buffers();
             // Evaluate our frame's world-change instructions
             let mut eval_output = renderer.evaluate_instructions();
+            // Rebuild the pbr routine if the texture array grew while evaluating.
+            pbr_routine.rebuild_if_outdated(
+                &renderer,
+                &mut renderer.data_core.write(),
+                &spp,
+                &base_rendergraph.interfaces,
+            );
 
             // Build a rendergraph
             let mut graph = rend3::graph::RenderGraph::new();
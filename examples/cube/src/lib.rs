@@ -59,6 +59,7 @@ const SAMPLE_COUNT: rend3::types::SampleCount = rend3::types::SampleCount::One;
 struct CubeExample {
     object_handle: Option<rend3::types::ObjectHandle>,
     directional_light_handle: Option<rend3::types::DirectionalLightHandle>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 
 impl rend3_framework::App for CubeExample {
@@ -74,8 +75,9 @@ impl rend3_framework::App for CubeExample {
         _window: &winit::window::Window,
         renderer: &Arc<rend3::Renderer>,
         _routines: &Arc<rend3_framework::DefaultRoutines>,
-        _surface_format: rend3::types::TextureFormat,
+        surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         // Create mesh and calculate smooth normals based on vertices
         let mesh = create_mesh();
 
@@ -97,6 +99,12 @@ impl rend3_framework::App for CubeExample {
             mesh_kind: rend3::types::ObjectMeshKind::Static(mesh_handle),
             material: material_handle,
             transform: glam::Mat4::IDENTITY,
+            overlay: false,
+            layers: u32::MAX,
+            cast_shadows: true,
+            receive_shadows: true,
+            tint: glam::Vec4::ONE,
+            clip: rend3::types::ObjectClip::default(),
         };
         // Creating an object will hold onto both the mesh and the material
         // even if they are deleted.
@@ -123,7 +131,9 @@ impl rend3_framework::App for CubeExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
+            caster_margin: 0.0,
             resolution: 2048,
+            cast_shadows: true,
         }));
     }
 
@@ -152,12 +162,26 @@ impl rend3_framework::App for CubeExample {
             // Render!
             rend3_framework::Event::RedrawRequested(_) => {
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
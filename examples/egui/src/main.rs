@@ -17,6 +17,7 @@ const SAMPLE_COUNT: rend3::types::SampleCount = rend3::types::SampleCount::One;
 struct EguiExample {
     data: Option<EguiExampleData>,
     rust_logo: egui::TextureId,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 impl rend3_framework::App for EguiExample {
     const HANDEDNESS: rend3::types::Handedness = rend3::types::Handedness::Left;
@@ -33,6 +34,7 @@ impl rend3_framework::App for EguiExample {
         _routines: &Arc<rend3_framework::DefaultRoutines>,
         surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         let window_size = window.inner_size();
 
         // Create the egui render routine
@@ -67,6 +69,12 @@ impl rend3_framework::App for EguiExample {
             mesh_kind: rend3::types::ObjectMeshKind::Static(mesh_handle),
             material: material_handle.clone(),
             transform: glam::Mat4::IDENTITY,
+            overlay: false,
+            layers: u32::MAX,
+            cast_shadows: true,
+            receive_shadows: true,
+            tint: glam::Vec4::ONE,
+            clip: rend3::types::ObjectClip::default(),
         };
 
         // Creating an object will hold onto both the mesh and the material
@@ -98,7 +106,9 @@ impl rend3_framework::App for EguiExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
+            caster_margin: 0.0,
             resolution: 2048,
+            cast_shadows: true,
         });
 
         // Create the egui context
@@ -194,12 +204,26 @@ impl rend3_framework::App for EguiExample {
                 };
 
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
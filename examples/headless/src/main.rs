@@ -0,0 +1,118 @@
+use std::io::Write;
+
+fn vertex(pos: [f32; 3]) -> glam::Vec3 {
+    glam::Vec3::from(pos)
+}
+
+fn create_mesh() -> rend3::types::Mesh {
+    let vertex_positions = [
+        // far side (0.0, 0.0, 1.0)
+        vertex([-1.0, -1.0, 1.0]),
+        vertex([1.0, -1.0, 1.0]),
+        vertex([1.0, 1.0, 1.0]),
+        vertex([-1.0, 1.0, 1.0]),
+        // near side (0.0, 0.0, -1.0)
+        vertex([-1.0, 1.0, -1.0]),
+        vertex([1.0, 1.0, -1.0]),
+        vertex([1.0, -1.0, -1.0]),
+        vertex([-1.0, -1.0, -1.0]),
+        // right side (1.0, 0.0, 0.0)
+        vertex([1.0, -1.0, -1.0]),
+        vertex([1.0, 1.0, -1.0]),
+        vertex([1.0, 1.0, 1.0]),
+        vertex([1.0, -1.0, 1.0]),
+        // left side (-1.0, 0.0, 0.0)
+        vertex([-1.0, -1.0, 1.0]),
+        vertex([-1.0, 1.0, 1.0]),
+        vertex([-1.0, 1.0, -1.0]),
+        vertex([-1.0, -1.0, -1.0]),
+        // top (0.0, 1.0, 0.0)
+        vertex([1.0, 1.0, -1.0]),
+        vertex([-1.0, 1.0, -1.0]),
+        vertex([-1.0, 1.0, 1.0]),
+        vertex([1.0, 1.0, 1.0]),
+        // bottom (0.0, -1.0, 0.0)
+        vertex([1.0, -1.0, 1.0]),
+        vertex([-1.0, -1.0, 1.0]),
+        vertex([-1.0, -1.0, -1.0]),
+        vertex([1.0, -1.0, -1.0]),
+    ];
+
+    let index_data: &[u32] = &[
+        0, 1, 2, 2, 3, 0, // far
+        4, 5, 6, 6, 7, 4, // near
+        8, 9, 10, 10, 11, 8, // right
+        12, 13, 14, 14, 15, 12, // left
+        16, 17, 18, 18, 19, 16, // top
+        20, 21, 22, 22, 23, 20, // bottom
+    ];
+
+    rend3::types::MeshBuilder::new(vertex_positions.to_vec(), rend3::types::Handedness::Left)
+        .with_indices(index_data.to_vec())
+        .build()
+        .unwrap()
+}
+
+fn main() {
+    // Setup logging
+    env_logger::init();
+
+    let path = std::env::args().nth(1).unwrap_or_else(|| String::from("headless.ppm"));
+    let resolution = glam::UVec2::new(512, 512);
+
+    // Create the Instance, Adapter, and Device. No surface is needed.
+    let iad = pollster::block_on(rend3::create_iad(None, None, None, None)).unwrap();
+
+    // Make us a renderer, along with the default routines, rendering to an offscreen texture.
+    let mut headless =
+        rend3_routine::headless::HeadlessRenderer::new(iad, rend3::types::Handedness::Left, resolution).unwrap();
+    let renderer = &headless.renderer;
+
+    // Add a cube, exactly like we would with a window.
+    let mesh_handle = renderer.add_mesh(create_mesh());
+    let material_handle = renderer.add_material(rend3_routine::pbr::PbrMaterial {
+        albedo: rend3_routine::pbr::AlbedoComponent::Value(glam::Vec4::new(0.0, 0.5, 0.5, 1.0)),
+        ..rend3_routine::pbr::PbrMaterial::default()
+    });
+    let _object_handle = renderer.add_object(rend3::types::Object {
+        mesh_kind: rend3::types::ObjectMeshKind::Static(mesh_handle),
+        material: material_handle,
+        transform: glam::Mat4::IDENTITY,
+        overlay: false,
+        layers: u32::MAX,
+        cast_shadows: true,
+        receive_shadows: true,
+        tint: glam::Vec4::ONE,
+        clip: rend3::types::ObjectClip::default(),
+    });
+
+    let view_location = glam::Vec3::new(3.0, 3.0, -5.0);
+    let view = glam::Mat4::from_euler(glam::EulerRot::XYZ, -0.55, 0.5, 0.0);
+    let view = view * glam::Mat4::from_translation(-view_location);
+    renderer.set_camera_data(rend3::types::Camera {
+        projection: rend3::types::CameraProjection::Perspective { vfov: 60.0, near: 0.1 },
+        view,
+    });
+
+    let _directional_handle = renderer.add_directional_light(rend3::types::DirectionalLight {
+        color: glam::Vec3::ONE,
+        intensity: 10.0,
+        direction: glam::Vec3::new(-1.0, -4.0, 2.0),
+        distance: 400.0,
+        caster_margin: 0.0,
+        resolution: 2048,
+        cast_shadows: true,
+    });
+
+    // Render a couple frames so everything has been uploaded, then read back the image.
+    let pixels = headless.render_and_read_back(2);
+
+    // Write it out as a binary ppm, which needs no image library to produce.
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path).unwrap());
+    write!(file, "P6\n{} {}\n255\n", resolution.x, resolution.y).unwrap();
+    for pixel in pixels.chunks_exact(4) {
+        file.write_all(&pixel[..3]).unwrap();
+    }
+
+    println!("Wrote {path}");
+}
@@ -18,6 +18,7 @@ const SAMPLE_COUNT: rend3::types::SampleCount = rend3::types::SampleCount::One;
 #[derive(Default)]
 struct ImguiExample {
     data: Option<ImguiExampleData>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 impl rend3_framework::App for ImguiExample {
     const HANDEDNESS: rend3::types::Handedness = rend3::types::Handedness::Left;
@@ -35,6 +36,7 @@ impl rend3_framework::App for ImguiExample {
         _routines: &Arc<rend3_framework::DefaultRoutines>,
         surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         panic!("The rend3 imgui example does not run due to imgui_winit_support not being upgraded");
 
         // Set up imgui
@@ -81,6 +83,12 @@ impl rend3_framework::App for ImguiExample {
             mesh_kind: rend3::types::ObjectMeshKind::Static(mesh_handle),
             material: material_handle,
             transform: glam::Mat4::IDENTITY,
+            overlay: false,
+            layers: u32::MAX,
+            cast_shadows: true,
+            receive_shadows: true,
+            tint: glam::Vec4::ONE,
+            clip: rend3::types::ObjectClip::default(),
         };
 
         // Creating an object will hold onto both the mesh and the material
@@ -112,7 +120,9 @@ impl rend3_framework::App for ImguiExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
+            caster_margin: 0.0,
             resolution: 2048,
+            cast_shadows: true,
         });
 
         let frame_start = instant::Instant::now();
@@ -161,12 +171,26 @@ impl rend3_framework::App for ImguiExample {
                 // data.platform.prepare_render(ui, window); REMOVED UNTIL IMGUI_WINIT_PLATFORM UPDATE
 
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
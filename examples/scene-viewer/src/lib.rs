@@ -55,6 +55,7 @@ async fn load_skybox(
         label: Some("background".into()),
         mip_count: rend3::types::MipmapCount::ONE,
         mip_source: rend3::types::MipmapSource::Uploaded,
+        color_space: None,
     });
     lock(skybox_routine).set_background_texture(Some(handle));
     Ok(())
@@ -285,6 +286,7 @@ struct SceneViewer {
     last_mouse_delta: Option<DVec2>,
 
     grabber: Option<rend3_framework::Grabber>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 impl SceneViewer {
     pub fn new() -> Self {
@@ -392,6 +394,7 @@ impl SceneViewer {
             last_mouse_delta: None,
 
             grabber: None,
+            surface_format: None,
         }
     }
 }
@@ -438,8 +441,9 @@ impl rend3_framework::App for SceneViewer {
         window: &'a winit::window::Window,
         renderer: &'a Arc<Renderer>,
         routines: &'a Arc<rend3_framework::DefaultRoutines>,
-        _surface_format: rend3::types::TextureFormat,
+        surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         self.grabber = Some(rend3_framework::Grabber::new(window));
 
         if let Some(direction) = self.directional_light_direction {
@@ -448,7 +452,9 @@ impl rend3_framework::App for SceneViewer {
                 intensity: self.directional_light_intensity,
                 direction,
                 distance: self.gltf_settings.directional_light_shadow_distance,
+                caster_margin: 0.0,
                 resolution: 2048,
+                cast_shadows: true,
             }));
         }
 
@@ -579,16 +585,30 @@ impl rend3_framework::App for SceneViewer {
                 });
 
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
-                // Lock all the routines
-                let pbr_routine = lock(&routines.pbr);
-                let mut skybox_routine = lock(&routines.skybox);
-                let tonemapping_routine = lock(&routines.tonemapping);
-
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    self.present_mode,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
+
+                // Lock all the routines
+                let pbr_routine = lock(&routines.pbr);
+                let mut skybox_routine = lock(&routines.skybox);
+                let tonemapping_routine = lock(&routines.tonemapping);
                 // Evaluate changes to routines.
                 skybox_routine.evaluate(renderer);
 
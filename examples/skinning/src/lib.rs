@@ -11,6 +11,7 @@ struct SkinningExample {
     directional_light_handle: Option<rend3::types::DirectionalLightHandle>,
     armature: Option<rend3_gltf::Armature>,
     start_time: Option<Instant>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 
 /// Locates an object in the node list that corresponds to an animated mesh
@@ -69,8 +70,9 @@ impl rend3_framework::App for SkinningExample {
         _window: &winit::window::Window,
         renderer: &Arc<rend3::Renderer>,
         _routines: &Arc<rend3_framework::DefaultRoutines>,
-        _surface_format: rend3::types::TextureFormat,
+        surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         // Store the startup time. Use later to animate the joint rotation
         self.start_time = Some(Instant::now());
 
@@ -114,7 +116,9 @@ impl rend3_framework::App for SkinningExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
+            caster_margin: 0.0,
             resolution: 2048,
+            cast_shadows: true,
         }));
     }
 
@@ -144,12 +148,26 @@ impl rend3_framework::App for SkinningExample {
             // Render!
             rend3_framework::Event::RedrawRequested(_) => {
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
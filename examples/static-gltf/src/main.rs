@@ -55,6 +55,7 @@ const SAMPLE_COUNT: rend3::types::SampleCount = rend3::types::SampleCount::One;
 struct GltfExample {
     object_handle: Option<rend3::types::ObjectHandle>,
     directional_light_handle: Option<rend3::types::DirectionalLightHandle>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 
 impl rend3_framework::App for GltfExample {
@@ -70,8 +71,9 @@ impl rend3_framework::App for GltfExample {
         _window: &winit::window::Window,
         renderer: &Arc<rend3::Renderer>,
         _routines: &Arc<rend3_framework::DefaultRoutines>,
-        _surface_format: rend3::types::TextureFormat,
+        surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         // Create mesh and calculate smooth normals based on vertices.
         //
         // We do not need to keep these handles alive once we make the object
@@ -82,6 +84,12 @@ impl rend3_framework::App for GltfExample {
             mesh_kind: rend3::types::ObjectMeshKind::Static(mesh),
             material,
             transform: glam::Mat4::from_scale(glam::Vec3::new(1.0, 1.0, -1.0)),
+            overlay: false,
+            layers: u32::MAX,
+            cast_shadows: true,
+            receive_shadows: true,
+            tint: glam::Vec4::ONE,
+            clip: rend3::types::ObjectClip::default(),
         };
         // We need to keep the object alive.
         self.object_handle = Some(renderer.add_object(object));
@@ -105,7 +113,9 @@ impl rend3_framework::App for GltfExample {
             // Direction will be normalized
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 20.0,
+            caster_margin: 0.0,
             resolution: 2048,
+            cast_shadows: true,
         }));
     }
 
@@ -134,12 +144,26 @@ impl rend3_framework::App for GltfExample {
             // Render!
             rend3_framework::Event::RedrawRequested(..) => {
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
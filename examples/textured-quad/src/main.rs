@@ -39,6 +39,7 @@ const SAMPLE_COUNT: rend3::types::SampleCount = rend3::types::SampleCount::One;
 #[derive(Default)]
 struct TexturedQuadExample {
     data: Option<TexturedQuadExampleData>,
+    surface_format: Option<rend3::types::TextureFormat>,
 }
 impl rend3_framework::App for TexturedQuadExample {
     const HANDEDNESS: rend3::types::Handedness = rend3::types::Handedness::Left;
@@ -53,8 +54,9 @@ impl rend3_framework::App for TexturedQuadExample {
         window: &winit::window::Window,
         renderer: &Arc<rend3::Renderer>,
         _routines: &Arc<rend3_framework::DefaultRoutines>,
-        _surface_format: rend3::types::TextureFormat,
+        surface_format: rend3::types::TextureFormat,
     ) {
+        self.surface_format = Some(surface_format);
         // Create mesh and calculate smooth normals based on vertices
         let mesh = create_quad(300.0);
 
@@ -75,6 +77,7 @@ impl rend3_framework::App for TexturedQuadExample {
             size: glam::UVec2::new(image_checker.dimensions().0, image_checker.dimensions().1),
             mip_count: rend3::types::MipmapCount::ONE,
             mip_source: rend3::types::MipmapSource::Uploaded,
+            color_space: None,
         };
         let texture_checker_handle = renderer.add_texture_2d(texture_checker);
 
@@ -96,6 +99,12 @@ impl rend3_framework::App for TexturedQuadExample {
                 glam::Quat::from_euler(glam::EulerRot::XYZ, 0.0, 0.0, 0.0),
                 glam::Vec3::new(0.0, 0.0, 0.0),
             ),
+            overlay: false,
+            layers: u32::MAX,
+            cast_shadows: true,
+            receive_shadows: true,
+            tint: glam::Vec4::ONE,
+            clip: rend3::types::ObjectClip::default(),
         };
 
         // Creating an object will hold onto both the mesh and the material
@@ -159,12 +168,26 @@ impl rend3_framework::App for TexturedQuadExample {
             // Render!
             winit::event::Event::MainEventsCleared => {
                 // Get a frame
-                let frame = surface.unwrap().get_current_texture().unwrap();
+                let frame = match rend3::get_surface_texture(
+                    surface.unwrap(),
+                    &renderer.device,
+                    self.surface_format.unwrap(),
+                    resolution,
+                    rend3::types::PresentMode::Fifo,
+                ) {
+                    Ok(frame) => frame,
+                    // Try again next frame.
+                    Err(rend3::SurfaceAcquireError::Skip) => return,
+                    // A real application would recreate the renderer here, see the docs of Renderer.
+                    Err(rend3::SurfaceAcquireError::Lost) => panic!("Device lost"),
+                };
 
                 // Swap the instruction buffers so that our frame's changes can be processed.
                 renderer.swap_instruction_buffers();
                 // Evaluate our frame's world-change instructions
                 let mut eval_output = renderer.evaluate_instructions();
+                // Rebuild routines invalidated by the evaluation.
+                routines.rebuild_outdated(renderer, base_rendergraph);
 
                 // Lock the routines
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
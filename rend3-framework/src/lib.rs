@@ -118,6 +118,9 @@ pub trait App<T: 'static = ()> {
     /// size is non-zero. As such you should always render
     /// in RedrawRequested and use MainEventsCleared for things that need to
     /// keep running when minimized.
+    ///
+    /// [`BaseRenderGraph::evaluate`] has already been called with `resolution`
+    /// when RedrawRequested arrives here.
     #[allow(clippy::too_many_arguments)]
     fn handle_event(
         &mut self,
@@ -156,6 +159,23 @@ pub struct DefaultRoutines {
     pub pbr: Mutex<rend3_routine::pbr::PbrRoutine>,
     pub skybox: Mutex<rend3_routine::skybox::SkyboxRoutine>,
     pub tonemapping: Mutex<rend3_routine::tonemapping::TonemappingRoutine>,
+    /// Shaders the routines were built from.
+    pub spp: ShaderPreProcessor,
+}
+
+impl DefaultRoutines {
+    /// Rebuild the routines whose pipelines no longer match the renderer,
+    /// like the PBR routine after the texture array grew. Call this every
+    /// frame after [`Renderer::evaluate_instructions`], before locking the
+    /// routines to build the graph.
+    pub fn rebuild_outdated(&self, renderer: &Renderer, base_rendergraph: &BaseRenderGraph) {
+        lock(&self.pbr).rebuild_if_outdated(
+            renderer,
+            &mut renderer.data_core.write(),
+            &self.spp,
+            &base_rendergraph.interfaces,
+        );
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -249,26 +269,30 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
     let mut spp = rend3::ShaderPreProcessor::new();
     rend3_routine::builtin_shaders(&mut spp);
 
-    let base_rendergraph = app.create_base_rendergraph(&renderer, &spp);
-    let mut data_core = renderer.data_core.lock();
+    let mut base_rendergraph = app.create_base_rendergraph(&renderer, &spp);
+    let mut data_core = renderer.data_core.write();
+    // The routines are independent, so build their pipelines in parallel.
+    let (pbr, (skybox, tonemapping)) = rend3::util::parallel::join(
+        || rend3_routine::pbr::PbrRoutine::new(&renderer, &mut data_core, &spp, &base_rendergraph.interfaces),
+        || {
+            rend3::util::parallel::join(
+                || rend3_routine::skybox::SkyboxRoutine::new(&renderer, &spp, &base_rendergraph.interfaces),
+                || {
+                    rend3_routine::tonemapping::TonemappingRoutine::new(
+                        &renderer,
+                        &spp,
+                        &base_rendergraph.interfaces,
+                        format,
+                    )
+                },
+            )
+        },
+    );
     let routines = Arc::new(DefaultRoutines {
-        pbr: Mutex::new(rend3_routine::pbr::PbrRoutine::new(
-            &renderer,
-            &mut data_core,
-            &spp,
-            &base_rendergraph.interfaces,
-        )),
-        skybox: Mutex::new(rend3_routine::skybox::SkyboxRoutine::new(
-            &renderer,
-            &spp,
-            &base_rendergraph.interfaces,
-        )),
-        tonemapping: Mutex::new(rend3_routine::tonemapping::TonemappingRoutine::new(
-            &renderer,
-            &spp,
-            &base_rendergraph.interfaces,
-            format,
-        )),
+        pbr: Mutex::new(pbr),
+        skybox: Mutex::new(skybox),
+        tonemapping: Mutex::new(tonemapping),
+        spp,
     });
     drop(data_core);
 
@@ -329,6 +353,8 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
             }
         }
 
+        prepare_redraw(&event, &renderer, &mut base_rendergraph, stored_surface_info.size);
+
         app.handle_event(
             &window,
             &renderer,
@@ -345,6 +371,19 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
     });
 }
 
+/// Advance the per-frame state of the base rendergraph right before the app
+/// gets to render the frame.
+fn prepare_redraw<T>(
+    event: &Event<'_, T>,
+    renderer: &Renderer,
+    base_rendergraph: &mut BaseRenderGraph,
+    resolution: UVec2,
+) {
+    if let Event::RedrawRequested(_) = event {
+        base_rendergraph.evaluate(renderer, resolution);
+    }
+}
+
 struct StoredSurfaceInfo {
     size: UVec2,
     scale_factor: f32,
@@ -417,3 +456,69 @@ pub fn start<A: App<T> + 'static, T: 'static>(app: A, window_builder: WindowBuil
         pollster::block_on(async_start(app, window_builder));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use glam::UVec2;
+    use rend3::{
+        graph::{RenderGraph, ViewportRect},
+        types::{Handedness, TextureFormat},
+        util::readback,
+        Renderer, ShaderPreProcessor,
+    };
+    use rend3_routine::{base::BaseRenderGraph, pbr::PbrRoutine, tonemapping::TonemappingRoutine};
+    use winit::window::WindowId;
+
+    use super::{prepare_redraw, Event};
+
+    #[test]
+    fn taa_is_evaluated_before_redraw() {
+        let Ok(iad) = pollster::block_on(rend3::create_iad(None, None, None, None)) else {
+            return;
+        };
+        let resolution = UVec2::splat(16);
+        let renderer = Renderer::new(iad, Handedness::Left, Some(1.0)).unwrap();
+
+        let mut spp = ShaderPreProcessor::new();
+        rend3_routine::builtin_shaders(&mut spp);
+        let mut base_rendergraph = BaseRenderGraph::builder().taa(true).build(&renderer, &spp).unwrap();
+
+        let mut data_core = renderer.data_core.write();
+        let pbr = PbrRoutine::new(&renderer, &mut data_core, &spp, &base_rendergraph.interfaces);
+        drop(data_core);
+        let tonemapping = TonemappingRoutine::new(
+            &renderer,
+            &spp,
+            &base_rendergraph.interfaces,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let texture = readback::create_readback_texture(&renderer.device, resolution);
+
+        // Two frames, so the second one reads the history of the first.
+        for _ in 0..2 {
+            // What the frame loop does before handing the redraw to the app.
+            let event = Event::<()>::RedrawRequested(unsafe { WindowId::dummy() });
+            prepare_redraw(&event, &renderer, &mut base_rendergraph, resolution);
+
+            // What an app does when rendering, TAA panics here without the evaluation.
+            renderer.swap_instruction_buffers();
+            let mut eval_output = renderer.evaluate_instructions();
+            let mut graph = RenderGraph::new();
+            let frame_handle = graph.add_imported_render_target(&texture, 0..1, ViewportRect::from_size(resolution));
+            base_rendergraph.add_to_graph_configured(
+                &mut graph,
+                &eval_output,
+                &pbr,
+                None,
+                &tonemapping,
+                frame_handle,
+                resolution,
+            );
+            graph.execute(&renderer, &mut eval_output);
+        }
+
+        // The empty scene is the default opaque black clear color, TAA included.
+        let pixels = readback::read_texture_rgba8(&renderer.device, &renderer.queue, &texture);
+        assert!(pixels.chunks(4).all(|pixel| pixel == [0, 0, 0, 255]));
+    }
+}
@@ -470,6 +470,12 @@ pub fn add_mesh_by_index<E: std::error::Error + 'static>(
             mesh_kind,
             material: mat.clone(),
             transform,
+            overlay: false,
+            layers: u32::MAX,
+            cast_shadows: true,
+            receive_shadows: true,
+            tint: Vec4::ONE,
+            clip: types::ObjectClip::default(),
         }));
     }
 
@@ -567,7 +573,9 @@ pub fn instance_loaded_scene<'a, E: std::error::Error + 'static>(
                         intensity: light.intensity(),
                         direction,
                         distance: settings.directional_light_shadow_distance,
+                        caster_margin: 0.0,
                         resolution: settings.directional_light_resolution,
+                        cast_shadows: true,
                     }))
                 }
                 _ => None,
@@ -848,12 +856,27 @@ fn load_animations<E: std::error::Error + 'static>(
     Ok(result)
 }
 
+/// Wrap mode of both axes of the sampler. Materials wrap both axes the same way,
+/// so if the axes differ, clamping wins over mirroring, which wins over
+/// repeating. This keeps textures which clamp along one axis, like strips of an
+/// atlas, from bleeding into their neighbors.
+fn wrap_mode(sampler: &gltf::texture::Sampler<'_>) -> pbr::WrapMode {
+    use gltf::texture::WrappingMode;
+
+    match (sampler.wrap_s(), sampler.wrap_t()) {
+        (WrappingMode::ClampToEdge, _) | (_, WrappingMode::ClampToEdge) => pbr::WrapMode::ClampToEdge,
+        (WrappingMode::MirroredRepeat, _) | (_, WrappingMode::MirroredRepeat) => pbr::WrapMode::MirrorRepeat,
+        (WrappingMode::Repeat, WrappingMode::Repeat) => pbr::WrapMode::Repeat,
+    }
+}
+
 /// Creates a gltf default material.
 pub fn load_default_material(renderer: &Arc<Renderer>) -> types::MaterialHandle {
     profiling::scope!("creating default material");
     renderer.add_material(pbr::PbrMaterial {
         albedo: pbr::AlbedoComponent::Value(Vec4::splat(1.0)),
         transparency: pbr::Transparency::Opaque,
+        blend_mode: pbr::BlendMode::Alpha,
         normal: pbr::NormalTexture::None,
         aomr_textures: pbr::AoMRTextures::None,
         ao_factor: Some(1.0),
@@ -868,7 +891,13 @@ pub fn load_default_material(renderer: &Arc<Renderer>) -> types::MaterialHandle
         uv_transform0: Mat3::IDENTITY,
         uv_transform1: Mat3::IDENTITY,
         unlit: false,
+        toon: None,
+        parallax: None,
+        subsurface: None,
+        flat_shading: false,
+        two_sided_lighting: false,
         sample_type: pbr::SampleType::Linear,
+        wrap_mode: pbr::WrapMode::Repeat,
     })
 }
 
@@ -917,6 +946,11 @@ where
             })
             .unwrap_or_default();
 
+        let wrap_mode = albedo
+            .as_ref()
+            .map(|i| wrap_mode(&i.texture().sampler()))
+            .unwrap_or_default();
+
         let uv_transform = albedo
             .as_ref()
             .and_then(|i| {
@@ -970,6 +1004,7 @@ where
                 },
                 gltf::material::AlphaMode::Blend => pbr::Transparency::Blend,
             },
+            blend_mode: pbr::BlendMode::Alpha,
             normal: match normals_tex {
                 Some(tex) if util::format_components(tex.format) == Some(2) => {
                     pbr::NormalTexture::Bicomponent(tex.handle, settings.normal_direction)
@@ -1012,6 +1047,7 @@ where
             uv_transform1: uv_transform,
             unlit: material.unlit(),
             sample_type: nearest,
+            wrap_mode,
             ..pbr::PbrMaterial::default()
         });
 
@@ -1146,6 +1182,7 @@ where
             } else {
                 types::MipmapSource::Uploaded
             },
+            color_space: None,
         })
     }
 
@@ -1201,6 +1238,7 @@ where
                 } else {
                     types::MipmapSource::Uploaded
                 },
+                color_space: None,
             })
         }
     }
@@ -1219,6 +1257,7 @@ where
             data,
             mip_count: types::MipmapCount::Maximum,
             mip_source: types::MipmapSource::Generated,
+            color_space: None,
         })
     };
 
@@ -0,0 +1,324 @@
+// Fits linearly transformed cosines to the GGX BRDF with height-correlated
+// Smith shadowing, following Heitz et al. 2016, "Real-Time Polygonal-Light
+// Shading with Linearly Transformed Cosines".
+#include <math.h>
+#include <stdio.h>
+#include <stdint.h>
+#include <string.h>
+#include <float.h>
+
+#define N 64
+#define NSAMPLE 32
+#define MIN_ALPHA 0.00001f
+#define PI 3.14159265358979f
+
+typedef struct { double x, y, z; } vec3;
+static vec3 v3(double x, double y, double z) { vec3 r = {x, y, z}; return r; }
+static vec3 add(vec3 a, vec3 b) { return v3(a.x + b.x, a.y + b.y, a.z + b.z); }
+static vec3 scale(vec3 a, double s) { return v3(a.x * s, a.y * s, a.z * s); }
+static double dot(vec3 a, vec3 b) { return a.x * b.x + a.y * b.y + a.z * b.z; }
+static double length(vec3 a) { return sqrt(dot(a, a)); }
+static vec3 normalize(vec3 a) { return scale(a, 1.0 / length(a)); }
+
+// Row major 3x3.
+typedef struct { double m[3][3]; } mat3;
+static vec3 mul(mat3 a, vec3 v) {
+    return v3(a.m[0][0] * v.x + a.m[0][1] * v.y + a.m[0][2] * v.z,
+              a.m[1][0] * v.x + a.m[1][1] * v.y + a.m[1][2] * v.z,
+              a.m[2][0] * v.x + a.m[2][1] * v.y + a.m[2][2] * v.z);
+}
+static mat3 mmul(mat3 a, mat3 b) {
+    mat3 r;
+    for (int i = 0; i < 3; ++i)
+        for (int j = 0; j < 3; ++j) {
+            r.m[i][j] = 0;
+            for (int k = 0; k < 3; ++k) r.m[i][j] += a.m[i][k] * b.m[k][j];
+        }
+    return r;
+}
+static double det(mat3 a) {
+    return a.m[0][0] * (a.m[1][1] * a.m[2][2] - a.m[1][2] * a.m[2][1]) -
+           a.m[0][1] * (a.m[1][0] * a.m[2][2] - a.m[1][2] * a.m[2][0]) +
+           a.m[0][2] * (a.m[1][0] * a.m[2][1] - a.m[1][1] * a.m[2][0]);
+}
+static mat3 inverse(mat3 a) {
+    mat3 r;
+    double d = det(a);
+    r.m[0][0] = (a.m[1][1] * a.m[2][2] - a.m[1][2] * a.m[2][1]) / d;
+    r.m[0][1] = (a.m[0][2] * a.m[2][1] - a.m[0][1] * a.m[2][2]) / d;
+    r.m[0][2] = (a.m[0][1] * a.m[1][2] - a.m[0][2] * a.m[1][1]) / d;
+    r.m[1][0] = (a.m[1][2] * a.m[2][0] - a.m[1][0] * a.m[2][2]) / d;
+    r.m[1][1] = (a.m[0][0] * a.m[2][2] - a.m[0][2] * a.m[2][0]) / d;
+    r.m[1][2] = (a.m[0][2] * a.m[1][0] - a.m[0][0] * a.m[1][2]) / d;
+    r.m[2][0] = (a.m[1][0] * a.m[2][1] - a.m[1][1] * a.m[2][0]) / d;
+    r.m[2][1] = (a.m[0][1] * a.m[2][0] - a.m[0][0] * a.m[2][1]) / d;
+    r.m[2][2] = (a.m[0][0] * a.m[1][1] - a.m[0][1] * a.m[1][0]) / d;
+    return r;
+}
+
+// GGX
+static double lambda(double alpha, double cos_theta) {
+    if (cos_theta >= 1.0) return 0.0;
+    double tan2 = (1.0 - cos_theta * cos_theta) / (cos_theta * cos_theta);
+    return 0.5 * (-1.0 + sqrt(1.0 + alpha * alpha * tan2));
+}
+
+// Returns brdf * cos(theta_l), writes the pdf of brdf_sample.
+static double brdf_eval(vec3 V, vec3 L, double alpha, double *pdf) {
+    if (V.z <= 0) { *pdf = 0; return 0; }
+    double lv = lambda(alpha, V.z);
+    double g2 = 0;
+    if (L.z > 0) g2 = 1.0 / (1.0 + lv + lambda(alpha, L.z));
+    vec3 H = normalize(add(V, L));
+    double sx = H.x / H.z, sy = H.y / H.z;
+    double D = 1.0 / (1.0 + (sx * sx + sy * sy) / alpha / alpha);
+    D = D * D;
+    D = D / (PI * alpha * alpha * H.z * H.z * H.z * H.z);
+    *pdf = fabs(D * H.z / 4.0 / dot(V, H));
+    return D * g2 / 4.0 / V.z;
+}
+
+static vec3 brdf_sample(vec3 V, double alpha, double u1, double u2) {
+    double phi = 2.0 * PI * u1;
+    double r = alpha * sqrt(u2 / (1.0 - u2));
+    vec3 n = normalize(v3(r * cos(phi), r * sin(phi), 1.0));
+    return add(scale(V, -1.0), scale(n, 2.0 * dot(n, V)));
+}
+
+typedef struct {
+    double magnitude, fresnel;
+    double m11, m22, m13;
+    vec3 X, Y, Z;
+    mat3 M, invM;
+    double detM;
+} ltc_t;
+
+static void ltc_update(ltc_t *l) {
+    mat3 basis = {{{l->X.x, l->Y.x, l->Z.x}, {l->X.y, l->Y.y, l->Z.y}, {l->X.z, l->Y.z, l->Z.z}}};
+    mat3 params = {{{l->m11, 0, l->m13}, {0, l->m22, 0}, {0, 0, 1}}};
+    l->M = mmul(basis, params);
+    l->invM = inverse(l->M);
+    l->detM = fabs(det(l->M));
+}
+
+static double ltc_eval(const ltc_t *l, vec3 L) {
+    vec3 lo = normalize(mul(l->invM, L));
+    vec3 lt = mul(l->M, lo);
+    double len = length(lt);
+    double jacobian = l->detM / (len * len * len);
+    double D = 1.0 / PI * fmax(0.0, lo.z);
+    return l->magnitude * D / jacobian;
+}
+
+static vec3 ltc_sample(const ltc_t *l, double u1, double u2) {
+    double theta = acos(sqrt(u1));
+    double phi = 2.0 * PI * u2;
+    return normalize(mul(l->M, v3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta))));
+}
+
+static double compute_error(const ltc_t *l, vec3 V, double alpha) {
+    double error = 0.0;
+    for (int j = 0; j < NSAMPLE; ++j)
+        for (int i = 0; i < NSAMPLE; ++i) {
+            double u1 = (i + 0.5) / NSAMPLE;
+            double u2 = (j + 0.5) / NSAMPLE;
+            {
+                vec3 L = ltc_sample(l, u1, u2);
+                double pdf_brdf;
+                double eval_brdf = brdf_eval(V, L, alpha, &pdf_brdf);
+                double eval_ltc = ltc_eval(l, L);
+                double pdf_ltc = eval_ltc / l->magnitude;
+                double e = fabs(eval_brdf - eval_ltc);
+                error += e * e * e / (pdf_ltc + pdf_brdf);
+            }
+            {
+                vec3 L = brdf_sample(V, alpha, u1, u2);
+                double pdf_brdf;
+                double eval_brdf = brdf_eval(V, L, alpha, &pdf_brdf);
+                double eval_ltc = ltc_eval(l, L);
+                double pdf_ltc = eval_ltc / l->magnitude;
+                double e = fabs(eval_brdf - eval_ltc);
+                error += e * e * e / (pdf_ltc + pdf_brdf);
+            }
+        }
+    return error / (double)(NSAMPLE * NSAMPLE);
+}
+
+static void compute_avg_terms(vec3 V, double alpha, double *norm, double *fresnel, vec3 *avg) {
+    *norm = 0; *fresnel = 0; *avg = v3(0, 0, 0);
+    for (int j = 0; j < NSAMPLE; ++j)
+        for (int i = 0; i < NSAMPLE; ++i) {
+            double u1 = (i + 0.5) / NSAMPLE;
+            double u2 = (j + 0.5) / NSAMPLE;
+            vec3 L = brdf_sample(V, alpha, u1, u2);
+            double pdf;
+            double eval = brdf_eval(V, L, alpha, &pdf);
+            if (pdf > 0) {
+                double w = eval / pdf;
+                vec3 H = normalize(add(V, L));
+                *norm += w;
+                *fresnel += w * pow(1.0 - fmax(dot(V, H), 0.0), 5.0);
+                *avg = add(*avg, scale(L, w));
+            }
+        }
+    *norm /= NSAMPLE * NSAMPLE;
+    *fresnel /= NSAMPLE * NSAMPLE;
+    avg->y = 0;
+    *avg = normalize(*avg);
+}
+
+typedef struct { ltc_t *ltc; vec3 V; double alpha; int isotropic; } fitter_t;
+
+static void fitter_update(fitter_t *f, const double *p) {
+    double m11 = fmax(p[0], 1e-7), m22 = fmax(p[1], 1e-7), m13 = p[2];
+    if (f->isotropic) { f->ltc->m11 = m11; f->ltc->m22 = m11; f->ltc->m13 = 0; }
+    else { f->ltc->m11 = m11; f->ltc->m22 = m22; f->ltc->m13 = m13; }
+    ltc_update(f->ltc);
+}
+static double fitter_eval(fitter_t *f, const double *p) {
+    fitter_update(f, p);
+    return compute_error(f->ltc, f->V, f->alpha);
+}
+
+// Downhill simplex, as in the reference implementation.
+#define DIM 3
+static double nelder_mead(double *pmin, const double *start, double delta, double tolerance, int max_iters, fitter_t *f) {
+    const double reflect = 1.0, expand = 2.0, contract = 0.5, shrink = 0.5;
+    double s[DIM + 1][DIM], fv[DIM + 1];
+    for (int i = 0; i < DIM + 1; ++i) {
+        memcpy(s[i], start, sizeof(double) * DIM);
+        if (i > 0) s[i][i - 1] += delta;
+        fv[i] = fitter_eval(f, s[i]);
+    }
+    int lo = 0, hi, nh;
+    for (int iter = 0; iter < max_iters; ++iter) {
+        lo = 0; hi = 0; nh = 0;
+        for (int i = 0; i < DIM + 1; ++i) {
+            if (fv[i] < fv[lo]) lo = i;
+            if (fv[i] > fv[hi]) { nh = hi; hi = i; } else if (fv[i] > fv[nh] || nh == hi) nh = i;
+        }
+        if (nh == hi) { for (int i = 0; i < DIM + 1; ++i) if (i != hi) { nh = i; break; } for (int i = 0; i < DIM + 1; ++i) if (i != hi && fv[i] > fv[nh]) nh = i; }
+        double a = fabs(fv[lo]), b = fabs(fv[hi]);
+        if (2.0 * fabs(a - b) < (a + b) * tolerance) break;
+        double o[DIM] = {0};
+        for (int i = 0; i < DIM + 1; ++i) if (i != hi) for (int k = 0; k < DIM; ++k) o[k] += s[i][k];
+        for (int k = 0; k < DIM; ++k) o[k] /= DIM;
+        double r[DIM];
+        for (int k = 0; k < DIM; ++k) r[k] = o[k] + reflect * (o[k] - s[hi][k]);
+        double fr = fitter_eval(f, r);
+        if (fr <= fv[lo]) {
+            double e[DIM];
+            for (int k = 0; k < DIM; ++k) e[k] = o[k] + expand * (r[k] - o[k]);
+            double fe = fitter_eval(f, e);
+            if (fe < fv[lo]) { memcpy(s[hi], e, sizeof e); fv[hi] = fe; }
+            else { memcpy(s[hi], r, sizeof r); fv[hi] = fr; }
+        } else if (fr < fv[nh]) {
+            memcpy(s[hi], r, sizeof r); fv[hi] = fr;
+        } else {
+            double c[DIM];
+            if (fr < fv[hi]) for (int k = 0; k < DIM; ++k) c[k] = o[k] + contract * (r[k] - o[k]);
+            else for (int k = 0; k < DIM; ++k) c[k] = o[k] + contract * (s[hi][k] - o[k]);
+            double fc = fitter_eval(f, c);
+            if (fc < fmin(fr, fv[hi])) { memcpy(s[hi], c, sizeof c); fv[hi] = fc; }
+            else {
+                for (int i = 0; i < DIM + 1; ++i) if (i != lo) {
+                    for (int k = 0; k < DIM; ++k) s[i][k] = s[lo][k] + shrink * (s[i][k] - s[lo][k]);
+                    fv[i] = fitter_eval(f, s[i]);
+                }
+            }
+        }
+    }
+    lo = 0;
+    for (int i = 0; i < DIM + 1; ++i) if (fv[i] < fv[lo]) lo = i;
+    memcpy(pmin, s[lo], sizeof(double) * DIM);
+    return fv[lo];
+}
+
+static uint16_t to_half(float value) {
+    uint32_t x;
+    memcpy(&x, &value, 4);
+    uint32_t sign = (x >> 16) & 0x8000;
+    int32_t exp = (int32_t)((x >> 23) & 0xff) - 127 + 15;
+    uint32_t mant = x & 0x7fffff;
+    if (exp >= 31) return (uint16_t)(sign | 0x7bff); // clamp to max
+    if (exp <= 0) {
+        if (exp < -10) return (uint16_t)sign;
+        mant |= 0x800000;
+        uint32_t shift = (uint32_t)(14 - exp);
+        uint32_t half = mant >> shift;
+        uint32_t rem = mant & ((1u << shift) - 1);
+        uint32_t mid = 1u << (shift - 1);
+        if (rem > mid || (rem == mid && (half & 1))) half++;
+        return (uint16_t)(sign | half);
+    }
+    uint32_t half = sign | ((uint32_t)exp << 10) | (mant >> 13);
+    uint32_t rem = mant & 0x1fff;
+    if (rem > 0x1000 || (rem == 0x1000 && (half & 1))) half++;
+    return (uint16_t)half;
+}
+
+int main(void) {
+    static mat3 tab[N * N];
+    static double mag[N * N], fres[N * N], err[N * N];
+    ltc_t ltc;
+    memset(&ltc, 0, sizeof ltc);
+    ltc.magnitude = 1; ltc.fresnel = 1; ltc.m11 = 1; ltc.m22 = 1; ltc.m13 = 0;
+    ltc.X = v3(1, 0, 0); ltc.Y = v3(0, 1, 0); ltc.Z = v3(0, 0, 1);
+    ltc_update(&ltc);
+
+    for (int a = N - 1; a >= 0; --a)
+        for (int t = 0; t <= N - 1; ++t) {
+            double x = t / (double)(N - 1);
+            double ct = 1.0 - x * x;
+            double theta = fmin(1.57, acos(ct));
+            vec3 V = v3(sin(theta), 0, cos(theta));
+            double roughness = a / (double)(N - 1);
+            double alpha = fmax(roughness * roughness, MIN_ALPHA);
+
+            vec3 avg;
+            compute_avg_terms(V, alpha, &ltc.magnitude, &ltc.fresnel, &avg);
+
+            int isotropic;
+            if (t == 0) {
+                ltc.X = v3(1, 0, 0); ltc.Y = v3(0, 1, 0); ltc.Z = v3(0, 0, 1);
+                if (a == N - 1) { ltc.m11 = 1; ltc.m22 = 1; }
+                else { ltc.m11 = tab[a + 1 + t * N].m[0][0]; ltc.m22 = tab[a + 1 + t * N].m[1][1]; }
+                ltc.m13 = 0;
+                ltc_update(&ltc);
+                isotropic = 1;
+            } else {
+                ltc.X = v3(avg.z, 0, -avg.x); ltc.Y = v3(0, 1, 0); ltc.Z = avg;
+                ltc_update(&ltc);
+                isotropic = 0;
+            }
+
+            fitter_t f = {&ltc, V, alpha, isotropic};
+            double start[3] = {ltc.m11, ltc.m22, ltc.m13}, result[3];
+            err[a + t * N] = nelder_mead(result, start, 0.05, 1e-5, 100, &f);
+            fitter_update(&f, result);
+
+            tab[a + t * N] = ltc.M;
+            mag[a + t * N] = ltc.magnitude;
+            fres[a + t * N] = ltc.fresnel;
+            tab[a + t * N].m[1][0] = 0; tab[a + t * N].m[0][1] = 0;
+            tab[a + t * N].m[1][2] = 0; tab[a + t * N].m[2][1] = 0;
+        }
+
+    FILE *fm = fopen("ltc_matrix.bin", "wb");
+    FILE *fa = fopen("ltc_amplitude.bin", "wb");
+    FILE *fd = fopen("ltc_debug.txt", "w");
+    for (int i = 0; i < N * N; ++i) {
+        mat3 inv = inverse(tab[i]);
+        double n = inv.m[1][1];
+        // Rows of the inverse, the shader rebuilds it from the four varying terms.
+        float m[4] = {(float)(inv.m[0][0] / n), (float)(inv.m[2][0] / n), (float)(inv.m[0][2] / n), (float)(inv.m[2][2] / n)};
+        uint16_t h[4];
+        for (int k = 0; k < 4; ++k) h[k] = to_half(m[k]);
+        fwrite(h, 2, 4, fm);
+        uint16_t g[2] = {to_half((float)mag[i]), to_half((float)fres[i])};
+        fwrite(g, 2, 2, fa);
+        fprintf(fd, "%d %d %g %g %g %g | %g %g | err %g\n", i % N, i / N, m[0], m[1], m[2], m[3], mag[i], fres[i], err[i]);
+    }
+    fclose(fm); fclose(fa); fclose(fd);
+    return 0;
+}
@@ -1,5 +1,19 @@
 {{include "rend3-routine/math/color.wgsl"}}
 
+const POST_FLAGS_VIGNETTE: u32             = 0x0001u;
+const POST_FLAGS_CHROMATIC_ABERRATION: u32 = 0x0002u;
+const POST_FLAGS_COLOR_GRADING: u32        = 0x0004u;
+const POST_FLAGS_DITHERING: u32            = 0x0008u;
+const POST_FLAGS_PREMULTIPLIED_ALPHA: u32  = 0x0010u;
+const POST_FLAGS_EDGE_UPSCALING: u32       = 0x0020u;
+
+struct PostSettings {
+    flags: u32,
+    vignette_strength: f32,
+    chromatic_aberration_strength: f32,
+    upscaling_sharpness: f32,
+}
+
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
@@ -17,15 +31,195 @@ fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
 var primary_sampler: sampler;
 @group(1) @binding(0)
 var source: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> post: PostSettings;
+@group(1) @binding(2)
+var lut: texture_3d<f32>;
+
+// Lanczos-2 approximation from FSR1's EASU, taking the squared distance. Smaller lobes
+// have a stronger negative lobe and sharpen more, 0.5 is close to a smooth cubic.
+fn upscale_weight(d2: f32, lobe: f32) -> f32 {
+    let x = min(d2, 1.0 / lobe);
+    let wa = lobe * x - 1.0;
+    let wb = 0.4 * x - 1.0;
+    return (25.0 / 16.0 * wb * wb - 9.0 / 16.0) * (wa * wa);
+}
+
+fn source_luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// Edge adaptive upscaling in the style of FSR1's EASU: a 4x4 lanczos kernel which is
+// stretched along edges and sharpened across them, clamped to the nearest 2x2 texels
+// to avoid ringing.
+fn sample_upscaled(coords: vec2<f32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(source));
+    let pixel = coords * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(pixel));
+    let t = pixel - floor(pixel);
+
+    var colors: array<vec4<f32>, 16>;
+    var lumas: array<f32, 16>;
+    for (var y = 0; y < 4; y += 1) {
+        for (var x = 0; x < 4; x += 1) {
+            let texel = clamp(base + vec2<i32>(x - 1, y - 1), vec2<i32>(0), size - 1);
+            let color = textureLoad(source, texel, 0);
+            colors[y * 4 + x] = color;
+            lumas[y * 4 + x] = source_luma(color.rgb);
+        }
+    }
+
+    // Gradient at each of the center texels, bilinearly weighted to the sample position.
+    var dir = vec2<f32>(0.0);
+    var luma_min = lumas[5];
+    var luma_max = lumas[5];
+    for (var y = 1; y < 3; y += 1) {
+        for (var x = 1; x < 3; x += 1) {
+            let i = y * 4 + x;
+            let weight = select(1.0 - t.x, t.x, x == 2) * select(1.0 - t.y, t.y, y == 2);
+            dir += vec2<f32>(lumas[i + 1] - lumas[i - 1], lumas[i + 4] - lumas[i - 4]) * weight;
+            luma_min = min(luma_min, lumas[i]);
+            luma_max = max(luma_max, lumas[i]);
+        }
+    }
+    let dir_length = length(dir);
+    let edge = saturate(dir_length / (2.0 * (luma_max - luma_min) + 0.0001));
+    dir = select(vec2<f32>(1.0, 0.0), dir / dir_length, dir_length > 0.0001);
+
+    // Diagonal edges are stretched further, as their texels are further apart.
+    let stretch = 1.0 / max(abs(dir.x), abs(dir.y));
+    let axis_scale = vec2<f32>(1.0 + (stretch - 1.0) * edge, 1.0 - 0.5 * edge);
+    let lobe = mix(0.5, 0.21, saturate(edge + post.upscaling_sharpness * (1.0 - edge)));
+
+    var total = vec4<f32>(0.0);
+    var total_weight = 0.0;
+    for (var y = 0; y < 4; y += 1) {
+        for (var x = 0; x < 4; x += 1) {
+            let offset = vec2<f32>(f32(x - 1), f32(y - 1)) - t;
+            let rotated = vec2<f32>(dot(offset, dir), dot(offset, vec2<f32>(-dir.y, dir.x))) * axis_scale;
+            let weight = upscale_weight(dot(rotated, rotated), lobe);
+            total += colors[y * 4 + x] * weight;
+            total_weight += weight;
+        }
+    }
+
+    let near_min = min(min(colors[5], colors[6]), min(colors[9], colors[10]));
+    let near_max = max(max(colors[5], colors[6]), max(colors[9], colors[10]));
+    return clamp(total / total_weight, near_min, near_max);
+}
+
+fn sample_scene(coords: vec2<f32>) -> vec4<f32> {
+    // Only upscale when the source is smaller than the output, otherwise plain bilinear is exact.
+    let source_texels = vec2<f32>(textureDimensions(source)) * fwidth(coords);
+    var sampled: vec4<f32>;
+    if bool(post.flags & POST_FLAGS_EDGE_UPSCALING) && any(source_texels < vec2<f32>(0.999)) {
+        sampled = sample_upscaled(coords);
+    } else {
+        sampled = textureSampleLevel(source, primary_sampler, coords, 0.0);
+    }
+
+    // Offset red and blue radially away from the center of the screen.
+    if bool(post.flags & POST_FLAGS_CHROMATIC_ABERRATION) {
+        let offset = (coords - 0.5) * post.chromatic_aberration_strength;
+        sampled.r = textureSampleLevel(source, primary_sampler, coords + offset, 0.0).r;
+        sampled.b = textureSampleLevel(source, primary_sampler, coords - offset, 0.0).b;
+    }
+
+    // Radial falloff which is fully black in the corners at a strength of 0.5.
+    if bool(post.flags & POST_FLAGS_VIGNETTE) {
+        let centered = (coords - 0.5) * 2.0;
+        let vignette = saturate(1.0 - post.vignette_strength * dot(centered, centered));
+        sampled = vec4<f32>(sampled.rgb * vignette, sampled.a);
+    }
+
+    return sampled;
+}
+
+// The LUT isn't filterable, so we do the trilinear interpolation ourselves.
+fn sample_lut(display: vec3<f32>) -> vec3<f32> {
+    let size = vec3<i32>(textureDimensions(lut));
+    let coords = clamp(display, vec3<f32>(0.0), vec3<f32>(1.0)) * vec3<f32>(size - 1);
+    let low = vec3<i32>(floor(coords));
+    let high = min(low + 1, size - 1);
+    let t = coords - vec3<f32>(low);
+
+    let c000 = textureLoad(lut, vec3<i32>(low.x, low.y, low.z), 0).rgb;
+    let c100 = textureLoad(lut, vec3<i32>(high.x, low.y, low.z), 0).rgb;
+    let c010 = textureLoad(lut, vec3<i32>(low.x, high.y, low.z), 0).rgb;
+    let c110 = textureLoad(lut, vec3<i32>(high.x, high.y, low.z), 0).rgb;
+    let c001 = textureLoad(lut, vec3<i32>(low.x, low.y, high.z), 0).rgb;
+    let c101 = textureLoad(lut, vec3<i32>(high.x, low.y, high.z), 0).rgb;
+    let c011 = textureLoad(lut, vec3<i32>(low.x, high.y, high.z), 0).rgb;
+    let c111 = textureLoad(lut, vec3<i32>(high.x, high.y, high.z), 0).rgb;
+
+    let c00 = mix(c000, c100, t.x);
+    let c10 = mix(c010, c110, t.x);
+    let c01 = mix(c001, c101, t.x);
+    let c11 = mix(c011, c111, t.x);
+
+    let c0 = mix(c00, c10, t.y);
+    let c1 = mix(c01, c11, t.y);
+
+    return mix(c0, c1, t.z);
+}
+
+// Ordered dithering with a 4x4 bayer matrix, returns a value in [-0.5, 0.5).
+fn bayer_dither(position: vec2<f32>) -> f32 {
+    var bayer = array<f32, 16>(
+         0.0,  8.0,  2.0, 10.0,
+        12.0,  4.0, 14.0,  6.0,
+         3.0, 11.0,  1.0,  9.0,
+        15.0,  7.0, 13.0,  5.0,
+    );
+    let pixel = vec2<u32>(position) % 4u;
+    return (bayer[pixel.y * 4u + pixel.x] + 0.5) / 16.0 - 0.5;
+}
+
+// Returns the display-referred color of the scene.
+fn sample_display(coords: vec2<f32>, position: vec2<f32>) -> vec4<f32> {
+    var sampled = sample_scene(coords);
+
+    // The hdr buffer holds premultiplied color. Encode the unpremultiplied color so partial
+    // coverage doesn't change the curve, then premultiply the display color again. Pixels
+    // without coverage are purely additive and are left alone.
+    let premultiplied = bool(post.flags & POST_FLAGS_PREMULTIPLIED_ALPHA);
+    let alpha = saturate(sampled.a);
+    if premultiplied && alpha > 0.0 {
+        sampled = vec4<f32>(sampled.rgb / alpha, alpha);
+    }
+
+    var display = srgb_scene_to_display(sampled.rgb);
+
+    if bool(post.flags & POST_FLAGS_COLOR_GRADING) {
+        display = sample_lut(display);
+    }
+
+    // Dither by a fraction of an 8 bit step, right before quantization.
+    if bool(post.flags & POST_FLAGS_DITHERING) {
+        display += bayer_dither(position) / 255.0;
+    }
+
+    if premultiplied {
+        if alpha > 0.0 {
+            display *= alpha;
+        }
+        return vec4<f32>(display, alpha);
+    }
+
+    return vec4<f32>(display, sampled.a);
+}
 
 @fragment
 fn fs_main_scene(vout: VertexOutput) -> @location(0) vec4<f32> {
-    var sampled = textureSample(source, primary_sampler, vout.tex_coords);
-    return sampled;
+    // Premultiplying has to happen on the display color, so it can't be left to the srgb encoding either.
+    if bool(post.flags & (POST_FLAGS_COLOR_GRADING | POST_FLAGS_DITHERING | POST_FLAGS_PREMULTIPLIED_ALPHA)) {
+        let display = sample_display(vout.tex_coords, vout.position.xy);
+        return vec4<f32>(srgb_display_to_scene(display.rgb), display.a);
+    }
+    return sample_scene(vout.tex_coords);
 }
 
 @fragment
 fn fs_main_monitor(vout: VertexOutput) -> @location(0) vec4<f32> {
-    var sampled = textureSample(source, primary_sampler, vout.tex_coords);
-    return vec4<f32>(srgb_scene_to_display(sampled.rgb), sampled.a);
-}
\ No newline at end of file
+    return sample_display(vout.tex_coords, vout.position.xy);
+}
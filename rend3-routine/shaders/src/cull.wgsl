@@ -10,6 +10,15 @@ var<storage> culling_job: BatchData;
 @group(0) @binding(3)
 var<storage, read_write> output_buffer: array<u32>;
 
+// Load the nth index of the object's mesh, taking care of 16 bit indices packed two to a u32.
+fn load_index(object: Object, n: u32) -> u32 {
+    if (object.flags & OBJECT_FLAG_U16_INDICES) != 0u {
+        let index = object.first_index + n;
+        return (vertex_buffer[index / 2u] >> ((index % 2u) * 16u)) & 0xFFFFu;
+    }
+    return vertex_buffer[object.first_index + n];
+}
+
 struct ObjectRangeIndex {
     range: ObjectRange,
     index: u32,
@@ -66,9 +75,9 @@ fn cs_main(
 
     let object = object_buffer[object_range.object_id];
 
-    let index0 = vertex_buffer[object.first_index + index_0_index];
-    let index1 = vertex_buffer[object.first_index + index_1_index];
-    let index2 = vertex_buffer[object.first_index + index_2_index];
+    let index0 = load_index(object, index_0_index);
+    let index1 = load_index(object, index_1_index);
+    let index2 = load_index(object, index_2_index);
 
     output_buffer[(culling_job.base_output_invocation + gid.x) * 3u + 0u] = (local_object_index << 24u) | (index0 & ((1u << 24u) - 1u));
     output_buffer[(culling_job.base_output_invocation + gid.x) * 3u + 1u] = (local_object_index << 24u) | (index1 & ((1u << 24u) - 1u));
@@ -0,0 +1,27 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+
+@vertex
+fn vs_main(vin: VertexInput) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = uniforms.view_proj * vec4<f32>(vin.position, 1.0);
+    output.color = vin.color;
+    return output;
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vout.color;
+}
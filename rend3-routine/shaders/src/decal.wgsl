@@ -0,0 +1,73 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+struct DecalUniforms {
+    transform: mat4x4<f32>,
+    inv_transform: mat4x4<f32>,
+    color: vec4<f32>,
+    has_texture: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+@group(1) @binding(0)
+var hdr_depth: texture_depth_2d;
+@group(1) @binding(1)
+var<uniform> decal: DecalUniforms;
+@group(1) @binding(2)
+var decal_texture: texture_2d<f32>;
+
+// Unit cube centered on the origin, with outward facing triangles. Corner bits are xyz.
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var indices = array<u32, 36>(
+        0u, 6u, 2u, 0u, 4u, 6u,
+        1u, 3u, 7u, 1u, 7u, 5u,
+        0u, 1u, 5u, 0u, 5u, 4u,
+        2u, 7u, 3u, 2u, 6u, 7u,
+        0u, 3u, 1u, 0u, 2u, 3u,
+        4u, 5u, 7u, 4u, 7u, 6u,
+    );
+    let corner = indices[id];
+    let local = vec3<f32>(f32(corner & 1u), f32((corner >> 1u) & 1u), f32((corner >> 2u) & 1u)) - 0.5;
+
+    var output: VertexOutput;
+    output.position = uniforms.view_proj * decal.transform * vec4<f32>(local, 1.0);
+    return output;
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let dims = vec2<f32>(textureDimensions(hdr_depth));
+    let depth = textureLoad(hdr_depth, vec2<i32>(vout.position.xy), 0);
+
+    let uv = vout.position.xy / dims;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let world_h = uniforms.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    let world = world_h.xyz / world_h.w;
+
+    // We have no normal buffer, so reconstruct the normal from the depth buffer.
+    // Derivatives need to happen before any non-uniform control flow.
+    let normal = normalize(cross(dpdy(world), dpdx(world)));
+
+    let local = (decal.inv_transform * vec4<f32>(world, 1.0)).xyz;
+    if depth == 0.0 || any(abs(local) > vec3<f32>(0.5)) {
+        discard;
+    }
+
+    var color = decal.color;
+    if decal.has_texture != 0u {
+        color *= textureSampleLevel(decal_texture, primary_sampler, local.xz + 0.5, 0.0);
+    }
+
+    // Fade out on surfaces parallel to the projection direction, where the decal would stretch.
+    let axis = normalize((decal.transform * vec4<f32>(0.0, 1.0, 0.0, 0.0)).xyz);
+    color.a *= smoothstep(0.2, 0.5, abs(dot(normal, axis)));
+
+    return color;
+}
@@ -4,9 +4,9 @@
 
 @group(0) @binding(0)
 var primary_sampler: sampler;
-@group(0) @binding(3)
+@group(0) @binding(9)
 var<uniform> uniforms: UniformData;
-@group(0) @binding(4)
+@group(0) @binding(10)
 var<storage> directional_lights: DirectionalLightData;
 
 @group(1) @binding(0)
@@ -46,6 +46,9 @@ struct VertexOutput {
     @location(0) coords0: vec2<f32>,
     @location(1) alpha: f32,
     @location(2) @interpolate(flat) material: u32,
+    @location(3) @interpolate(flat) object: u32,
+    @location(4) world_position: vec3<f32>,
+    @location(5) model_position: vec3<f32>,
 }
 
 @vertex
@@ -69,6 +72,9 @@ fn vs_main(@builtin(instance_index) shadow_number: u32, @builtin(vertex_index) v
     vs_out.material = data.material_index;
     vs_out.coords0 = vs_in.texture_coords_0;
     vs_out.alpha = vs_in.color_0.a;
+    vs_out.object = indices.object;
+    vs_out.world_position = (data.transform * position_vec4).xyz;
+    vs_out.model_position = vs_in.position;
     vs_out.position = model_view_proj * position_vec4;
 
     return vs_out;
@@ -112,4 +118,9 @@ fn fs_main(vs_out: VertexOutput) {
         discard;
     }
     {{/if}}
+
+    // Keep depth and shadows consistent with clipping in the forward pass.
+    if object_clipped(object_buffer[vs_out.object], vs_out.world_position, vs_out.model_position) {
+        discard;
+    }
 }
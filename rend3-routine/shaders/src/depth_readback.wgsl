@@ -0,0 +1,33 @@
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/math/depth.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    return output;
+}
+
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+@group(1) @binding(0)
+var hdr_depth: texture_depth_2d;
+@group(1) @binding(1)
+var<uniform> region_offset: vec2<u32>;
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) f32 {
+    let coords = vec2<u32>(vout.position.xy) + region_offset;
+    let depth = textureLoad(hdr_depth, vec2<i32>(coords), 0);
+
+    // Nothing was drawn here, so it is infinitely far away.
+    if depth == 0.0 {
+        return bitcast<f32>(0x7f800000u);
+    }
+
+    return linearize_depth(uniforms.inv_proj, depth);
+}
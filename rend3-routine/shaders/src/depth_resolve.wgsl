@@ -0,0 +1,26 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    return output;
+}
+
+@group(0) @binding(0)
+var source_depth: texture_depth_multisampled_2d;
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @builtin(frag_depth) f32 {
+    let coords = vec2<i32>(vout.position.xy);
+    let samples = i32(textureNumSamples(source_depth));
+
+    // Depth is reversed, so the max is the closest sample.
+    var depth = 0.0;
+    for (var i = 0; i < samples; i += 1) {
+        depth = max(depth, textureLoad(source_depth, coords, i));
+    }
+    return depth;
+}
@@ -0,0 +1,601 @@
+// Everything a forward shader needs to render PBR materials: bindings, the vertex shader,
+// and reading the material into PixelData. Include this file and provide your own fs_main
+// to reuse rend3's culling, per-object data, and bind group layouts with custom shading.
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/structures_object.wgsl"}}
+{{include "rend3-routine/material.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+{{include "rend3-routine/math/color.wgsl"}}
+{{include "rend3-routine/math/ltc.wgsl"}}
+{{include "rend3-routine/math/matrix.wgsl"}}
+{{include "rend3-routine/shadow/pcf.wgsl"}}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(1)
+var nearest_sampler: sampler;
+@group(0) @binding(2)
+var comparison_sampler: sampler_comparison; 
+@group(0) @binding(3)
+var clamp_sampler: sampler;
+@group(0) @binding(4)
+var nearest_clamp_sampler: sampler;
+@group(0) @binding(5)
+var mirror_sampler: sampler;
+@group(0) @binding(6)
+var nearest_mirror_sampler: sampler;
+@group(0) @binding(7)
+var border_sampler: sampler;
+@group(0) @binding(8)
+var nearest_border_sampler: sampler;
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+@group(0) @binding(10)
+var<storage> directional_lights: DirectionalLightData;
+@group(0) @binding(11)
+var shadows: texture_depth_2d;
+@group(0) @binding(12)
+var<storage> area_lights: AreaLightData;
+@group(0) @binding(13)
+var ltc_matrix: texture_2d<f32>;
+@group(0) @binding(14)
+var ltc_amplitude: texture_2d<f32>;
+
+@group(1) @binding(0)
+var<storage> object_buffer: array<Object>;
+@group(1) @binding(1)
+var<storage> batch_data: BatchData;
+@group(1) @binding(2)
+var<storage> vertex_buffer: array<u32>;
+
+{{#if (eq profile "GpuDriven")}}
+@group(1) @binding(3)
+var<storage> materials: array<GpuMaterialData>;
+@group(2) @binding(0)
+var textures: binding_array<texture_2d<f32>>;
+{{/if}}
+
+{{#if (eq profile "CpuDriven")}}
+@group(1) @binding(3)
+var<storage> materials: array<CpuMaterialData>;
+// Picked on the cpu for each draw, as some backends can only read a texture with a single sampler.
+@group(1) @binding(4)
+var material_sampler: sampler;
+@group(2) @binding(0)
+var albedo_tex: texture_2d<f32>;
+@group(2) @binding(1)
+var normal_tex: texture_2d<f32>;
+@group(2) @binding(2)
+var roughness_tex: texture_2d<f32>;
+@group(2) @binding(3)
+var metallic_tex: texture_2d<f32>;
+@group(2) @binding(4)
+var reflectance_tex: texture_2d<f32>;
+@group(2) @binding(5)
+var clear_coat_tex: texture_2d<f32>;
+@group(2) @binding(6)
+var clear_coat_roughness_tex: texture_2d<f32>;
+@group(2) @binding(7)
+var emissive_tex: texture_2d<f32>;
+@group(2) @binding(8)
+var anisotropy_tex: texture_2d<f32>;
+@group(2) @binding(9)
+var ambient_occlusion_tex: texture_2d<f32>;
+@group(2) @binding(10)
+var height_tex: texture_2d<f32>;
+@group(2) @binding(11)
+var thickness_tex: texture_2d<f32>;
+{{/if}}
+
+{{
+    vertex_fetch
+    
+    object_buffer
+    batch_data
+
+    position
+    normal
+    tangent
+    texture_coords_0
+    texture_coords_1
+    color_0
+}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) view_position: vec4<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tangent: vec3<f32>,
+    @location(3) coords0: vec2<f32>,
+    @location(4) coords1: vec2<f32>,
+    @location(6) color: vec4<f32>,
+    @location(7) @interpolate(flat) material: u32,
+    @location(8) @interpolate(flat) object_flags: u32,
+    @location(9) @interpolate(flat) tint: vec4<f32>,
+    @location(10) @interpolate(flat) object: u32,
+    @location(11) world_position: vec3<f32>,
+    @location(12) model_position: vec3<f32>,
+    {{#if velocity}}
+    @location(13) clip_position: vec4<f32>,
+    @location(14) prev_clip_position: vec4<f32>,
+    {{/if}}
+}
+
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    if vertex_index == 0x00FFFFFFu {
+        var vs_out: VertexOutput;
+        vs_out.position = vec4<f32>(0.0);
+        return vs_out;
+    }
+    let indices = unpack_vertex_index(vertex_index);
+
+    let vs_in = get_vertices(indices);
+    let data = object_buffer[indices.object];
+
+    // TODO: Store these in uniforms
+    let model_view = uniforms.view * data.transform;
+    let model_view_proj = uniforms.view_proj * data.transform;
+
+    let position_vec4 = vec4<f32>(vs_in.position, 1.0);
+    let mv_mat3 = mat3x3<f32>(model_view[0].xyz, model_view[1].xyz, model_view[2].xyz);
+
+    let normal_mat3 = mat3_normal_matrix(mv_mat3);
+
+    var vs_out: VertexOutput;
+    vs_out.material = data.material_index;
+    vs_out.object_flags = data.flags;
+    vs_out.tint = data.tint;
+    vs_out.object = indices.object;
+    vs_out.world_position = (data.transform * position_vec4).xyz;
+    vs_out.model_position = vs_in.position;
+    vs_out.view_position = model_view * position_vec4;
+    vs_out.normal = normalize(normal_mat3 * vs_in.normal);
+    // Tangents lie along the surface, so are transformed like positions.
+    vs_out.tangent = normalize(mv_mat3 * vs_in.tangent);
+    vs_out.color = vs_in.color_0;
+    vs_out.coords0 = vs_in.texture_coords_0;
+    vs_out.coords1 = vs_in.texture_coords_1;
+    vs_out.position = model_view_proj * position_vec4;
+    {{#if velocity}}
+    vs_out.clip_position = vs_out.position;
+    vs_out.prev_clip_position = uniforms.prev_view_proj * data.prev_transform * position_vec4;
+    {{/if}}
+
+    return vs_out;
+}
+
+{{#if velocity}}
+// How far the fragment moved since the previous frame, in uv space. The previous position is unjittered, so remove
+// this frame's jitter too, leaving only the motion.
+fn screen_velocity(vs_out: VertexOutput) -> vec2<f32> {
+    let ndc = vs_out.clip_position.xy / vs_out.clip_position.w - uniforms.jitter;
+    let prev_ndc = vs_out.prev_clip_position.xy / vs_out.prev_clip_position.w;
+    return (ndc - prev_ndc) * vec2<f32>(0.5, -0.5);
+}
+{{/if}}
+
+{{#if (eq profile "GpuDriven")}}
+alias Material = GpuMaterialData;
+
+fn has_albedo_texture(material: ptr<function, Material>) -> bool { return (*material).albedo_tex != 0u; }
+fn has_normal_texture(material: ptr<function, Material>) -> bool { return (*material).normal_tex != 0u; }
+fn has_roughness_texture(material: ptr<function, Material>) -> bool { return (*material).roughness_tex != 0u; }
+fn has_metallic_texture(material: ptr<function, Material>) -> bool { return (*material).metallic_tex != 0u; }
+fn has_reflectance_texture(material: ptr<function, Material>) -> bool { return (*material).reflectance_tex != 0u; }
+fn has_clear_coat_texture(material: ptr<function, Material>) -> bool { return (*material).clear_coat_tex != 0u; }
+fn has_clear_coat_roughness_texture(material: ptr<function, Material>) -> bool { return (*material).clear_coat_roughness_tex != 0u; }
+fn has_emissive_texture(material: ptr<function, Material>) -> bool { return (*material).emissive_tex != 0u; }
+fn has_anisotropy_texture(material: ptr<function, Material>) -> bool { return (*material).anisotropy_tex != 0u; }
+fn has_ambient_occlusion_texture(material: ptr<function, Material>) -> bool { return (*material).ambient_occlusion_tex != 0u; }
+fn has_height_texture(material: ptr<function, Material>) -> bool { return (*material).height_tex != 0u; }
+fn has_thickness_texture(material: ptr<function, Material>) -> bool { return (*material).thickness_tex != 0u; }
+
+fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).albedo_tex - 1u], samp, coords, ddx, ddy); }
+fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).normal_tex - 1u], samp, coords, ddx, ddy); }
+fn roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).roughness_tex - 1u], samp, coords, ddx, ddy); }
+fn metallic_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).metallic_tex - 1u], samp, coords, ddx, ddy); }
+fn reflectance_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).reflectance_tex - 1u], samp, coords, ddx, ddy); }
+fn clear_coat_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).clear_coat_tex - 1u], samp, coords, ddx, ddy); }
+fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).clear_coat_roughness_tex - 1u], samp, coords, ddx, ddy); }
+fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).emissive_tex - 1u], samp, coords, ddx, ddy); }
+fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).anisotropy_tex - 1u], samp, coords, ddx, ddy); }
+fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).ambient_occlusion_tex - 1u], samp, coords, ddx, ddy); }
+fn height_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).height_tex - 1u], samp, coords, ddx, ddy); }
+fn thickness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).thickness_tex - 1u], samp, coords, ddx, ddy); }
+{{else}}
+alias Material = CpuMaterialData;
+
+fn has_albedo_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 0u) & 0x1u); }
+fn has_normal_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 1u) & 0x1u); }
+fn has_roughness_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 2u) & 0x1u); }
+fn has_metallic_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 3u) & 0x1u); }
+fn has_reflectance_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 4u) & 0x1u); }
+fn has_clear_coat_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 5u) & 0x1u); }
+fn has_clear_coat_roughness_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 6u) & 0x1u); }
+fn has_emissive_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 7u) & 0x1u); }
+fn has_anisotropy_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 8u) & 0x1u); }
+fn has_ambient_occlusion_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 9u) & 0x1u); }
+fn has_height_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 10u) & 0x1u); }
+fn has_thickness_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 11u) & 0x1u); }
+
+fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(albedo_tex, samp, coords, ddx, ddy); }
+fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(normal_tex, samp, coords, ddx, ddy); }
+fn roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(roughness_tex, samp, coords, ddx, ddy); }
+fn metallic_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(metallic_tex, samp, coords, ddx, ddy); }
+fn reflectance_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(reflectance_tex, samp, coords, ddx, ddy); }
+fn clear_coat_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(clear_coat_tex, samp, coords, ddx, ddy); }
+fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(clear_coat_roughness_tex, samp, coords, ddx, ddy); }
+fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(emissive_tex, samp, coords, ddx, ddy); }
+fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(anisotropy_tex, samp, coords, ddx, ddy); }
+fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(ambient_occlusion_tex, samp, coords, ddx, ddy); }
+fn height_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(height_tex, samp, coords, ddx, ddy); }
+fn thickness_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(thickness_tex, samp, coords, ddx, ddy); }
+{{/if}}
+
+fn compute_diffuse_color(base_color: vec3<f32>, metallic: f32) -> vec3<f32> {
+    return base_color * (1.0 - metallic);
+}
+
+fn compute_f0(base_color: vec3<f32>, metallic: f32, reflectance: f32) -> vec3<f32> {
+    return base_color * metallic + (reflectance * (1.0 - metallic));
+}
+
+fn compute_dielectric_f0(reflectance: f32) -> f32 {
+    return 0.16 * reflectance * reflectance;
+}
+
+fn perceptual_roughness_to_roughness(perceptual_roughness: f32) -> f32 {
+    return perceptual_roughness * perceptual_roughness;
+}
+
+// Parallax occlusion mapping: marches the ray along the tangent space view direction `v`
+// from the surface down to parallax_scale below it, and returns the texture coordinates
+// where it first goes below the height map. Derivatives of the original coordinates are
+// used for all reads, as the offset ones jump at the steps.
+fn parallax_coords(material: ptr<function, Material>, s: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>, v: vec3<f32>) -> vec2<f32> {
+    let max_steps = f32(max((*material).parallax_steps, 1u));
+    let steps = u32(ceil(mix(max_steps, max_steps * 0.25, saturate(v.z))));
+    let step_depth = 1.0 / f32(steps);
+    // Going into the surface moves away from the viewer. Limit how far
+    // grazing rays travel so they don't skip over the whole texture.
+    let coords_step = -v.xy / max(v.z, 0.05) * (*material).parallax_scale * step_depth;
+
+    var ray_depth = 0.0;
+    var current = coords;
+    var surface_depth = 1.0 - height_texture(material, s, current, ddx, ddy).r;
+    var previous = current;
+    var previous_gap = 0.0;
+    for (var i = 0u; i < steps && ray_depth < surface_depth; i += 1u) {
+        previous = current;
+        previous_gap = surface_depth - ray_depth;
+        ray_depth += step_depth;
+        current += coords_step;
+        surface_depth = 1.0 - height_texture(material, s, current, ddx, ddy).r;
+    }
+
+    // Interpolate between the last two steps, to where the ray crossed the height map.
+    let gap = ray_depth - surface_depth;
+    let total = previous_gap + gap;
+    if (total <= 0.0) {
+        return current;
+    }
+    return mix(previous, current, previous_gap / total);
+}
+
+fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput) -> PixelData {
+    var material = material_arg;
+    var pixel: PixelData;
+
+    var coords = (material.uv_transform0 * vec3<f32>(vs_out.coords0, 1.0)).xy;
+    let uvdx = dpdx(coords);
+    let uvdy = dpdy(coords);
+
+    // The face normal, facing the same side as the vertex normal so it doesn't depend on winding or handedness.
+    var face_normal = normalize(cross(dpdx(vs_out.view_position.xyz), dpdy(vs_out.view_position.xyz)));
+    if (dot(face_normal, vs_out.normal) < 0.0) {
+        face_normal = -face_normal;
+    }
+
+    // --- PARALLAX ---
+
+    if (extract_material_flag(material.flags, FLAGS_PARALLAX)) {
+        let normal_norm = normalize(vs_out.normal);
+        let tangent_norm = normalize(vs_out.tangent);
+        let tbn = mat3x3(tangent_norm, cross(normal_norm, tangent_norm), normal_norm);
+        let v = normalize(transpose(tbn) * -vs_out.view_position.xyz);
+
+        coords = parallax_coords(&material, s, coords, uvdx, uvdy, v);
+
+        if (extract_material_flag(material.flags, FLAGS_PARALLAX_CLIP) && (any(coords < vec2<f32>(0.0)) || any(coords > vec2<f32>(1.0)))) {
+            discard;
+        }
+    }
+
+    // --- ALBEDO ---
+
+    if (extract_material_flag(material.flags, FLAGS_ALBEDO_ACTIVE)) {
+        if (has_albedo_texture(&material)) {
+            pixel.albedo = albedo_texture(&material, s, coords, uvdx, uvdy);
+        } else {
+            pixel.albedo = vec4<f32>(1.0);
+        }
+        if (extract_material_flag(material.flags, FLAGS_ALBEDO_BLEND)) {
+            if (extract_material_flag(material.flags, FLAGS_ALBEDO_VERTEX_SRGB)) {
+                pixel.albedo *= vec4<f32>(srgb_display_to_scene(vs_out.color.rgb), vs_out.color.a);
+            } else {
+                pixel.albedo *= vs_out.color;
+            }
+        }
+    } else {
+        pixel.albedo = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    pixel.albedo *= material.albedo;
+
+    {{#if discard}}
+    if (pixel.albedo.a < material.alpha_cutout) {
+        discard;
+    }
+    {{/if}}
+
+    // --- STOP IF UNLIT ---
+
+    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
+        pixel.normal = normalize(vs_out.normal);
+        return pixel;
+    }
+
+    // --- NORMAL TEXTURE ---
+
+    if (extract_material_flag(material.flags, FLAGS_FLAT)) {
+        pixel.normal = face_normal;
+    } else if (has_normal_texture(&material)) {
+        let texture_read = normal_texture(&material, s, coords, uvdx, uvdy);
+        var normal: vec3<f32>;
+        if (extract_material_flag(material.flags, FLAGS_BICOMPONENT_NORMAL)) {
+            var bicomp: vec2<f32>;
+            if (extract_material_flag(material.flags, FLAGS_SWIZZLED_NORMAL)) {
+                bicomp = texture_read.ag;
+            } else {
+                bicomp = texture_read.rg;
+            }
+            bicomp = bicomp * 2.0 - 1.0;
+            let bicomp_sq = bicomp * bicomp;
+
+            normal = vec3<f32>(bicomp, sqrt(1.0 - bicomp_sq.r - bicomp_sq.g));
+        } else {
+            normal = normalize(texture_read.rgb * 2.0 - 1.0);
+        }
+        if (extract_material_flag(material.flags, FLAGS_YDOWN_NORMAL)) {
+            normal.y = -normal.y;
+        }
+        let normal_norm = normalize(vs_out.normal);
+        let tangent_norm = normalize(vs_out.tangent);
+        let bitangent = cross(normal_norm, tangent_norm);
+
+        let tbn = mat3x3(tangent_norm, bitangent, normal_norm);
+
+        pixel.normal = tbn * normal;
+    } else {
+        pixel.normal = vs_out.normal;
+    }
+    pixel.normal = normalize(pixel.normal);
+
+    // --- AO, Metallic, and Roughness ---
+
+    if (extract_material_flag(material.flags, FLAGS_AOMR_COMBINED)) {
+        // In roughness texture:
+        // Red: AO
+        // Green: Roughness
+        // Blue: Metallic
+        if (has_roughness_texture(&material)) {
+            let aomr = roughness_texture(&material, s, coords, uvdx, uvdy);
+            pixel.ambient_occlusion = material.ambient_occlusion * aomr[0];
+            pixel.perceptual_roughness = material.roughness * aomr[1];
+            pixel.metallic = material.metallic * aomr[2];
+        } else {
+            pixel.ambient_occlusion = material.ambient_occlusion;
+            pixel.perceptual_roughness = material.roughness;
+            pixel.metallic = material.metallic;
+        }
+    } else if (extract_material_flag(material.flags, FLAGS_AOMR_BW_SPLIT)) {
+        // In ao texture:
+        // Red: AO
+        // In metallic texture:
+        // Red: Metallic
+        // In roughness texture:
+        // Red: Roughness
+        if (has_roughness_texture(&material)) {
+            pixel.perceptual_roughness = material.roughness * roughness_texture(&material, s, coords, uvdx, uvdy).r;
+        } else {
+            pixel.perceptual_roughness = material.roughness;
+        }
+
+        if (has_metallic_texture(&material)) {
+            pixel.metallic = material.metallic * metallic_texture(&material, s, coords, uvdx, uvdy).r;
+        } else {
+            pixel.metallic = material.metallic;
+        }
+
+        if (has_ambient_occlusion_texture(&material)) {
+            pixel.ambient_occlusion = material.ambient_occlusion * ambient_occlusion_texture(&material, s, coords, uvdx, uvdy).r;
+        } else {
+            pixel.ambient_occlusion = material.ambient_occlusion;
+        }
+    } else {
+        // In ao texture:
+        // Red: AO
+        //
+        // In roughness texture (FLAGS_AOMR_SPLIT):
+        // Red: Roughness
+        // Green: Metallic
+        //
+        // In roughness texture (FLAGS_AOMR_SWIZZLED_SPLIT):
+        // Green: Roughness
+        // Blue: Metallic
+        if (has_roughness_texture(&material)) {
+            let texture_read = roughness_texture(&material, s, coords, uvdx, uvdy);
+            var rm: vec2<f32>;
+            if (extract_material_flag(material.flags, FLAGS_AOMR_SWIZZLED_SPLIT)) {
+                rm = texture_read.gb;
+            } else {
+                rm = texture_read.rg;
+            }
+            pixel.perceptual_roughness = material.roughness * rm[0];
+            pixel.metallic = material.metallic * rm[1];
+        } else {
+            pixel.perceptual_roughness = material.roughness;
+            pixel.metallic = material.metallic;
+        }
+
+        if (has_ambient_occlusion_texture(&material)) {
+            let texture_read = ambient_occlusion_texture(&material, s, coords, uvdx, uvdy);
+            pixel.ambient_occlusion = material.ambient_occlusion * texture_read.r;
+        } else {
+            pixel.ambient_occlusion = material.ambient_occlusion;
+        }
+    }
+
+    // --- REFLECTANCE ---
+
+    if (has_reflectance_texture(&material)) {
+        pixel.reflectance = material.reflectance * reflectance_texture(&material, s, coords, uvdx, uvdy).r;
+    } else {
+        pixel.reflectance = material.reflectance;
+    }
+
+    // --- CLEARCOAT ---
+
+    if (extract_material_flag(material.flags, FLAGS_CC_GLTF_COMBINED)) {
+        if (has_clear_coat_texture(&material)) {
+            let texture_read = clear_coat_texture(&material, s, coords, uvdx, uvdy);
+            pixel.clear_coat = material.clear_coat * texture_read.r;
+            pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture_read.g;
+        } else {
+            pixel.clear_coat = material.clear_coat;
+            pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness;
+        }
+    } else {
+        if (has_clear_coat_texture(&material)) {
+            pixel.clear_coat = material.clear_coat * clear_coat_texture(&material, s, coords, uvdx, uvdy).r;
+        } else {
+            pixel.clear_coat = material.clear_coat;
+        }
+
+        if (has_clear_coat_roughness_texture(&material)) {
+            let texture_read = clear_coat_roughness_texture(&material, s, coords, uvdx, uvdy);
+
+            if (extract_material_flag(material.flags, FLAGS_CC_GLTF_SPLIT)) {
+                pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture_read.g;
+            } else {
+                pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness * texture_read.r;
+            }
+        } else {
+            pixel.clear_coat_perceptual_roughness = material.clear_coat_roughness;
+        }
+    }
+
+    // --- EMISSIVE ---
+
+    if (has_emissive_texture(&material)) {
+        pixel.emissive = material.emissive * emissive_texture(&material, s, coords, uvdx, uvdy).rgb;
+    } else {
+        pixel.emissive = material.emissive;
+    }
+
+    // --- ANISOTROPY ---
+
+    if (has_anisotropy_texture(&material)) {
+        pixel.anisotropy = material.anisotropy * anisotropy_texture(&material, s, coords, uvdx, uvdy).r;
+    } else {
+        pixel.anisotropy = material.anisotropy;
+    }
+
+    // --- SUBSURFACE ---
+
+    pixel.material_flags = material.flags;
+    pixel.subsurface_color = material.subsurface_color;
+    pixel.subsurface_wrap = material.subsurface_wrap;
+    if (has_thickness_texture(&material)) {
+        pixel.thickness = material.thickness * thickness_texture(&material, s, coords, uvdx, uvdy).r;
+    } else {
+        pixel.thickness = material.thickness;
+    }
+
+    // --- COMPUTATIONS---
+
+    pixel.diffuse_color = compute_diffuse_color(pixel.albedo.xyz, pixel.metallic);
+
+    // Assumes an interface from air to an IOR of 1.5 for dielectrics
+    let reflectance = compute_dielectric_f0(pixel.reflectance);
+    pixel.f0 = compute_f0(pixel.albedo.rgb, pixel.metallic, reflectance);
+
+    if (pixel.clear_coat != 0.0) {
+        let base_perceptual_roughness = max(pixel.perceptual_roughness, pixel.clear_coat_perceptual_roughness);
+        pixel.perceptual_roughness = mix(pixel.perceptual_roughness, base_perceptual_roughness, pixel.clear_coat);
+        pixel.clear_coat_roughness = perceptual_roughness_to_roughness(pixel.clear_coat_perceptual_roughness);
+    }
+    pixel.roughness = perceptual_roughness_to_roughness(pixel.perceptual_roughness);
+
+    return pixel;
+}
+
+{{#if (eq profile "GpuDriven")}}
+fn get_pixel_data_filtered(material: Material, linear: sampler, nearest: sampler, vs_out: VertexOutput) -> PixelData {
+    if (extract_material_flag(material.flags, FLAGS_NEAREST)) {
+        return get_pixel_data_inner(material, nearest, vs_out);
+    } else {
+        return get_pixel_data_inner(material, linear, vs_out);
+    }
+}
+
+fn get_pixel_data(material: Material, vs_out: VertexOutput) -> PixelData {
+    if (extract_material_flag(material.flags, FLAGS_CLAMP)) {
+        return get_pixel_data_filtered(material, clamp_sampler, nearest_clamp_sampler, vs_out);
+    } else if (extract_material_flag(material.flags, FLAGS_MIRROR)) {
+        return get_pixel_data_filtered(material, mirror_sampler, nearest_mirror_sampler, vs_out);
+    } else if (extract_material_flag(material.flags, FLAGS_BORDER)) {
+        return get_pixel_data_filtered(material, border_sampler, nearest_border_sampler, vs_out);
+    } else {
+        return get_pixel_data_filtered(material, primary_sampler, nearest_sampler, vs_out);
+    }
+}
+{{else}}
+fn get_pixel_data(material: Material, vs_out: VertexOutput) -> PixelData {
+    return get_pixel_data_inner(material, material_sampler, vs_out);
+}
+{{/if}}
+
+// Returns how lit the given view space position is by the given light, from 0 (fully shadowed) to 1 (fully lit).
+fn directional_shadow(light: DirectionalLight, view_position: vec4<f32>) -> f32 {
+    // Get the shadow ndc coordinates, then convert to texture sample coordinates
+    let shadow_ndc = (light.view_proj * uniforms.inv_view * view_position).xyz;
+    let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
+    let shadow_local_coords = vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y);
+
+    // Texture sample coordinates of 
+    var top_left = light.offset;
+    var top_right = top_left + light.size;
+    let shadow_coords = mix(top_left, top_right, shadow_local_coords);
+
+    // The shadow is stored in an atlas, so we need to make sure we don't linear blend
+    // across atlasses. We move our conditional borders in a half a pixel for standard
+    // linear blending (so we're hitting texel centers on the edge). We move it an additional
+    // pixel in so that our pcf5 offsets don't move off the edge of the atlasses.
+    let shadow_border = light.inv_resolution * 1.5;
+    top_left += shadow_border;
+    top_right -= shadow_border;
+
+    var shadow_value = 1.0;
+    if (
+        any(shadow_flipped >= top_left) && // XY lower
+        any(shadow_flipped <= top_right) && // XY upper
+        shadow_ndc.z >= 0.0 && // Z lower
+        shadow_ndc.z <= 1.0 // Z upper
+    ) {
+        shadow_value = shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, shadow_ndc.z);
+    }
+
+    return shadow_value;
+}
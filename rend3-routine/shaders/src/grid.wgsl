@@ -0,0 +1,72 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+struct GridUniforms {
+    minor_color: vec4<f32>,
+    major_color: vec4<f32>,
+    spacing: f32,
+    major_spacing: f32,
+    fade_distance: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) clip_position: vec2<f32>,
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    let clip_position = vec2<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0);
+
+    return VertexOutput(vec4<f32>(clip_position, 0.0, 1.0), clip_position);
+}
+
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+@group(1) @binding(0)
+var<uniform> grid: GridUniforms;
+
+fn unproject(clip: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = uniforms.inv_view_proj * vec4<f32>(clip, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// How much of a line of the grid with the given spacing covers this pixel.
+fn grid_coverage(position: vec2<f32>, spacing: f32) -> f32 {
+    let scaled = position / spacing;
+    let pixels = abs(fract(scaled - 0.5) - 0.5) / fwidth(scaled);
+    return 1.0 - min(min(pixels.x, pixels.y), 1.0);
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> FragmentOutput {
+    // Two points along the view ray, which works for both perspective and orthographic projections. Depth is reversed,
+    // so 1.0 is the near plane.
+    let origin = unproject(vout.clip_position, 1.0);
+    let direction = unproject(vout.clip_position, 0.5) - origin;
+    let t = -origin.y / direction.y;
+    let hit = origin + direction * t;
+
+    // Derivatives need to happen before any non-uniform control flow.
+    let minor = grid_coverage(hit.xz, grid.spacing);
+    let major = grid_coverage(hit.xz, grid.major_spacing);
+
+    let camera = (uniforms.inv_view * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    let fade = 1.0 - smoothstep(0.0, grid.fade_distance, distance(hit, camera));
+
+    var color = mix(grid.minor_color, grid.major_color, major);
+    color.a *= max(minor, major) * fade;
+
+    let clip = uniforms.view_proj * vec4<f32>(hit, 1.0);
+
+    // Also catches rays parallel to the grid, where t is NaN.
+    if !(t > 0.0) || color.a <= 0.0 {
+        discard;
+    }
+
+    return FragmentOutput(color, clip.z / clip.w);
+}
@@ -13,6 +13,15 @@ const FLAGS_CC_GLTF_SPLIT: u32        = 0x0800u;
 const FLAGS_CC_BW_SPLIT: u32          = 0x1000u;
 const FLAGS_UNLIT: u32                = 0x2000u;
 const FLAGS_NEAREST: u32              = 0x4000u;
+const FLAGS_TOON: u32                 = 0x8000u;
+const FLAGS_FLAT: u32                 = 0x10000u;
+const FLAGS_TWO_SIDED_LIGHTING: u32   = 0x20000u;
+const FLAGS_CLAMP: u32                = 0x40000u;
+const FLAGS_MIRROR: u32               = 0x80000u;
+const FLAGS_BORDER: u32               = 0x100000u;
+const FLAGS_PARALLAX: u32             = 0x200000u;
+const FLAGS_PARALLAX_CLIP: u32        = 0x400000u;
+const FLAGS_SUBSURFACE: u32           = 0x800000u;
 
 fn extract_material_flag(data: u32, flag: u32) -> bool {
     return bool(data & flag);
@@ -31,8 +40,8 @@ struct GpuMaterialData {
     // -- 16 --
     anisotropy_tex: u32,
     ambient_occlusion_tex: u32,
-    _padding0: u32,
-    _padding1: u32,
+    height_tex: u32,
+    thickness_tex: u32,
     
     // -- 16 --
 
@@ -54,6 +63,17 @@ struct GpuMaterialData {
     ambient_occlusion: f32,
     alpha_cutout: f32,
     flags: u32,
+    // -- 16 --
+    rim_color: vec3<f32>,
+    rim_power: f32,
+    // -- 16 --
+    toon_bands: u32,
+    parallax_scale: f32,
+    parallax_steps: u32,
+    thickness: f32,
+    // -- 16 --
+    subsurface_color: vec3<f32>,
+    subsurface_wrap: f32,
 }
 
 struct CpuMaterialData {
@@ -75,6 +95,17 @@ struct CpuMaterialData {
     ambient_occlusion: f32,
     alpha_cutout: f32,
     flags: u32,
+    // -- 16 --
+    rim_color: vec3<f32>,
+    rim_power: f32,
+    // -- 16 --
+    toon_bands: u32,
+    parallax_scale: f32,
+    parallax_steps: u32,
+    thickness: f32,
+    // -- 16 --
+    subsurface_color: vec3<f32>,
+    subsurface_wrap: f32,
     
     // -- 16 --
     texture_enable: u32,
@@ -13,7 +13,7 @@ fn srgb_display_to_scene(electro: vec3<f32>) -> vec3<f32> {
 fn srgb_scene_to_display(opto: vec3<f32>) -> vec3<f32> {
     let selector = opto > vec3<f32>(0.0031308);
     let under = opto * 12.92;
-    let over = 1.055 * pow(opto, vec3<f32>(0.4166)) - 0.055;
+    let over = 1.055 * pow(opto, vec3<f32>(1.0 / 2.4)) - 0.055;
     let electrical = select(under, over, selector);
     return electrical;
 }
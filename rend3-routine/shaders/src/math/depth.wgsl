@@ -0,0 +1,6 @@
+// Converts a value from the reversed depth buffer into the distance from the camera's plane,
+// matching CameraManager::linearize_depth. Pass uniforms.inv_proj as the inverse projection.
+fn linearize_depth(inv_proj: mat4x4<f32>, depth: f32) -> f32 {
+    let view = inv_proj * vec4<f32>(0.0, 0.0, depth, 1.0);
+    return abs(view.z / view.w);
+}
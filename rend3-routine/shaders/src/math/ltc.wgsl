@@ -0,0 +1,74 @@
+{{include "rend3-routine/math/consts.wgsl"}}
+
+// Area light integration with linearly transformed cosines, from Heitz et al. 2016,
+// "Real-Time Polygonal-Light Shading with Linearly Transformed Cosines".
+
+const LTC_LUT_SIZE = 64.0;
+
+// Coordinates into the ltc lookup tables, which are indexed by perceptual roughness
+// and sqrt(1 - nov), with texel centers on both ends.
+fn ltc_lut_coords(perceptual_roughness: f32, nov: f32) -> vec2<f32> {
+    let coords = vec2<f32>(perceptual_roughness, sqrt(1.0 - saturate(nov)));
+    return coords * ((LTC_LUT_SIZE - 1.0) / LTC_LUT_SIZE) + 0.5 / LTC_LUT_SIZE;
+}
+
+// Rebuilds the inverse transform from the four terms stored in the matrix table.
+fn ltc_inverse_matrix(terms: vec4<f32>) -> mat3x3<f32> {
+    return mat3x3<f32>(
+        vec3<f32>(terms.x, 0.0, terms.y),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(terms.z, 0.0, terms.w)
+    );
+}
+
+// Vector form factor of the arc between two points on the unit sphere, using a cubic
+// fit of theta / (2 pi sin(theta)) which stays accurate when the points are nearly opposite.
+fn ltc_integrate_edge(v1: vec3<f32>, v2: vec3<f32>) -> vec3<f32> {
+    let x = dot(v1, v2);
+    let y = abs(x);
+    let a = 0.8543985 + (0.4965155 + 0.0145206 * y) * y;
+    let b = 3.4175940 + (4.1616724 + y) * y;
+    let v = a / b;
+    let theta_sintheta = select(0.5 * inverseSqrt(max(1.0 - x * x, 0.0000001)) - v, v, x > 0.0);
+    return cross(v1, v2) * theta_sintheta;
+}
+
+// Form factor of a sphere with sin^2 of its angular radius equal to `form_factor`,
+// at an elevation of acos(`cos_theta`), clipped by the horizon. From Lagarde and
+// de Rousiers 2014, "Moving Frostbite to Physically Based Rendering".
+fn ltc_horizon_clipped_form_factor(cos_theta: f32, form_factor: f32) -> f32 {
+    let sin_sigma_sqr = min(form_factor, 0.9999);
+    if (cos_theta * cos_theta > sin_sigma_sqr) {
+        return saturate(cos_theta) * sin_sigma_sqr;
+    }
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let x = sqrt(1.0 / sin_sigma_sqr - 1.0);
+    let y = clamp(-x * (cos_theta / sin_theta), -1.0, 1.0);
+    let sin_theta_sqrt_y = sin_theta * sqrt(1.0 - y * y);
+    let illuminance = (cos_theta * acos(y) - x * sin_theta_sqrt_y) * sin_sigma_sqr + atan(sin_theta_sqrt_y / x);
+    return max(illuminance, 0.0) / PI;
+}
+
+// Integral of the clamped cosine distribution, transformed by the inverse of `minv`,
+// over the polygon with the given corners relative to the shading point. `flip` must
+// be set when the polygon is seen from its back, so it winds the right way around.
+fn ltc_evaluate(minv: mat3x3<f32>, corners: array<vec3<f32>, 8>, count: u32, flip: bool) -> f32 {
+    var points = corners;
+    for (var i = 0u; i < count; i += 1u) {
+        points[i] = normalize(minv * points[i]);
+    }
+
+    var vsum = vec3<f32>(0.0);
+    for (var i = 0u; i < count; i += 1u) {
+        vsum += ltc_integrate_edge(points[i], points[(i + 1u) % count]);
+    }
+    if (flip) {
+        vsum = -vsum;
+    }
+
+    let len = length(vsum);
+    if (len < 0.000001) {
+        return 0.0;
+    }
+    return ltc_horizon_clipped_form_factor(vsum.z / len, len);
+}
@@ -1,7 +1,13 @@
-fn mat3_inv_scale_squared(transform: mat3x3<f32>) -> vec3<f32> {
-    return vec3<f32>(
-        1.0 / dot(transform[0].xyz, transform[0].xyz),
-        1.0 / dot(transform[1].xyz, transform[1].xyz),
-        1.0 / dot(transform[2].xyz, transform[2].xyz)
+// Matrix which transforms normals by the given transform. This is the inverse
+// transpose, scaled by the absolute value of the determinant, so the result must
+// be normalized. Unlike dividing out the scale of each axis, this is also correct
+// for transforms with shear, such as non-uniform scale under a rotated parent.
+fn mat3_normal_matrix(transform: mat3x3<f32>) -> mat3x3<f32> {
+    let cofactor = mat3x3<f32>(
+        cross(transform[1], transform[2]),
+        cross(transform[2], transform[0]),
+        cross(transform[0], transform[1])
     );
+    // Keep normals facing outwards on mirrored transforms.
+    return cofactor * sign(dot(transform[0], cofactor[0]));
 }
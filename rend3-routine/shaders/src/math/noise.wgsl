@@ -0,0 +1,31 @@
+fn hash_vec3(p: vec3<u32>) -> f32 {
+    var h = (p.x * 0x8da6b343u) ^ (p.y * 0xd8163841u) ^ (p.z * 0xcb1ab31fu);
+    h = (h ^ (h >> 16u)) * 0x7feb352du;
+    h = (h ^ (h >> 15u)) * 0x846ca68bu;
+    h = h ^ (h >> 16u);
+    return f32(h) / 4294967295.0;
+}
+
+// Trilinearly interpolated value noise in [0, 1].
+fn value_noise_3d(p: vec3<f32>) -> f32 {
+    let cell = floor(p);
+    let t = p - cell;
+    let s = t * t * (3.0 - 2.0 * t);
+    let base = vec3<u32>(vec3<i32>(cell));
+
+    let c000 = hash_vec3(base);
+    let c100 = hash_vec3(base + vec3<u32>(1u, 0u, 0u));
+    let c010 = hash_vec3(base + vec3<u32>(0u, 1u, 0u));
+    let c110 = hash_vec3(base + vec3<u32>(1u, 1u, 0u));
+    let c001 = hash_vec3(base + vec3<u32>(0u, 0u, 1u));
+    let c101 = hash_vec3(base + vec3<u32>(1u, 0u, 1u));
+    let c011 = hash_vec3(base + vec3<u32>(0u, 1u, 1u));
+    let c111 = hash_vec3(base + vec3<u32>(1u, 1u, 1u));
+
+    let c00 = mix(c000, c100, s.x);
+    let c10 = mix(c010, c110, s.x);
+    let c01 = mix(c001, c101, s.x);
+    let c11 = mix(c011, c111, s.x);
+
+    return mix(mix(c00, c10, s.y), mix(c01, c11, s.y), s.z);
+}
@@ -0,0 +1,36 @@
+struct DownsampleUniforms {
+    /// Number of source texels along each axis which make up one destination texel.
+    scale: u32,
+    /// Width of the destination.
+    size: u32,
+    /// Mirror the source horizontally.
+    flip: u32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> downsample: DownsampleUniforms;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+// Averages the block of source texels under this texel. This is a single texel when
+// copying a rendered face into the cube, and 2x2 texels when building the next mip.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    var texel = vec2<u32>(position.xy);
+    if (downsample.flip != 0u) {
+        texel.x = downsample.size - 1u - texel.x;
+    }
+
+    var color = vec4<f32>(0.0);
+    for (var y = 0u; y < downsample.scale; y += 1u) {
+        for (var x = 0u; x < downsample.scale; x += 1u) {
+            color += textureLoad(source, vec2<i32>(texel * downsample.scale + vec2<u32>(x, y)), 0);
+        }
+    }
+    return color / f32(downsample.scale * downsample.scale);
+}
@@ -0,0 +1,111 @@
+{{include "rend3-routine/math/brdf.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct PrefilterUniforms {
+    /// Face of the cube being written, in the order +X, -X, +Y, -Y, +Z, -Z.
+    face: u32,
+    /// Perceptual roughness of the lobe to convolve with.
+    roughness: f32,
+    /// Resolution of the top mip of the source.
+    source_resolution: f32,
+    sample_count: u32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(0)
+var source_sampler: sampler;
+@group(0) @binding(1)
+var source: texture_cube<f32>;
+@group(0) @binding(2)
+var<uniform> prefilter: PrefilterUniforms;
+
+// Direction through the given texel coordinates of a cube face.
+fn face_direction(face: u32, tex_coords: vec2<f32>) -> vec3<f32> {
+    let st = tex_coords * 2.0 - 1.0;
+    var dir: vec3<f32>;
+    switch face {
+        case 0u: {
+            dir = vec3<f32>(1.0, -st.y, -st.x);
+        }
+        case 1u: {
+            dir = vec3<f32>(-1.0, -st.y, st.x);
+        }
+        case 2u: {
+            dir = vec3<f32>(st.x, 1.0, st.y);
+        }
+        case 3u: {
+            dir = vec3<f32>(st.x, -1.0, -st.y);
+        }
+        case 4u: {
+            dir = vec3<f32>(st.x, -st.y, 1.0);
+        }
+        default: {
+            dir = vec3<f32>(-st.x, -st.y, -1.0);
+        }
+    }
+    return normalize(dir);
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Half vector around +Z, distributed by the GGX normal distribution.
+fn importance_sample_ggx(xi: vec2<f32>, a: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+// Convolves the source with a GGX lobe, assuming the view and reflection directions
+// are the normal as in the split sum approximation. Each sample reads from the mip
+// whose texels cover the sample's solid angle, which keeps the noise down with few
+// samples.
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(prefilter.face, vout.tex_coords);
+
+    if (prefilter.roughness == 0.0) {
+        return vec4<f32>(textureSampleLevel(source, source_sampler, n, 0.0).rgb, 1.0);
+    }
+
+    let a = prefilter.roughness * prefilter.roughness;
+    let up = select(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), abs(n.z) > 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+
+    let texel_solid_angle = 4.0 * PI / (6.0 * prefilter.source_resolution * prefilter.source_resolution);
+
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < prefilter.sample_count; i += 1u) {
+        let h_tangent = importance_sample_ggx(hammersley(i, prefilter.sample_count), a);
+        let h = tangent * h_tangent.x + bitangent * h_tangent.y + n * h_tangent.z;
+        let l = 2.0 * dot(n, h) * h - n;
+
+        let nol = dot(n, l);
+        if (nol > 0.0) {
+            // With n == v, the pdf of l is D / 4.
+            let pdf = brdf_d_ggx(saturate(dot(n, h)), a) * 0.25;
+            let sample_solid_angle = 1.0 / (f32(prefilter.sample_count) * pdf + 0.0001);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+
+            color += textureSampleLevel(source, source_sampler, l, lod).rgb * nol;
+            weight += nol;
+        }
+    }
+
+    return vec4<f32>(color / max(weight, 0.0001), 1.0);
+}
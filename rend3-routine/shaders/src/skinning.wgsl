@@ -72,9 +72,8 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
             let joint_matrix3 = mat3x3<f32>(joint_matrix[0].xyz, joint_matrix[1].xyz, joint_matrix[2].xyz);
             pos_acc += (joint_matrix * vec4<f32>(pos, 1.0)).xyz * weight;
             
-            let inv_scale_sq = mat3_inv_scale_squared(joint_matrix3);
-            norm_acc += (joint_matrix3 * (inv_scale_sq * normal)) * weight;
-            tang_acc += (joint_matrix3 * (inv_scale_sq * tangent)) * weight;
+            norm_acc += (mat3_normal_matrix(joint_matrix3) * normal) * weight;
+            tang_acc += (joint_matrix3 * tangent) * weight;
         }
     }
 
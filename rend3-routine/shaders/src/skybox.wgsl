@@ -1,5 +1,11 @@
 {{include "rend3-routine/structures.wgsl"}}
 
+struct SkyboxUniforms {
+    inv_rotation: mat4x4<f32>,
+    intensity: f32,
+    blend: f32,
+}
+
 struct VertexOutput {
     @builtin(position) pos: vec4<f32>,
     @location(0) clip_position: vec2<f32>,
@@ -14,10 +20,14 @@ fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
 
 @group(0) @binding(0)
 var primary_sampler: sampler;
-@group(0) @binding(3)
+@group(0) @binding(9)
 var<uniform> uniforms: UniformData;
 @group(1) @binding(0)
 var skybox: texture_cube<f32>;
+@group(1) @binding(1)
+var blend_skybox: texture_cube<f32>;
+@group(1) @binding(2)
+var<uniform> skybox_uniforms: SkyboxUniforms;
 
 @fragment
 fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
@@ -27,8 +37,11 @@ fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
     let world_undiv = uniforms.inv_origin_view_proj * clip;
     let world = world_undiv.xyz / world_undiv.w;
     let world_dir = normalize(world);
+    let sky_dir = (skybox_uniforms.inv_rotation * vec4<f32>(world_dir, 0.0)).xyz;
 
-    let background = textureSample(skybox, primary_sampler, world_dir).rgb;
+    let primary = textureSample(skybox, primary_sampler, sky_dir).rgb;
+    let secondary = textureSample(blend_skybox, primary_sampler, sky_dir).rgb;
+    let background = mix(primary, secondary, skybox_uniforms.blend) * skybox_uniforms.intensity;
 
     return vec4<f32>(background, 1.0);
 }
@@ -0,0 +1,118 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct SsrUniforms {
+    max_steps: u32,
+    step_size: f32,
+    thickness: f32,
+    intensity: f32,
+    edge_fade: f32,
+    blur_radius: f32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+@group(1) @binding(0)
+var hdr_color: texture_2d<f32>;
+@group(1) @binding(1)
+var hdr_depth: texture_depth_2d;
+@group(1) @binding(2)
+var gbuffer_normal: texture_2d<f32>;
+@group(1) @binding(3)
+var<uniform> ssr: SsrUniforms;
+
+fn uv_to_ndc(uv: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+fn ndc_to_uv(ndc: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = uniforms.inv_view_proj * vec4<f32>(uv_to_ndc(uv), depth, 1.0);
+    let view = uniforms.view * (world / world.w);
+    return view.xyz;
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let dims = vec2<i32>(textureDimensions(hdr_depth));
+    let coords = vec2<i32>(vout.position.xy);
+    let depth = textureLoad(hdr_depth, coords, 0);
+
+    let view_pos = view_position(vout.tex_coords, depth);
+
+    // Without a gbuffer, a 1x1 texture of zeros is bound in place of the normals, and we
+    // reconstruct the normal from the depth buffer instead. This is blocky along edges and
+    // loses normal maps. Derivatives need to happen before any non-uniform control flow.
+    var derived_normal = normalize(cross(dpdy(view_pos), dpdx(view_pos)));
+    if dot(derived_normal, view_pos) > 0.0 {
+        derived_normal = -derived_normal;
+    }
+
+    let normal_coords = min(coords, vec2<i32>(textureDimensions(gbuffer_normal)) - 1);
+    let world_normal = textureLoad(gbuffer_normal, normal_coords, 0).xyz;
+    var normal = derived_normal;
+    if any(world_normal != vec3<f32>(0.0)) {
+        normal = normalize((uniforms.view * vec4<f32>(world_normal, 0.0)).xyz);
+    }
+
+    // Nothing to reflect off of in the sky.
+    if depth == 0.0 {
+        return vec4<f32>(0.0);
+    }
+
+    let proj = uniforms.view_proj * uniforms.inv_view;
+    let view_dir = normalize(view_pos);
+    let reflected = reflect(view_dir, normal);
+
+    for (var i = 1u; i <= ssr.max_steps; i += 1u) {
+        let ray_pos = view_pos + reflected * (ssr.step_size * f32(i));
+        let ray_clip = proj * vec4<f32>(ray_pos, 1.0);
+        if ray_clip.w <= 0.0 {
+            break;
+        }
+        let ray_ndc = ray_clip.xy / ray_clip.w;
+        if any(abs(ray_ndc) > vec2<f32>(1.0)) {
+            break;
+        }
+
+        let ray_uv = ndc_to_uv(ray_ndc);
+        let sample_coords = clamp(vec2<i32>(ray_uv * vec2<f32>(dims)), vec2<i32>(0), dims - 1);
+        let scene_depth = textureLoad(hdr_depth, sample_coords, 0);
+        if scene_depth == 0.0 {
+            continue;
+        }
+
+        let scene_pos = view_position(ray_uv, scene_depth);
+        let behind = length(ray_pos) - length(scene_pos);
+        if behind > 0.0 && behind < ssr.thickness {
+            // Fade out reflections that come from near the edge of the screen, as well as
+            // those near the end of the ray, as we have no information past either.
+            let edge_distance = max(abs(ray_ndc.x), abs(ray_ndc.y));
+            let edge = 1.0 - smoothstep(1.0 - ssr.edge_fade, 1.0, edge_distance);
+            let distance = 1.0 - f32(i) / f32(ssr.max_steps);
+            let fade = edge * distance;
+
+            let color = textureSampleLevel(hdr_color, primary_sampler, ray_uv, 0.0).rgb;
+            return vec4<f32>(color * ssr.intensity * fade, fade);
+        }
+    }
+
+    return vec4<f32>(0.0);
+}
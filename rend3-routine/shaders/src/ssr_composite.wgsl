@@ -0,0 +1,47 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct SsrUniforms {
+    max_steps: u32,
+    step_size: f32,
+    thickness: f32,
+    intensity: f32,
+    edge_fade: f32,
+    blur_radius: f32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(1) @binding(0)
+var reflections: texture_2d<f32>;
+@group(1) @binding(1)
+var material: texture_2d<f32>;
+@group(1) @binding(2)
+var<uniform> ssr: SsrUniforms;
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let perceptual_roughness = textureSampleLevel(material, primary_sampler, vout.tex_coords, 0.0).g;
+    let texel = ssr.blur_radius * perceptual_roughness / vec2<f32>(textureDimensions(reflections));
+
+    // Small blur to soften the hard edges of the reflection, wider for rougher surfaces.
+    var sum = vec4<f32>(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            sum += textureSampleLevel(reflections, primary_sampler, vout.tex_coords + offset, 0.0);
+        }
+    }
+
+    return vec4<f32>(sum.rgb / 9.0, 0.0);
+}
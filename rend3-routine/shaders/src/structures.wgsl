@@ -1,22 +1,6 @@
 {{include "rend3-routine/math/sphere.wgsl"}}
 {{include "rend3-routine/math/frustum.wgsl"}}
 
-struct ObjectInputData {
-    start_idx: u32,
-    count: u32,
-    vertex_offset: i32,
-    material_idx: u32,
-    transform: mat4x4<f32>,
-    bounding_sphere: Sphere,
-}
-
-struct ObjectOutputData {
-    model_view: mat4x4<f32>,
-    model_view_proj: mat4x4<f32>,
-    material_idx: u32,
-    inv_scale_sq: vec3<f32>,
-}
-
 struct IndirectCall {
     vertex_count: u32,
     instance_count: u32,
@@ -32,9 +16,12 @@ struct UniformData {
     inv_view: mat4x4<f32>,
     inv_view_proj: mat4x4<f32>,
     inv_origin_view_proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
     frustum: Frustum,
     ambient: vec4<f32>,
     resolution: vec2<u32>,
+    jitter: vec2<f32>,
 }
 
 struct DirectionalLight {
@@ -58,6 +45,27 @@ struct DirectionalLightData {
     data: array<DirectionalLight>,
 }
 
+struct AreaLight {
+    /// World space center of the light.
+    position: vec3<f32>,
+    /// 0 for rectangles, 1 for disks.
+    shape: u32,
+    /// Color/intensity of the light
+    color: vec3<f32>,
+    /// 1 if the light emits from both sides.
+    two_sided: u32,
+    /// World space half extent of the light along its local X. For disks,
+    /// the circumradius of the octagon the disk is shaded as.
+    axis_x: vec3<f32>,
+    /// World space half extent of the light along its local Y.
+    axis_y: vec3<f32>,
+}
+
+struct AreaLightData {
+    count: u32,
+    data: array<AreaLight>,
+}
+
 struct PixelData {
     albedo: vec4<f32>,
     diffuse_color: vec3<f32>,
@@ -74,4 +82,7 @@ struct PixelData {
     anisotropy: f32,
     ambient_occlusion: f32,
     material_flags: u32,
+    subsurface_color: vec3<f32>,
+    subsurface_wrap: f32,
+    thickness: f32,
 }
\ No newline at end of file
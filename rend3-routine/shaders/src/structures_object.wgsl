@@ -1,12 +1,37 @@
 {{include "rend3-routine/math/sphere.wgsl"}}
+{{include "rend3-routine/math/noise.wgsl"}}
+
+const OBJECT_FLAG_RECEIVE_SHADOWS: u32 = 0x1u;
+const OBJECT_FLAG_CLIPPED: u32         = 0x2u;
+const OBJECT_FLAG_U16_INDICES: u32     = 0x4u;
 
 struct Object {
     transform: mat4x4<f32>,
     bounding_sphere: Sphere,
+    tint: vec4<f32>,
+    clip_planes: array<vec4<f32>, 4>,
+    dissolve: f32,
+    dissolve_scale: f32,
     first_index: u32,
     index_count: u32,
     material_index: u32,
+    flags: u32,
     vertex_attribute_start_offsets: array<u32, {{vertex_array_counts}}>,
+    prev_transform: mat4x4<f32>,
+}
+
+// Returns true if the fragment should be discarded because of the object's clip planes or dissolve.
+fn object_clipped(object: Object, world_position: vec3<f32>, model_position: vec3<f32>) -> bool {
+    if (object.flags & OBJECT_FLAG_CLIPPED) == 0u {
+        return false;
+    }
+    for (var i = 0; i < 4; i += 1) {
+        let plane = object.clip_planes[i];
+        if dot(plane.xyz, world_position) + plane.w < 0.0 {
+            return true;
+        }
+    }
+    return object.dissolve > 0.0 && value_noise_3d(model_position * object.dissolve_scale) < object.dissolve;
 }
 
 struct ObjectRange {
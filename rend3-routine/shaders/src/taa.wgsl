@@ -0,0 +1,99 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct TaaUniforms {
+    /// Jitter applied to this frame's projection, in NDC.
+    jitter: vec2<f32>,
+    /// Weight of the current frame when blending with the history.
+    blend_factor: f32,
+    /// 0 if the history contains garbage and should be ignored.
+    history_valid: u32,
+    /// 0 if there is no velocity buffer bound.
+    velocity_valid: u32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(9)
+var<uniform> uniforms: UniformData;
+@group(1) @binding(0)
+var current_color: texture_2d<f32>;
+@group(1) @binding(1)
+var history_color: texture_2d<f32>;
+@group(1) @binding(2)
+var current_depth: texture_depth_2d;
+@group(1) @binding(3)
+var current_velocity: texture_2d<f32>;
+@group(1) @binding(4)
+var<uniform> taa: TaaUniforms;
+
+fn uv_to_ndc(uv: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+fn ndc_to_uv(ndc: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(vout.position.xy);
+    let dims = vec2<i32>(textureDimensions(current_color));
+
+    let current = textureLoad(current_color, coords, 0);
+
+    if taa.history_valid == 0u {
+        return current;
+    }
+
+    // Build the bounding box of the current neighborhood so we can reject stale history.
+    var color_min = current.rgb;
+    var color_max = current.rgb;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let sample_coords = clamp(coords + vec2<i32>(x, y), vec2<i32>(0), dims - 1);
+            let neighbor = textureLoad(current_color, sample_coords, 0).rgb;
+            color_min = min(color_min, neighbor);
+            color_max = max(color_max, neighbor);
+        }
+    }
+
+    let depth = textureLoad(current_depth, coords, 0);
+
+    var prev_uv: vec2<f32>;
+    if taa.velocity_valid != 0u && depth != 0.0 {
+        // Velocity is of the unjittered position, so remove this frame's jitter first.
+        let velocity = textureLoad(current_velocity, coords, 0).xy;
+        prev_uv = ndc_to_uv(uv_to_ndc(vout.tex_coords) - taa.jitter) - velocity;
+    } else {
+        // Nothing wrote a velocity here, so only follow the camera. The inverse
+        // view projection is jittered, which removes this frame's jitter.
+        let world = uniforms.inv_view_proj * vec4<f32>(uv_to_ndc(vout.tex_coords), depth, 1.0);
+        let prev_clip = uniforms.prev_view_proj * world;
+        if prev_clip.w <= 0.0 {
+            return current;
+        }
+        prev_uv = ndc_to_uv(prev_clip.xy / prev_clip.w);
+    }
+
+    if any(prev_uv < vec2<f32>(0.0)) || any(prev_uv > vec2<f32>(1.0)) {
+        return current;
+    }
+
+    let history = textureSampleLevel(history_color, primary_sampler, prev_uv, 0.0).rgb;
+    let clamped_history = clamp(history, color_min, color_max);
+
+    return vec4<f32>(mix(clamped_history, current.rgb, taa.blend_factor), current.a);
+}
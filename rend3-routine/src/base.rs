@@ -8,14 +8,25 @@
 //! This will allow you to insert your own routines and customize the behavior
 //! of the existing routines.
 //!
+//! If you only want to replace what happens after the scene is rendered, use
+//! [`BaseRenderGraph::add_scene_to_graph`], which renders the scene into the
+//! hdr targets of a [`BaseRenderGraphIntermediateState`]. You can then run
+//! your own passes on those targets and finish with
+//! [`BaseRenderGraphIntermediateState::tonemapping`].
+//!
+//! To change how the graph is created, like the formats of the depth and hdr
+//! buffers, the sample count, or which post effects run, use
+//! [`BaseRenderGraph::builder`], which checks that the options can be used
+//! together before creating anything.
+//!
 //! [`BaseRenderGraphIntermediateState`] intentionally has all of its members
 //! public. If you want to change what rendergraph image things are rendering
 //! to, or muck with any of the data in there, you are free to, and the
 //! following routines will behave as you configure.
 
-use std::{iter::zip, sync::Arc};
+use std::{fmt, iter::zip, sync::Arc};
 
-use glam::{UVec2, Vec4};
+use glam::{UVec2, Vec2, Vec4};
 use rend3::{
     format_sso,
     graph::{
@@ -25,27 +36,120 @@ use rend3::{
     types::{SampleCount, TextureFormat, TextureUsages},
     Renderer, ShaderPreProcessor, INTERNAL_SHADOW_DEPTH_FORMAT,
 };
-use wgpu::{BindGroup, Buffer};
+use wgpu::{BindGroup, Buffer, Features, FrontFace, TextureFormatFeatureFlags};
 
-use crate::{common, culling, forward::RoutineAddToGraphArgs, pbr, skinning, skybox, tonemapping};
+use crate::{
+    common, culling, debug_lines, decal, depth_resolve, forward::RoutineAddToGraphArgs, grid, pbr, skinning, skybox,
+    ssr, taa, tonemapping,
+};
+
+/// Callback which adds custom nodes to the graph after the scene has been
+/// rendered, but before tonemapping.
+///
+/// The hdr targets to read from and render to are available through the
+/// intermediate state.
+pub type PreTonemappingHook = Box<dyn Fn(&mut RenderGraph<'_>, &BaseRenderGraphIntermediateState) + Send + Sync>;
 
 /// Starter RenderGraph.
 ///
 /// See module for documentation.
 pub struct BaseRenderGraph {
+    /// Change the hdr format with
+    /// [`WholeFrameInterfaces::set_hdr_format`](common::WholeFrameInterfaces::set_hdr_format)
+    /// before creating any routines.
     pub interfaces: common::WholeFrameInterfaces,
+    /// Replace with [`Samplers::new`](common::Samplers::new) to change the
+    /// [`SamplerOptions`](common::SamplerOptions), like anisotropic filtering.
     pub samplers: common::Samplers,
+    /// Lookup tables used to shade area lights.
+    pub ltc: common::LtcTables,
     pub gpu_culler: culling::GpuCuller,
     pub gpu_skinner: skinning::GpuSkinner,
+    /// Hooks run in order after the scene is rendered, before tonemapping.
+    pub pre_tonemapping_hooks: Vec<PreTonemappingHook>,
+    /// Render at this fixed aspect ratio (width / height) into the centered
+    /// [`letterbox`] of the output, leaving black bars on the sides or on the
+    /// top and bottom. `None`, the default, fills the whole output.
+    ///
+    /// The camera's aspect ratio isn't changed, so set it to the same value
+    /// with [`Renderer::set_aspect_ratio`].
+    pub letterbox_aspect: Option<f32>,
+    /// Fraction of the output resolution the scene is rendered at, from 0.0
+    /// to 1.0. Defaults to 1.0. Tonemapping upscales the scene to the output
+    /// with bilinear filtering.
+    ///
+    /// The scene's render targets are allocated by the graph every frame, so
+    /// this can be changed from frame to frame.
+    pub render_scale: f32,
+    /// Render into this rect of the output instead of all of it, leaving the
+    /// rest of the output untouched if it was imported into the graph.
+    /// Letterboxing happens within this rect, and its bars are left untouched
+    /// as well.
+    ///
+    /// As with letterboxing, set the camera's aspect ratio to the one of the
+    /// rect with [`Renderer::set_aspect_ratio`].
+    pub viewport: Option<ViewportRect>,
+    /// Whether the output is cleared before the scene is drawn into it, or
+    /// the scene is drawn over what is already there.
+    pub output_load: OutputLoadOp,
+    /// Sample count used by [`Self::add_to_graph_configured`].
+    pub samples: SampleCount,
+    /// Ambient light used by [`Self::add_to_graph_configured`].
+    pub ambient: Vec4,
+    /// Clear color used by [`Self::add_to_graph_configured`].
+    pub clear_color: Vec4,
+    ssr: Option<ssr::SsrRoutine>,
+    taa: Option<taa::TaaRoutine>,
+    /// Resolves multisampled depth for SSR and TAA. Always set when either
+    /// of them is.
+    depth_resolve: Option<depth_resolve::DepthResolveRoutine>,
+}
+
+/// How [`BaseRenderGraph::add_to_graph`] treats the existing contents of the
+/// output.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OutputLoadOp {
+    /// Replace the output with the scene, which is cleared to the
+    /// `clear_color` passed to [`BaseRenderGraph::add_to_graph`].
+    #[default]
+    Clear,
+    /// Keep the contents of the output wherever nothing in the scene is drawn,
+    /// like a background rendered before the graph. The output must be
+    /// imported into the graph for there to be anything to keep.
+    ///
+    /// The scene is cleared to `Vec4::ZERO` and tonemapped with premultiplied
+    /// alpha over the output, so blended surfaces are blended with it as well.
+    /// A skybox covers the whole output, so don't pass one. Depth is still
+    /// cleared every frame.
+    Load,
 }
 
 impl BaseRenderGraph {
+    /// Start configuring a graph with non-default options.
+    pub fn builder() -> BaseRenderGraphBuilder {
+        BaseRenderGraphBuilder::default()
+    }
+
     pub fn new(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor) -> Self {
+        Self::new_with_depth_format(renderer, spp, TextureFormat::Depth32Float)
+    }
+
+    /// Create the graph data with a main depth buffer of the given format.
+    ///
+    /// See [`WholeFrameInterfaces::new_with_depth_format`](common::WholeFrameInterfaces::new_with_depth_format)
+    /// for which formats are usable.
+    pub fn new_with_depth_format(
+        renderer: &Arc<Renderer>,
+        spp: &ShaderPreProcessor,
+        depth_format: TextureFormat,
+    ) -> Self {
         profiling::scope!("DefaultRenderGraphData::new");
 
-        let interfaces = common::WholeFrameInterfaces::new(&renderer.device);
+        let interfaces = common::WholeFrameInterfaces::new_with_depth_format(&renderer.device, depth_format);
 
-        let samplers = common::Samplers::new(&renderer.device);
+        let samplers = common::Samplers::new(renderer, common::SamplerOptions::default());
+
+        let ltc = common::LtcTables::new(&renderer.device, &renderer.queue);
 
         // TODO: Support more materials
         let gpu_culler = culling::GpuCuller::new::<pbr::PbrMaterial>(renderer, spp);
@@ -55,13 +159,113 @@ impl BaseRenderGraph {
         Self {
             interfaces,
             samplers,
+            ltc,
             gpu_culler,
             gpu_skinner,
+            pre_tonemapping_hooks: Vec::new(),
+            letterbox_aspect: None,
+            render_scale: 1.0,
+            viewport: None,
+            output_load: OutputLoadOp::Clear,
+            samples: SampleCount::One,
+            ambient: Vec4::ZERO,
+            clear_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            ssr: None,
+            taa: None,
+            depth_resolve: None,
+        }
+    }
+
+    /// The rect of the output the scene is rendered into and the resolution
+    /// it is rendered at, after applying [`Self::viewport`],
+    /// [`Self::letterbox_aspect`], and [`Self::render_scale`].
+    fn scene_viewport(&self, resolution: UVec2) -> (ViewportRect, UVec2) {
+        let output_rect = self.viewport.unwrap_or_else(|| ViewportRect::from_size(resolution));
+
+        let mut viewport = match self.letterbox_aspect {
+            Some(aspect) => letterbox(output_rect.size, aspect),
+            None => ViewportRect::from_size(output_rect.size),
+        };
+        viewport.offset += output_rect.offset;
+        (viewport, scaled_resolution(viewport.size, self.render_scale))
+    }
+
+    /// Screen-space reflections added to the hdr color after forward
+    /// rendering, before the [pre tonemapping hooks](Self::pre_tonemapping_hooks).
+    /// Enable them with [`BaseRenderGraphBuilder::ssr`].
+    pub fn ssr(&self) -> Option<&ssr::SsrRoutine> {
+        self.ssr.as_ref()
+    }
+
+    /// Mutable access to [`Self::ssr`], to change its settings.
+    pub fn ssr_mut(&mut self) -> Option<&mut ssr::SsrRoutine> {
+        self.ssr.as_mut()
+    }
+
+    /// Temporal anti-aliasing resolved right before tonemapping. Enable it
+    /// with [`BaseRenderGraphBuilder::taa`], and call [`Self::evaluate`]
+    /// every frame when it is.
+    pub fn taa(&self) -> Option<&taa::TaaRoutine> {
+        self.taa.as_ref()
+    }
+
+    /// Mutable access to [`Self::taa`], to change its settings or reset its
+    /// history.
+    pub fn taa_mut(&mut self) -> Option<&mut taa::TaaRoutine> {
+        self.taa.as_mut()
+    }
+
+    /// Advance the per-frame state of the post effects. Must be called every
+    /// frame before adding to the graph if [`Self::taa`] is set. The
+    /// framework and [`HeadlessRenderer`](crate::headless::HeadlessRenderer)
+    /// do this for you.
+    pub fn evaluate(&mut self, renderer: &Renderer, resolution: UVec2) {
+        let (_, resolution) = self.scene_viewport(resolution);
+        if let Some(taa) = &mut self.taa {
+            taa.evaluate(renderer, resolution);
         }
     }
 
+    /// Same as [`Self::add_to_graph`], using the configured [`Self::samples`],
+    /// [`Self::ambient`], and [`Self::clear_color`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_to_graph_configured<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        eval_output: &InstructionEvaluationOutput,
+        pbr: &'node crate::pbr::PbrRoutine,
+        skybox: Option<&'node crate::skybox::SkyboxRoutine>,
+        tonemapping: &'node crate::tonemapping::TonemappingRoutine,
+        target_texture: RenderTargetHandle,
+        resolution: UVec2,
+    ) {
+        self.add_to_graph(
+            graph,
+            eval_output,
+            pbr,
+            skybox,
+            tonemapping,
+            target_texture,
+            resolution,
+            self.samples,
+            self.ambient,
+            self.clear_color,
+        );
+    }
+
     /// Add this to the rendergraph. This is the function you should start
     /// customizing.
+    ///
+    /// If `resolution`, or the size of [`Self::viewport`], is zero in either
+    /// dimension, such as when a window is minimized, nothing is added to the
+    /// graph.
+    ///
+    /// For a transparent background, pass no skybox and a `clear_color` with
+    /// zero alpha. Opaque surfaces write an alpha of one and blended ones
+    /// accumulate their coverage, so the alpha of the output is how much of
+    /// each pixel is covered by the scene. Enable
+    /// [`PostSettings::premultiplied_alpha`](crate::tonemapping::PostSettings::premultiplied_alpha)
+    /// when compositing the output, and use an RGBA output format.
     #[allow(clippy::too_many_arguments)]
     pub fn add_to_graph<'node>(
         &'node self,
@@ -76,9 +280,104 @@ impl BaseRenderGraph {
         ambient: Vec4,
         clear_color: Vec4,
     ) {
+        let output_size = self.viewport.map_or(resolution, |viewport| viewport.size);
+
+        // Nothing would be visible, so don't render at all.
+        if output_size.cmpeq(UVec2::ZERO).any() {
+            return;
+        }
+
+        let (viewport, resolution) = self.scene_viewport(resolution);
+
+        let clear_color = match self.output_load {
+            OutputLoadOp::Clear => clear_color,
+            OutputLoadOp::Load => Vec4::ZERO,
+        };
+
         // Create intermediate storage
-        let state = BaseRenderGraphIntermediateState::new(graph, eval_output, resolution, samples);
+        let mut state = BaseRenderGraphIntermediateState::new_with_formats(
+            graph,
+            eval_output,
+            resolution,
+            samples,
+            self.interfaces.depth_format,
+            self.interfaces.hdr_format,
+        );
+        if self.interfaces.gbuffer {
+            state.create_gbuffer(graph, resolution, samples);
+        }
+        if self.interfaces.velocity {
+            state.create_velocity(graph, resolution, samples);
+        }
+        if let Some(taa) = &self.taa {
+            state.jitter = taa.jitter();
+        }
+
+        // Render the scene into the hdr targets
+        self.add_scene_to_graph(
+            graph,
+            &state,
+            eval_output,
+            pbr,
+            skybox,
+            resolution,
+            samples,
+            ambient,
+            clear_color,
+        );
+
+        // Post effects which need single sampled depth
+        if let Some(depth_resolve) = &self.depth_resolve {
+            state.resolve_depth(graph, depth_resolve, resolution);
+        }
+        if let Some(ssr) = &self.ssr {
+            state.ssr(graph, ssr, resolution);
+        }
+
+        // Custom passes on the hdr targets
+        for hook in &self.pre_tonemapping_hooks {
+            hook(graph, &state);
+        }
 
+        // Anti-aliasing
+        let hdr = match &self.taa {
+            Some(taa) => state.taa(graph, taa),
+            None => state.hdr_output(),
+        };
+
+        // Make the reference to the surface. Tonemapping clears the whole
+        // target, which leaves the bars around a letterbox black, unless only
+        // a part of the target is rendered to.
+        let target_texture = target_texture.restrict(0..1, viewport);
+        if self.output_load == OutputLoadOp::Load {
+            tonemapping.add_to_graph_over(graph, hdr, target_texture, state.forward_uniform_bg);
+        } else if self.viewport.is_some() {
+            tonemapping.add_to_graph_preserving(graph, hdr, target_texture, state.forward_uniform_bg);
+        } else {
+            tonemapping.add_to_graph(graph, hdr, target_texture, state.forward_uniform_bg);
+        }
+    }
+
+    /// Add everything needed to render the scene into the hdr targets of
+    /// `state`, stopping right before tonemapping.
+    ///
+    /// This is the minimal sequence of uploading uniforms, skinning, culling,
+    /// shadow rendering, and forward rendering. Use this if you want to keep
+    /// the default scene rendering but provide your own post processing chain,
+    /// reading from [`BaseRenderGraphIntermediateState::hdr_output`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_scene_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        state: &BaseRenderGraphIntermediateState,
+        eval_output: &InstructionEvaluationOutput,
+        pbr: &'node crate::pbr::PbrRoutine,
+        skybox: Option<&'node crate::skybox::SkyboxRoutine>,
+        resolution: UVec2,
+        samples: SampleCount,
+        ambient: Vec4,
+        clear_color: Vec4,
+    ) {
         // Preparing and uploading data
         state.create_frame_uniforms(graph, self, ambient, resolution);
 
@@ -90,22 +389,279 @@ impl BaseRenderGraph {
         state.pbr_culling(graph, self);
 
         // Depth-only rendering
-        state.pbr_shadow_rendering(graph, pbr, &eval_output.shadows);
+        state.pbr_shadow_rendering(graph, self, pbr, &eval_output.shadows);
 
         // Clear targets
         state.clear(graph, clear_color);
 
+        let skybox_order = skybox.map_or(skybox::SkyboxOrder::default(), |skybox| skybox.order);
+
+        // Skybox
+        if skybox_order == skybox::SkyboxOrder::BeforeOpaque {
+            state.skybox(graph, skybox, samples);
+        }
+
         // Forward rendering opaque
-        state.pbr_forward_rendering_opaque(graph, pbr, samples);
+        state.pbr_forward_rendering_opaque(graph, self, pbr, samples);
 
         // Skybox
-        state.skybox(graph, skybox, samples);
+        if skybox_order == skybox::SkyboxOrder::AfterOpaque {
+            state.skybox(graph, skybox, samples);
+        }
 
         // Forward rendering transparent
-        state.pbr_forward_rendering_transparent(graph, pbr, samples);
+        state.pbr_forward_rendering_transparent(graph, self, pbr, samples);
+
+        // Forward rendering overlay
+        state.pbr_forward_rendering_overlay(graph, self, pbr, samples);
+    }
+}
+
+/// The largest rect with the given aspect ratio (width / height) which fits
+/// centered into the given resolution.
+///
+/// This is where [`BaseRenderGraph::letterbox_aspect`] puts the scene, use it
+/// to map window coordinates to the scene.
+pub fn letterbox(resolution: UVec2, aspect: f32) -> ViewportRect {
+    let size = if resolution.x as f32 > resolution.y as f32 * aspect {
+        UVec2::new((resolution.y as f32 * aspect).round() as u32, resolution.y)
+    } else {
+        UVec2::new(resolution.x, (resolution.x as f32 / aspect).round() as u32)
+    };
+    let size = size.clamp(UVec2::ONE, resolution);
+    ViewportRect::new((resolution - size) / 2, size)
+}
 
-        // Make the reference to the surface
-        state.tonemapping(graph, tonemapping, target_texture);
+/// The resolution the scene is rendered at for the given output resolution
+/// and [`BaseRenderGraph::render_scale`]. Never zero in either dimension.
+pub fn scaled_resolution(resolution: UVec2, render_scale: f32) -> UVec2 {
+    let scale = render_scale.clamp(0.0, 1.0);
+    (resolution.as_vec2() * scale).round().as_uvec2().max(UVec2::ONE)
+}
+
+/// Error returned by [`BaseRenderGraphBuilder::build`] if the options can't be
+/// used with each other or with the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BaseRenderGraphError {
+    /// The depth format has no depth aspect.
+    NotADepthFormat(TextureFormat),
+    /// The format needs device features which aren't enabled.
+    MissingFeatures { format: TextureFormat, missing: Features },
+    /// The format can't be used as the hdr format, see
+    /// [`hdr_format_supported`](common::hdr_format_supported).
+    UnsupportedHdrFormat(TextureFormat),
+    /// One of the targets rendered to can't be multisampled with the sample
+    /// count.
+    UnsupportedSampleCount {
+        format: TextureFormat,
+        samples: SampleCount,
+    },
+}
+
+impl fmt::Display for BaseRenderGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotADepthFormat(format) => write!(f, "{format:?} can't be used as the depth format"),
+            Self::MissingFeatures { format, missing } => {
+                write!(f, "{format:?} requires the missing device features {missing:?}")
+            }
+            Self::UnsupportedHdrFormat(format) => write!(f, "{format:?} can't be used as the hdr format"),
+            Self::UnsupportedSampleCount { format, samples } => {
+                write!(f, "{format:?} can't be rendered to with {} samples", *samples as u8)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BaseRenderGraphError {}
+
+/// Builder for a [`BaseRenderGraph`] with non-default options.
+///
+/// Every option starts out as the value [`BaseRenderGraph::new`] uses.
+///
+/// The cheap post effects applied while tonemapping, like vignette and
+/// dithering, stay on
+/// [`TonemappingRoutine::post_settings`](tonemapping::TonemappingRoutine::post_settings),
+/// as a tonemapping routine is created for every output format.
+#[derive(Debug, Clone)]
+pub struct BaseRenderGraphBuilder {
+    depth_format: TextureFormat,
+    hdr_format: TextureFormat,
+    front_face: Option<FrontFace>,
+    gbuffer: bool,
+    velocity: bool,
+    sampler_options: common::SamplerOptions,
+    samples: SampleCount,
+    ambient: Vec4,
+    clear_color: Vec4,
+    ssr: bool,
+    taa: bool,
+}
+
+impl Default for BaseRenderGraphBuilder {
+    fn default() -> Self {
+        Self {
+            depth_format: TextureFormat::Depth32Float,
+            hdr_format: TextureFormat::Rgba16Float,
+            front_face: None,
+            gbuffer: false,
+            velocity: false,
+            sampler_options: common::SamplerOptions::default(),
+            samples: SampleCount::One,
+            ambient: Vec4::ZERO,
+            clear_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            ssr: false,
+            taa: false,
+        }
+    }
+}
+
+impl BaseRenderGraphBuilder {
+    /// Format of the main depth buffer. See
+    /// [`WholeFrameInterfaces::new_with_depth_format`](common::WholeFrameInterfaces::new_with_depth_format)
+    /// for which formats are usable.
+    pub fn depth_format(mut self, format: TextureFormat) -> Self {
+        self.depth_format = format;
+        self
+    }
+
+    /// Format of the hdr color buffer. See
+    /// [`WholeFrameInterfaces::set_hdr_format`](common::WholeFrameInterfaces::set_hdr_format).
+    pub fn hdr_format(mut self, format: TextureFormat) -> Self {
+        self.hdr_format = format;
+        self
+    }
+
+    /// Winding of front faces, for meshes which aren't wound counter
+    /// clockwise. See
+    /// [`WholeFrameInterfaces::front_face`](common::WholeFrameInterfaces::front_face).
+    pub fn front_face(mut self, front_face: FrontFace) -> Self {
+        self.front_face = Some(front_face);
+        self
+    }
+
+    /// Also write a gbuffer from the opaque forward passes, into
+    /// [`BaseRenderGraphIntermediateState::gbuffer`]. See
+    /// [`WholeFrameInterfaces::gbuffer`](common::WholeFrameInterfaces::gbuffer).
+    pub fn gbuffer(mut self, gbuffer: bool) -> Self {
+        self.gbuffer = gbuffer;
+        self
+    }
+
+    /// Also write a velocity buffer from the opaque forward passes, returned
+    /// by [`BaseRenderGraphIntermediateState::velocity_view`]. See
+    /// [`WholeFrameInterfaces::velocity`](common::WholeFrameInterfaces::velocity).
+    pub fn velocity(mut self, velocity: bool) -> Self {
+        self.velocity = velocity;
+        self
+    }
+
+    /// Options for the samplers used by materials.
+    pub fn sampler_options(mut self, options: common::SamplerOptions) -> Self {
+        self.sampler_options = options;
+        self
+    }
+
+    /// Sample count of the scene, see [`BaseRenderGraph::samples`]. Every
+    /// target rendered to, including the gbuffer and velocity buffer if
+    /// enabled, must support it.
+    pub fn samples(mut self, samples: SampleCount) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Ambient light, see [`BaseRenderGraph::ambient`].
+    pub fn ambient(mut self, ambient: Vec4) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    /// Color the scene is cleared to, see [`BaseRenderGraph::clear_color`].
+    pub fn clear_color(mut self, clear_color: Vec4) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    /// Add screen-space reflections after forward rendering, see
+    /// [`BaseRenderGraph::ssr`].
+    pub fn ssr(mut self, ssr: bool) -> Self {
+        self.ssr = ssr;
+        self
+    }
+
+    /// Resolve the scene with temporal anti-aliasing, see
+    /// [`BaseRenderGraph::taa`]. This also enables [`Self::velocity`], which
+    /// TAA reprojects its history with.
+    pub fn taa(mut self, taa: bool) -> Self {
+        self.taa = taa;
+        self
+    }
+
+    /// Check that the options can be used with a device with the given
+    /// features. [`Self::build`] does this for you.
+    pub fn validate(&self, features: Features) -> Result<(), BaseRenderGraphError> {
+        if !self.depth_format.has_depth_aspect() {
+            return Err(BaseRenderGraphError::NotADepthFormat(self.depth_format));
+        }
+        let missing = self.depth_format.required_features() - features;
+        if !missing.is_empty() {
+            return Err(BaseRenderGraphError::MissingFeatures {
+                format: self.depth_format,
+                missing,
+            });
+        }
+        if !common::hdr_format_supported(features, self.hdr_format) {
+            return Err(BaseRenderGraphError::UnsupportedHdrFormat(self.hdr_format));
+        }
+        if self.samples == SampleCount::Four {
+            let gbuffer: &[TextureFormat] = if self.gbuffer { &common::GBUFFER_FORMATS } else { &[] };
+            let velocity = (self.velocity || self.taa).then_some(common::VELOCITY_FORMAT);
+            let targets = [self.depth_format, self.hdr_format]
+                .into_iter()
+                .chain(gbuffer.iter().copied())
+                .chain(velocity);
+            for format in targets {
+                let flags = format.guaranteed_format_features(features).flags;
+                if !flags.contains(TextureFormatFeatureFlags::MULTISAMPLE_X4) {
+                    return Err(BaseRenderGraphError::UnsupportedSampleCount {
+                        format,
+                        samples: self.samples,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create the graph data. Nothing is created if the options are invalid.
+    pub fn build(
+        self,
+        renderer: &Arc<Renderer>,
+        spp: &ShaderPreProcessor,
+    ) -> Result<BaseRenderGraph, BaseRenderGraphError> {
+        self.validate(renderer.features)?;
+
+        let mut base = BaseRenderGraph::new_with_depth_format(renderer, spp, self.depth_format);
+        base.interfaces.set_hdr_format(renderer.features, self.hdr_format);
+        base.interfaces.front_face = self.front_face;
+        base.interfaces.gbuffer = self.gbuffer;
+        base.interfaces.velocity = self.velocity || self.taa;
+        if self.sampler_options != common::SamplerOptions::default() {
+            base.samplers = common::Samplers::new(renderer, self.sampler_options);
+        }
+        base.samples = self.samples;
+        base.ambient = self.ambient;
+        base.clear_color = self.clear_color;
+        if self.ssr {
+            base.ssr = Some(ssr::SsrRoutine::new(renderer, spp, &base.interfaces));
+        }
+        if self.taa {
+            base.taa = Some(taa::TaaRoutine::new(renderer, spp, &base.interfaces));
+        }
+        if self.ssr || self.taa {
+            base.depth_resolve = Some(depth_resolve::DepthResolveRoutine::new(renderer, spp));
+        }
+        Ok(base)
     }
 }
 
@@ -123,8 +679,28 @@ pub struct BaseRenderGraphIntermediateState {
     pub shadow: RenderTargetHandle,
     pub color: RenderTargetHandle,
     pub resolve: Option<RenderTargetHandle>,
+    /// Targets the opaque forward passes write the surface properties into,
+    /// with the formats [`GBUFFER_FORMATS`](common::GBUFFER_FORMATS). Empty
+    /// unless [`Self::create_gbuffer`] was called. They have the sample count
+    /// of [`Self::color`] and aren't resolved.
+    pub gbuffer: Vec<RenderTargetHandle>,
+    /// Target the opaque forward passes write the velocity into, see
+    /// [`Self::velocity_view`]. None unless [`Self::create_velocity`] was
+    /// called.
+    pub velocity: Option<RenderTargetHandle>,
     pub depth: RenderTargetHandle,
+    /// Format of [`Self::depth`].
+    pub depth_format: TextureFormat,
+    /// Format of [`Self::color`] and [`Self::resolve`].
+    pub hdr_format: TextureFormat,
+    /// Single sampled copy of [`Self::depth`], if
+    /// [`Self::resolve_depth`] was called while multisampling.
+    pub depth_resolve: Option<RenderTargetHandle>,
     pub pre_skinning_buffers: DataHandle<skinning::PreSkinningBuffers>,
+
+    /// Jitter applied to the camera projection, in NDC. Set this to
+    /// [`TaaRoutine::jitter`](taa::TaaRoutine::jitter) when using TAA.
+    pub jitter: Vec2,
 }
 impl BaseRenderGraphIntermediateState {
     /// Create the default setting for all state.
@@ -133,6 +709,42 @@ impl BaseRenderGraphIntermediateState {
         eval_output: &InstructionEvaluationOutput,
         resolution: UVec2,
         samples: SampleCount,
+    ) -> Self {
+        Self::new_with_depth_format(graph, eval_output, resolution, samples, TextureFormat::Depth32Float)
+    }
+
+    /// Create the default setting for all state, with the depth buffer in
+    /// the given format. This must match the format of the
+    /// [`WholeFrameInterfaces`](common::WholeFrameInterfaces) the routines
+    /// were created with.
+    pub fn new_with_depth_format(
+        graph: &mut RenderGraph<'_>,
+        eval_output: &InstructionEvaluationOutput,
+        resolution: UVec2,
+        samples: SampleCount,
+        depth_format: TextureFormat,
+    ) -> Self {
+        Self::new_with_formats(
+            graph,
+            eval_output,
+            resolution,
+            samples,
+            depth_format,
+            TextureFormat::Rgba16Float,
+        )
+    }
+
+    /// Create the default setting for all state, with the depth and hdr
+    /// buffers in the given formats. These must match the formats of the
+    /// [`WholeFrameInterfaces`](common::WholeFrameInterfaces) the routines
+    /// were created with.
+    pub fn new_with_formats(
+        graph: &mut RenderGraph<'_>,
+        eval_output: &InstructionEvaluationOutput,
+        resolution: UVec2,
+        samples: SampleCount,
+        depth_format: TextureFormat,
+        hdr_format: TextureFormat,
     ) -> Self {
         // We need to know how many shadows we need to render
         let shadow_count = eval_output.shadows.len();
@@ -157,7 +769,7 @@ impl BaseRenderGraphIntermediateState {
             resolution,
             depth: 1,
             samples,
-            format: TextureFormat::Rgba16Float,
+            format: hdr_format,
             usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
         });
         let resolve = samples.needs_resolve().then(|| {
@@ -166,7 +778,7 @@ impl BaseRenderGraphIntermediateState {
                 resolution,
                 depth: 1,
                 samples: SampleCount::One,
-                format: TextureFormat::Rgba16Float,
+                format: hdr_format,
                 usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
             })
         });
@@ -175,8 +787,8 @@ impl BaseRenderGraphIntermediateState {
             resolution,
             depth: 1,
             samples,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: depth_format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
         });
 
         let pre_skinning_buffers = graph.add_data::<skinning::PreSkinningBuffers>();
@@ -195,11 +807,78 @@ impl BaseRenderGraphIntermediateState {
             shadow,
             color,
             resolve,
+            gbuffer: Vec::new(),
+            velocity: None,
             depth,
+            depth_format,
+            hdr_format,
+            depth_resolve: None,
             pre_skinning_buffers,
+
+            jitter: Vec2::ZERO,
         }
     }
 
+    /// The single sampled hdr color the scene ends up in, which is what post
+    /// processing and tonemapping read: [`Self::resolve`] when multisampling,
+    /// [`Self::color`] otherwise.
+    ///
+    /// It has [`TextureUsages::TEXTURE_BINDING`], so your own nodes, like the
+    /// ones added by [`BaseRenderGraph::pre_tonemapping_hooks`], can sample it
+    /// through [`RenderGraphDataStore::get_render_target`](rend3::graph::RenderGraphDataStore::get_render_target).
+    /// Its format is [`Self::hdr_format`].
+    pub fn hdr_output(&self) -> RenderTargetHandle {
+        self.resolve.unwrap_or(self.color)
+    }
+
+    /// Create the targets of [`Self::gbuffer`]. Only call this if the
+    /// routines were created with
+    /// [`WholeFrameInterfaces::gbuffer`](common::WholeFrameInterfaces::gbuffer)
+    /// set, as their pipelines need to match.
+    pub fn create_gbuffer(&mut self, graph: &mut RenderGraph<'_>, resolution: UVec2, samples: SampleCount) {
+        let labels = ["gbuffer albedo", "gbuffer normal", "gbuffer material"];
+        self.gbuffer = zip(labels, common::GBUFFER_FORMATS)
+            .map(|(label, format)| {
+                graph.add_render_target(RenderTargetDescriptor {
+                    label: Some(label.into()),
+                    resolution,
+                    depth: 1,
+                    samples,
+                    format,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                })
+            })
+            .collect();
+    }
+
+    /// Create the target of [`Self::velocity`]. Only call this if the
+    /// routines were created with
+    /// [`WholeFrameInterfaces::velocity`](common::WholeFrameInterfaces::velocity)
+    /// set, as their pipelines need to match.
+    pub fn create_velocity(&mut self, graph: &mut RenderGraph<'_>, resolution: UVec2, samples: SampleCount) {
+        self.velocity = Some(graph.add_render_target(RenderTargetDescriptor {
+            label: Some("velocity".into()),
+            resolution,
+            depth: 1,
+            samples,
+            format: common::VELOCITY_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        }));
+    }
+
+    /// The velocity buffer, once the opaque forward passes have run, if it
+    /// was created with [`Self::create_velocity`].
+    ///
+    /// It holds how far each pixel moved since the previous frame in uv
+    /// space, with the format [`VELOCITY_FORMAT`](common::VELOCITY_FORMAT).
+    /// Pixels no opaque geometry covers, like the sky, have zero velocity.
+    /// Skinned meshes only get the velocity of their object's transform, as
+    /// the previous pose isn't kept. It has the sample count of
+    /// [`Self::color`] and isn't resolved.
+    pub fn velocity_view(&self) -> Option<RenderTargetHandle> {
+        self.velocity
+    }
+
     /// Create all the uniforms all the shaders in this graph need.
     pub fn create_frame_uniforms<'node>(
         &self,
@@ -215,12 +894,20 @@ impl BaseRenderGraphIntermediateState {
             self.shadow,
             &base.interfaces,
             &base.samplers,
+            &base.ltc,
             ambient,
             resolution,
+            self.jitter,
         );
     }
 
     /// Does all shadow culling for the PBR materials.
+    ///
+    /// Shadows are culled by the same [`GpuCuller`](culling::GpuCuller) as
+    /// the main camera, once per shadow, against the frustum of the shadow's
+    /// own camera. Objects are culled against the frustum on the cpu while
+    /// batching, triangles are culled on the gpu when GpuDriven. Per shadow
+    /// numbers are available in [`FrameStats::shadows`](culling::FrameStats::shadows).
     pub fn pbr_shadow_culling<'node>(&self, graph: &mut RenderGraph<'node>, base: &'node BaseRenderGraph) {
         for (shadow_index, &shadow_culled) in self.shadow_cull.iter().enumerate() {
             base.gpu_culler.add_culling_to_graph::<pbr::PbrMaterial>(
@@ -244,13 +931,22 @@ impl BaseRenderGraphIntermediateState {
 
     /// Clear all the targets to their needed values
     pub fn clear<'node>(&self, graph: &mut RenderGraph<'node>, clear_color: Vec4) {
-        crate::clear::add_clear_to_graph(graph, self.color, self.resolve, self.depth, clear_color, 0.0);
+        crate::clear::add_clear_to_graph(
+            graph,
+            self.color,
+            self.resolve,
+            self.depth,
+            clear_color,
+            0.0,
+            common::has_stencil(self.depth_format).then_some(0),
+        );
     }
 
     /// Render all shadows for the PBR materials.
     pub fn pbr_shadow_rendering<'node>(
         &self,
         graph: &mut RenderGraph<'node>,
+        base: &'node BaseRenderGraph,
         pbr: &'node pbr::PbrRoutine,
         shadows: &[ShadowDesc],
     ) {
@@ -263,11 +959,13 @@ impl BaseRenderGraphIntermediateState {
                     whole_frame_uniform_bg: self.shadow_uniform_bg,
                     culled: *shadow_cull,
                     per_material: &pbr.per_material,
+                    samplers: &base.samplers,
                     extra_bgs: None,
                     label: &format!("pbr shadow renderering S{shadow_index}"),
                     samples: SampleCount::One,
                     color: None,
                     resolve: None,
+                    extra_targets: &[],
                     depth: self
                         .shadow
                         .restrict(0..1, ViewportRect::new(desc.map.offset, UVec2::splat(desc.map.size))),
@@ -296,13 +994,23 @@ impl BaseRenderGraphIntermediateState {
         }
     }
 
-    /// Render the PBR materials.
+    /// Render the PBR materials, also writing [`Self::gbuffer`] and
+    /// [`Self::velocity`] if they were created.
     pub fn pbr_forward_rendering_opaque<'node>(
         &self,
         graph: &mut RenderGraph<'node>,
+        base: &'node BaseRenderGraph,
         pbr: &'node pbr::PbrRoutine,
         samples: SampleCount,
     ) {
+        if pbr.gbuffer {
+            assert_eq!(
+                self.gbuffer.len(),
+                common::GBUFFER_FORMATS.len(),
+                "The pbr routine writes a gbuffer, call create_gbuffer first"
+            );
+        }
+        let extra_targets: Vec<_> = self.gbuffer.iter().copied().chain(self.velocity).collect();
         let routines = [&pbr.opaque_routine, &pbr.cutout_routine];
         for routine in routines {
             routine.add_forward_to_graph(RoutineAddToGraphArgs {
@@ -310,11 +1018,13 @@ impl BaseRenderGraphIntermediateState {
                 whole_frame_uniform_bg: self.forward_uniform_bg,
                 culled: self.cull,
                 per_material: &pbr.per_material,
+                samplers: &base.samplers,
                 extra_bgs: None,
                 label: "PBR Forward",
                 samples,
                 color: Some(self.color),
                 resolve: self.resolve,
+                extra_targets: &extra_targets,
                 depth: self.depth,
                 data: 0,
             });
@@ -325,22 +1035,145 @@ impl BaseRenderGraphIntermediateState {
     pub fn pbr_forward_rendering_transparent<'node>(
         &self,
         graph: &mut RenderGraph<'node>,
+        base: &'node BaseRenderGraph,
         pbr: &'node pbr::PbrRoutine,
         samples: SampleCount,
     ) {
-        pbr.blend_routine.add_forward_to_graph(RoutineAddToGraphArgs {
+        // Each blend mode has its own pipeline, so objects are only sorted back to front within a blend mode.
+        for routine in &pbr.blend_routines {
+            routine.add_forward_to_graph(RoutineAddToGraphArgs {
+                graph,
+                whole_frame_uniform_bg: self.forward_uniform_bg,
+                culled: self.cull,
+                per_material: &pbr.per_material,
+                samplers: &base.samplers,
+                extra_bgs: None,
+                label: "PBR Forward",
+                samples,
+                color: Some(self.color),
+                resolve: self.resolve,
+                extra_targets: &[],
+                depth: self.depth,
+                data: 0,
+            });
+        }
+    }
+
+    /// Render the PBR materials of objects marked as overlay, on top of
+    /// everything else.
+    pub fn pbr_forward_rendering_overlay<'node>(
+        &self,
+        graph: &mut RenderGraph<'node>,
+        base: &'node BaseRenderGraph,
+        pbr: &'node pbr::PbrRoutine,
+        samples: SampleCount,
+    ) {
+        let routines = [&pbr.overlay_opaque_routine, &pbr.overlay_cutout_routine]
+            .into_iter()
+            .chain(&pbr.overlay_blend_routines);
+        for routine in routines {
+            routine.add_forward_to_graph(RoutineAddToGraphArgs {
+                graph,
+                whole_frame_uniform_bg: self.forward_uniform_bg,
+                culled: self.cull,
+                per_material: &pbr.per_material,
+                samplers: &base.samplers,
+                extra_bgs: None,
+                label: "PBR Forward Overlay",
+                samples,
+                color: Some(self.color),
+                resolve: self.resolve,
+                extra_targets: &[],
+                depth: self.depth,
+                data: 0,
+            });
+        }
+    }
+
+    /// Resolve the multisampled depth buffer so post processing can sample
+    /// it. Does nothing if not multisampling.
+    ///
+    /// Must be called after all forward rendering.
+    pub fn resolve_depth<'node>(
+        &mut self,
+        graph: &mut RenderGraph<'node>,
+        depth_resolve: &'node depth_resolve::DepthResolveRoutine,
+        resolution: UVec2,
+    ) {
+        if self.resolve.is_some() {
+            self.depth_resolve = Some(depth_resolve.add_to_graph(graph, self.depth, resolution));
+        }
+    }
+
+    /// Blend decals onto the hdr color.
+    pub fn decals<'node>(&self, graph: &mut RenderGraph<'node>, decals: &'node decal::DecalRoutine) {
+        decals.add_to_graph(
+            graph,
+            self.hdr_output(),
+            self.depth_resolve.unwrap_or(self.depth),
+            self.forward_uniform_bg,
+        );
+    }
+
+    /// Draw the reference grid into the hdr color.
+    pub fn grid<'node>(&self, graph: &mut RenderGraph<'node>, grid: &'node grid::GridRoutine, samples: SampleCount) {
+        grid.add_to_graph(
+            graph,
+            self.color,
+            self.resolve,
+            self.depth,
+            self.forward_uniform_bg,
+            samples,
+        );
+    }
+
+    /// Draw the debug lines of `debug_lines` into the hdr color.
+    pub fn debug_lines<'node>(
+        &self,
+        graph: &mut RenderGraph<'node>,
+        debug_lines: &'node debug_lines::DebugLineRoutine,
+        samples: SampleCount,
+    ) {
+        debug_lines.add_to_graph(
             graph,
-            whole_frame_uniform_bg: self.forward_uniform_bg,
-            culled: self.cull,
-            per_material: &pbr.per_material,
-            extra_bgs: None,
-            label: "PBR Forward",
+            self.color,
+            self.resolve,
+            self.depth,
+            self.forward_uniform_bg,
             samples,
-            color: Some(self.color),
-            resolve: self.resolve,
-            depth: self.depth,
-            data: 0,
-        });
+        );
+    }
+
+    /// Add screen-space reflections into the hdr color. Rays reflect off the
+    /// normals in [`Self::gbuffer`], and the blur follows its roughness, if it
+    /// is there and single sampled.
+    pub fn ssr<'node>(&self, graph: &mut RenderGraph<'node>, ssr: &'node ssr::SsrRoutine, resolution: UVec2) {
+        let normal = self.gbuffer.get(1).copied().filter(|_| self.resolve.is_none());
+        let material = self.gbuffer.get(2).copied().filter(|_| self.resolve.is_none());
+        ssr.add_to_graph(
+            graph,
+            self.hdr_output(),
+            self.depth_resolve.unwrap_or(self.depth),
+            normal,
+            material,
+            self.forward_uniform_bg,
+            resolution,
+        );
+    }
+
+    /// Resolve the hdr color against the TAA history, returning the
+    /// anti-aliased image to tonemap.
+    ///
+    /// History is reprojected with [`Self::velocity`] when single sampling.
+    pub fn taa<'node>(&self, graph: &mut RenderGraph<'node>, taa: &'node taa::TaaRoutine) -> RenderTargetHandle {
+        let velocity = self.velocity.filter(|_| self.resolve.is_none());
+        taa.add_to_graph(
+            graph,
+            self.hdr_output(),
+            self.depth_resolve.unwrap_or(self.depth),
+            velocity,
+            self.forward_uniform_bg,
+        )
     }
 
     /// Tonemap onto the given render target.
@@ -350,11 +1183,86 @@ impl BaseRenderGraphIntermediateState {
         tonemapping: &'node tonemapping::TonemappingRoutine,
         target: RenderTargetHandle,
     ) {
-        tonemapping.add_to_graph(
-            graph,
-            self.resolve.unwrap_or(self.color),
-            target,
-            self.forward_uniform_bg,
+        tonemapping.add_to_graph(graph, self.hdr_output(), target, self.forward_uniform_bg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::{Features, TextureFormat};
+
+    use glam::UVec2;
+    use rend3::graph::ViewportRect;
+
+    use rend3::types::SampleCount;
+
+    use super::{letterbox, scaled_resolution, BaseRenderGraph, BaseRenderGraphError};
+
+    #[test]
+    fn render_scale() {
+        assert_eq!(scaled_resolution(UVec2::new(1920, 1080), 1.0), UVec2::new(1920, 1080));
+        assert_eq!(scaled_resolution(UVec2::new(1920, 1080), 0.5), UVec2::new(960, 540));
+        assert_eq!(scaled_resolution(UVec2::new(1920, 1080), 2.0), UVec2::new(1920, 1080));
+        assert_eq!(scaled_resolution(UVec2::new(1920, 1080), 0.0), UVec2::ONE);
+    }
+
+    #[test]
+    fn letterbox_rects() {
+        // Wider than 16:9 gets bars on the sides, taller gets them on top and bottom.
+        assert_eq!(
+            letterbox(UVec2::new(2560, 1080), 16.0 / 9.0),
+            ViewportRect::new(UVec2::new(320, 0), UVec2::new(1920, 1080))
+        );
+        assert_eq!(
+            letterbox(UVec2::new(1920, 1200), 16.0 / 9.0),
+            ViewportRect::new(UVec2::new(0, 60), UVec2::new(1920, 1080))
+        );
+        assert_eq!(
+            letterbox(UVec2::new(1920, 1080), 16.0 / 9.0),
+            ViewportRect::from_size(UVec2::new(1920, 1080))
+        );
+    }
+
+    #[test]
+    fn builder_validation() {
+        assert_eq!(BaseRenderGraph::builder().validate(Features::empty()), Ok(()));
+        assert_eq!(
+            BaseRenderGraph::builder()
+                .depth_format(TextureFormat::Rgba8Unorm)
+                .validate(Features::empty()),
+            Err(BaseRenderGraphError::NotADepthFormat(TextureFormat::Rgba8Unorm))
+        );
+        assert_eq!(
+            BaseRenderGraph::builder()
+                .depth_format(TextureFormat::Depth32FloatStencil8)
+                .validate(Features::empty()),
+            Err(BaseRenderGraphError::MissingFeatures {
+                format: TextureFormat::Depth32FloatStencil8,
+                missing: Features::DEPTH32FLOAT_STENCIL8,
+            })
+        );
+        assert_eq!(
+            BaseRenderGraph::builder()
+                .hdr_format(TextureFormat::Rgba8UnormSrgb)
+                .validate(Features::empty()),
+            Err(BaseRenderGraphError::UnsupportedHdrFormat(
+                TextureFormat::Rgba8UnormSrgb
+            ))
+        );
+    }
+
+    #[test]
+    fn builder_sample_count_validation() {
+        // Every target, including the optional ones, is checked against the
+        // sample count.
+        assert_eq!(
+            BaseRenderGraph::builder()
+                .depth_format(TextureFormat::Depth32FloatStencil8)
+                .gbuffer(true)
+                .velocity(true)
+                .samples(SampleCount::Four)
+                .validate(Features::DEPTH32FLOAT_STENCIL8),
+            Ok(())
         );
     }
 }
@@ -14,6 +14,7 @@ pub fn add_clear_to_graph(
     depth: RenderTargetHandle,
     clear_color: Vec4,
     depth_clear: f32,
+    stencil_clear: Option<u32>,
 ) {
     let mut builder = graph.add_node("Clear");
 
@@ -35,9 +36,39 @@ pub fn add_clear_to_graph(
         depth_stencil: Some(RenderPassDepthTarget {
             target: hdr_depth_handle,
             depth_clear: Some(depth_clear),
-            stencil_clear: None,
+            stencil_clear,
         }),
     });
 
     builder.build(|_| ())
 }
+
+/// Uses the given target to create a node which does nothing but clear it to
+/// the given color.
+///
+/// This is the smallest frame which can be rendered, without any of the
+/// forward, shadow, or tonemapping routines. Import the output with
+/// [`RenderGraph::add_imported_render_target`], add only this node, and
+/// execute the graph to test windowing, surface, and readback code without
+/// needing any scene or shaders.
+pub fn add_clear_output_to_graph(graph: &mut RenderGraph<'_>, target: RenderTargetHandle, clear_color: Vec4) {
+    let mut builder = graph.add_node("Clear Output");
+
+    let output_handle = builder.add_render_target(target, NodeResourceUsage::Output);
+
+    let _rpass_handle = builder.add_renderpass(RenderPassTargets {
+        targets: vec![RenderPassTarget {
+            color: output_handle,
+            clear: wgpu::Color {
+                r: clear_color.x as f64,
+                g: clear_color.y as f64,
+                b: clear_color.z as f64,
+                a: clear_color.w as f64,
+            },
+            resolve: None,
+        }],
+        depth_stencil: None,
+    });
+
+    builder.build(|_| ())
+}
@@ -1,28 +1,96 @@
 use std::{marker::PhantomData, mem, num::NonZeroU64};
 
-use glam::{Mat4, Vec3};
-use rend3::{managers::DirectionalLightManager, types::Material, util::bind_merge::BindGroupLayoutBuilder};
+use rend3::{
+    managers::{AreaLightManager, DirectionalLightManager},
+    types::{Handedness, Material},
+    util::bind_merge::BindGroupLayoutBuilder,
+};
 use wgpu::{
-    BindGroupLayout, BindingType, BufferBindingType, Device, ShaderStages, TextureSampleType, TextureViewDimension,
+    BindGroupLayout, BindingType, BufferBindingType, Device, Features, FrontFace, SamplerBindingType, ShaderStages,
+    TextureFormat, TextureFormatFeatureFlags, TextureSampleType, TextureUsages, TextureViewDimension,
+};
+
+use crate::{
+    common::{samplers::Samplers, LtcTables},
+    uniforms::FrameUniforms,
 };
 
-use crate::{common::samplers::Samplers, uniforms::FrameUniforms};
+/// Formats of the gbuffer written next to the hdr color when
+/// [`WholeFrameInterfaces::gbuffer`] is set, in the order of their color
+/// attachments after the hdr color:
+///
+/// 1. Albedo, tinted, with its alpha.
+/// 2. World space normal in rgb.
+/// 3. Metallic, perceptual roughness, and ambient occlusion in rgb.
+pub const GBUFFER_FORMATS: [TextureFormat; 3] = [
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Rgba16Float,
+    TextureFormat::Rgba8Unorm,
+];
+
+/// Format of the velocity buffer written after the gbuffer when
+/// [`WholeFrameInterfaces::velocity`] is set.
+pub const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
 
 /// Interfaces which are used throughout the whole frame.
 ///
-/// Contains the samplers, per frame uniforms, and directional light
-/// information.
+/// Contains the samplers, per frame uniforms, directional light information,
+/// and area lights along with their lookup tables.
 pub struct WholeFrameInterfaces {
     /// Includes everything excluding the directional light information to
     /// prevent cycles when rendering to shadow maps.
     pub depth_uniform_bgl: BindGroupLayout,
     /// Includes everything.
     pub forward_uniform_bgl: BindGroupLayout,
+    /// Format of the main depth buffer, which all forward pipelines are
+    /// built against.
+    pub depth_format: TextureFormat,
+    /// Format of the hdr color buffer, which all forward pipelines and
+    /// effects rendering into the hdr buffer are built against. Change it
+    /// with [`Self::set_hdr_format`].
+    pub hdr_format: TextureFormat,
+    /// Winding of the front faces of meshes, which all forward pipelines are
+    /// built against. `None`, the default, picks the winding of counter
+    /// clockwise meshes for the renderer's handedness, see
+    /// [`Self::front_face_for`]. Set this before creating any routines.
+    pub front_face: Option<FrontFace>,
+    /// Have opaque and cutout forward pipelines also write the surface
+    /// properties into a gbuffer with the formats [`GBUFFER_FORMATS`], for
+    /// building deferred effects on top of forward rendering. Set this
+    /// before creating any routines.
+    pub gbuffer: bool,
+    /// Have opaque and cutout forward pipelines also write how far every
+    /// pixel moved since the previous frame, in uv space, into a buffer with
+    /// the format [`VELOCITY_FORMAT`]. It is computed from the current and
+    /// previous transform of each object and the current and previous
+    /// camera, so it covers both moving objects and a moving camera. The
+    /// pixel was at `uv - velocity` in the previous frame. Set this before
+    /// creating any routines.
+    pub velocity: bool,
 }
 
 impl WholeFrameInterfaces {
     pub fn new(device: &Device) -> Self {
-        profiling::scope!("ShaderInterfaces::new");
+        Self::new_with_depth_format(device, TextureFormat::Depth32Float)
+    }
+
+    /// Create the interfaces with a main depth buffer of the given format.
+    ///
+    /// Use a format with a stencil aspect, like
+    /// [`TextureFormat::Depth24PlusStencil8`], to use stencil in forward
+    /// routines. The stencil is cleared to zero every frame. Routines which
+    /// sample the depth buffer, like depth resolve, SSR, TAA, and depth
+    /// readback, only support formats without stencil.
+    ///
+    /// Depth is reversed, with the near plane at 1.0 and infinity at 0.0. With
+    /// a float format like the default [`TextureFormat::Depth32Float`] this
+    /// gives close to uniform precision at any distance. Fixed point formats
+    /// like [`TextureFormat::Depth24Plus`] don't benefit from reversing, so
+    /// distant surfaces z-fight sooner, but they can be faster on some mobile
+    /// gpus. Shadow maps always use
+    /// [`INTERNAL_SHADOW_DEPTH_FORMAT`](rend3::INTERNAL_SHADOW_DEPTH_FORMAT).
+    pub fn new_with_depth_format(device: &Device, depth_format: TextureFormat) -> Self {
+        profiling::scope!("WholeFrameInterfaces::new");
 
         let mut uniform_bglb = BindGroupLayoutBuilder::new();
 
@@ -53,33 +121,84 @@ impl WholeFrameInterfaces {
             None,
         );
 
+        AreaLightManager::add_to_bgl(&mut uniform_bglb);
+        LtcTables::add_to_bgl(&mut uniform_bglb);
+
         let forward_uniform_bgl = uniform_bglb.build(device, Some("forward uniform bgl"));
 
         Self {
             depth_uniform_bgl: shadow_uniform_bgl,
             forward_uniform_bgl,
+            depth_format,
+            hdr_format: TextureFormat::Rgba16Float,
+            front_face: None,
+            gbuffer: false,
+            velocity: false,
         }
     }
+
+    /// The front face forward pipelines use with the given handedness.
+    ///
+    /// Counter clockwise triangles are front facing in right handed
+    /// renderers. Left handed renderers mirror the scene, so there clockwise
+    /// triangles are front facing. [`Self::front_face`] overrides this.
+    pub fn front_face_for(&self, handedness: Handedness) -> FrontFace {
+        self.front_face.unwrap_or(match handedness {
+            Handedness::Left => FrontFace::Cw,
+            Handedness::Right => FrontFace::Ccw,
+        })
+    }
+
+    /// Use the given format for the hdr color buffer instead of
+    /// `Rgba16Float`. This must be done before creating any routines, as they
+    /// build their pipelines against this format.
+    ///
+    /// [`TextureFormat::Rg11b10Float`] and [`TextureFormat::Rgb10a2Unorm`]
+    /// halve the bandwidth of the hdr buffer at the cost of precision.
+    /// `Rg11b10Float` has no alpha channel, and `Rgb10a2Unorm` can't hold
+    /// values above 1, so bright parts of the image will clip.
+    ///
+    /// Panics if the format can't be used with the given device features, see
+    /// [`hdr_format_supported`].
+    pub fn set_hdr_format(&mut self, features: Features, format: TextureFormat) {
+        assert!(
+            hdr_format_supported(features, format),
+            "{format:?} can't be used as the hdr format with features {features:?}"
+        );
+        self.hdr_format = format;
+    }
 }
 
-/// The input structure that the culling shaders/functions output and drawing
-/// shaders read.
-#[repr(C, align(16))]
-#[derive(Debug, Copy, Clone)]
-pub struct PerObjectDataAbi {
-    pub model_view: Mat4,
-    pub model_view_proj: Mat4,
-    // Only read when GpuDriven. Materials are directly bound when CpuDriven.
-    pub material_idx: u32,
-    pub pad0: [u8; 12],
-    pub inv_squared_scale: Vec3,
+/// Returns true if the given format can be used as the hdr format with the
+/// given device features.
+///
+/// The format must be renderable, blendable, filterable, and support 4x
+/// multisampling. sRGB formats are rejected, as the hdr buffer is linear and
+/// tonemapping does the encoding.
+pub fn hdr_format_supported(features: Features, format: TextureFormat) -> bool {
+    let supported = format.guaranteed_format_features(features);
+    !format.is_srgb()
+        && supported
+            .allowed_usages
+            .contains(TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
+        && supported.flags.contains(
+            TextureFormatFeatureFlags::FILTERABLE
+                | TextureFormatFeatureFlags::BLENDABLE
+                | TextureFormatFeatureFlags::MULTISAMPLE_X4,
+        )
 }
 
-unsafe impl bytemuck::Pod for PerObjectDataAbi {}
-unsafe impl bytemuck::Zeroable for PerObjectDataAbi {}
+/// Returns true if the given depth format has a stencil aspect.
+pub fn has_stencil(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::Stencil8 | TextureFormat::Depth24PlusStencil8 | TextureFormat::Depth32FloatStencil8
+    )
+}
 
 /// Interface which has all per-material-archetype data: the object output
-/// buffer and the gpu material buffer.
+/// buffer, the gpu material buffer, and on the CpuDriven profile, the sampler
+/// the material textures are read with.
 pub struct PerMaterialArchetypeInterface<M> {
     pub bgl: BindGroupLayout,
     _phantom: PhantomData<M>,
@@ -123,6 +242,11 @@ impl<M: Material> PerMaterialArchetypeInterface<M> {
                 },
                 None,
             )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Sampler(SamplerBindingType::Filtering),
+                None,
+            )
             .build(device, Some("per material bgl"));
 
         Self {
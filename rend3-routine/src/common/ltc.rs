@@ -0,0 +1,134 @@
+use rend3::util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder};
+use wgpu::{
+    util::DeviceExt, BindingType, Device, Extent3d, Queue, ShaderStages, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+/// Resolution of both lookup tables.
+const LTC_LUT_SIZE: u32 = 64;
+
+static LTC_MATRIX: &[u8] = include_bytes!("../../data/ltc_matrix.bin");
+static LTC_AMPLITUDE: &[u8] = include_bytes!("../../data/ltc_amplitude.bin");
+
+/// Lookup tables for shading area lights with linearly transformed cosines,
+/// from Heitz et al. 2016, "Real-Time Polygonal-Light Shading with Linearly
+/// Transformed Cosines".
+///
+/// Both tables are indexed by perceptual roughness along u and
+/// `sqrt(1 - cos(theta_v))` along v, and are fit against the GGX BRDF with
+/// height-correlated Smith shadowing, the same BRDF forward shading uses for
+/// directional lights. They are generated by `data/ltc_fit.c`, see
+/// `data/README.md` for how to regenerate them.
+///
+/// - `matrix` holds the four varying terms of the inverse transform,
+///   normalized so its middle element is one.
+/// - `amplitude` holds the directional albedo of the BRDF and the part of it
+///   weighted by Schlick fresnel.
+pub struct LtcTables {
+    pub matrix: TextureView,
+    pub amplitude: TextureView,
+}
+
+impl LtcTables {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        profiling::scope!("LtcTables::new");
+
+        Self {
+            matrix: create_table(device, queue, "ltc matrix", TextureFormat::Rgba16Float, LTC_MATRIX),
+            amplitude: create_table(device, queue, "ltc amplitude", TextureFormat::Rg16Float, LTC_AMPLITUDE),
+        }
+    }
+
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        for _ in 0..2 {
+            bglb.append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            );
+        }
+    }
+
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_texture_view(&self.matrix);
+        bgb.append_texture_view(&self.amplitude);
+    }
+}
+
+fn create_table(device: &Device, queue: &Queue, label: &str, format: TextureFormat, data: &[u8]) -> TextureView {
+    let texture = device.create_texture_with_data(
+        queue,
+        &TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: LTC_LUT_SIZE,
+                height: LTC_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        data,
+    );
+
+    texture.create_view(&TextureViewDescriptor::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LTC_AMPLITUDE, LTC_LUT_SIZE, LTC_MATRIX};
+    use crate::common::test_util::f16_to_f32;
+
+    fn texel<const C: usize>(data: &[u8], x: u32, y: u32) -> [f32; C] {
+        let offset = ((y * LTC_LUT_SIZE + x) as usize) * C * 2;
+        let mut out = [0.0; C];
+        for (i, value) in out.iter_mut().enumerate() {
+            let o = offset + i * 2;
+            *value = f16_to_f32(u16::from_le_bytes([data[o], data[o + 1]]));
+        }
+        out
+    }
+
+    #[test]
+    fn table_sizes() {
+        let texels = (LTC_LUT_SIZE * LTC_LUT_SIZE) as usize;
+        assert_eq!(LTC_MATRIX.len(), texels * 4 * 2);
+        assert_eq!(LTC_AMPLITUDE.len(), texels * 2 * 2);
+    }
+
+    #[test]
+    fn rough_normal_incidence_is_near_identity() {
+        // Seen head on, the lobe of a fully rough surface is close to a
+        // clamped cosine, so its transform is close to the identity.
+        let [m00, m20, m02, m22] = texel::<4>(LTC_MATRIX, LTC_LUT_SIZE - 1, 0);
+        assert_eq!(m00, 1.0);
+        assert_eq!(m20, 0.0);
+        assert_eq!(m02, 0.0);
+        assert!((m22 - 1.0).abs() < 0.2, "{m22}");
+
+        // Every head on entry is isotropic.
+        for x in 0..LTC_LUT_SIZE {
+            let [m00, m20, m02, _] = texel::<4>(LTC_MATRIX, x, 0);
+            assert_eq!([m00, m20, m02], [1.0, 0.0, 0.0], "roughness texel {x}");
+        }
+    }
+
+    #[test]
+    fn albedo_is_energy_conserving() {
+        for y in 0..LTC_LUT_SIZE {
+            for x in 0..LTC_LUT_SIZE {
+                let [albedo, fresnel] = texel::<2>(LTC_AMPLITUDE, x, y);
+                assert!((0.0..=1.001).contains(&albedo), "texel {x}, {y}: {albedo}");
+                assert!((0.0..=albedo).contains(&fresnel), "texel {x}, {y}: {fresnel}");
+            }
+        }
+    }
+}
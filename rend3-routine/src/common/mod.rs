@@ -1,7 +1,11 @@
 //! Common utilities used throughout the crate.
 
 mod interfaces;
+mod ltc;
 mod samplers;
+#[cfg(test)]
+pub(crate) mod test_util;
 
 pub use interfaces::*;
+pub use ltc::*;
 pub use samplers::*;
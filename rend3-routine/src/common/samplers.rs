@@ -1,34 +1,124 @@
-use std::num::NonZeroU8;
-
-use rend3::util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder};
+use rend3::{
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer,
+};
 use wgpu::{
-    AddressMode, BindingType, CompareFunction, Device, FilterMode, Sampler, SamplerBindingType, SamplerDescriptor,
-    ShaderStages,
+    AddressMode, BindingType, CompareFunction, Device, DownlevelFlags, Features, FilterMode, Sampler,
+    SamplerBindingType, SamplerBorderColor, SamplerDescriptor, ShaderStages,
 };
 
+/// Options for the samplers used by materials.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SamplerOptions {
+    /// Maximum anisotropy of the linear sampler. One of 1, 2, 4, 8, or 16,
+    /// where 1 disables anisotropic filtering. Higher values keep textures
+    /// sharp at grazing angles at the cost of more texture fetches.
+    pub anisotropy_clamp: u16,
+    /// Color returned outside of the texture by the clamp-to-border samplers.
+    pub border_color: SamplerBorderColor,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self {
+            anisotropy_clamp: 1,
+            border_color: SamplerBorderColor::TransparentBlack,
+        }
+    }
+}
+
 /// Container holding a variety of samplers.
 pub struct Samplers {
-    /// Aniso 16 sampler
+    /// Trilinear sampler, anisotropic if enabled in the [`SamplerOptions`]
     pub linear: Sampler,
     /// Nearest neighbor sampler
     pub nearest: Sampler,
     /// Bilinear greater-or-equal comparison sampler
     pub shadow: Sampler,
+    /// Same as [`Self::linear`], but clamping to the edge instead of repeating
+    pub linear_clamp: Sampler,
+    /// Same as [`Self::nearest`], but clamping to the edge instead of repeating
+    pub nearest_clamp: Sampler,
+    /// Same as [`Self::linear`], but mirroring every other repetition
+    pub linear_mirror: Sampler,
+    /// Same as [`Self::nearest`], but mirroring every other repetition
+    pub nearest_mirror: Sampler,
+    /// Same as [`Self::linear`], but returning the
+    /// [`border_color`](SamplerOptions::border_color) outside of the texture
+    pub linear_border: Sampler,
+    /// Same as [`Self::nearest`], but returning the
+    /// [`border_color`](SamplerOptions::border_color) outside of the texture
+    pub nearest_border: Sampler,
 }
 
 impl Samplers {
-    /// Create a new set of samplers with this device.
-    pub fn new(device: &Device) -> Self {
+    /// Number of samplers materials can pick with
+    /// [`Material::sampler_index`](rend3::types::Material::sampler_index).
+    pub const MATERIAL_SAMPLER_COUNT: u8 = 8;
+
+    /// Create a new set of samplers with this renderer.
+    ///
+    /// If the anisotropy is not supported by the device, it is lowered to
+    /// the closest supported value with a warning. If the device doesn't
+    /// support [`Features::ADDRESS_MODE_CLAMP_TO_BORDER`], the border
+    /// samplers clamp to the edge instead.
+    pub fn new(renderer: &Renderer, options: SamplerOptions) -> Self {
         profiling::scope!("Samplers::new");
 
-        let linear = create_sampler(device, FilterMode::Linear, None);
-        let nearest = create_sampler(device, FilterMode::Nearest, None);
-        let shadow = create_sampler(device, FilterMode::Linear, Some(CompareFunction::GreaterEqual));
+        let anisotropy_clamp = validate_anisotropy(renderer, options.anisotropy_clamp);
+        let border = match renderer.features.contains(Features::ADDRESS_MODE_CLAMP_TO_BORDER) {
+            true => AddressMode::ClampToBorder,
+            false => AddressMode::ClampToEdge,
+        };
+
+        let device = &renderer.device;
+        let linear = |address_mode| {
+            create_sampler(
+                device,
+                FilterMode::Linear,
+                address_mode,
+                None,
+                anisotropy_clamp,
+                options.border_color,
+            )
+        };
+        let nearest =
+            |address_mode| create_sampler(device, FilterMode::Nearest, address_mode, None, 1, options.border_color);
 
         Self {
-            linear,
-            nearest,
-            shadow,
+            linear: linear(AddressMode::Repeat),
+            nearest: nearest(AddressMode::Repeat),
+            shadow: create_sampler(
+                device,
+                FilterMode::Linear,
+                AddressMode::Repeat,
+                Some(CompareFunction::GreaterEqual),
+                1,
+                options.border_color,
+            ),
+            linear_clamp: linear(AddressMode::ClampToEdge),
+            nearest_clamp: nearest(AddressMode::ClampToEdge),
+            linear_mirror: linear(AddressMode::MirrorRepeat),
+            nearest_mirror: nearest(AddressMode::MirrorRepeat),
+            linear_border: linear(border),
+            nearest_border: nearest(border),
+        }
+    }
+
+    /// The sampler a material reads its textures with on the CpuDriven
+    /// profile. In order, these are the repeating, clamp-to-edge, mirroring,
+    /// and clamp-to-border samplers, each linear then nearest. Out of range
+    /// indices use [`Self::linear`].
+    pub fn material_sampler(&self, index: u8) -> &Sampler {
+        match index {
+            1 => &self.nearest,
+            2 => &self.linear_clamp,
+            3 => &self.nearest_clamp,
+            4 => &self.linear_mirror,
+            5 => &self.nearest_mirror,
+            6 => &self.linear_border,
+            7 => &self.nearest_border,
+            _ => &self.linear,
         }
     }
 
@@ -49,29 +139,71 @@ impl Samplers {
             BindingType::Sampler(SamplerBindingType::Comparison),
             None,
         );
+        // Clamp, mirror, and border variants of the linear and nearest samplers.
+        for _ in 0..3 {
+            bglb.append(
+                ShaderStages::FRAGMENT,
+                BindingType::Sampler(SamplerBindingType::Filtering),
+                None,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Sampler(SamplerBindingType::NonFiltering),
+                None,
+            );
+        }
     }
 
     /// Add the samplers to the given bind group builder.
     pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
         bgb.append_sampler(&self.linear)
             .append_sampler(&self.nearest)
-            .append_sampler(&self.shadow);
+            .append_sampler(&self.shadow)
+            .append_sampler(&self.linear_clamp)
+            .append_sampler(&self.nearest_clamp)
+            .append_sampler(&self.linear_mirror)
+            .append_sampler(&self.nearest_mirror)
+            .append_sampler(&self.linear_border)
+            .append_sampler(&self.nearest_border);
+    }
+}
+
+fn validate_anisotropy(renderer: &Renderer, requested: u16) -> u16 {
+    if !renderer.downlevel.flags.contains(DownlevelFlags::ANISOTROPIC_FILTERING) {
+        if requested > 1 {
+            log::warn!("Anisotropic filtering is not supported by this device, disabling it");
+        }
+        return 1;
+    }
+
+    // Largest power of two at most the requested value, within 1..=16.
+    let supported = 1 << (15 - requested.clamp(1, 16).leading_zeros());
+    if supported != requested {
+        log::warn!("Anisotropy clamp {requested} is not one of 1, 2, 4, 8, or 16, using {supported}");
     }
+    supported
 }
 
-fn create_sampler(device: &Device, filter: FilterMode, compare: Option<CompareFunction>) -> Sampler {
+fn create_sampler(
+    device: &Device,
+    filter: FilterMode,
+    address_mode: AddressMode,
+    compare: Option<CompareFunction>,
+    anisotropy_clamp: u16,
+    border_color: SamplerBorderColor,
+) -> Sampler {
     device.create_sampler(&SamplerDescriptor {
         label: Some("linear"),
-        address_mode_u: AddressMode::Repeat,
-        address_mode_v: AddressMode::Repeat,
-        address_mode_w: AddressMode::Repeat,
+        address_mode_u: address_mode,
+        address_mode_v: address_mode,
+        address_mode_w: address_mode,
         mag_filter: filter,
         min_filter: filter,
         mipmap_filter: filter,
         lod_min_clamp: 0.0,
         lod_max_clamp: 100.0,
         compare,
-        anisotropy_clamp: 1,
-        border_color: None,
+        anisotropy_clamp,
+        border_color: (address_mode == AddressMode::ClampToBorder).then_some(border_color),
     })
 }
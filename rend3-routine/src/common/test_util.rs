@@ -0,0 +1,79 @@
+//! Scene fixtures shared by the tests rendering with [`HeadlessRenderer`],
+//! and helpers for reading back their results.
+
+use std::sync::Arc;
+
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4};
+use rend3::{
+    types::{Handedness, MaterialHandle, MeshBuilder, Object, ObjectClip, ObjectHandle, ObjectMeshKind},
+    Renderer, RendererProfile,
+};
+
+use crate::{
+    headless::HeadlessRenderer,
+    pbr::{AlbedoComponent, PbrMaterial},
+};
+
+/// Decode the bits of a half float, as read back from `Rgba16Float` textures
+/// or stored in the LTC tables.
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((bits >> 10) & 0x1f) as i32;
+    let mant = (bits & 0x3ff) as f32;
+    match exp {
+        0 => sign * mant * 2.0f32.powi(-24),
+        31 => sign * f32::INFINITY,
+        _ => sign * (1.0 + mant / 1024.0) * 2.0f32.powi(exp - 15),
+    }
+}
+
+/// Create a left handed headless renderer of the given resolution. `None` if
+/// there is no gpu, or it doesn't support the requested profile, in which
+/// case the test should be skipped.
+pub fn headless_or_skip(profile: Option<RendererProfile>, resolution: UVec2) -> Option<HeadlessRenderer> {
+    let iad = pollster::block_on(rend3::create_iad(None, None, profile, None)).ok()?;
+    let headless = HeadlessRenderer::new(iad, Handedness::Left, resolution).ok()?;
+    if profile.map_or(false, |profile| headless.renderer.profile != profile) {
+        return None;
+    }
+    Some(headless)
+}
+
+/// A quad at z = 2 covering `min..max`, clockwise as seen by the default
+/// camera, looking along +Z.
+pub fn quad_mesh(min: Vec2, max: Vec2) -> MeshBuilder {
+    let positions = vec![
+        Vec3::new(min.x, max.y, 2.0),
+        Vec3::new(max.x, max.y, 2.0),
+        Vec3::new(max.x, min.y, 2.0),
+        Vec3::new(min.x, min.y, 2.0),
+    ];
+    MeshBuilder::new(positions, Handedness::Left).with_indices(vec![0, 1, 2, 2, 3, 0])
+}
+
+/// An object in every layer, which neither casts nor receives shadows.
+pub fn object(mesh_kind: ObjectMeshKind, material: MaterialHandle) -> Object {
+    Object {
+        mesh_kind,
+        material,
+        transform: Mat4::IDENTITY,
+        overlay: false,
+        layers: u32::MAX,
+        cast_shadows: false,
+        receive_shadows: false,
+        tint: Vec4::ONE,
+        clip: ObjectClip::default(),
+    }
+}
+
+/// Add an unlit quad of the given color, covering `x0..x1` horizontally
+/// and the middle of the screen vertically.
+pub fn add_quad(renderer: &Arc<Renderer>, x0: f32, x1: f32, color: Vec4) -> ObjectHandle {
+    let mesh = quad_mesh(Vec2::new(x0, -1.0), Vec2::new(x1, 1.0)).build().unwrap();
+    let material = renderer.add_material(PbrMaterial {
+        albedo: AlbedoComponent::Value(color),
+        unlit: true,
+        ..PbrMaterial::default()
+    });
+    renderer.add_object(object(ObjectMeshKind::Static(renderer.add_mesh(mesh)), material))
+}
@@ -2,21 +2,25 @@ use std::cmp::Ordering;
 
 use encase::ShaderType;
 use ordered_float::OrderedFloat;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use rend3::{
-    managers::{CameraManager, MaterialManager, ObjectManager, TextureBindGroupIndex},
-    types::{Material, SortingOrder, SortingReason},
+    managers::{CameraManager, InternalObject, MaterialManager, ObjectManager, TextureBindGroupIndex},
+    types::{Material, RawObjectHandle, SortingOrder, SortingReason},
     util::math::round_up,
+    RendererProfile,
 };
 
-use super::{BATCH_SIZE, WORKGROUP_SIZE};
+use super::{CameraStats, BATCH_SIZE, OVERLAY_MATERIAL_KEY_BIT, WORKGROUP_SIZE};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ShaderBatchDatas {
     pub(super) regions: Vec<JobSubRegion>,
     pub(super) jobs: Vec<ShaderBatchData>,
+    pub(super) stats: CameraStats,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(super) struct JobSubRegion {
     pub job_index: u32,
     pub base_invocation: u32,
@@ -28,8 +32,30 @@ pub(super) struct JobSubRegion {
 pub(super) struct ShaderJobKey {
     pub material_key: u64,
     pub bind_group_index: TextureBindGroupIndex,
+    /// Always zero on the GpuDriven profile, where the shader picks the
+    /// sampler.
+    pub sampler_index: u8,
 }
 
+impl ShaderJobKey {
+    /// Everything which needs to be rebound between draws of the same
+    /// material key.
+    fn bindings(&self) -> (TextureBindGroupIndex, u8) {
+        (self.bind_group_index, self.sampler_index)
+    }
+}
+
+/// Order in which culled objects are drawn.
+///
+/// Objects are grouped by material key first, which selects the pipeline, then
+/// by texture bind group and sampler, so every run of objects with the same
+/// key becomes a single draw call and state only changes between runs. Meshes
+/// don't need to be part of the key, as all of them live in the same vertex
+/// and index buffers.
+///
+/// Materials which require their sorting order, like blended ones, are sorted
+/// by distance before the bind group instead, so back to front order is kept
+/// at the cost of rebinding.
 #[derive(Debug, Clone, Copy, Eq)]
 pub(super) struct ShaderJobSortingKey {
     pub job_key: ShaderJobKey,
@@ -66,9 +92,9 @@ impl Ord for ShaderJobSortingKey {
                 Ordering::Equal => {}
                 ord => return ord,
             }
-            self.job_key.bind_group_index.cmp(&other.job_key.bind_group_index)
+            self.job_key.bindings().cmp(&other.job_key.bindings())
         } else {
-            match self.job_key.bind_group_index.cmp(&other.job_key.bind_group_index) {
+            match self.job_key.bindings().cmp(&other.job_key.bindings()) {
                 Ordering::Equal => {}
                 ord => return ord,
             }
@@ -77,7 +103,7 @@ impl Ord for ShaderJobSortingKey {
     }
 }
 
-#[derive(Debug, ShaderType)]
+#[derive(Debug, PartialEq, ShaderType)]
 pub struct ShaderBatchData {
     #[align(256)]
     pub(super) ranges: [ShaderObjectRange; BATCH_SIZE],
@@ -86,17 +112,19 @@ pub struct ShaderBatchData {
     pub(super) base_output_invocation: u32,
 }
 
-#[derive(Debug, Copy, Clone, Default, ShaderType)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, ShaderType)]
 pub(super) struct ShaderObjectRange {
     pub invocation_start: u32,
     pub invocation_end: u32,
     pub object_id: u32,
 }
 
-pub(super) fn batch_objects<M: Material>(
-    material_manager: &MaterialManager,
-    object_manager: &ObjectManager,
-    camera_manager: &CameraManager,
+pub(super) fn batch_objects<'a, M: Material>(
+    material_manager: &'a MaterialManager,
+    object_manager: &'a ObjectManager,
+    camera_manager: &'a CameraManager,
+    shadow_pass: bool,
+    layer_mask: u32,
     max_dispatch: u32,
 ) -> ShaderBatchDatas {
     profiling::scope!("Batch Objects");
@@ -104,6 +132,7 @@ pub(super) fn batch_objects<M: Material>(
     let mut jobs = ShaderBatchDatas {
         jobs: Vec::new(),
         regions: Vec::new(),
+        stats: CameraStats::default(),
     };
 
     let objects = match object_manager.enumerated_objects::<M>() {
@@ -113,115 +142,270 @@ pub(super) fn batch_objects<M: Material>(
 
     let material_archetype = material_manager.archetype_view::<M>();
 
-    let mut sorted_objects = Vec::with_capacity(objects.len());
-    {
-        profiling::scope!("Sort Key Creation");
-        for (handle, object) in objects {
-            // Frustum culling
-            if !camera_manager
-                .world_frustum()
-                .contains_sphere(object.inner.bounding_sphere)
-            {
-                continue;
-            }
+    let sort_key = |(handle, object): (RawObjectHandle, &'a InternalObject<M>)| {
+        // Frustum culling
+        if !camera_manager
+            .world_frustum()
+            .contains_sphere(object.inner.bounding_sphere)
+        {
+            return None;
+        }
 
-            let material = material_archetype.material(*object.material_handle);
-            let bind_group_index = material
-                .bind_group_index
-                .map_gpu(|_| TextureBindGroupIndex::DUMMY)
-                .into_common();
+        let material = material_archetype.material(*object.material_handle);
+        let sampler_index = match material.bind_group_index.profile() {
+            RendererProfile::CpuDriven => material.inner.sampler_index(),
+            RendererProfile::GpuDriven => 0,
+        };
+        let bind_group_index = material
+            .bind_group_index
+            .map_gpu(|_| TextureBindGroupIndex::DUMMY)
+            .into_common();
 
-            let material_key = material.inner.key();
-            let sorting = material.inner.sorting();
+        let mut material_key = material.inner.key();
+        if object.overlay {
+            material_key |= OVERLAY_MATERIAL_KEY_BIT;
+        }
+        let sorting = material.inner.sorting();
 
-            let mut distance_sq = camera_manager.location().distance_squared(object.location.into());
-            if sorting.order == SortingOrder::BackToFront {
-                distance_sq = -distance_sq;
-            }
-            sorted_objects.push((
-                ShaderJobSortingKey {
-                    job_key: ShaderJobKey {
-                        material_key,
-                        bind_group_index,
-                    },
-                    distance: OrderedFloat(distance_sq),
-                    sorting_reason: sorting.reason,
-                },
-                handle,
-                object,
-            ))
+        let mut distance_sq = camera_manager.location().distance_squared(object.location.into());
+        if sorting.order == SortingOrder::BackToFront {
+            distance_sq = -distance_sq;
         }
+        Some((
+            ShaderJobSortingKey {
+                job_key: ShaderJobKey {
+                    material_key,
+                    bind_group_index,
+                    sampler_index,
+                },
+                distance: OrderedFloat(distance_sq),
+                sorting_reason: sorting.reason,
+            },
+            handle,
+            object,
+        ))
+    };
+
+    // Hidden layers and objects not casting shadows aren't counted as culled.
+    let visible_objects =
+        objects.filter(|(_, object)| object.layers & layer_mask != 0 && (!shadow_pass || object.cast_shadows));
+
+    #[cfg(feature = "rayon")]
+    let (visible_count, sorted_objects) = sort_objects_parallel(visible_objects, sort_key);
+    #[cfg(not(feature = "rayon"))]
+    let (visible_count, sorted_objects) = sort_objects_serial(visible_objects, sort_key);
+    jobs.stats.objects_culled += (visible_count - sorted_objects.len()) as u32;
+
+    create_batches(
+        &mut jobs,
+        sorted_objects
+            .into_iter()
+            .map(|(key, handle, object)| (key.job_key, handle.idx as u32, object.inner.index_count)),
+        max_dispatch,
+    );
+
+    jobs
+}
+
+/// A sort key, the handle of the object it belongs to, and any data the
+/// batches are built from.
+type SortedObject<T> = (ShaderJobSortingKey, RawObjectHandle, T);
+
+/// Objects with equal keys are ordered by handle, so that the draw order, and
+/// with it blending and overdraw, is the same every frame.
+fn compare_sorted<T>((k1, h1, _): &SortedObject<T>, (k2, h2, _): &SortedObject<T>) -> Ordering {
+    k1.cmp(k2).then(h1.idx.cmp(&h2.idx))
+}
+
+/// Create the sort keys of `objects` on the rayon thread pool and sort them.
+/// Also returns the amount of objects, including the ones without a key.
+///
+/// Either way the keys end up in the order of the objects, so the result is the
+/// same as [`sort_objects_serial`].
+#[cfg(feature = "rayon")]
+fn sort_objects_parallel<I, T, F>(objects: impl Iterator<Item = I>, sort_key: F) -> (usize, Vec<SortedObject<T>>)
+where
+    I: Copy + Send + Sync,
+    T: Send,
+    F: Fn(I) -> Option<SortedObject<T>> + Send + Sync,
+{
+    let (count, mut sorted_objects) = {
+        profiling::scope!("Sort Key Creation");
+        let objects: Vec<_> = objects.collect();
+        let sorted_objects: Vec<_> = objects.par_iter().copied().filter_map(sort_key).collect();
+        (objects.len(), sorted_objects)
+    };
+    {
+        profiling::scope!("Sorting");
+        sorted_objects.par_sort_unstable_by(compare_sorted);
     }
+    (count, sorted_objects)
+}
 
+/// Create the sort keys of `objects` and sort them. Also returns the amount
+/// of objects, including the ones without a key.
+#[cfg(any(test, not(feature = "rayon")))]
+fn sort_objects_serial<I, T, F>(objects: impl Iterator<Item = I>, sort_key: F) -> (usize, Vec<SortedObject<T>>)
+where
+    F: Fn(I) -> Option<SortedObject<T>>,
+{
+    let (count, mut sorted_objects) = {
+        profiling::scope!("Sort Key Creation");
+        let mut count = 0;
+        let sorted_objects: Vec<_> = objects.inspect(|_| count += 1).filter_map(sort_key).collect();
+        (count, sorted_objects)
+    };
     {
         profiling::scope!("Sorting");
-        sorted_objects.sort_unstable_by_key(|(k, _, _)| *k);
+        sorted_objects.sort_unstable_by(compare_sorted);
     }
+    (count, sorted_objects)
+}
 
-    if !sorted_objects.is_empty() {
-        profiling::scope!("Batch Data Creation");
-        let mut current_base_invocation = 0_u32;
-        let mut current_region_invocation = 0_u32;
-        let mut current_invocation = 0_u32;
-        let mut current_object_index = 0_u32;
-        let mut current_ranges = [ShaderObjectRange::default(); BATCH_SIZE];
-        let mut current_key = sorted_objects.first().unwrap().0.job_key;
-
-        for (ShaderJobSortingKey { job_key: key, .. }, handle, object) in sorted_objects {
-            let invocation_count = object.inner.index_count / 3;
-
-            let key_difference = key != current_key;
-            let object_limit = current_object_index == 256;
-            let dispatch_limit = (current_invocation + invocation_count) >= max_dispatch * WORKGROUP_SIZE;
-
-            if key_difference || object_limit || dispatch_limit {
-                jobs.regions.push(JobSubRegion {
-                    job_index: jobs.jobs.len() as u32,
-                    base_invocation: current_region_invocation,
-                    invocation_count: current_invocation - current_region_invocation,
-                    key: current_key,
-                });
-                current_key = key;
-                current_region_invocation = current_invocation;
-            }
-            if object_limit || dispatch_limit {
-                jobs.jobs.push(ShaderBatchData {
-                    ranges: current_ranges,
-                    total_objects: current_object_index,
-                    total_invocations: current_invocation,
-                    base_output_invocation: current_base_invocation,
-                });
-
-                current_base_invocation += current_invocation;
-                current_invocation = 0;
-                current_region_invocation = 0;
-                current_object_index = 0;
-            }
+/// Split sorted objects, given by their job key, object id and index count,
+/// into batches and the regions of them drawn with the same key.
+fn create_batches(
+    jobs: &mut ShaderBatchDatas,
+    objects: impl IntoIterator<Item = (ShaderJobKey, u32, u32)>,
+    max_dispatch: u32,
+) {
+    profiling::scope!("Batch Data Creation");
+
+    let mut objects = objects.into_iter().peekable();
+    let Some(&(first_key, _, _)) = objects.peek() else {
+        return;
+    };
+
+    let mut current_key = first_key;
+    let mut current_base_invocation = 0_u32;
+    let mut current_region_invocation = 0_u32;
+    let mut current_invocation = 0_u32;
+    let mut current_object_index = 0_u32;
+    let mut current_ranges = [ShaderObjectRange::default(); BATCH_SIZE];
+    for (key, object_id, index_count) in objects {
+        let invocation_count = index_count / 3;
 
-            let range = ShaderObjectRange {
-                invocation_start: current_invocation,
-                invocation_end: current_invocation + invocation_count,
-                object_id: handle.idx as u32,
-            };
+        let pass_stats = jobs.stats.pass_mut(key.material_key);
+        pass_stats.objects += 1;
+        pass_stats.triangles += invocation_count as u64;
 
-            current_ranges[current_object_index as usize] = range;
-            current_object_index += 1;
-            current_invocation += round_up(invocation_count, WORKGROUP_SIZE);
+        let key_difference = key != current_key;
+        let object_limit = current_object_index == 256;
+        let dispatch_limit = (current_invocation + invocation_count) >= max_dispatch * WORKGROUP_SIZE;
+
+        if key_difference || object_limit || dispatch_limit {
+            jobs.regions.push(JobSubRegion {
+                job_index: jobs.jobs.len() as u32,
+                base_invocation: current_region_invocation,
+                invocation_count: current_invocation - current_region_invocation,
+                key: current_key,
+            });
+            current_key = key;
+            current_region_invocation = current_invocation;
+        }
+        if object_limit || dispatch_limit {
+            jobs.jobs.push(ShaderBatchData {
+                ranges: current_ranges,
+                total_objects: current_object_index,
+                total_invocations: current_invocation,
+                base_output_invocation: current_base_invocation,
+            });
+
+            current_base_invocation += current_invocation;
+            current_invocation = 0;
+            current_region_invocation = 0;
+            current_object_index = 0;
         }
 
-        jobs.regions.push(JobSubRegion {
-            job_index: jobs.jobs.len() as u32,
-            base_invocation: current_region_invocation,
-            invocation_count: current_invocation - current_region_invocation,
-            key: current_key,
-        });
-        jobs.jobs.push(ShaderBatchData {
-            ranges: current_ranges,
-            total_objects: current_object_index,
-            total_invocations: current_invocation,
-            base_output_invocation: current_base_invocation,
-        });
+        let range = ShaderObjectRange {
+            invocation_start: current_invocation,
+            invocation_end: current_invocation + invocation_count,
+            object_id,
+        };
+
+        current_ranges[current_object_index as usize] = range;
+        current_object_index += 1;
+        current_invocation += round_up(invocation_count, WORKGROUP_SIZE);
     }
 
-    jobs
+    jobs.regions.push(JobSubRegion {
+        job_index: jobs.jobs.len() as u32,
+        base_invocation: current_region_invocation,
+        invocation_count: current_invocation - current_region_invocation,
+        key: current_key,
+    });
+    jobs.jobs.push(ShaderBatchData {
+        ranges: current_ranges,
+        total_objects: current_object_index,
+        total_invocations: current_invocation,
+        base_output_invocation: current_base_invocation,
+    });
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod test {
+    use ordered_float::OrderedFloat;
+    use rend3::{
+        managers::TextureBindGroupIndex,
+        types::{RawObjectHandle, SortingReason},
+    };
+
+    use super::{
+        create_batches, sort_objects_parallel, sort_objects_serial, ShaderBatchDatas, ShaderJobKey,
+        ShaderJobSortingKey, SortedObject,
+    };
+    use crate::culling::CameraStats;
+
+    /// Objects as (handle index, index count), with keys sharing material keys,
+    /// samplers and distances, so the sort has to break many ties.
+    fn sort_key(&(idx, index_count): &(usize, u32)) -> Option<SortedObject<u32>> {
+        // Every seventh object is culled.
+        if idx % 7 == 0 {
+            return None;
+        }
+        let material_key = (idx % 3) as u64;
+        Some((
+            ShaderJobSortingKey {
+                job_key: ShaderJobKey {
+                    material_key,
+                    bind_group_index: TextureBindGroupIndex::DUMMY,
+                    sampler_index: (idx % 4) as u8,
+                },
+                distance: OrderedFloat((idx * 31 % 17) as f32),
+                sorting_reason: match material_key {
+                    2 => SortingReason::Requirement,
+                    _ => SortingReason::Optimization,
+                },
+            },
+            RawObjectHandle::new(idx),
+            index_count,
+        ))
+    }
+
+    fn batches(sorted: Vec<SortedObject<u32>>) -> ShaderBatchDatas {
+        let mut jobs = ShaderBatchDatas {
+            regions: Vec::new(),
+            jobs: Vec::new(),
+            stats: CameraStats::default(),
+        };
+        let objects = sorted
+            .into_iter()
+            .map(|(key, handle, index_count)| (key.job_key, handle.idx as u32, index_count));
+        create_batches(&mut jobs, objects, 4);
+        jobs
+    }
+
+    #[test]
+    fn rayon_batches_match_serial() {
+        let objects: Vec<_> = (0..2000).map(|idx| (idx, (idx as u32 * 13 % 50 + 1) * 3)).collect();
+
+        let (serial_count, serial) = sort_objects_serial(objects.iter(), sort_key);
+        let (parallel_count, parallel) = sort_objects_parallel(objects.iter(), sort_key);
+        assert_eq!(serial_count, parallel_count);
+
+        let serial = batches(serial);
+        // Splitting by sampler, key, and the dispatch limit gives many batches.
+        assert!(serial.jobs.len() > 1 && serial.regions.len() > 1);
+        assert_eq!(serial, batches(parallel));
+    }
 }
@@ -4,16 +4,24 @@ use std::{
     collections::{hash_map::Entry, HashMap},
     num::NonZeroU64,
     ops::Range,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 
 use encase::{ShaderSize, ShaderType, StorageBuffer};
+#[cfg(feature = "debug-readback")]
+use glam::{Mat4, Vec4};
+#[cfg(feature = "debug-readback")]
+use rend3::util::readback::read_buffer;
 use rend3::{
     format_sso,
     graph::{DataHandle, NodeExecutionContext, NodeResourceUsage, RenderGraph},
     managers::{ShaderObject, TextureBindGroupIndex},
     types::{GraphDataHandle, Material},
     util::{
+        buffer::BufferGrowth,
         math::{round_up, round_up_div},
         typedefs::FastHashMap,
     },
@@ -27,7 +35,7 @@ use wgpu::{
 
 use crate::culling::{
     batching::{batch_objects, JobSubRegion, ShaderBatchData, ShaderBatchDatas},
-    WORKGROUP_SIZE,
+    BufferStats, CameraStats, FrameStats, WORKGROUP_SIZE,
 };
 
 // 16 MB of indices
@@ -46,33 +54,58 @@ pub struct DrawCallSet {
 #[derive(Debug)]
 pub struct DrawCall {
     pub bind_group_index: TextureBindGroupIndex,
+    /// Sampler of the materials in this call on the CpuDriven profile, see
+    /// [`Samplers::material_sampler`](crate::common::Samplers::material_sampler).
+    pub sampler_index: u8,
     pub index_range: Range<u32>,
     pub batch_index: u32,
 }
 
-#[derive(Default)]
 struct CullingBufferMap {
     inner: FastHashMap<Option<usize>, CullingBuffers<Arc<Buffer>>>,
+    index_growth: BufferGrowth,
+    /// Bytes of each buffer written by the last culling pass of a camera.
+    #[cfg(feature = "debug-readback")]
+    used: FastHashMap<Option<usize>, CullingBuffers<u64>>,
+}
+impl Default for CullingBufferMap {
+    fn default() -> Self {
+        Self {
+            inner: FastHashMap::default(),
+            index_growth: BufferGrowth::Fixed(OUTPUT_BUFFER_ROUNDING_SIZE),
+            #[cfg(feature = "debug-readback")]
+            used: FastHashMap::default(),
+        }
+    }
 }
 impl CullingBufferMap {
     fn get_buffers(
         &mut self,
         device: &Device,
         camera: Option<usize>,
-        mut sizes: CullingBuffers<u64>,
+        needed: CullingBuffers<u64>,
     ) -> &CullingBuffers<Arc<Buffer>> {
-        sizes.object_reference = round_up(sizes.object_reference.max(1), BATCH_DATA_ROUNDING_SIZE);
-        sizes.index = round_up(sizes.index.max(1), OUTPUT_BUFFER_ROUNDING_SIZE);
+        let sizes = CullingBuffers {
+            object_reference: round_up(needed.object_reference.max(1), BATCH_DATA_ROUNDING_SIZE),
+            index: self.index_growth.grow(needed.index),
+        };
+
+        #[cfg(feature = "debug-readback")]
+        self.used.insert(camera, needed);
 
         match self.inner.entry(camera) {
             Entry::Occupied(b) => {
                 let b = b.into_mut();
 
-                let current_size = CullingBuffers {
-                    object_reference: b.object_reference.size(),
-                    index: b.index.size(),
-                };
-                if current_size != sizes {
+                // Grow when too small, and only shrink when way too large so sizes hovering around a boundary
+                // don't reallocate every frame.
+                let resize = |current: u64, needed: u64, size: u64| current < needed || size * 4 <= current;
+                if resize(
+                    b.object_reference.size(),
+                    needed.object_reference,
+                    sizes.object_reference,
+                ) || resize(b.index.size(), needed.index, sizes.index)
+                {
                     *b = CullingBuffers::new(device, sizes);
                 }
                 b
@@ -90,28 +123,103 @@ pub struct CullingBuffers<T> {
 
 impl CullingBuffers<Arc<Buffer>> {
     pub fn new(device: &Device, sizes: CullingBuffers<u64>) -> Self {
+        // Only needed to read the buffers back, so keep it out of release builds.
+        let debug_usage = if cfg!(feature = "debug-readback") {
+            BufferUsages::COPY_SRC
+        } else {
+            BufferUsages::empty()
+        };
+
         CullingBuffers {
             object_reference: Arc::new(device.create_buffer(&BufferDescriptor {
                 label: None,
                 size: sizes.object_reference,
-                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | debug_usage,
                 mapped_at_creation: false,
             })),
             index: Arc::new(device.create_buffer(&BufferDescriptor {
                 label: None,
                 size: sizes.index,
-                usage: BufferUsages::STORAGE | BufferUsages::INDEX,
+                usage: BufferUsages::STORAGE | BufferUsages::INDEX | debug_usage,
                 mapped_at_creation: false,
             })),
         }
     }
 }
 
+/// An object read back with [`GpuCuller::read_culled_objects`].
+#[cfg(feature = "debug-readback")]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CulledObject {
+    /// Index of the object in the object buffer of its material.
+    pub object_id: u32,
+    /// Model -> World matrix.
+    pub transform: Mat4,
+    /// World space bounding sphere, with the radius in `w`.
+    pub bounding_sphere: Vec4,
+    pub tint: Vec4,
+    /// World space clip planes.
+    pub clip_planes: [Vec4; 4],
+    pub first_index: u32,
+    pub index_count: u32,
+    pub material_index: u32,
+    /// Combination of the `OBJECT_FLAG_*` constants.
+    pub flags: u32,
+}
+
+/// Byte offsets of the fields of [`ShaderObject`](rend3::managers::ShaderObject)
+/// read back into a [`CulledObject`]. The layout test of the shaders checks
+/// them against the shader's `Object`.
+#[cfg(any(test, feature = "debug-readback"))]
+pub(crate) mod object_offsets {
+    pub const TRANSFORM: usize = 0;
+    pub const BOUNDING_SPHERE: usize = 64;
+    pub const TINT: usize = 80;
+    pub const CLIP_PLANES: usize = 96;
+    pub const FIRST_INDEX: usize = 168;
+    pub const INDEX_COUNT: usize = 172;
+    pub const MATERIAL_INDEX: usize = 176;
+    pub const FLAGS: usize = 180;
+}
+
+#[cfg(feature = "debug-readback")]
+impl CulledObject {
+    fn from_bytes(object_id: u32, bytes: &[u8]) -> Self {
+        use object_offsets::*;
+
+        let vec4 = |offset: usize| Vec4::from_array(bytemuck::pod_read_unaligned(&bytes[offset..offset + 16]));
+        let scalar = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        Self {
+            object_id,
+            transform: Mat4::from_cols(
+                vec4(TRANSFORM),
+                vec4(TRANSFORM + 16),
+                vec4(TRANSFORM + 32),
+                vec4(TRANSFORM + 48),
+            ),
+            bounding_sphere: vec4(BOUNDING_SPHERE),
+            tint: vec4(TINT),
+            clip_planes: [
+                vec4(CLIP_PLANES),
+                vec4(CLIP_PLANES + 16),
+                vec4(CLIP_PLANES + 32),
+                vec4(CLIP_PLANES + 48),
+            ],
+            first_index: scalar(FIRST_INDEX),
+            index_count: scalar(INDEX_COUNT),
+            material_index: scalar(MATERIAL_INDEX),
+            flags: scalar(FLAGS),
+        }
+    }
+}
+
 pub struct GpuCuller {
     bgl: BindGroupLayout,
     pipeline: ComputePipeline,
     type_id: TypeId,
     culling_buffer_map_handle: GraphDataHandle<CullingBufferMap>,
+    stats_handle: GraphDataHandle<Option<FrameStats>>,
+    layer_mask: AtomicU32,
 }
 
 impl GpuCuller {
@@ -194,12 +302,123 @@ impl GpuCuller {
         });
 
         let culling_buffer_map_handle = renderer.add_graph_data(CullingBufferMap::default());
+        let stats_handle = renderer.add_graph_data(None);
 
         Self {
             bgl,
             pipeline,
             type_id: TypeId::of::<M>(),
             culling_buffer_map_handle,
+            stats_handle,
+            layer_mask: AtomicU32::new(u32::MAX),
+        }
+    }
+
+    /// Only render objects which are in at least one of the given
+    /// [`layers`](rend3::types::Object::layers). Defaults to all layers.
+    pub fn set_layer_mask(&self, layer_mask: u32) {
+        self.layer_mask.store(layer_mask, Ordering::Relaxed);
+    }
+
+    /// The current layer mask.
+    pub fn layer_mask(&self) -> u32 {
+        self.layer_mask.load(Ordering::Relaxed)
+    }
+
+    /// How the culled index buffers grow when more triangles survive culling
+    /// than fit. Defaults to growing in 16MB steps. Buffers shrink again once
+    /// they are four times larger than needed.
+    ///
+    /// The current sizes are part of the [`FrameStats`].
+    pub fn set_buffer_growth(&self, renderer: &Renderer, growth: BufferGrowth) {
+        // Graph storage can't be accessed concurrently, so keep out the graph.
+        let data_core = renderer.data_core.write();
+        data_core
+            .graph_storage
+            .get_mut(&self.culling_buffer_map_handle)
+            .index_growth = growth;
+    }
+
+    /// Enable or disable collection of [`FrameStats`]. When disabled, which
+    /// is the default, no stats are stored.
+    pub fn set_stats_enabled(&self, renderer: &Renderer, enabled: bool) {
+        // Graph storage can't be accessed concurrently, so keep out the graph while it records stats.
+        let data_core = renderer.data_core.write();
+        let mut stats = data_core.graph_storage.get_mut(&self.stats_handle);
+        match (enabled, stats.is_some()) {
+            (true, false) => *stats = Some(FrameStats::default()),
+            (false, _) => *stats = None,
+            (true, true) => {}
+        }
+    }
+
+    /// Stats from the most recently executed graph, if stats are enabled.
+    pub fn stats(&self, renderer: &Renderer) -> Option<FrameStats> {
+        let data_core = renderer.data_core.write();
+        let stats = data_core.graph_storage.get_mut(&self.stats_handle);
+        stats.clone()
+    }
+
+    /// Read back every object the last culling pass of the given camera
+    /// submitted for drawing, as the gpu sees them. `None` is the main camera,
+    /// `Some(i)` the i-th shadow camera.
+    ///
+    /// Objects come in the order of the culling batches, so an object split
+    /// across batches shows up more than once. Returns nothing if the camera
+    /// had no objects to draw.
+    ///
+    /// This waits for the gpu, so it is only meant for debugging.
+    #[cfg(feature = "debug-readback")]
+    pub fn read_culled_objects<M: Material>(&self, renderer: &Renderer, camera: Option<usize>) -> Vec<CulledObject> {
+        profiling::scope!("GpuCuller::read_culled_objects");
+
+        assert_eq!(TypeId::of::<M>(), self.type_id);
+
+        // Graph storage can't be accessed concurrently, so keep out the graph while reading.
+        let data_core = renderer.data_core.write();
+        let map = data_core.graph_storage.get_mut(&self.culling_buffer_map_handle);
+        let (Some(buffers), Some(used)) = (map.inner.get(&camera), map.used.get(&camera)) else {
+            return Vec::new();
+        };
+        let Some(object_buffer) = data_core.object_manager.buffer::<M>() else {
+            return Vec::new();
+        };
+
+        let batch_bytes = read_buffer(
+            &renderer.device,
+            &renderer.queue,
+            &buffers.object_reference,
+            0..used.object_reference,
+        );
+        let batches: Vec<ShaderBatchData> = StorageBuffer::new(batch_bytes).create().unwrap();
+
+        let object_ids: Vec<u32> = batches
+            .iter()
+            .flat_map(|batch| batch.ranges[..batch.total_objects as usize].iter().map(|r| r.object_id))
+            .collect();
+        let Some(&max_id) = object_ids.iter().max() else {
+            return Vec::new();
+        };
+
+        // Object buffers always have COPY_SRC, as they are copied when they grow.
+        let stride = ShaderObject::<M>::SHADER_SIZE.get();
+        let object_bytes = read_buffer(
+            &renderer.device,
+            &renderer.queue,
+            object_buffer,
+            0..(max_id as u64 + 1) * stride,
+        );
+
+        object_ids
+            .into_iter()
+            .map(|object_id| CulledObject::from_bytes(object_id, &object_bytes[object_id as usize * stride as usize..]))
+            .collect()
+    }
+
+    fn record_stats(&self, ctx: &NodeExecutionContext, camera: Option<usize>, camera_stats: CameraStats) {
+        let mut stats = ctx.data_core.graph_storage.get_mut(&self.stats_handle);
+        if let Some(stats) = stats.as_mut() {
+            *stats.camera_mut(camera, ctx.eval_output.shadows.len()) = camera_stats;
         }
     }
 
@@ -222,18 +441,15 @@ impl GpuCuller {
             })
             .sum();
 
+        let needed = CullingBuffers {
+            object_reference: jobs.jobs.size().get(),
+            index: <u64 as Ord>::max(total_invocations as u64 * 3 * 4, 4),
+        };
         let buffers = ctx
             .data_core
             .graph_storage
             .get_mut(&self.culling_buffer_map_handle)
-            .get_buffers(
-                &ctx.renderer.device,
-                camera,
-                CullingBuffers {
-                    object_reference: jobs.jobs.size().get(),
-                    index: <u64 as Ord>::max(total_invocations as u64 * 3 * 4, 4),
-                },
-            )
+            .get_buffers(&ctx.renderer.device, camera, needed)
             .clone();
 
         {
@@ -279,8 +495,18 @@ impl GpuCuller {
 
         let mut current_material_key_range_start = 0;
         let mut current_material_key = jobs.regions.first().map(|k| k.key.material_key).unwrap_or(0);
+        let mut stats = jobs.stats;
+        stats.index_buffer = BufferStats {
+            used: needed.index,
+            capacity: buffers.index.size(),
+        };
+        stats.batch_buffer = BufferStats {
+            used: needed.object_reference,
+            capacity: buffers.object_reference.size(),
+        };
         for region in jobs.regions {
             let region: JobSubRegion = region;
+            stats.pass_mut(region.key.material_key).draw_calls += 1;
 
             if current_material_key != region.key.material_key {
                 let range_end = draw_calls.len();
@@ -295,6 +521,7 @@ impl GpuCuller {
             draw_calls.push(DrawCall {
                 index_range: start..end,
                 bind_group_index: region.key.bind_group_index,
+                sampler_index: region.key.sampler_index,
                 batch_index: region.job_index,
             });
         }
@@ -314,6 +541,8 @@ impl GpuCuller {
         }
         drop(cpass);
 
+        self.record_stats(ctx, camera, stats);
+
         DrawCallSet {
             buffers,
             draw_calls,
@@ -341,10 +570,19 @@ impl GpuCuller {
                 &ctx.data_core.material_manager,
                 &ctx.data_core.object_manager,
                 camera_manager,
+                camera.is_some(),
+                self.layer_mask(),
                 ctx.renderer.limits.max_compute_workgroups_per_dimension,
             );
 
             if jobs.jobs.is_empty() {
+                #[cfg(feature = "debug-readback")]
+                ctx.data_core
+                    .graph_storage
+                    .get_mut(&self.culling_buffer_map_handle)
+                    .used
+                    .remove(&camera);
+                self.record_stats(&ctx, camera, jobs.stats);
                 return;
             }
 
@@ -1,8 +1,21 @@
 const BATCH_SIZE: usize = 256;
 const WORKGROUP_SIZE: u32 = 256;
 
+/// Bit set in the material key of objects marked as
+/// [`overlay`](rend3::types::Object::overlay).
+///
+/// This puts overlay objects into their own ranges of the [`DrawCallSet`], so
+/// routines using the plain material key will never draw them.
+pub const OVERLAY_MATERIAL_KEY_BIT: u64 = 1 << 63;
+
 mod batching;
 mod culler;
+mod stats;
 
 pub use batching::{ShaderBatchData, ShaderBatchDatas};
+#[cfg(any(test, feature = "debug-readback"))]
+pub(crate) use culler::object_offsets;
+#[cfg(feature = "debug-readback")]
+pub use culler::CulledObject;
 pub use culler::{DrawCall, DrawCallSet, GpuCuller};
+pub use stats::{BufferStats, CameraStats, FrameStats, PassStats};
@@ -0,0 +1,120 @@
+/// Counts for all objects that share a single material key.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    /// Objects which survived frustum culling.
+    pub objects: u32,
+    /// Triangles submitted for culling and drawing, before any gpu culling.
+    pub triangles: u64,
+    /// Draw calls a routine issues when drawing this material key.
+    pub draw_calls: u32,
+}
+
+impl PassStats {
+    fn accumulate(&mut self, other: &PassStats) {
+        self.objects += other.objects;
+        self.triangles += other.triangles;
+        self.draw_calls += other.draw_calls;
+    }
+}
+
+/// Bytes used and allocated in a buffer.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BufferStats {
+    pub used: u64,
+    pub capacity: u64,
+}
+
+/// Statistics for a single culling pass for a single camera.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CameraStats {
+    /// Objects rejected by frustum culling.
+    pub objects_culled: u32,
+    /// Culled index buffer of this camera, which every surviving triangle is
+    /// written into. Zero if no objects were culled. The growth of this buffer
+    /// is controlled by [`GpuCuller::set_buffer_growth`](super::GpuCuller::set_buffer_growth).
+    pub index_buffer: BufferStats,
+    /// Buffer holding the culling batches of this camera. Zero if no objects
+    /// were culled.
+    pub batch_buffer: BufferStats,
+    // Material keys are sorted, so this is always sorted and tiny.
+    passes: Vec<(u64, PassStats)>,
+}
+
+impl CameraStats {
+    /// Stats for all objects with the given material key. For
+    /// [`PbrMaterial`](crate::pbr::PbrMaterial) this is given by
+    /// [`pbr_material_key`](crate::pbr::pbr_material_key).
+    pub fn pass(&self, material_key: u64) -> PassStats {
+        self.passes
+            .iter()
+            .find_map(|&(key, stats)| (key == material_key).then_some(stats))
+            .unwrap_or_default()
+    }
+
+    /// Iterate over all material keys which had objects in them.
+    pub fn passes(&self) -> impl Iterator<Item = (u64, PassStats)> + '_ {
+        self.passes.iter().copied()
+    }
+
+    /// Stats summed over every material key.
+    pub fn total(&self) -> PassStats {
+        let mut total = PassStats::default();
+        for (_, stats) in &self.passes {
+            total.accumulate(stats);
+        }
+        total
+    }
+
+    pub(super) fn pass_mut(&mut self, material_key: u64) -> &mut PassStats {
+        let idx = match self.passes.iter().rposition(|&(key, _)| key == material_key) {
+            Some(idx) => idx,
+            None => {
+                self.passes.push((material_key, PassStats::default()));
+                self.passes.len() - 1
+            }
+        };
+        &mut self.passes[idx].1
+    }
+}
+
+/// Statistics gathered during culling of the last rendered frame.
+///
+/// Collection is disabled by default, enable it with
+/// [`GpuCuller::set_stats_enabled`](super::GpuCuller::set_stats_enabled).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Stats for the main camera.
+    pub camera: CameraStats,
+    /// Stats for each shadow camera, in the order of the shadows in the
+    /// [`InstructionEvaluationOutput`](rend3::graph::InstructionEvaluationOutput).
+    pub shadows: Vec<CameraStats>,
+}
+
+impl FrameStats {
+    /// Stats for all shadow cameras, summed over every material key.
+    pub fn shadow_total(&self) -> PassStats {
+        let mut total = PassStats::default();
+        for shadow in &self.shadows {
+            total.accumulate(&shadow.total());
+        }
+        total
+    }
+
+    /// Draw calls issued across all cameras.
+    pub fn draw_calls(&self) -> u32 {
+        self.camera.total().draw_calls + self.shadow_total().draw_calls
+    }
+
+    /// Triangles submitted across all cameras.
+    pub fn triangles(&self) -> u64 {
+        self.camera.total().triangles + self.shadow_total().triangles
+    }
+
+    pub(super) fn camera_mut(&mut self, camera: Option<usize>, shadow_count: usize) -> &mut CameraStats {
+        self.shadows.resize_with(shadow_count, CameraStats::default);
+        match camera {
+            Some(idx) => &mut self.shadows[idx],
+            None => &mut self.camera,
+        }
+    }
+}
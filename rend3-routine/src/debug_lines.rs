@@ -0,0 +1,252 @@
+//! Immediate mode drawing of world space lines over the scene, for debugging.
+//!
+//! Debug lines are opt-in and are not part of
+//! [`BaseRenderGraph::add_to_graph`](crate::base::BaseRenderGraph::add_to_graph).
+//! Add them after forward rendering with
+//! [`BaseRenderGraphIntermediateState::debug_lines`](crate::base::BaseRenderGraphIntermediateState::debug_lines).
+//!
+//! Lines accumulate with [`DebugLineRoutine::draw_line`] and friends until
+//! [`DebugLineRoutine::clear`] is called, usually once per frame after the
+//! graph has executed. They are unlit, one pixel wide, and tested against the
+//! depth of the scene, unless [`DebugLineRoutine::on_top`] is set.
+
+use std::{borrow::Cow, mem};
+
+use glam::{BVec3, Vec3, Vec4};
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle,
+    },
+    types::SampleCount,
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BufferAddress, BufferUsages, Color, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
+    DepthStencilState, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
+    StencilState, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
+};
+
+use crate::{
+    common::{self, WholeFrameInterfaces},
+    pbr::BlendMode,
+};
+
+/// A single end of a line, as laid out in the vertex buffer.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+unsafe impl bytemuck::Zeroable for DebugVertex {}
+unsafe impl bytemuck::Pod for DebugVertex {}
+
+/// Debug line routine.
+///
+/// See module for documentation.
+pub struct DebugLineRoutine {
+    /// Depth tested then on top, for one then four samples.
+    pipelines: [RenderPipeline; 4],
+    depth_format: TextureFormat,
+    vertices: Vec<DebugVertex>,
+    /// Draw the lines over everything instead of testing them against the
+    /// depth of the scene.
+    pub on_top: bool,
+}
+
+impl DebugLineRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("DebugLineRoutine::new");
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("debug lines"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/debug_lines.wgsl", &ShaderConfig::default(), None)
+                    .unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("debug lines pass"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let inner = |samples: SampleCount, on_top: bool| {
+            renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("debug lines pass"),
+                layout: Some(&pll),
+                vertex: VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[VertexBufferLayout {
+                        array_stride: mem::size_of::<DebugVertex>() as BufferAddress,
+                        step_mode: VertexStepMode::Vertex,
+                        attributes: &[
+                            VertexAttribute {
+                                format: VertexFormat::Float32x3,
+                                offset: 0,
+                                shader_location: 0,
+                            },
+                            VertexAttribute {
+                                format: VertexFormat::Float32x4,
+                                offset: mem::size_of::<[f32; 3]>() as BufferAddress,
+                                shader_location: 1,
+                            },
+                        ],
+                    }],
+                },
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::LineList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: interfaces.depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: match on_top {
+                        true => CompareFunction::Always,
+                        false => CompareFunction::GreaterEqual,
+                    },
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: MultisampleState {
+                    count: samples as u32,
+                    ..Default::default()
+                },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        format: interfaces.hdr_format,
+                        // Don't square the alpha of the lines, which would make them too
+                        // faint over a transparent background.
+                        blend: Some(BlendMode::Alpha.to_blend_state()),
+                        write_mask: ColorWrites::all(),
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self {
+            pipelines: [
+                inner(SampleCount::One, false),
+                inner(SampleCount::One, true),
+                inner(SampleCount::Four, false),
+                inner(SampleCount::Four, true),
+            ],
+            depth_format: interfaces.depth_format,
+            vertices: Vec::new(),
+            on_top: false,
+        }
+    }
+
+    /// Draw a line from `start` to `end`.
+    pub fn draw_line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        let color = color.to_array();
+        self.vertices.push(DebugVertex {
+            position: start.to_array(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: end.to_array(),
+            color,
+        });
+    }
+
+    /// Draw the edges of the axis aligned box between `min` and `max`.
+    pub fn draw_aabb(&mut self, min: Vec3, max: Vec3, color: Vec4) {
+        let corner = |i: u32| Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min);
+        for i in 0..8 {
+            // Connect each corner to the neighbours that differ in one more bit, so every edge is drawn once.
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.draw_line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Draw a point as a cross along the world axes, `size` across.
+    pub fn draw_point(&mut self, position: Vec3, size: f32, color: Vec4) {
+        let half = size * 0.5;
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.draw_line(position - axis * half, position + axis * half, color);
+        }
+    }
+
+    /// Remove all lines drawn so far.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Draw all lines into `color`, testing against `depth`.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        resolve: Option<RenderTargetHandle>,
+        depth: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let mut builder = graph.add_node("Debug Lines");
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::InputOutput);
+        let resolve_handle = builder.add_optional_render_target(resolve, NodeResourceUsage::InputOutput);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: color_handle,
+                clear: Color::BLACK,
+                resolve: resolve_handle,
+            }],
+            depth_stencil: Some(RenderPassDepthTarget {
+                target: depth_handle,
+                depth_clear: Some(0.0),
+                stencil_clear: common::has_stencil(self.depth_format).then_some(0),
+            }),
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            profiling::scope!("debug lines");
+
+            let buffer = ctx
+                .temps
+                .add(ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("debug line vertices"),
+                    contents: bytemuck::cast_slice(&self.vertices),
+                    usage: BufferUsages::VERTEX,
+                }));
+
+            let pipeline = match samples {
+                SampleCount::One => &self.pipelines[self.on_top as usize],
+                SampleCount::Four => &self.pipelines[2 + self.on_top as usize],
+            };
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_vertex_buffer(0, buffer.slice(..));
+            rpass.draw(0..self.vertices.len() as u32, 0..1);
+        });
+    }
+}
@@ -0,0 +1,244 @@
+//! Projected decals which blend onto whatever surfaces are inside of a box.
+//!
+//! Decals are opt-in and are not part of
+//! [`BaseRenderGraph::add_to_graph`](crate::base::BaseRenderGraph::add_to_graph).
+//! Add them after opaque forward rendering with
+//! [`BaseRenderGraphIntermediateState::decals`](crate::base::BaseRenderGraphIntermediateState::decals).
+//!
+//! Each decal is a box which reconstructs the world position of the surface
+//! behind every pixel from the depth buffer and blends its color over the hdr
+//! color where that position is inside the box. Only albedo is affected, and
+//! the decal is not lit. Decals need a single sampled depth buffer, so when
+//! multisampling, resolve it first with
+//! [`BaseRenderGraphIntermediateState::resolve_depth`](crate::base::BaseRenderGraphIntermediateState::resolve_depth).
+//! This needs to happen after all forward rendering, so decals will then also
+//! draw over transparent objects.
+
+use std::{borrow::Cow, mem};
+
+use glam::{Mat4, Vec4};
+use rend3::{
+    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle},
+    types::{Handedness, Texture2DHandle},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState,
+    BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, Face, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureSampleType,
+    TextureViewDimension, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// A single decal.
+#[derive(Debug, Clone)]
+pub struct Decal {
+    /// Transform from a unit cube centered on the origin to world space.
+    ///
+    /// The decal is projected along the local Y axis, with the texture's u
+    /// coordinate along local X and v along local Z.
+    pub transform: Mat4,
+    /// Color of the decal. Alpha controls how much of the surface is covered.
+    pub color: Vec4,
+    /// Texture multiplied with the color, if any.
+    pub texture: Option<Texture2DHandle>,
+}
+
+/// The actual structure passed to the shader.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct DecalUniforms {
+    transform: Mat4,
+    inv_transform: Mat4,
+    color: Vec4,
+    has_texture: u32,
+    _padding: [u32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for DecalUniforms {}
+unsafe impl bytemuck::Pod for DecalUniforms {}
+
+/// Decal routine.
+///
+/// See module for documentation.
+pub struct DecalRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+    /// Decals drawn every frame, in order.
+    pub decals: Vec<Decal>,
+}
+
+impl DecalRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("DecalRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append_buffer(
+                ShaderStages::VERTEX_FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<DecalUniforms>() as _,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("decal bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("decal"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/decal.wgsl", &ShaderConfig::default(), None)
+                    .unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("decal pass"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("decal pass"),
+            layout: Some(&pll),
+            vertex: VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: match renderer.handedness {
+                    Handedness::Left => FrontFace::Cw,
+                    Handedness::Right => FrontFace::Ccw,
+                },
+                // Draw the back of the box, so the decal still shows when the camera is inside it.
+                cull_mode: Some(Face::Front),
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: interfaces.hdr_format,
+                    blend: Some(BlendState {
+                        color: BlendComponent {
+                            src_factor: BlendFactor::SrcAlpha,
+                            dst_factor: BlendFactor::OneMinusSrcAlpha,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha: BlendComponent {
+                            src_factor: BlendFactor::Zero,
+                            dst_factor: BlendFactor::One,
+                            operation: BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            bgl,
+            pipeline,
+            decals: Vec::new(),
+        }
+    }
+
+    /// Blend all decals onto `color`.
+    ///
+    /// `color` and `depth` must be single sampled.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        if self.decals.is_empty() {
+            return;
+        }
+
+        let mut builder = graph.add_node("Decals");
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::InputOutput);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: color_handle,
+                clear: Color::BLACK,
+                resolve: None,
+            }],
+            depth_stencil: None,
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            profiling::scope!("decals");
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+
+            for decal in &self.decals {
+                let uniforms = DecalUniforms {
+                    transform: decal.transform,
+                    inv_transform: decal.transform.inverse(),
+                    color: decal.color,
+                    has_texture: decal.texture.is_some() as u32,
+                    _padding: [0; 3],
+                };
+                let buffer = ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("decal uniforms"),
+                    contents: bytemuck::bytes_of(&uniforms),
+                    usage: BufferUsages::UNIFORM,
+                });
+                let texture = match decal.texture {
+                    Some(ref handle) => ctx.data_core.d2_texture_manager.get_view(handle.get_raw()),
+                    None => ctx.data_core.d2_texture_manager.get_null_view(),
+                };
+
+                let buffer = ctx.temps.add(buffer);
+                let decal_bg = ctx.temps.add(
+                    BindGroupBuilder::new()
+                        .append_texture_view(depth)
+                        .append_buffer(buffer)
+                        .append_texture_view(texture)
+                        .build(&ctx.renderer.device, Some("decal bg"), &self.bgl),
+                );
+
+                rpass.set_bind_group(1, decal_bg, &[]);
+                rpass.draw(0..36, 0..1);
+            }
+        });
+    }
+}
@@ -0,0 +1,215 @@
+//! Reads back the depth buffer to the cpu as linear view space depth.
+//!
+//! Depth is reversed and non-linear, so reading the depth buffer directly does
+//! not give useful distances. This routine converts the depth of a region of
+//! the screen into the distance from the camera's plane, which is
+//! then read back with [`DepthReadbackRoutine::read`]. Pixels where nothing
+//! was rendered read back as infinity.
+//!
+//! Add it after all forward rendering. It needs a single sampled depth buffer,
+//! so when multisampling, resolve it first with
+//! [`BaseRenderGraphIntermediateState::resolve_depth`](crate::base::BaseRenderGraphIntermediateState::resolve_depth).
+
+use std::borrow::Cow;
+
+use glam::UVec2;
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle,
+        ViewportRect,
+    },
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        readback,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Color,
+    ColorTargetState, ColorWrites, Extent3d, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureViewDimension, VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// Depth readback routine.
+///
+/// See module for documentation.
+pub struct DepthReadbackRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+    offset_buffer: Buffer,
+    target: Option<Texture>,
+}
+
+impl DepthReadbackRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("DepthReadbackRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, 8)
+            .build(&renderer.device, Some("depth readback bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("depth readback"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/depth_readback.wgsl", &ShaderConfig::default(), None)
+                    .unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("depth readback pass"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("depth readback pass"),
+            layout: Some(&pll),
+            vertex: VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::R32Float,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        let offset_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("depth readback offset"),
+            size: 8,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bgl,
+            pipeline,
+            offset_buffer,
+            target: None,
+        }
+    }
+
+    /// Set the region of the screen, in pixels, to read back. Must be called
+    /// before [`DepthReadbackRoutine::add_to_graph`], and only needs to be
+    /// called again when the region changes.
+    pub fn set_region(&mut self, renderer: &Renderer, region: ViewportRect) {
+        let size = region.size.max(UVec2::ONE);
+        if self.target.as_ref().map(|t| UVec2::new(t.width(), t.height())) != Some(size) {
+            self.target = Some(renderer.device.create_texture(&TextureDescriptor {
+                label: Some("depth readback"),
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R32Float,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            }));
+        }
+        renderer
+            .queue
+            .write_buffer(&self.offset_buffer, 0, bytemuck::bytes_of(&region.offset));
+    }
+
+    /// Add the conversion of the depth region to the graph.
+    ///
+    /// `depth` must be single sampled and the region must be within it.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let target = self
+            .target
+            .as_ref()
+            .expect("DepthReadbackRoutine::set_region must be called before DepthReadbackRoutine::add_to_graph");
+        let output = graph.add_imported_render_target(
+            target,
+            0..1,
+            ViewportRect::from_size(UVec2::new(target.width(), target.height())),
+        );
+
+        let mut builder = graph.add_node("Depth Readback");
+
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let output_handle = builder.add_render_target(output, NodeResourceUsage::Output);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: output_handle,
+                clear: Color::BLACK,
+                resolve: None,
+            }],
+            depth_stencil: None,
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            profiling::scope!("depth readback");
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(depth)
+                    .append_buffer(&self.offset_buffer)
+                    .build(&ctx.renderer.device, Some("depth readback bg"), &self.bgl),
+            );
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    /// Read back the linear depth of the region, row by row, top row first.
+    /// Call after the graph has been executed. This blocks until the gpu is
+    /// done, see [`read_texture_r32float`](readback::read_texture_r32float).
+    pub fn read(&self, renderer: &Renderer) -> Vec<f32> {
+        let target = self
+            .target
+            .as_ref()
+            .expect("DepthReadbackRoutine::set_region must be called before DepthReadbackRoutine::read");
+        readback::read_texture_r32float(&renderer.device, &renderer.queue, target)
+    }
+}
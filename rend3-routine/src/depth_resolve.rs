@@ -0,0 +1,153 @@
+//! Resolves a multisampled depth buffer into a single sampled one.
+//!
+//! wgpu cannot resolve depth as part of a renderpass, so this is done manually
+//! by writing the closest of all samples through `frag_depth`. The resolved
+//! depth can then be sampled by post processing, such as
+//! [`SsrRoutine`](crate::ssr::SsrRoutine) and
+//! [`TaaRoutine`](crate::taa::TaaRoutine), while multisampling.
+
+use std::borrow::Cow;
+
+use glam::UVec2;
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTargets, RenderTargetDescriptor,
+        RenderTargetHandle,
+    },
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, CompareFunction, DepthBiasState, DepthStencilState, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureFormat,
+    TextureSampleType, TextureUsages, TextureViewDimension, VertexState,
+};
+
+/// Depth resolve routine.
+///
+/// See module for documentation.
+pub struct DepthResolveRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl DepthResolveRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("DepthResolveRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("depth resolve bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("depth resolve"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/depth_resolve.wgsl", &ShaderConfig::default(), None)
+                    .unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("depth resolve pass"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("depth resolve pass"),
+            layout: Some(&pll),
+            vertex: VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[],
+            }),
+            multiview: None,
+        });
+
+        Self { bgl, pipeline }
+    }
+
+    /// Add the resolve to the graph, returning the single sampled depth.
+    ///
+    /// `depth` must be multisampled.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        resolution: UVec2,
+    ) -> RenderTargetHandle {
+        let resolved = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("hdr depth resolve".into()),
+            resolution,
+            depth: 1,
+            samples: SampleCount::One,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        let mut builder = graph.add_node("Depth Resolve");
+
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let resolved_handle = builder.add_render_target(resolved, NodeResourceUsage::Output);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![],
+            depth_stencil: Some(RenderPassDepthTarget {
+                target: resolved_handle,
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+            }),
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+
+            profiling::scope!("depth resolve");
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(depth).build(
+                &ctx.renderer.device,
+                Some("depth resolve bg"),
+                &self.bgl,
+            ));
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+
+        resolved
+    }
+}
@@ -1,6 +1,20 @@
 //! Material agnostic routine for forward rendering.
 //!
 //! Will default to the PBR shader code if custom code is not specified.
+//!
+//! # Stencil
+//!
+//! When the main depth buffer has a stencil aspect (see
+//! [`WholeFrameInterfaces::new_with_depth_format`]), forward routines can
+//! write and test stencil. Create a routine whose `descriptor_callback` sets
+//! the stencil state, for example to [`stencil_write_state`] or
+//! [`stencil_equal_state`], and set its [`ForwardRoutine::stencil_reference`].
+//!
+//! To restrict a routine to a group of objects, put those objects in their
+//! own [`layers`](rend3::types::Object::layers) and cull them with a separate
+//! [`GpuCuller`](crate::culling::GpuCuller) that has a matching layer mask.
+//! For a portal, draw the portal surfaces with a stencil writing routine,
+//! then draw the world behind the portal with a stencil testing routine.
 
 use std::marker::PhantomData;
 
@@ -11,20 +25,20 @@ use rend3::{
         DataHandle, NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
         RenderTargetHandle,
     },
-    types::{Handedness, Material, SampleCount},
-    util::bind_merge::BindGroupBuilder,
-    ProfileData, Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor,
+    types::{Material, SampleCount},
+    util::{bind_merge::BindGroupBuilder, parallel},
+    ProfileData, Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor, INTERNAL_SHADOW_DEPTH_FORMAT,
 };
 use serde::Serialize;
 use wgpu::{
     BindGroup, BindGroupLayout, Color, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
-    DepthStencilState, Face, FragmentState, FrontFace, IndexFormat, MultisampleState, PipelineLayoutDescriptor,
-    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule,
-    StencilState, TextureFormat, VertexState,
+    DepthStencilState, Face, Features, FragmentState, FrontFace, IndexFormat, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, StencilFaceState, StencilOperation, StencilState, TextureFormat, VertexState,
 };
 
 use crate::{
-    common::{PerMaterialArchetypeInterface, WholeFrameInterfaces},
+    common::{self, PerMaterialArchetypeInterface, Samplers, WholeFrameInterfaces},
     culling::{self, DrawCall},
 };
 
@@ -62,8 +76,41 @@ pub struct RoutineArgs<'a, M> {
     pub shaders: ShaderModulePair<'a>,
 
     pub extra_bgls: &'a [&'a BindGroupLayout],
+    /// Formats of additional color targets of forward routines, which the
+    /// fragment shader writes at locations 1 and up, after the hdr color at
+    /// location 0. At most 7 are supported. Leave empty to only write the
+    /// hdr color.
+    pub extra_targets: &'a [TextureFormat],
+    /// Called on each pipeline descriptor before creation. Pipelines may be
+    /// created on multiple threads, so this must be `Sync`.
     #[allow(clippy::type_complexity)]
-    pub descriptor_callback: Option<&'a dyn Fn(&mut RenderPipelineDescriptor<'_>, &mut [Option<ColorTargetState>])>,
+    pub descriptor_callback:
+        Option<&'a (dyn Fn(&mut RenderPipelineDescriptor<'_>, &mut [Option<ColorTargetState>]) + Sync)>,
+    /// Rasterize conservatively, covering every pixel a triangle touches at
+    /// all, like for voxelization. Needs
+    /// [`Features::CONSERVATIVE_RASTERIZATION`], without it triangles are
+    /// rasterized normally and a warning is logged.
+    ///
+    /// [`PbrRoutine`](crate::pbr::PbrRoutine) always rasterizes normally, build
+    /// a separate routine with this set to render into a voxel grid.
+    pub conservative: bool,
+}
+
+/// The parts of [`RoutineArgs`] needed to build a pipeline, which can be shared
+/// between threads.
+struct PipelineArgs<'a> {
+    name: &'a str,
+    renderer: &'a Renderer,
+    routine_type: &'a RoutineType,
+    hdr_format: TextureFormat,
+    extra_targets: &'a [TextureFormat],
+    depth_format: TextureFormat,
+    front_face: FrontFace,
+    conservative: bool,
+    shaders: &'a ShaderModulePair<'a>,
+    #[allow(clippy::type_complexity)]
+    descriptor_callback:
+        Option<&'a (dyn Fn(&mut RenderPipelineDescriptor<'_>, &mut [Option<ColorTargetState>]) + Sync)>,
 }
 
 pub struct RoutineAddToGraphArgs<'a, 'node, M> {
@@ -71,11 +118,17 @@ pub struct RoutineAddToGraphArgs<'a, 'node, M> {
     pub whole_frame_uniform_bg: DataHandle<BindGroup>,
     pub culled: DataHandle<culling::DrawCallSet>,
     pub per_material: &'node PerMaterialArchetypeInterface<M>,
+    /// Provides the sampler of each material on the CpuDriven profile.
+    pub samplers: &'node Samplers,
     pub extra_bgs: Option<&'node [BindGroup]>,
     pub label: &'a str,
     pub samples: SampleCount,
     pub color: Option<RenderTargetHandle>,
     pub resolve: Option<RenderTargetHandle>,
+    /// Additional color targets, matching the
+    /// [`extra_targets`](RoutineArgs::extra_targets) of the routine. They
+    /// have the sample count of `color` and aren't resolved.
+    pub extra_targets: &'a [RenderTargetHandle],
     pub depth: RenderTargetHandle,
     /// Passed to the shader through the instance index.
     pub data: u32,
@@ -86,6 +139,11 @@ pub struct ForwardRoutine<M: Material> {
     pub pipeline_s1: RenderPipeline,
     pub pipeline_s4: RenderPipeline,
     pub material_key: u64,
+    /// Format of the depth target the pipelines were built for.
+    pub depth_format: TextureFormat,
+    /// Reference value used by the stencil test, if the depth target has
+    /// stencil.
+    pub stencil_reference: u32,
     pub _phantom: PhantomData<M>,
 }
 impl<M: Material> ForwardRoutine<M> {
@@ -126,10 +184,39 @@ impl<M: Material> ForwardRoutine<M> {
             push_constant_ranges: &[],
         });
 
+        // Depth routines render shadows, which always use the internal format.
+        let depth_format = match args.routine_type {
+            RoutineType::Depth => INTERNAL_SHADOW_DEPTH_FORMAT,
+            RoutineType::Forward => args.interfaces.depth_format,
+        };
+
+        let conservative = conservative_rasterization(args.name, args.conservative, args.renderer.features);
+
+        let pipeline_args = PipelineArgs {
+            name: args.name,
+            renderer: args.renderer,
+            routine_type: &args.routine_type,
+            hdr_format: args.interfaces.hdr_format,
+            extra_targets: args.extra_targets,
+            depth_format,
+            front_face: args.interfaces.front_face_for(args.renderer.handedness),
+            conservative,
+            shaders: &args.shaders,
+            descriptor_callback: args.descriptor_callback,
+        };
+
+        // The pipelines are independent, so compile them in parallel.
+        let (pipeline_s1, pipeline_s4) = parallel::join(
+            || build_forward_pipeline_inner(&pll, &pipeline_args, SampleCount::One),
+            || build_forward_pipeline_inner(&pll, &pipeline_args, SampleCount::Four),
+        );
+
         Self {
-            pipeline_s1: build_forward_pipeline_inner(&pll, &args, SampleCount::One),
-            pipeline_s4: build_forward_pipeline_inner(&pll, &args, SampleCount::Four),
+            pipeline_s1,
+            pipeline_s4,
             material_key: args.material_key,
+            depth_format,
+            stencil_reference: 0,
             _phantom: PhantomData,
         }
     }
@@ -141,23 +228,32 @@ impl<M: Material> ForwardRoutine<M> {
 
         let color_handle = builder.add_optional_render_target(args.color, NodeResourceUsage::InputOutput);
         let resolve_handle = builder.add_optional_render_target(args.resolve, NodeResourceUsage::InputOutput);
+        let extra_handles: Vec<_> = args
+            .extra_targets
+            .iter()
+            .map(|&target| builder.add_render_target(target, NodeResourceUsage::InputOutput))
+            .collect();
         let depth_handle = builder.add_render_target(args.depth, NodeResourceUsage::InputOutput);
 
         builder.add_side_effect();
 
         let rpass_handle = builder.add_renderpass(RenderPassTargets {
             targets: match color_handle {
-                Some(color) => vec![RenderPassTarget {
-                    color,
-                    clear: Color::BLACK,
-                    resolve: resolve_handle,
-                }],
+                Some(color) => [(color, resolve_handle)]
+                    .into_iter()
+                    .chain(extra_handles.into_iter().map(|extra| (extra, None)))
+                    .map(|(color, resolve)| RenderPassTarget {
+                        color,
+                        clear: Color::BLACK,
+                        resolve,
+                    })
+                    .collect(),
                 None => vec![],
             },
             depth_stencil: Some(RenderPassDepthTarget {
                 target: depth_handle,
                 depth_clear: Some(0.0),
-                stencil_clear: None,
+                stencil_clear: common::has_stencil(self.depth_format).then_some(0),
             }),
         });
 
@@ -172,25 +268,44 @@ impl<M: Material> ForwardRoutine<M> {
                 None => return,
             };
 
-            let per_material_bg = ctx.temps.add(
-                BindGroupBuilder::new()
-                    .append_buffer(ctx.data_core.object_manager.buffer::<M>().unwrap())
-                    .append_buffer_with_size(
-                        &culled.buffers.object_reference,
-                        culling::ShaderBatchData::SHADER_SIZE.get(),
+            // One bind group per sampler, only created once a draw needs it. On the GpuDriven profile all
+            // draws use index zero and the shader picks the sampler itself.
+            let mut per_material_bgs: [Option<&BindGroup>; Samplers::MATERIAL_SAMPLER_COUNT as usize] =
+                [None; Samplers::MATERIAL_SAMPLER_COUNT as usize];
+            let mut per_material_bg = |sampler_index: u8| {
+                // Out of range indices use the first sampler, the same as `Samplers::material_sampler`.
+                let sampler_index = match sampler_index < Samplers::MATERIAL_SAMPLER_COUNT {
+                    true => sampler_index,
+                    false => 0,
+                };
+                *per_material_bgs[sampler_index as usize].get_or_insert_with(|| {
+                    &*ctx.temps.add(
+                        BindGroupBuilder::new()
+                            .append_buffer(ctx.data_core.object_manager.buffer::<M>().unwrap())
+                            .append_buffer_with_size(
+                                &culled.buffers.object_reference,
+                                culling::ShaderBatchData::SHADER_SIZE.get(),
+                            )
+                            .append_buffer(&ctx.eval_output.mesh_buffer)
+                            .append_buffer(ctx.data_core.material_manager.archetype_view::<M>().buffer())
+                            .append_sampler(args.samplers.material_sampler(sampler_index))
+                            .build(&ctx.renderer.device, Some("Per-Material BG"), &args.per_material.bgl),
                     )
-                    .append_buffer(&ctx.eval_output.mesh_buffer)
-                    .append_buffer(ctx.data_core.material_manager.archetype_view::<M>().buffer())
-                    .build(&ctx.renderer.device, Some("Per-Material BG"), &args.per_material.bgl),
-            );
+                })
+            };
 
             let pipeline = match args.samples {
                 SampleCount::One => &self.pipeline_s1,
                 SampleCount::Four => &self.pipeline_s4,
             };
 
+            // Culling always outputs 32 bit indices, as the object index is packed into the top bits, no matter
+            // the index format of the meshes.
             rpass.set_index_buffer(culled.buffers.index.slice(..), IndexFormat::Uint32);
             rpass.set_pipeline(pipeline);
+            if common::has_stencil(self.depth_format) {
+                rpass.set_stencil_reference(self.stencil_reference);
+            }
             rpass.set_bind_group(0, whole_frame_uniform_bg, &[]);
             if let Some(v) = args.extra_bgs {
                 for (idx, bg) in v.iter().enumerate() {
@@ -204,38 +319,65 @@ impl<M: Material> ForwardRoutine<M> {
             let Some(range) = culled.material_key_ranges.get(&self.material_key) else {
                 return;
             };
+            // Draws are sorted by texture bind group, so materials which share textures only need to
+            // bind them once.
+            let mut bound_textures = None;
+            let mut bound_batch = None;
+            let mut bound_sampler = None;
             for call in &culled.draw_calls[range.clone()] {
                 let call: &DrawCall = call;
 
-                if ctx.renderer.profile.is_cpu_driven() {
+                if ctx.renderer.profile.is_cpu_driven() && bound_textures != Some(call.bind_group_index) {
                     rpass.set_bind_group(
                         2,
                         ctx.data_core.material_manager.texture_bind_group(call.bind_group_index),
                         &[],
                     );
+                    bound_textures = Some(call.bind_group_index);
+                }
+                if bound_batch != Some(call.batch_index) || bound_sampler != Some(call.sampler_index) {
+                    rpass.set_bind_group(
+                        1,
+                        per_material_bg(call.sampler_index),
+                        &[call.batch_index * culling::ShaderBatchData::SHADER_SIZE.get() as u32],
+                    );
+                    bound_batch = Some(call.batch_index);
+                    bound_sampler = Some(call.sampler_index);
                 }
-                rpass.set_bind_group(
-                    1,
-                    per_material_bg,
-                    &[call.batch_index * culling::ShaderBatchData::SHADER_SIZE.get() as u32],
-                );
                 rpass.draw_indexed(call.index_range.clone(), 0, args.data..args.data + 1);
             }
         });
     }
 }
 
-fn build_forward_pipeline_inner<M: Material>(
+/// Whether to rasterize conservatively, warning if it was requested but the
+/// device doesn't support it.
+fn conservative_rasterization(name: &str, requested: bool, features: Features) -> bool {
+    let conservative = requested && features.contains(Features::CONSERVATIVE_RASTERIZATION);
+    if requested && !conservative {
+        log::warn!(
+            "{}: conservative rasterization needs {:?}, falling back to normal rasterization",
+            name,
+            Features::CONSERVATIVE_RASTERIZATION
+        );
+    }
+    conservative
+}
+
+fn build_forward_pipeline_inner(
     pll: &wgpu::PipelineLayout,
-    args: &RoutineArgs<'_, M>,
+    args: &PipelineArgs<'_>,
     samples: SampleCount,
 ) -> RenderPipeline {
-    let mut render_targets: ArrayVec<_, 1> = ArrayVec::new();
+    let mut render_targets: ArrayVec<_, 8> = ArrayVec::new();
     if matches!(args.routine_type, RoutineType::Forward) {
-        render_targets.push(Some(ColorTargetState {
-            format: TextureFormat::Rgba16Float,
-            blend: None,
-            write_mask: ColorWrites::all(),
+        let formats = [args.hdr_format].into_iter().chain(args.extra_targets.iter().copied());
+        render_targets.extend(formats.map(|format| {
+            Some(ColorTargetState {
+                format,
+                blend: None,
+                write_mask: ColorWrites::all(),
+            })
         }));
     }
     let mut desc = RenderPipelineDescriptor {
@@ -249,17 +391,14 @@ fn build_forward_pipeline_inner<M: Material>(
         primitive: PrimitiveState {
             topology: PrimitiveTopology::TriangleList,
             strip_index_format: None,
-            front_face: match args.renderer.handedness {
-                Handedness::Left => FrontFace::Cw,
-                Handedness::Right => FrontFace::Ccw,
-            },
+            front_face: args.front_face,
             cull_mode: Some(Face::Back),
             unclipped_depth: false,
             polygon_mode: PolygonMode::Fill,
-            conservative: false,
+            conservative: args.conservative,
         },
         depth_stencil: Some(DepthStencilState {
-            format: TextureFormat::Depth32Float,
+            format: args.depth_format,
             depth_write_enabled: true,
             depth_compare: CompareFunction::GreaterEqual,
             stencil: StencilState::default(),
@@ -289,3 +428,59 @@ fn build_forward_pipeline_inner<M: Material>(
     desc.fragment.as_mut().unwrap().targets = &render_targets;
     args.renderer.device.create_render_pipeline(&desc)
 }
+
+/// Stencil state which replaces the stencil with the routine's
+/// [`stencil_reference`](ForwardRoutine::stencil_reference) wherever it draws.
+pub fn stencil_write_state() -> StencilState {
+    let face = StencilFaceState {
+        compare: CompareFunction::Always,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Keep,
+        pass_op: StencilOperation::Replace,
+    };
+    StencilState {
+        front: face,
+        back: face,
+        read_mask: 0xFF,
+        write_mask: 0xFF,
+    }
+}
+
+/// Stencil state which only draws where the stencil equals the routine's
+/// [`stencil_reference`](ForwardRoutine::stencil_reference).
+pub fn stencil_equal_state() -> StencilState {
+    let face = StencilFaceState {
+        compare: CompareFunction::Equal,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Keep,
+        pass_op: StencilOperation::Keep,
+    };
+    StencilState {
+        front: face,
+        back: face,
+        read_mask: 0xFF,
+        write_mask: 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wgpu::Features;
+
+    use super::conservative_rasterization;
+
+    #[test]
+    fn conservative_rasterization_falls_back() {
+        assert!(!conservative_rasterization("test", true, Features::empty()));
+        assert!(conservative_rasterization(
+            "test",
+            true,
+            Features::CONSERVATIVE_RASTERIZATION
+        ));
+        assert!(!conservative_rasterization(
+            "test",
+            false,
+            Features::CONSERVATIVE_RASTERIZATION
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+//! Infinite reference grid on the ground plane, as used by editors.
+//!
+//! The grid is opt-in and is not part of
+//! [`BaseRenderGraph::add_to_graph`](crate::base::BaseRenderGraph::add_to_graph).
+//! Add it after opaque forward rendering and the skybox with
+//! [`BaseRenderGraphIntermediateState::grid`](crate::base::BaseRenderGraphIntermediateState::grid).
+//!
+//! The grid is drawn analytically by intersecting the view ray of every pixel
+//! with the plane at Y = 0, so it goes on forever without any geometry. It is
+//! depth tested against the scene, but doesn't write depth, so transparent
+//! objects drawn after it blend over it.
+
+use std::{borrow::Cow, mem};
+
+use glam::Vec4;
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
+        RenderTargetHandle,
+    },
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CompareFunction,
+    DepthBiasState, DepthStencilState, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StencilState, TextureFormat, VertexState,
+};
+
+use crate::{
+    common::{self, WholeFrameInterfaces},
+    pbr::BlendMode,
+};
+
+/// The actual structure passed to the shader.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct GridUniforms {
+    minor_color: Vec4,
+    major_color: Vec4,
+    spacing: f32,
+    major_spacing: f32,
+    fade_distance: f32,
+    _padding: u32,
+}
+
+unsafe impl bytemuck::Zeroable for GridUniforms {}
+unsafe impl bytemuck::Pod for GridUniforms {}
+
+/// Grid routine.
+///
+/// See module for documentation.
+pub struct GridRoutine {
+    bgl: BindGroupLayout,
+    pipeline_s1: RenderPipeline,
+    pipeline_s4: RenderPipeline,
+    depth_format: TextureFormat,
+    /// Distance between minor lines, in world units.
+    pub spacing: f32,
+    /// Major lines are drawn every this many minor lines.
+    pub major_every: u32,
+    /// Color of the minor lines. Alpha is how opaque the lines are.
+    pub minor_color: Vec4,
+    /// Color of the major lines. Alpha is how opaque the lines are.
+    pub major_color: Vec4,
+    /// Distance from the camera at which the grid has faded out completely.
+    pub fade_distance: f32,
+}
+
+impl GridRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("GridRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<GridUniforms>() as _,
+            )
+            .build(&renderer.device, Some("grid bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("grid"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/grid.wgsl", &ShaderConfig::default(), None)
+                    .unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("grid pass"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+
+        let inner = |samples: SampleCount| {
+            renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("grid pass"),
+                layout: Some(&pll),
+                vertex: VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Cw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: interfaces.depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::GreaterEqual,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: MultisampleState {
+                    count: samples as u32,
+                    ..Default::default()
+                },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        format: interfaces.hdr_format,
+                        // Alpha accumulates coverage, like blended materials, so the grid
+                        // keeps the right opacity over a transparent background.
+                        blend: Some(BlendMode::Alpha.to_blend_state()),
+                        write_mask: ColorWrites::all(),
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self {
+            bgl,
+            pipeline_s1: inner(SampleCount::One),
+            pipeline_s4: inner(SampleCount::Four),
+            depth_format: interfaces.depth_format,
+            spacing: 1.0,
+            major_every: 10,
+            minor_color: Vec4::new(0.5, 0.5, 0.5, 0.5),
+            major_color: Vec4::new(0.8, 0.8, 0.8, 0.8),
+            fade_distance: 100.0,
+        }
+    }
+
+    /// Draw the grid into `color`, testing against `depth`.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        resolve: Option<RenderTargetHandle>,
+        depth: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+    ) {
+        let mut builder = graph.add_node("Grid");
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::InputOutput);
+        let resolve_handle = builder.add_optional_render_target(resolve, NodeResourceUsage::InputOutput);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: color_handle,
+                clear: Color::BLACK,
+                resolve: resolve_handle,
+            }],
+            depth_stencil: Some(RenderPassDepthTarget {
+                target: depth_handle,
+                depth_clear: Some(0.0),
+                stencil_clear: common::has_stencil(self.depth_format).then_some(0),
+            }),
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            profiling::scope!("grid");
+
+            let uniforms = GridUniforms {
+                minor_color: self.minor_color,
+                major_color: self.major_color,
+                spacing: self.spacing,
+                major_spacing: self.spacing * self.major_every.max(1) as f32,
+                fade_distance: self.fade_distance,
+                _padding: 0,
+            };
+            let buffer = ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("grid uniforms"),
+                contents: bytemuck::bytes_of(&uniforms),
+                usage: BufferUsages::UNIFORM,
+            });
+            let grid_bg = ctx.temps.add(BindGroupBuilder::new().append_buffer(&buffer).build(
+                &ctx.renderer.device,
+                Some("grid bg"),
+                &self.bgl,
+            ));
+
+            let pipeline = match samples {
+                SampleCount::One => &self.pipeline_s1,
+                SampleCount::Four => &self.pipeline_s4,
+            };
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, grid_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,578 @@
+//! Rendering without a window, for automated tests and offline rendering.
+//!
+//! [`HeadlessRenderer`] bundles a [`Renderer`] with the default routines,
+//! rendering into an offscreen RGBA8 texture which can be read back after
+//! rendering. The scene is built the same way as with a window, through
+//! [`HeadlessRenderer::renderer`].
+//!
+//! To compare the output against reference images, enable rend3's `testing`
+//! feature and use `rend3::util::testing::compare_rgba8`.
+
+use std::sync::Arc;
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{RenderGraph, ViewportRect},
+    types::{Handedness, SampleCount},
+    util::{output::OutputFrame, readback},
+    InstanceAdapterDevice, Renderer, RendererInitializationError, ShaderPreProcessor,
+};
+use wgpu::TextureFormat;
+
+use crate::{base::BaseRenderGraph, pbr::PbrRoutine, tonemapping::TonemappingRoutine};
+
+/// A renderer and the default routines, rendering to an offscreen texture.
+pub struct HeadlessRenderer {
+    pub renderer: Arc<Renderer>,
+    pub spp: ShaderPreProcessor,
+    pub base_rendergraph: BaseRenderGraph,
+    pub pbr_routine: PbrRoutine,
+    pub tonemapping_routine: TonemappingRoutine,
+    pub samples: SampleCount,
+    pub ambient: Vec4,
+    pub clear_color: Vec4,
+    frame: OutputFrame,
+    resolution: UVec2,
+}
+
+impl HeadlessRenderer {
+    /// Create a renderer which renders to an offscreen texture of the given
+    /// resolution. Use [`create_iad`](rend3::create_iad) to get the IAD.
+    pub fn new(
+        iad: InstanceAdapterDevice,
+        handedness: Handedness,
+        resolution: UVec2,
+    ) -> Result<Self, RendererInitializationError> {
+        profiling::scope!("HeadlessRenderer::new");
+
+        let renderer = Renderer::new(iad, handedness, Some(resolution.x as f32 / resolution.y as f32))?;
+
+        let mut spp = ShaderPreProcessor::new();
+        crate::builtin_shaders(&mut spp);
+
+        let base_rendergraph = BaseRenderGraph::new(&renderer, &spp);
+
+        let mut data_core = renderer.data_core.write();
+        let pbr_routine = PbrRoutine::new(&renderer, &mut data_core, &spp, &base_rendergraph.interfaces);
+        drop(data_core);
+        let tonemapping_routine = TonemappingRoutine::new(
+            &renderer,
+            &spp,
+            &base_rendergraph.interfaces,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+
+        let texture = Arc::new(readback::create_readback_texture(&renderer.device, resolution));
+
+        Ok(Self {
+            renderer,
+            spp,
+            base_rendergraph,
+            pbr_routine,
+            tonemapping_routine,
+            samples: SampleCount::One,
+            ambient: Vec4::ZERO,
+            clear_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            frame: OutputFrame::from_texture(texture),
+            resolution,
+        })
+    }
+
+    pub fn resolution(&self) -> UVec2 {
+        self.resolution
+    }
+
+    /// The frame rendered to, holding an `Rgba8UnormSrgb` texture.
+    pub fn frame(&self) -> &OutputFrame {
+        &self.frame
+    }
+
+    /// Render `frames` frames into the offscreen texture. Rendering more
+    /// than one frame lets anything which accumulates over frames, like
+    /// shadows or temporal effects, settle.
+    ///
+    /// The PBR routine is rebuilt when the texture array had to grow, and
+    /// [`BaseRenderGraph::evaluate`] is called every frame.
+    pub fn render(&mut self, frames: usize) {
+        let texture = self.frame.as_texture().unwrap();
+
+        for _ in 0..frames {
+            profiling::scope!("Headless Frame");
+
+            self.renderer.swap_instruction_buffers();
+            let mut eval_output = self.renderer.evaluate_instructions();
+
+            self.pbr_routine.rebuild_if_outdated(
+                &self.renderer,
+                &mut self.renderer.data_core.write(),
+                &self.spp,
+                &self.base_rendergraph.interfaces,
+            );
+            self.base_rendergraph.evaluate(&self.renderer, self.resolution);
+
+            let mut graph = RenderGraph::new();
+            let frame_handle =
+                graph.add_imported_render_target(&**texture, 0..1, ViewportRect::from_size(self.resolution));
+            self.base_rendergraph.add_to_graph(
+                &mut graph,
+                &eval_output,
+                &self.pbr_routine,
+                None,
+                &self.tonemapping_routine,
+                frame_handle,
+                self.resolution,
+                self.samples,
+                self.ambient,
+                self.clear_color,
+            );
+            graph.execute(&self.renderer, &mut eval_output);
+        }
+    }
+
+    /// Read the offscreen texture back as tightly packed RGBA8 rows, top row
+    /// first. Blocks until rendering is done.
+    pub fn read_back(&self) -> Vec<u8> {
+        readback::read_texture_rgba8(
+            &self.renderer.device,
+            &self.renderer.queue,
+            self.frame.as_texture().unwrap(),
+        )
+    }
+
+    /// Render `frames` frames and read back the result.
+    pub fn render_and_read_back(&mut self, frames: usize) -> Vec<u8> {
+        self.render(frames);
+        self.read_back()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, thread};
+
+    use glam::{Mat4, UVec2, Vec2, Vec3, Vec4};
+    use rend3::{
+        managers::MeshUpdateError,
+        types::{
+            DirectionalLight, Handedness, LightUnits, MeshBuilder, MipmapCount, MipmapSource, ObjectMeshKind, Skeleton,
+            StoredVertexAttributeData, Texture, VERTEX_ATTRIBUTE_COLOR_0, VERTEX_ATTRIBUTE_NORMAL,
+            VERTEX_ATTRIBUTE_POSITION,
+        },
+        util::output::OutputFrame,
+        RendererProfile,
+    };
+    use wgpu::{Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
+
+    use crate::{
+        common::test_util::{add_quad, headless_or_skip, object, quad_mesh},
+        pbr::{pbr_material_key, AlbedoComponent, BlendMode, PbrMaterial, SampleType, TransparencyType, WrapMode},
+        tonemapping::TonemappingRoutine,
+    };
+
+    /// A linear 0.5 is 187.5 after sRGB encoding.
+    const ENCODED_HALF: u8 = 188;
+
+    /// Clear the hdr buffer to a linear 0.5 and read back the 8-bit output of
+    /// the given format. `None` if there is no gpu.
+    fn render_linear_half(format: TextureFormat) -> Option<Vec<u8>> {
+        let resolution = UVec2::splat(4);
+        let mut headless = headless_or_skip(None, resolution)?;
+        headless.clear_color = Vec4::new(0.5, 0.5, 0.5, 1.0);
+
+        headless.tonemapping_routine = TonemappingRoutine::new(
+            &headless.renderer,
+            &headless.spp,
+            &headless.base_rendergraph.interfaces,
+            format,
+        );
+        let texture = headless.renderer.device.create_texture(&TextureDescriptor {
+            label: Some("linear half target"),
+            size: Extent3d {
+                width: resolution.x,
+                height: resolution.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        headless.frame = OutputFrame::from_texture(Arc::new(texture));
+
+        Some(headless.render_and_read_back(1))
+    }
+
+    fn assert_encoded_half(pixels: &[u8]) {
+        for pixel in pixels.chunks_exact(4) {
+            for &channel in &pixel[..3] {
+                assert!(
+                    channel.abs_diff(ENCODED_HALF) <= 1,
+                    "Expected {ENCODED_HALF}, got {pixel:?}"
+                );
+            }
+            assert_eq!(pixel[3], 255);
+        }
+    }
+
+    #[test]
+    fn linear_half_srgb_output() {
+        let Some(pixels) = render_linear_half(TextureFormat::Rgba8UnormSrgb) else {
+            return;
+        };
+        assert_encoded_half(&pixels);
+    }
+
+    #[test]
+    fn linear_half_unorm_output() {
+        let Some(pixels) = render_linear_half(TextureFormat::Rgba8Unorm) else {
+            return;
+        };
+        assert_encoded_half(&pixels);
+    }
+
+    /// Render a grey lit quad filling the screen, lit head on by a sun of the
+    /// given intensity. `None` if there is no gpu.
+    fn render_sun(light_units: LightUnits, intensity: f32) -> Option<Vec<u8>> {
+        let mut headless = headless_or_skip(None, UVec2::splat(4))?;
+        assert_eq!(
+            headless.base_rendergraph.interfaces.hdr_format,
+            TextureFormat::Rgba16Float
+        );
+        let renderer = Arc::clone(&headless.renderer);
+
+        renderer.set_light_units(light_units);
+        let _sun = renderer.add_directional_light(DirectionalLight {
+            color: Vec3::ONE,
+            resolution: 256,
+            cast_shadows: false,
+            intensity,
+            direction: Vec3::Z,
+            distance: 10.0,
+            caster_margin: 0.0,
+        });
+
+        let mesh = quad_mesh(Vec2::splat(-10.0), Vec2::splat(10.0)).build().unwrap();
+        let material = renderer.add_material(PbrMaterial {
+            albedo: AlbedoComponent::Value(Vec4::new(0.5, 0.5, 0.5, 1.0)),
+            ..PbrMaterial::default()
+        });
+        let _quad = renderer.add_object(object(ObjectMeshKind::Static(renderer.add_mesh(mesh)), material));
+
+        Some(headless.render_and_read_back(1))
+    }
+
+    #[test]
+    fn photometric_sun_fits_half_float() {
+        // Direct sunlight on a sunny day.
+        let units = LightUnits::Photometric { ev100: 15.0 };
+        let Some(photometric) = render_sun(units, 100_000.0) else {
+            return;
+        };
+        // The light is exposed before shading, so the hdr buffer holds the same
+        // values as for a relative light of the exposed intensity. Exposing
+        // after shading would overflow the half floats.
+        let relative = render_sun(LightUnits::Relative, 100_000.0 * units.exposure()).unwrap();
+        assert_eq!(photometric, relative);
+        for pixel in photometric.chunks_exact(4) {
+            assert!(
+                pixel[..3].iter().all(|&channel| channel > 0 && channel < 255),
+                "Expected a lit but not saturated pixel, got {pixel:?}"
+            );
+        }
+    }
+
+    /// Render a red and a green material next to each other with the given
+    /// profile. `None` if there is no gpu supporting it.
+    fn render_two_materials(profile: RendererProfile) -> Option<Vec<u8>> {
+        let mut headless = headless_or_skip(Some(profile), UVec2::new(8, 4))?;
+
+        let _red = add_quad(&headless.renderer, -1.0, 0.0, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let _green = add_quad(&headless.renderer, 0.0, 1.0, Vec4::new(0.0, 1.0, 0.0, 1.0));
+
+        Some(headless.render_and_read_back(1))
+    }
+
+    #[test]
+    fn cpu_driven_matches_gpu_driven() {
+        let Some(cpu) = render_two_materials(RendererProfile::CpuDriven) else {
+            return;
+        };
+
+        let row = &cpu[2 * 8 * 4..3 * 8 * 4];
+        assert_eq!(&row[2 * 4..3 * 4], &[255, 0, 0, 255], "Expected red, got {row:?}");
+        assert_eq!(&row[5 * 4..6 * 4], &[0, 255, 0, 255], "Expected green, got {row:?}");
+
+        // The profiles use different shaders, which may round differently.
+        if let Some(gpu) = render_two_materials(RendererProfile::GpuDriven) {
+            assert_eq!(cpu.len(), gpu.len());
+            for (cpu, gpu) in cpu.chunks_exact(4).zip(gpu.chunks_exact(4)) {
+                assert!(
+                    cpu.iter().zip(gpu).all(|(&cpu, &gpu)| cpu.abs_diff(gpu) <= 1),
+                    "Expected {cpu:?}, got {gpu:?}"
+                );
+            }
+        }
+    }
+
+    /// Render a repeating and a clamping quad next to each other with the given
+    /// profile, both reading the red and green halves of the same texture
+    /// at u from 1 to 2. `None` if there is no gpu supporting it.
+    fn render_wrap_modes(profile: RendererProfile) -> Option<Vec<u8>> {
+        let mut headless = headless_or_skip(Some(profile), UVec2::new(8, 4))?;
+        let renderer = Arc::clone(&headless.renderer);
+
+        let texture = renderer.add_texture_2d(Texture {
+            label: None,
+            data: vec![255, 0, 0, 255, 0, 255, 0, 255],
+            format: TextureFormat::Rgba8Unorm,
+            size: UVec2::new(2, 1),
+            mip_count: MipmapCount::ONE,
+            mip_source: MipmapSource::Uploaded,
+            color_space: None,
+        });
+
+        let objects: Vec<_> = [(-1.0, WrapMode::Repeat), (0.0, WrapMode::ClampToEdge)]
+            .into_iter()
+            .map(|(x0, wrap_mode)| {
+                let uvs = vec![
+                    Vec2::new(1.0, 0.0),
+                    Vec2::new(2.0, 0.0),
+                    Vec2::new(2.0, 1.0),
+                    Vec2::new(1.0, 1.0),
+                ];
+                let mesh = quad_mesh(Vec2::new(x0, -1.0), Vec2::new(x0 + 1.0, 1.0))
+                    .with_vertex_texture_coordinates_0(uvs)
+                    .build()
+                    .unwrap();
+                let material = renderer.add_material(PbrMaterial {
+                    albedo: AlbedoComponent::Texture(texture.clone()),
+                    unlit: true,
+                    sample_type: SampleType::Nearest,
+                    wrap_mode,
+                    ..PbrMaterial::default()
+                });
+
+                renderer.add_object(object(ObjectMeshKind::Static(renderer.add_mesh(mesh)), material))
+            })
+            .collect();
+
+        let pixels = headless.render_and_read_back(1);
+        drop(objects);
+        Some(pixels)
+    }
+
+    #[test]
+    fn wrap_mode_per_material() {
+        for profile in [RendererProfile::CpuDriven, RendererProfile::GpuDriven] {
+            let Some(pixels) = render_wrap_modes(profile) else {
+                continue;
+            };
+
+            // Repeating starts over at red, clamping stays at the green edge.
+            let row = &pixels[2 * 8 * 4..3 * 8 * 4];
+            assert_eq!(&row[0..4], &[255, 0, 0, 255], "{profile:?}: Expected red, got {row:?}");
+            assert_eq!(
+                &row[3 * 4..4 * 4],
+                &[0, 255, 0, 255],
+                "{profile:?}: Expected green, got {row:?}"
+            );
+            assert_eq!(
+                &row[4 * 4..5 * 4],
+                &[0, 255, 0, 255],
+                "{profile:?}: Expected green, got {row:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn gpu_driven_selects_material_per_object() {
+        let Some(mut headless) = headless_or_skip(Some(RendererProfile::GpuDriven), UVec2::new(8, 4)) else {
+            return;
+        };
+        headless
+            .base_rendergraph
+            .gpu_culler
+            .set_stats_enabled(&headless.renderer, true);
+
+        let _red = add_quad(&headless.renderer, -1.0, 0.0, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let _green = add_quad(&headless.renderer, 0.0, 1.0, Vec4::new(0.0, 1.0, 0.0, 1.0));
+
+        let pixels = headless.render_and_read_back(1);
+        let row = &pixels[2 * 8 * 4..3 * 8 * 4];
+        assert_eq!(&row[2 * 4..3 * 4], &[255, 0, 0, 255], "Expected red, got {row:?}");
+        assert_eq!(&row[5 * 4..6 * 4], &[0, 255, 0, 255], "Expected green, got {row:?}");
+
+        // Both materials share the bindless bind group, so both objects are
+        // drawn by one call and only the material index tells them apart.
+        let stats = headless.base_rendergraph.gpu_culler.stats(&headless.renderer).unwrap();
+        let opaque = stats
+            .camera
+            .pass(pbr_material_key(TransparencyType::Opaque, BlendMode::Alpha));
+        assert_eq!(opaque.objects, 2);
+        assert_eq!(opaque.draw_calls, 1);
+    }
+
+    #[test]
+    fn invalid_skeleton_is_skipped() {
+        let Some(mut headless) = headless_or_skip(None, UVec2::new(8, 4)) else {
+            return;
+        };
+        let renderer = Arc::clone(&headless.renderer);
+
+        // The mesh has no joints, so neither the skeleton nor the object using
+        // it can be added.
+        let mesh = quad_mesh(Vec2::new(-1.0, -1.0), Vec2::new(0.0, 1.0)).build().unwrap();
+        let skeleton = renderer.add_skeleton(Skeleton {
+            joint_matrices: vec![Mat4::IDENTITY],
+            mesh: renderer.add_mesh(mesh),
+        });
+        let material = renderer.add_material(PbrMaterial {
+            albedo: AlbedoComponent::Value(Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            unlit: true,
+            ..PbrMaterial::default()
+        });
+        let invalid = renderer.add_object(object(ObjectMeshKind::Animated(skeleton.clone()), material));
+        let _green = add_quad(&renderer, 0.0, 1.0, Vec4::new(0.0, 1.0, 0.0, 1.0));
+
+        let pixels = headless.render_and_read_back(1);
+        let row = &pixels[2 * 8 * 4..3 * 8 * 4];
+        assert_eq!(
+            &row[2 * 4..3 * 4],
+            &[0, 0, 0, 255],
+            "Expected the clear color, got {row:?}"
+        );
+        assert_eq!(&row[5 * 4..6 * 4], &[0, 255, 0, 255], "Expected green, got {row:?}");
+
+        // Changes to the skipped skeleton and object are ignored.
+        renderer.set_skeleton_joint_matrices(&skeleton, vec![Mat4::IDENTITY]);
+        renderer.set_object_transform(&invalid, Mat4::from_translation(Vec3::X));
+        drop(invalid);
+        drop(skeleton);
+        headless.render(1);
+    }
+
+    #[test]
+    fn mesh_update_changes_vertex_count() {
+        let Some(mut headless) = headless_or_skip(None, UVec2::splat(4)) else {
+            return;
+        };
+        let renderer = Arc::clone(&headless.renderer);
+
+        let positions = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+        let mesh = MeshBuilder::new(positions, Handedness::Left)
+            .without_tangents()
+            .build()
+            .unwrap();
+        let mesh = renderer.add_mesh(mesh);
+
+        // Validated right away, before the mesh is filled during evaluation.
+        let grown = vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::ONE];
+        assert_eq!(
+            renderer.update_mesh_vertices(
+                &mesh,
+                vec![StoredVertexAttributeData::new(
+                    &VERTEX_ATTRIBUTE_POSITION,
+                    grown.clone()
+                )]
+            ),
+            Err(MeshUpdateError::IncompleteResize(VERTEX_ATTRIBUTE_NORMAL.name()))
+        );
+        assert_eq!(
+            renderer.update_mesh_vertices(
+                &mesh,
+                vec![
+                    StoredVertexAttributeData::new(&VERTEX_ATTRIBUTE_POSITION, grown.clone()),
+                    StoredVertexAttributeData::new(&VERTEX_ATTRIBUTE_NORMAL, vec![Vec3::Z; 3]),
+                ]
+            ),
+            Err(MeshUpdateError::VertexCountMismatch {
+                name: VERTEX_ATTRIBUTE_NORMAL.name(),
+                expected: 4,
+                actual: 3,
+            })
+        );
+        assert_eq!(
+            renderer.update_mesh_vertices(
+                &mesh,
+                vec![StoredVertexAttributeData::new(
+                    &VERTEX_ATTRIBUTE_COLOR_0,
+                    vec![[0; 4]; 3]
+                )]
+            ),
+            Err(MeshUpdateError::MissingAttribute(VERTEX_ATTRIBUTE_COLOR_0.name()))
+        );
+
+        let moved = vec![Vec3::ZERO, Vec3::Z, Vec3::Y];
+        renderer
+            .update_mesh_vertices(
+                &mesh,
+                vec![StoredVertexAttributeData::new(&VERTEX_ATTRIBUTE_POSITION, moved)],
+            )
+            .unwrap();
+        headless.render(1);
+
+        // Growing moves the attributes, and later updates are validated
+        // against the new vertex count.
+        renderer
+            .update_mesh_vertices(
+                &mesh,
+                vec![
+                    StoredVertexAttributeData::new(&VERTEX_ATTRIBUTE_POSITION, grown.clone()),
+                    StoredVertexAttributeData::new(&VERTEX_ATTRIBUTE_NORMAL, vec![Vec3::Z; 4]),
+                ],
+            )
+            .unwrap();
+        renderer
+            .update_mesh_vertices(
+                &mesh,
+                vec![StoredVertexAttributeData::new(&VERTEX_ATTRIBUTE_POSITION, grown)],
+            )
+            .unwrap();
+        headless.render(1);
+    }
+
+    #[test]
+    fn render_while_changing_the_world() {
+        const WRITERS: usize = 4;
+        const OBJECTS: usize = 100;
+
+        let Some(mut headless) = headless_or_skip(None, UVec2::splat(4)) else {
+            return;
+        };
+
+        // Renders on this thread while the writers add and remove objects, as
+        // an application with a loading thread would.
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let renderer = Arc::clone(&headless.renderer);
+                thread::spawn(move || {
+                    let mut objects = Vec::new();
+                    for i in 0..OBJECTS {
+                        objects.push(add_quad(&renderer, -1.0, 1.0, Vec4::ONE));
+                        if i % 2 == 0 {
+                            objects.remove(0);
+                        }
+                    }
+                    objects
+                })
+            })
+            .collect();
+
+        while writers.iter().any(|writer| !writer.is_finished()) {
+            headless.render(1);
+        }
+        let objects: Vec<_> = writers.into_iter().flat_map(|writer| writer.join().unwrap()).collect();
+        assert_eq!(objects.len(), WRITERS * OBJECTS / 2);
+
+        // Picks up the last additions, then the removals of everything.
+        let pixels = headless.render_and_read_back(1);
+        assert!(
+            pixels.chunks_exact(4).any(|pixel| pixel == [255; 4]),
+            "Expected the quads, got {pixels:?}"
+        );
+        drop(objects);
+        headless.render(1);
+    }
+}
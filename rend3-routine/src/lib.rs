@@ -15,17 +15,43 @@
 //! your own code and adding/modifying the routine to your hearts content. The
 //! abstraction is designed to be easily replaced and extended without needing
 //! too much user side boilerplate.
+//!
+//! # Color Spaces
+//!
+//! All rendering happens in linear space, and the output is encoded as sRGB
+//! exactly once, while tonemapping:
+//!
+//! - Material values and vertex colors are linear, unless the material says
+//!   vertex colors are sRGB, in which case they are decoded in the shader.
+//!   Textures with an sRGB format are decoded by the hardware when sampled.
+//! - The hdr buffer, and everything rendered into it, is linear. It is
+//!   `Rgba16Float` unless changed with
+//!   [`WholeFrameInterfaces::set_hdr_format`](common::WholeFrameInterfaces::set_hdr_format).
+//! - [`TonemappingRoutine`](tonemapping::TonemappingRoutine) encodes to sRGB.
+//!   For sRGB output formats the shader writes linear color and the hardware
+//!   encodes it, for all other formats the shader encodes it itself.
+//!
+//! A linear value of 0.5 thus ends up as 188 in an 8-bit output of either kind.
 
 pub mod base;
 pub mod clear;
 pub mod common;
 pub mod culling;
+pub mod debug_lines;
+pub mod decal;
+pub mod depth_readback;
+pub mod depth_resolve;
 pub mod forward;
+pub mod grid;
+pub mod headless;
 pub mod pbr;
+pub mod probe;
 mod shaders;
 pub mod skinning;
 pub mod skybox;
+pub mod ssr;
+pub mod taa;
 pub mod tonemapping;
 pub mod uniforms;
 
-pub use shaders::builtin_shaders;
+pub use shaders::{builtin_shaders, ShaderOverrideError};
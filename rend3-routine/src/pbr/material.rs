@@ -7,6 +7,7 @@ use rend3::types::{
     VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
     VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_0, VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_1,
 };
+use wgpu::{BlendComponent, BlendFactor, BlendOperation, BlendState};
 
 bitflags::bitflags! {
     /// Flags which shaders use to determine properties of a material
@@ -27,6 +28,15 @@ bitflags::bitflags! {
         const CC_BW_SPLIT =         0b0001_0000_0000_0000;
         const UNLIT =               0b0010_0000_0000_0000;
         const NEAREST =             0b0100_0000_0000_0000;
+        const TOON =                0b1000_0000_0000_0000;
+        const FLAT =                0b1_0000_0000_0000_0000;
+        const TWO_SIDED_LIGHTING =  0b10_0000_0000_0000_0000;
+        const CLAMP =               0b100_0000_0000_0000_0000;
+        const MIRROR =              0b1000_0000_0000_0000_0000;
+        const BORDER =              0b1_0000_0000_0000_0000_0000;
+        const PARALLAX =            0b10_0000_0000_0000_0000_0000;
+        const PARALLAX_CLIP =       0b100_0000_0000_0000_0000_0000;
+        const SUBSURFACE =          0b1000_0000_0000_0000_0000_0000;
     }
 }
 
@@ -416,6 +426,28 @@ impl Default for SampleType {
     }
 }
 
+/// What happens to texture coordinates outside of the 0-1 range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WrapMode {
+    /// The texture tiles.
+    Repeat,
+    /// The edge texels are stretched out. Use this for textures which must
+    /// not bleed into the opposite edge, like decals or UI.
+    ClampToEdge,
+    /// The texture tiles, mirroring every other tile.
+    MirrorRepeat,
+    /// The [`border_color`](crate::common::SamplerOptions::border_color) is
+    /// used outside of the texture. Falls back to [`Self::ClampToEdge`] if the
+    /// device doesn't support
+    /// [`Features::ADDRESS_MODE_CLAMP_TO_BORDER`](wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER).
+    ClampToBorder,
+}
+impl Default for WrapMode {
+    fn default() -> Self {
+        Self::Repeat
+    }
+}
+
 /// The type of transparency in a material.
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -483,15 +515,191 @@ impl Default for Transparency {
     }
 }
 
+/// How a material with [`Transparency::Blend`] is blended with what is behind
+/// it.
+///
+/// Every mode has its own pipeline, so objects are drawn grouped by blend mode,
+/// and only sorted back to front within each mode.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Standard "over" blending using the alpha of the material.
+    Alpha,
+    /// The color, scaled by alpha, is added to the background. Useful for
+    /// fire and other glowing effects.
+    Additive,
+    /// The background is multiplied by the color, ignoring alpha. Useful
+    /// for tinted glass.
+    Multiply,
+    /// The color is added to the background scaled by `1 - alpha`, without
+    /// scaling the color itself by alpha. This keeps highlights on mostly
+    /// transparent surfaces bright.
+    PremultipliedAlpha,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        Self::Alpha
+    }
+}
+
+impl BlendMode {
+    /// All blend modes, in the order of their discriminants.
+    pub const ALL: [Self; 4] = [Self::Alpha, Self::Additive, Self::Multiply, Self::PremultipliedAlpha];
+
+    /// The blend state of the pipeline for this mode.
+    pub fn to_blend_state(self) -> BlendState {
+        // Additive and multiplicative blending leave the alpha of the target untouched.
+        let keep_alpha = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        match self {
+            // Alpha accumulates coverage, so the output can be composited with premultiplied alpha.
+            Self::Alpha => BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::OneMinusSrcAlpha,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent::OVER,
+            },
+            Self::Additive => BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            },
+            Self::Multiply => BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::Dst,
+                    dst_factor: BlendFactor::Zero,
+                    operation: BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            },
+            Self::PremultipliedAlpha => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        }
+    }
+}
+
+/// The material key of [`PbrMaterial`]s with the given transparency and blend
+/// mode. The blend mode only matters with [`TransparencyType::Blend`].
+pub fn pbr_material_key(transparency: TransparencyType, blend_mode: BlendMode) -> u64 {
+    match transparency {
+        TransparencyType::Blend => transparency as u64 | (blend_mode as u64) << 8,
+        _ => transparency as u64,
+    }
+}
+
+/// Parameters for toon (cel) shading.
+///
+/// Diffuse light is quantized into bands, specular highlights have a hard
+/// edge, and a rim light is added around the silhouette.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToonShading {
+    /// Amount of discrete steps diffuse lighting is quantized into.
+    pub bands: u32,
+    /// Color of the rim light. Black disables the rim light.
+    pub rim_color: Vec3,
+    /// Exponent controlling the width of the rim. Higher values make a
+    /// thinner rim.
+    pub rim_power: f32,
+}
+impl Default for ToonShading {
+    fn default() -> Self {
+        Self {
+            bands: 3,
+            rim_color: Vec3::splat(0.2),
+            rim_power: 4.0,
+        }
+    }
+}
+
+/// Parallax occlusion mapping, which offsets the texture coordinates of the
+/// whole material by ray marching a height map, so the surface appears to
+/// have relief.
+///
+/// The height is read from the red channel of the texture, with white at the
+/// surface and black at [`Self::scale`] below it. The ray is marched in the
+/// same tangent frame as normal textures, so the mesh needs tangents. Only
+/// shading is affected: the depth of the surface and shadows cast by it stay
+/// flat.
+#[derive(Debug, Clone)]
+pub struct ParallaxMapping {
+    /// Height texture. Must be a linear format.
+    pub texture: Texture2DHandle,
+    /// Depth of the lowest point of the height map, in texture coordinates.
+    pub scale: f32,
+    /// Amount of steps the ray is marched with when looking along the surface.
+    /// A quarter of these are used when looking straight at it. More steps
+    /// remove stair stepping at grazing angles, at the cost of a texture read
+    /// each.
+    pub steps: u32,
+    /// Discard pixels whose offset texture coordinates leave the 0-1 range, so
+    /// the relief shows on the silhouette at the edges of the texture. Only
+    /// useful when the texture covers the surface exactly once.
+    pub clip_edges: bool,
+}
+impl ParallaxMapping {
+    pub fn new(texture: Texture2DHandle) -> Self {
+        Self {
+            texture,
+            scale: 0.05,
+            steps: 32,
+            clip_edges: false,
+        }
+    }
+}
+
+/// A cheap approximation of light scattering below the surface, for skin, wax
+/// and foliage.
+///
+/// Diffuse light from directional lights wraps around past the terminator,
+/// tinted by [`Self::color`], and light shining through thin parts of the
+/// surface shows up on the side facing away from the light. Area lights
+/// ignore it.
+#[derive(Debug, Clone)]
+pub struct Subsurface {
+    /// Color of light after it scattered below the surface.
+    pub color: Vec3,
+    /// How far diffuse light wraps around the surface. 0 doesn't wrap at all,
+    /// 1 lights the whole side facing away from the light.
+    pub wrap: f32,
+    /// Thickness of the surface, from 0 letting all light through to 1
+    /// letting none through. Read from the red channel of the texture.
+    pub thickness: MaterialComponent<f32>,
+}
+impl Default for Subsurface {
+    fn default() -> Self {
+        Self {
+            color: Vec3::new(1.0, 0.2, 0.1),
+            wrap: 0.5,
+            thickness: MaterialComponent::Value(0.5),
+        }
+    }
+}
+
 // Consider:
 //
 // - Green screen value
 /// A set of textures and values that determine the how an object interacts with
 /// light.
+///
+/// The albedo and emissive textures hold colors, so they should be created with
+/// an sRGB format. All other textures hold data and should be created with
+/// a linear format, otherwise they will be decoded as if they were colors. Set
+/// [`Texture::color_space`](rend3::types::Texture::color_space) to pick the
+/// right format.
 #[derive(Default)]
 pub struct PbrMaterial {
     pub albedo: AlbedoComponent,
     pub transparency: Transparency,
+    /// How the material is blended, only used with [`Transparency::Blend`].
+    pub blend_mode: BlendMode,
     pub normal: NormalTexture,
     pub aomr_textures: AoMRTextures,
     pub ao_factor: Option<f32>,
@@ -505,14 +713,35 @@ pub struct PbrMaterial {
     pub anisotropy: MaterialComponent<f32>,
     pub uv_transform0: Mat3,
     pub uv_transform1: Mat3,
+    /// Skip all lighting and output the albedo (including vertex color, as
+    /// configured by [`AlbedoComponent`]) directly. Transparency is still
+    /// respected.
     // TODO: Make unlit a different shader entirely.
     pub unlit: bool,
+    /// If set, use toon shading instead of physically based shading.
+    pub toon: Option<ToonShading>,
+    /// If set, offset texture coordinates with parallax occlusion mapping.
+    /// Materials without it don't pay for it.
+    pub parallax: Option<ParallaxMapping>,
+    /// If set, approximate subsurface scattering.
+    pub subsurface: Option<Subsurface>,
+    /// Light the material with per-face normals, derived from the screen space
+    /// derivatives of the position, instead of interpolated vertex normals.
+    /// Normal textures are ignored.
+    pub flat_shading: bool,
+    /// Flip the shading normal of back faces, so they are lit like front
+    /// faces. This doesn't change culling, so back faces still need to be
+    /// visible, like for thin foliage.
+    pub two_sided_lighting: bool,
     pub sample_type: SampleType,
+    /// Wrapping of all textures of the material. Only respected in forward
+    /// rendering, other passes always repeat.
+    pub wrap_mode: WrapMode,
 }
 
 impl Material for PbrMaterial {
     type DataType = ShaderMaterial;
-    type TextureArrayType = [Option<RawTexture2DHandle>; 10];
+    type TextureArrayType = [Option<RawTexture2DHandle>; 12];
     type RequiredAttributeArrayType = [&'static VertexAttributeId; 1];
     type SupportedAttributeArrayType = [&'static VertexAttributeId; 6];
 
@@ -532,7 +761,7 @@ impl Material for PbrMaterial {
     }
 
     fn key(&self) -> u64 {
-        TransparencyType::from(self.transparency) as u64
+        pbr_material_key(self.transparency.into(), self.blend_mode)
     }
 
     fn sorting(&self) -> Sorting {
@@ -551,6 +780,8 @@ impl Material for PbrMaterial {
             self.emissive.to_texture(),
             self.anisotropy.to_texture(),
             self.aomr_textures.to_ao_texture(),
+            self.parallax.as_ref().map(|p| &p.texture),
+            self.subsurface.as_ref().and_then(|s| s.thickness.to_texture()),
         ]
         .map(|opt| opt.map(|r| r.get_raw()))
     }
@@ -558,6 +789,21 @@ impl Material for PbrMaterial {
     fn to_data(&self) -> Self::DataType {
         ShaderMaterial::from_material(self)
     }
+
+    fn sampler_index(&self) -> u8 {
+        // Matches the order of `Samplers::material_sampler`.
+        let wrap = match self.wrap_mode {
+            WrapMode::Repeat => 0,
+            WrapMode::ClampToEdge => 1,
+            WrapMode::MirrorRepeat => 2,
+            WrapMode::ClampToBorder => 3,
+        };
+        let filter = match self.sample_type {
+            SampleType::Linear => 0,
+            SampleType::Nearest => 1,
+        };
+        wrap * 2 + filter
+    }
 }
 
 #[repr(C)]
@@ -578,6 +824,16 @@ pub struct ShaderMaterial {
     alpha_cutout: f32,
 
     material_flags: MaterialFlags,
+
+    rim_color: Vec3,
+    rim_power: f32,
+    toon_bands: u32,
+    parallax_scale: f32,
+    parallax_steps: u32,
+    thickness: f32,
+
+    subsurface_color: Vec3,
+    subsurface_wrap: f32,
 }
 
 unsafe impl bytemuck::Zeroable for ShaderMaterial {}
@@ -601,12 +857,29 @@ impl ShaderMaterial {
                 Transparency::Cutout { cutout } => cutout,
                 _ => 0.0,
             },
+            rim_color: material.toon.map_or(Vec3::ZERO, |t| t.rim_color),
+            rim_power: material.toon.map_or(0.0, |t| t.rim_power),
+            toon_bands: material.toon.map_or(0, |t| t.bands),
+            parallax_scale: material.parallax.as_ref().map_or(0.0, |p| p.scale),
+            parallax_steps: material.parallax.as_ref().map_or(0, |p| p.steps),
+            thickness: material.subsurface.as_ref().map_or(1.0, |s| s.thickness.to_value(1.0)),
+            subsurface_color: material.subsurface.as_ref().map_or(Vec3::ZERO, |s| s.color),
+            subsurface_wrap: material.subsurface.as_ref().map_or(0.0, |s| s.wrap),
             material_flags: {
                 let mut flags = material.albedo.to_flags();
                 flags |= material.normal.to_flags();
                 flags |= material.aomr_textures.to_flags();
                 flags |= material.clearcoat_textures.to_flags();
                 flags.set(MaterialFlags::UNLIT, material.unlit);
+                flags.set(MaterialFlags::TOON, material.toon.is_some());
+                flags.set(MaterialFlags::PARALLAX, material.parallax.is_some());
+                flags.set(MaterialFlags::SUBSURFACE, material.subsurface.is_some());
+                flags.set(
+                    MaterialFlags::PARALLAX_CLIP,
+                    material.parallax.as_ref().map_or(false, |p| p.clip_edges),
+                );
+                flags.set(MaterialFlags::FLAT, material.flat_shading);
+                flags.set(MaterialFlags::TWO_SIDED_LIGHTING, material.two_sided_lighting);
                 flags.set(
                     MaterialFlags::NEAREST,
                     match material.sample_type {
@@ -614,6 +887,12 @@ impl ShaderMaterial {
                         SampleType::Linear => false,
                     },
                 );
+                flags |= match material.wrap_mode {
+                    WrapMode::Repeat => MaterialFlags::empty(),
+                    WrapMode::ClampToEdge => MaterialFlags::CLAMP,
+                    WrapMode::MirrorRepeat => MaterialFlags::MIRROR,
+                    WrapMode::ClampToBorder => MaterialFlags::BORDER,
+                };
                 flags
             },
         }
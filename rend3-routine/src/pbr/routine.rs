@@ -1,19 +1,60 @@
 use std::borrow::Cow;
 
-use rend3::{Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor, ShaderVertexBufferConfig};
+use rend3::{
+    util::parallel, Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor, ShaderVertexBufferConfig,
+};
 use serde::Serialize;
-use wgpu::{BlendState, ShaderModuleDescriptor, ShaderSource};
+use wgpu::{CompareFunction, ShaderModuleDescriptor, ShaderSource, TextureFormat};
 
 use crate::{
-    common::{PerMaterialArchetypeInterface, WholeFrameInterfaces},
+    common::{PerMaterialArchetypeInterface, WholeFrameInterfaces, GBUFFER_FORMATS, VELOCITY_FORMAT},
+    culling::OVERLAY_MATERIAL_KEY_BIT,
     forward::{ForwardRoutine, RoutineArgs, RoutineType, ShaderModulePair},
-    pbr::{PbrMaterial, TransparencyType},
+    pbr::{pbr_material_key, BlendMode, PbrMaterial, TransparencyType},
+    shaders::{check_override, ShaderOverrideError},
 };
 
 #[derive(Serialize)]
 struct BlendModeWrapper {
     profile: RendererProfile,
     discard: bool,
+    blend: bool,
+    gbuffer: bool,
+    velocity: bool,
+}
+
+/// Replacement shaders for individual passes of the [`PbrRoutine`].
+///
+/// Each entry is the name of a shader registered with the
+/// [`ShaderPreProcessor`], so add the replacement source with
+/// [`ShaderPreProcessor::add_shader`] first. A replacement must use the same
+/// bindings as the shader it replaces and provide a `vs_main` and `fs_main`
+/// entry point. Like the builtin shaders, it is rendered with `discard` set for
+/// the cutout routines, `blend` set for the blend routines, and `profile` set
+/// to the renderer's profile. With
+/// [`WholeFrameInterfaces::gbuffer`], `gbuffer` is set for the opaque and
+/// cutout forward shaders, which then also need to write the gbuffer at
+/// locations 1 to 3. Likewise with [`WholeFrameInterfaces::velocity`],
+/// `velocity` is set and the velocity needs to be written to the location
+/// after the gbuffer, or location 1 without one. `forward_interface.wgsl`
+/// provides a `screen_velocity` function computing it.
+///
+/// Replacements are checked against the builtin shader with the same
+/// configuration when the routine is created: they need `vs_main` and
+/// `fs_main`, every binding they declare needs to exist in the builtin shader
+/// with the same type, and `fs_main` needs to write the same locations. Buffer
+/// layouts are only checked by wgpu's validation, with the shader's name in
+/// the pipeline label.
+#[derive(Debug, Default, Clone)]
+pub struct ShaderOverrides {
+    /// Replaces `rend3-routine/opaque.wgsl`, used by the forward, blend, and
+    /// overlay routines. The easiest way to write one is to include
+    /// `rend3-routine/forward_interface.wgsl`, which provides the bindings,
+    /// vertex shader, and material sampling, and only write `fs_main`.
+    pub forward: Option<String>,
+    /// Replaces `rend3-routine/depth.wgsl`, used by the depth prepass and
+    /// shadow routines.
+    pub depth: Option<String>,
 }
 
 /// Render routine that renders the using PBR materials
@@ -22,8 +63,19 @@ pub struct PbrRoutine {
     pub cutout_depth: ForwardRoutine<PbrMaterial>,
     pub opaque_routine: ForwardRoutine<PbrMaterial>,
     pub cutout_routine: ForwardRoutine<PbrMaterial>,
-    pub blend_routine: ForwardRoutine<PbrMaterial>,
+    /// One routine per [`BlendMode`], in the order of [`BlendMode::ALL`].
+    pub blend_routines: Vec<ForwardRoutine<PbrMaterial>>,
+    /// Routines for objects marked as [`overlay`](rend3::types::Object::overlay).
+    /// These always pass the depth test and never write depth.
+    pub overlay_opaque_routine: ForwardRoutine<PbrMaterial>,
+    pub overlay_cutout_routine: ForwardRoutine<PbrMaterial>,
+    pub overlay_blend_routines: Vec<ForwardRoutine<PbrMaterial>>,
     pub per_material: PerMaterialArchetypeInterface<PbrMaterial>,
+    overrides: ShaderOverrides,
+    /// If the opaque and cutout forward routines write the gbuffer, see
+    /// [`WholeFrameInterfaces::gbuffer`].
+    pub(crate) gbuffer: bool,
+    texture_layout_generation: u64,
 }
 
 impl PbrRoutine {
@@ -33,8 +85,49 @@ impl PbrRoutine {
         spp: &ShaderPreProcessor,
         interfaces: &WholeFrameInterfaces,
     ) -> Self {
+        Self::new_with_overrides(renderer, data_core, spp, interfaces, &ShaderOverrides::default())
+            .expect("Failed to create the builtin PBR routine")
+    }
+
+    /// Create the routine, using the given shader for forward rendering
+    /// instead of the builtin PBR shading.
+    ///
+    /// Shorthand for [`PbrRoutine::new_with_overrides`] with only
+    /// [`ShaderOverrides::forward`] set.
+    pub fn new_with_forward_shader(
+        renderer: &Renderer,
+        data_core: &mut RendererDataCore,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        forward_shader: &str,
+    ) -> Result<Self, ShaderOverrideError> {
+        let overrides = ShaderOverrides {
+            forward: Some(forward_shader.to_owned()),
+            ..ShaderOverrides::default()
+        };
+        Self::new_with_overrides(renderer, data_core, spp, interfaces, &overrides)
+    }
+
+    /// Create the routine, replacing the builtin shaders of some passes.
+    ///
+    /// See [`ShaderOverrides`] for the requirements on the replacements,
+    /// nothing is created if one of them doesn't meet them.
+    pub fn new_with_overrides(
+        renderer: &Renderer,
+        data_core: &mut RendererDataCore,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        overrides: &ShaderOverrides,
+    ) -> Result<Self, ShaderOverrideError> {
         profiling::scope!("PbrRenderRoutine::new");
 
+        // TODO: Persist the compiled pipelines between runs once wgpu has a pipeline cache, 0.16 doesn't expose
+        // compiled artifacts.
+        let forward_builtin = "rend3-routine/opaque.wgsl";
+        let depth_builtin = "rend3-routine/depth.wgsl";
+        let forward_shader = overrides.forward.as_deref().unwrap_or(forward_builtin);
+        let depth_shader = overrides.depth.as_deref().unwrap_or(depth_builtin);
+
         // This ensures the BGLs for the material are created
         data_core
             .material_manager
@@ -42,75 +135,90 @@ impl PbrRoutine {
 
         let per_material = PerMaterialArchetypeInterface::<PbrMaterial>::new(&renderer.device);
 
-        let pbr_depth_cutout = renderer.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("pbr depth cutout sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/depth.wgsl",
-                    &BlendModeWrapper {
-                        profile: renderer.profile,
-                        discard: true,
-                    },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
-                )
-                .unwrap(),
-            )),
-        });
-
-        let pbr_depth = renderer.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("pbr depth sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/depth.wgsl",
-                    &BlendModeWrapper {
-                        profile: renderer.profile,
-                        discard: false,
-                    },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
-                )
-                .unwrap(),
-            )),
-        });
-
-        let pbr_cutout = renderer.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("pbr opaque cutout sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/opaque.wgsl",
-                    &BlendModeWrapper {
-                        profile: renderer.profile,
-                        discard: true,
-                    },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+        // Only the main opaque and cutout forward passes write the gbuffer and velocity.
+        let create_module = |label: &str, (shader, builtin): (&str, &str), discard: bool, blend: bool, main: bool| {
+            let config = BlendModeWrapper {
+                profile: renderer.profile,
+                discard,
+                blend,
+                gbuffer: main && interfaces.gbuffer,
+                velocity: main && interfaces.velocity,
+            };
+            let vertex_config = ShaderVertexBufferConfig::from_material::<PbrMaterial>();
+            let render = |shader: &str| spp.render_shader(shader, &config, Some(&vertex_config));
+
+            let source = render(shader).map_err(|e| ShaderOverrideError::Preprocess {
+                shader: shader.to_owned(),
+                message: e.to_string(),
+            })?;
+            if shader != builtin {
+                let builtin_source =
+                    render(builtin).unwrap_or_else(|e| panic!("Failed to preprocess shader {builtin}: {e}"));
+                check_override(shader, &source, &builtin_source, &["vs_main", "fs_main"])?;
+            }
+            Ok(renderer.device.create_shader_module(ShaderModuleDescriptor {
+                label: Some(&format!("{label} ({shader})")),
+                source: ShaderSource::Wgsl(Cow::Owned(source)),
+            }))
+        };
+        let depth = (depth_shader, depth_builtin);
+        let forward = (forward_shader, forward_builtin);
+
+        // All the shader modules are independent, so compile them in parallel.
+        let ((pbr_depth_cutout, pbr_depth), (pbr_cutout, (pbr_forward, pbr_blend))) = parallel::join(
+            || {
+                parallel::join(
+                    || create_module("pbr depth cutout sm", depth, true, false, false),
+                    || create_module("pbr depth sm", depth, false, false, false),
                 )
-                .unwrap(),
-            )),
-        });
-
-        let pbr_forward = renderer.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("pbr opaque sm"),
-            source: ShaderSource::Wgsl(Cow::Owned(
-                spp.render_shader(
-                    "rend3-routine/opaque.wgsl",
-                    &BlendModeWrapper {
-                        profile: renderer.profile,
-                        discard: false,
+            },
+            || {
+                parallel::join(
+                    || create_module("pbr opaque cutout sm", forward, true, false, true),
+                    || {
+                        parallel::join(
+                            || create_module("pbr opaque sm", forward, false, false, true),
+                            || create_module("pbr blend sm", forward, false, true, false),
+                        )
                     },
-                    Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
                 )
-                .unwrap(),
-            )),
-        });
+            },
+        );
+        let (pbr_depth_cutout, pbr_depth) = (pbr_depth_cutout?, pbr_depth?);
+        let (pbr_cutout, pbr_forward, pbr_blend) = (pbr_cutout?, pbr_forward?, pbr_blend?);
+
+        let mut main_targets: Vec<TextureFormat> = Vec::new();
+        if interfaces.gbuffer {
+            main_targets.extend(GBUFFER_FORMATS);
+        }
+        if interfaces.velocity {
+            main_targets.push(VELOCITY_FORMAT);
+        }
 
-        let mut inner = |routine_type, module, transparency| {
+        let mut inner = |routine_type, module, transparency, blend_mode: BlendMode, overlay: bool| {
+            let material_key = match overlay {
+                true => pbr_material_key(transparency, blend_mode) | OVERLAY_MATERIAL_KEY_BIT,
+                false => pbr_material_key(transparency, blend_mode),
+            };
+            let blend_name = match transparency {
+                TransparencyType::Blend => format!(" {blend_mode:?}"),
+                _ => String::new(),
+            };
+            // Only the main opaque and cutout passes write the gbuffer and velocity, overlays and blended surfaces
+            // don't belong into them.
+            let writes_main_targets =
+                matches!(routine_type, RoutineType::Forward) && transparency != TransparencyType::Blend && !overlay;
             ForwardRoutine::new(RoutineArgs {
-                name: &format!("pbr {routine_type:?} {transparency:?}"),
+                name: &format!(
+                    "pbr {routine_type:?} {transparency:?}{blend_name}{}",
+                    if overlay { " overlay" } else { "" }
+                ),
                 renderer,
                 data_core,
                 spp,
                 interfaces,
                 per_material: &per_material,
-                material_key: transparency as u64,
+                material_key,
                 routine_type,
                 shaders: ShaderModulePair {
                     vs_entry: "vs_main",
@@ -119,22 +227,119 @@ impl PbrRoutine {
                     fs_module: module,
                 },
                 extra_bgls: &[],
+                extra_targets: match writes_main_targets {
+                    true => &main_targets,
+                    false => &[],
+                },
                 descriptor_callback: Some(&|desc, targets| {
                     if transparency == TransparencyType::Blend {
                         desc.depth_stencil.as_mut().unwrap().depth_write_enabled = false;
-                        targets[0].as_mut().unwrap().blend = Some(BlendState::ALPHA_BLENDING)
+                        targets[0].as_mut().unwrap().blend = Some(blend_mode.to_blend_state())
+                    }
+                    if overlay {
+                        let depth_stencil = desc.depth_stencil.as_mut().unwrap();
+                        depth_stencil.depth_write_enabled = false;
+                        depth_stencil.depth_compare = CompareFunction::Always;
                     }
                 }),
+                conservative: false,
             })
         };
 
-        Self {
-            opaque_depth: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque),
-            cutout_depth: inner(RoutineType::Depth, &pbr_depth_cutout, TransparencyType::Cutout),
-            opaque_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Opaque),
-            cutout_routine: inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout),
-            blend_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Blend),
+        let alpha = BlendMode::Alpha;
+        Ok(Self {
+            opaque_depth: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque, alpha, false),
+            cutout_depth: inner(
+                RoutineType::Depth,
+                &pbr_depth_cutout,
+                TransparencyType::Cutout,
+                alpha,
+                false,
+            ),
+            opaque_routine: inner(
+                RoutineType::Forward,
+                &pbr_forward,
+                TransparencyType::Opaque,
+                alpha,
+                false,
+            ),
+            cutout_routine: inner(
+                RoutineType::Forward,
+                &pbr_cutout,
+                TransparencyType::Cutout,
+                alpha,
+                false,
+            ),
+            blend_routines: BlendMode::ALL
+                .iter()
+                .map(|&mode| inner(RoutineType::Forward, &pbr_blend, TransparencyType::Blend, mode, false))
+                .collect(),
+            overlay_opaque_routine: inner(
+                RoutineType::Forward,
+                &pbr_forward,
+                TransparencyType::Opaque,
+                alpha,
+                true,
+            ),
+            overlay_cutout_routine: inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout, alpha, true),
+            overlay_blend_routines: BlendMode::ALL
+                .iter()
+                .map(|&mode| inner(RoutineType::Forward, &pbr_blend, TransparencyType::Blend, mode, true))
+                .collect(),
             per_material,
+            overrides: overrides.clone(),
+            gbuffer: interfaces.gbuffer,
+            texture_layout_generation: data_core.d2_texture_manager.layout_generation(),
+        })
+    }
+
+    /// True if the pipelines were built against an older layout of the
+    /// texture array, which happens when more textures are alive than fit
+    /// into it, see
+    /// [`TextureManager::max_textures`](rend3::managers::TextureManager::max_textures).
+    /// Call [`Self::rebuild_if_outdated`] after evaluating instructions,
+    /// before adding the routine to the graph.
+    pub fn is_outdated(&self, data_core: &RendererDataCore) -> bool {
+        self.texture_layout_generation != data_core.d2_texture_manager.layout_generation()
+    }
+
+    /// Rebuild all pipelines if [`Self::is_outdated`]. Returns true if the
+    /// pipelines were rebuilt.
+    pub fn rebuild_if_outdated(
+        &mut self,
+        renderer: &Renderer,
+        data_core: &mut RendererDataCore,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+    ) -> bool {
+        if !self.is_outdated(data_core) {
+            return false;
         }
+        profiling::scope!("PbrRenderRoutine::rebuild_if_outdated");
+
+        let overrides = std::mem::take(&mut self.overrides);
+        *self = Self::new_with_overrides(renderer, data_core, spp, interfaces, &overrides)
+            .expect("Shader overrides were checked when the routine was created");
+        true
+    }
+
+    /// Rebuild all pipelines from the current contents of `spp`. The scene
+    /// lives in the renderer, so nothing is lost.
+    ///
+    /// See [`builtin_shaders`](crate::builtin_shaders) for re-reading the
+    /// builtin shaders from disk. If an edited override no longer matches the
+    /// builtin shader, the old pipelines are kept.
+    #[cfg(feature = "shader-reload")]
+    pub fn reload_shaders(
+        &mut self,
+        renderer: &Renderer,
+        data_core: &mut RendererDataCore,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+    ) -> Result<(), ShaderOverrideError> {
+        profiling::scope!("PbrRenderRoutine::reload_shaders");
+
+        *self = Self::new_with_overrides(renderer, data_core, spp, interfaces, &self.overrides)?;
+        Ok(())
     }
 }
@@ -0,0 +1,670 @@
+//! Capturing the scene into cubemaps for image based reflections.
+//!
+//! [`ProbeRenderer::render_probe`] renders the scene around a point into the
+//! six faces of a cube, using [`BaseRenderGraph::add_scene_to_graph`] with a
+//! 90 degree camera for each face, then prefilters the result so it can be used
+//! for glossy reflections. Mip `n` of the returned cube is the environment
+//! convolved with a GGX lobe of perceptual roughness `n / (mip_count - 1)`,
+//! with the view direction assumed to be the normal, as in the split sum
+//! approximation. Mip 0 is the unfiltered capture.
+//!
+//! Faces always cover their whole texture at full resolution, so the viewport,
+//! letterbox, render scale and output load op of the [`BaseRenderGraph`] are
+//! ignored, as are its post effects and pre tonemapping hooks.
+//!
+//! Captures are static: render a probe again when the scene around it
+//! changes. Only objects sharing a layer with the `layer_mask` passed to
+//! [`ProbeRenderer::render_probe`] are captured, so put objects which
+//! shouldn't show up in their own reflections into a layer of their own and
+//! leave it out of the mask.
+
+use std::{borrow::Cow, mem, sync::Arc};
+
+use glam::{Mat4, UVec2, Vec3, Vec4};
+use rend3::{
+    graph::{InstructionEvaluationOutput, RenderGraph, ViewportRect},
+    types::{Camera, CameraProjection, Handedness, SampleCount, TextureCubeHandle},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupLayout, BindingType, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites,
+    CommandEncoder, CommandEncoderDescriptor, Device, Extent3d, FragmentState, FrontFace, LoadOp, MultisampleState,
+    Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, SamplerBindingType, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::{
+    base::{BaseRenderGraph, BaseRenderGraphIntermediateState},
+    common::WholeFrameInterfaces,
+    pbr::PbrRoutine,
+    skybox::SkyboxRoutine,
+    tonemapping::TonemappingRoutine,
+};
+
+/// Format of the captured and prefiltered cubes.
+const PROBE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Forward and up directions of the camera for each face, in the order of the
+/// cube's layers.
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::NEG_Z),
+    (Vec3::NEG_Y, Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y),
+];
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct DownsampleUniforms {
+    scale: u32,
+    size: u32,
+    flip: u32,
+    _padding: u32,
+}
+
+unsafe impl bytemuck::Zeroable for DownsampleUniforms {}
+unsafe impl bytemuck::Pod for DownsampleUniforms {}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct PrefilterUniforms {
+    face: u32,
+    roughness: f32,
+    source_resolution: f32,
+    sample_count: u32,
+}
+
+unsafe impl bytemuck::Zeroable for PrefilterUniforms {}
+unsafe impl bytemuck::Pod for PrefilterUniforms {}
+
+/// Renders environment probes.
+///
+/// See module for documentation.
+pub struct ProbeRenderer {
+    /// Writes the hdr buffer into the probe face without tonemapping.
+    blit: TonemappingRoutine,
+    downsample_bgl: BindGroupLayout,
+    downsample_pipeline: RenderPipeline,
+    prefilter_bgl: BindGroupLayout,
+    prefilter_pipeline: RenderPipeline,
+    /// Ambient light used while capturing.
+    pub ambient: Vec4,
+    /// Color of anything not covered by geometry or the skybox.
+    pub clear_color: Vec4,
+    /// Near plane of the capture camera.
+    pub near: f32,
+    /// Samples taken for every texel while prefiltering.
+    pub sample_count: u32,
+}
+
+impl ProbeRenderer {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("ProbeRenderer::new");
+
+        let blit = TonemappingRoutine::new_with_shader(
+            renderer,
+            spp,
+            interfaces,
+            PROBE_FORMAT,
+            "rend3-routine/probe_blit.wgsl",
+        )
+        .expect("The probe blit shader matches the tonemapping shader");
+
+        let downsample_bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<DownsampleUniforms>() as _,
+            )
+            .build(&renderer.device, Some("probe downsample bgl"));
+
+        let prefilter_bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Sampler(SamplerBindingType::Filtering),
+                None,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<PrefilterUniforms>() as _,
+            )
+            .build(&renderer.device, Some("probe prefilter bgl"));
+
+        let downsample_pipeline = build_pipeline(
+            renderer,
+            spp,
+            "probe downsample",
+            "rend3-routine/probe_downsample.wgsl",
+            &downsample_bgl,
+        );
+        let prefilter_pipeline = build_pipeline(
+            renderer,
+            spp,
+            "probe prefilter",
+            "rend3-routine/probe_prefilter.wgsl",
+            &prefilter_bgl,
+        );
+
+        Self {
+            blit,
+            downsample_bgl,
+            downsample_pipeline,
+            prefilter_bgl,
+            prefilter_pipeline,
+            ambient: Vec4::ZERO,
+            clear_color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            near: 0.1,
+            sample_count: 64,
+        }
+    }
+
+    /// Render the objects in `layer_mask` around `position` into a
+    /// prefiltered cube with faces of `resolution` by `resolution` texels.
+    /// `resolution` is rounded up to the next power of two, so every mip
+    /// halves the faces exactly.
+    ///
+    /// This renders six whole frames, so it is meant to be called on demand,
+    /// not every frame. Call it from the thread which renders, after
+    /// evaluating instructions for the frame, with the output of that
+    /// evaluation. Shadows are the ones evaluated for the main camera. The
+    /// camera and the [layer mask](crate::culling::GpuCuller::set_layer_mask)
+    /// of `base` are overridden while it runs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_probe(
+        &self,
+        renderer: &Arc<Renderer>,
+        base: &BaseRenderGraph,
+        eval_output: &mut InstructionEvaluationOutput,
+        pbr: &PbrRoutine,
+        skybox: Option<&SkyboxRoutine>,
+        position: Vec3,
+        resolution: u32,
+        layer_mask: u32,
+    ) -> TextureCubeHandle {
+        let output = self.render_probe_texture(
+            renderer,
+            base,
+            eval_output,
+            pbr,
+            skybox,
+            position,
+            resolution,
+            layer_mask,
+        );
+        renderer.add_texture_cube_from_gpu(output)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_probe_texture(
+        &self,
+        renderer: &Arc<Renderer>,
+        base: &BaseRenderGraph,
+        eval_output: &mut InstructionEvaluationOutput,
+        pbr: &PbrRoutine,
+        skybox: Option<&SkyboxRoutine>,
+        position: Vec3,
+        resolution: u32,
+        layer_mask: u32,
+    ) -> Texture {
+        profiling::scope!("ProbeRenderer::render_probe");
+
+        let resolution = resolution.max(1).next_power_of_two();
+        let mip_count = resolution.trailing_zeros() + 1;
+
+        let face = create_texture(&renderer.device, "probe face", resolution, 1, 1);
+        let capture = create_texture(&renderer.device, "probe capture", resolution, 6, mip_count);
+        let output = create_texture(&renderer.device, "probe", resolution, 6, mip_count);
+        let face_view = face.create_view(&TextureViewDescriptor::default());
+
+        let (old_camera, old_aspect_ratio) = {
+            let data_core = renderer.data_core.read();
+            (
+                data_core.camera_manager.get_data(),
+                data_core.camera_manager.aspect_ratio(),
+            )
+        };
+        let old_layer_mask = base.gpu_culler.layer_mask();
+        base.gpu_culler.set_layer_mask(layer_mask);
+
+        for (layer, &(forward, up)) in FACES.iter().enumerate() {
+            profiling::scope!("Probe Face");
+
+            // Cube faces are laid out as seen from the inside by a left handed
+            // camera. A right handed camera sees them mirrored, so mirror them
+            // back while copying.
+            let view = match renderer.handedness {
+                Handedness::Left => Mat4::look_at_lh(position, position + forward, up),
+                Handedness::Right => Mat4::look_at_rh(position, position + forward, up),
+            };
+            renderer.data_core.write().camera_manager.set_aspect_data(
+                Camera {
+                    projection: CameraProjection::Perspective {
+                        vfov: 90.0,
+                        near: self.near,
+                    },
+                    view,
+                },
+                1.0,
+            );
+
+            // Only the scene and tonemapping, the rest of the base graph is
+            // made for the main camera.
+            let face_size = UVec2::splat(resolution);
+            let mut graph = RenderGraph::new();
+            let face_handle = graph.add_imported_render_target(&face, 0..1, ViewportRect::from_size(face_size));
+            let mut state = BaseRenderGraphIntermediateState::new_with_formats(
+                &mut graph,
+                eval_output,
+                face_size,
+                SampleCount::One,
+                base.interfaces.depth_format,
+                base.interfaces.hdr_format,
+            );
+            if base.interfaces.gbuffer {
+                state.create_gbuffer(&mut graph, face_size, SampleCount::One);
+            }
+            if base.interfaces.velocity {
+                state.create_velocity(&mut graph, face_size, SampleCount::One);
+            }
+            base.add_scene_to_graph(
+                &mut graph,
+                &state,
+                eval_output,
+                pbr,
+                skybox,
+                face_size,
+                SampleCount::One,
+                self.ambient,
+                self.clear_color,
+            );
+            state.tonemapping(&mut graph, &self.blit, face_handle);
+            graph.execute(renderer, eval_output);
+
+            let mut encoder = renderer.device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("probe face copy"),
+            });
+            self.downsample(
+                &renderer.device,
+                &mut encoder,
+                &face_view,
+                &layer_view(&capture, 0, layer as u32),
+                1,
+                resolution,
+                renderer.handedness == Handedness::Right,
+            );
+            renderer.queue.submit(Some(encoder.finish()));
+        }
+
+        renderer
+            .data_core
+            .write()
+            .camera_manager
+            .set_aspect_data(old_camera, old_aspect_ratio);
+        base.gpu_culler.set_layer_mask(old_layer_mask);
+
+        let mut encoder = renderer.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("probe prefilter"),
+        });
+
+        for mip in 1..mip_count {
+            for layer in 0..6 {
+                self.downsample(
+                    &renderer.device,
+                    &mut encoder,
+                    &layer_view(&capture, mip - 1, layer),
+                    &layer_view(&capture, mip, layer),
+                    2,
+                    resolution >> mip,
+                    false,
+                );
+            }
+        }
+
+        let capture_view = capture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..TextureViewDescriptor::default()
+        });
+
+        for mip in 0..mip_count {
+            let roughness = if mip_count > 1 {
+                mip as f32 / (mip_count - 1) as f32
+            } else {
+                0.0
+            };
+
+            for layer in 0..6 {
+                let buffer = renderer.device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("probe prefilter uniforms"),
+                    contents: bytemuck::bytes_of(&PrefilterUniforms {
+                        face: layer,
+                        roughness,
+                        source_resolution: resolution as f32,
+                        sample_count: self.sample_count,
+                    }),
+                    usage: BufferUsages::UNIFORM,
+                });
+
+                let bg = BindGroupBuilder::new()
+                    .append_sampler(&base.samplers.linear_clamp)
+                    .append_texture_view(&capture_view)
+                    .append_buffer(&buffer)
+                    .build(&renderer.device, Some("probe prefilter bg"), &self.prefilter_bgl);
+
+                let target = layer_view(&output, mip, layer);
+                let mut rpass = begin_pass(&mut encoder, &target);
+                rpass.set_pipeline(&self.prefilter_pipeline);
+                rpass.set_bind_group(0, &bg, &[]);
+                rpass.draw(0..3, 0..1);
+            }
+        }
+
+        renderer.queue.submit(Some(encoder.finish()));
+
+        output
+    }
+
+    /// Write the average of every `scale` by `scale` block of `source` into
+    /// `target`, which is `size` texels wide.
+    #[allow(clippy::too_many_arguments)]
+    fn downsample(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        target: &TextureView,
+        scale: u32,
+        size: u32,
+        flip: bool,
+    ) {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("probe downsample uniforms"),
+            contents: bytemuck::bytes_of(&DownsampleUniforms {
+                scale,
+                size,
+                flip: flip as u32,
+                _padding: 0,
+            }),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let bg = BindGroupBuilder::new()
+            .append_texture_view(source)
+            .append_buffer(&buffer)
+            .build(device, Some("probe downsample bg"), &self.downsample_bgl);
+
+        let mut rpass = begin_pass(encoder, target);
+        rpass.set_pipeline(&self.downsample_pipeline);
+        rpass.set_bind_group(0, &bg, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+fn build_pipeline(
+    renderer: &Renderer,
+    spp: &ShaderPreProcessor,
+    label: &str,
+    shader: &str,
+    bgl: &BindGroupLayout,
+) -> RenderPipeline {
+    let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader(shader, &ShaderConfig::default(), None).unwrap(),
+        )),
+    });
+
+    let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: PROBE_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+fn create_texture(device: &Device, label: &str, resolution: u32, layers: u32, mip_count: u32) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: mip_count,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: PROBE_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// View of a single mip of a single layer.
+fn layer_view(texture: &Texture, mip: u32, layer: u32) -> TextureView {
+    texture.create_view(&TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(1),
+        ..TextureViewDescriptor::default()
+    })
+}
+
+fn begin_pass<'a>(encoder: &'a mut CommandEncoder, target: &'a TextureView) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&RenderPassDescriptor {
+        label: None,
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{UVec2, Vec2, Vec3, Vec4};
+    use rend3::{
+        types::{Object, ObjectMeshKind},
+        util::readback,
+    };
+    use wgpu::{
+        BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer, ImageCopyTexture,
+        ImageDataLayout, Origin3d, Texture, TextureAspect, COPY_BYTES_PER_ROW_ALIGNMENT,
+    };
+
+    use super::ProbeRenderer;
+    use crate::{
+        common::test_util::{f16_to_f32, headless_or_skip, object, quad_mesh},
+        headless::HeadlessRenderer,
+        pbr::{AlbedoComponent, PbrMaterial},
+    };
+
+    const RESOLUTION: u32 = 4;
+    const CLEAR_COLOR: Vec4 = Vec4::new(0.25, 0.5, 0.75, 1.0);
+    const RED: Vec4 = Vec4::new(1.0, 0.0, 0.0, 1.0);
+
+    /// Read back mip 0 of every face of the cube, top row first.
+    fn read_faces(headless: &HeadlessRenderer, cube: &Texture) -> Vec<Vec<Vec4>> {
+        let device = &headless.renderer.device;
+        // A row of the test resolution fits into a single aligned row.
+        let padded_row = COPY_BYTES_PER_ROW_ALIGNMENT;
+        let face_size = (padded_row * RESOLUTION) as u64;
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("probe readback"),
+            size: face_size * 6,
+            usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("probe readback"),
+        });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: cube,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(RESOLUTION),
+                },
+            },
+            Extent3d {
+                width: RESOLUTION,
+                height: RESOLUTION,
+                depth_or_array_layers: 6,
+            },
+        );
+        headless.renderer.queue.submit(Some(encoder.finish()));
+
+        let bytes = readback::read_buffer(device, &headless.renderer.queue, &buffer, 0..face_size * 6);
+        bytes
+            .chunks_exact(face_size as usize)
+            .map(|face| {
+                face.chunks_exact(padded_row as usize)
+                    .flat_map(|row| row[..(RESOLUTION * 8) as usize].chunks_exact(8))
+                    .map(|texel| {
+                        let channel = |i: usize| f16_to_f32(u16::from_le_bytes([texel[i * 2], texel[i * 2 + 1]]));
+                        Vec4::new(channel(0), channel(1), channel(2), channel(3))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Render a probe at the origin of a scene with a red quad in front of
+    /// the +Z face, which is only in the second layer. `None` if there is no
+    /// gpu.
+    fn render_probe(layer_mask: u32) -> Option<Vec<Vec<Vec4>>> {
+        let headless = headless_or_skip(None, UVec2::splat(RESOLUTION))?;
+        let renderer = &headless.renderer;
+
+        let mut probes = ProbeRenderer::new(renderer, &headless.spp, &headless.base_rendergraph.interfaces);
+        probes.clear_color = CLEAR_COLOR;
+
+        let mesh = quad_mesh(Vec2::splat(-1.0), Vec2::ONE).build().unwrap();
+        let material = renderer.add_material(PbrMaterial {
+            albedo: AlbedoComponent::Value(RED),
+            unlit: true,
+            ..PbrMaterial::default()
+        });
+        let _quad = renderer.add_object(Object {
+            layers: 0b10,
+            ..object(ObjectMeshKind::Static(renderer.add_mesh(mesh)), material)
+        });
+
+        renderer.swap_instruction_buffers();
+        let mut eval_output = renderer.evaluate_instructions();
+        let cube = probes.render_probe_texture(
+            renderer,
+            &headless.base_rendergraph,
+            &mut eval_output,
+            &headless.pbr_routine,
+            None,
+            Vec3::ZERO,
+            RESOLUTION,
+            layer_mask,
+        );
+
+        Some(read_faces(&headless, &cube))
+    }
+
+    #[test]
+    fn solid_color_scene_fills_every_face() {
+        let Some(faces) = render_probe(0b01) else {
+            return;
+        };
+
+        for (layer, face) in faces.iter().enumerate() {
+            for texel in face {
+                assert!(
+                    texel.abs_diff_eq(CLEAR_COLOR, 0.01),
+                    "Face {layer}: expected {CLEAR_COLOR}, got {texel}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn layer_mask_includes_objects() {
+        let Some(faces) = render_probe(u32::MAX) else {
+            return;
+        };
+
+        // The quad covers the middle four texels of the +Z face.
+        let texel = faces[4][(RESOLUTION + 1) as usize];
+        assert!(texel.abs_diff_eq(RED, 0.01), "Expected {RED}, got {texel}");
+        let corner = faces[4][0];
+        assert!(
+            corner.abs_diff_eq(CLEAR_COLOR, 0.01),
+            "Expected {CLEAR_COLOR}, got {corner}"
+        );
+    }
+}
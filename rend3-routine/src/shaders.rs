@@ -1,4 +1,7 @@
 //! Holds the shader processing infrastructure for all shaders.
+use std::{fmt, mem};
+
+use naga::{Binding, EntryPoint, Handle, Module, ResourceBinding, ShaderStage, Type, TypeInner};
 use rend3::ShaderPreProcessor;
 use rust_embed::RustEmbed;
 
@@ -6,10 +9,187 @@ use rust_embed::RustEmbed;
 #[folder = "$CARGO_MANIFEST_DIR/shaders/src"]
 struct Rend3RoutineShaderSources;
 
+/// Add all the builtin shaders to the preprocessor, replacing any previous
+/// versions.
+///
+/// In debug builds the shaders are read from disk, so calling this again
+/// followed by the routines' `reload_shaders`, behind the `shader-reload`
+/// feature, picks up any edits without restarting. In release builds they are
+/// embedded in the binary.
 pub fn builtin_shaders(spp: &mut ShaderPreProcessor) {
     spp.add_shaders_embed::<Rend3RoutineShaderSources>("rend3-routine");
 }
 
+/// A shader replacing one of the builtin shaders, like through
+/// [`ShaderOverrides`](crate::pbr::ShaderOverrides), doesn't fit into the
+/// pipelines of the shader it replaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderOverrideError {
+    /// The shader couldn't be preprocessed.
+    Preprocess { shader: String, message: String },
+    /// The preprocessed shader isn't valid WGSL.
+    Parse { shader: String, message: String },
+    /// The shader has no entry point of the given name and stage.
+    MissingEntryPoint {
+        shader: String,
+        entry_point: String,
+        stage: ShaderStage,
+    },
+    /// The shader declares a binding which the builtin shader doesn't have,
+    /// or has with a different type.
+    MismatchedBinding { shader: String, group: u32, binding: u32 },
+    /// The fragment entry point writes other locations than the one of the
+    /// builtin shader.
+    MismatchedOutputs {
+        shader: String,
+        entry_point: String,
+        expected: Vec<u32>,
+        found: Vec<u32>,
+    },
+}
+
+impl fmt::Display for ShaderOverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Preprocess { shader, message } => write!(f, "Failed to preprocess shader {shader}: {message}"),
+            Self::Parse { shader, message } => write!(f, "Failed to parse shader {shader}: {message}"),
+            Self::MissingEntryPoint {
+                shader,
+                entry_point,
+                stage,
+            } => write!(f, "Shader {shader} is missing the {stage:?} entry point {entry_point}"),
+            Self::MismatchedBinding { shader, group, binding } => write!(
+                f,
+                "Shader {shader} declares @group({group}) @binding({binding}), which the builtin shader doesn't have with the same type"
+            ),
+            Self::MismatchedOutputs {
+                shader,
+                entry_point,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Entry point {entry_point} of shader {shader} writes the locations {found:?} instead of {expected:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ShaderOverrideError {}
+
+/// Check that the preprocessed `source` of `shader` can be used in place of
+/// the preprocessed `builtin_source`, through the given entry points.
+///
+/// The entry points need to exist with the same stage, every binding needs to
+/// exist in the builtin shader with the same type, and fragment entry points
+/// need to write the same locations.
+pub(crate) fn check_override(
+    shader: &str,
+    source: &str,
+    builtin_source: &str,
+    entry_points: &[&str],
+) -> Result<(), ShaderOverrideError> {
+    profiling::scope!("check_override");
+
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| ShaderOverrideError::Parse {
+        shader: shader.to_owned(),
+        message: e.emit_to_string(source),
+    })?;
+    let builtin = naga::front::wgsl::parse_str(builtin_source).unwrap_or_else(|e| {
+        panic!(
+            "Builtin shader replaced by {shader} is invalid: {}",
+            e.emit_to_string(builtin_source)
+        )
+    });
+
+    for &name in entry_points {
+        let builtin_entry = find_entry_point(&builtin, name)
+            .unwrap_or_else(|| panic!("Builtin shader replaced by {shader} has no entry point {name}"));
+        let entry = find_entry_point(&module, name)
+            .filter(|entry| entry.stage == builtin_entry.stage)
+            .ok_or_else(|| ShaderOverrideError::MissingEntryPoint {
+                shader: shader.to_owned(),
+                entry_point: name.to_owned(),
+                stage: builtin_entry.stage,
+            })?;
+
+        if entry.stage == ShaderStage::Fragment {
+            let expected = output_locations(&builtin, builtin_entry);
+            let found = output_locations(&module, entry);
+            if expected != found {
+                return Err(ShaderOverrideError::MismatchedOutputs {
+                    shader: shader.to_owned(),
+                    entry_point: name.to_owned(),
+                    expected,
+                    found,
+                });
+            }
+        }
+    }
+
+    for (_, global) in module.global_variables.iter() {
+        let Some(binding) = &global.binding else {
+            continue;
+        };
+        let matches = builtin.global_variables.iter().any(|(_, builtin_global)| {
+            builtin_global.binding.as_ref() == Some(binding)
+                && global.space == builtin_global.space
+                && same_binding_type(&module, global.ty, &builtin, builtin_global.ty)
+        });
+        if !matches {
+            let ResourceBinding { group, binding } = *binding;
+            return Err(ShaderOverrideError::MismatchedBinding {
+                shader: shader.to_owned(),
+                group,
+                binding,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn find_entry_point<'a>(module: &'a Module, name: &str) -> Option<&'a EntryPoint> {
+    module.entry_points.iter().find(|entry| entry.name == name)
+}
+
+/// Sorted locations written by the entry point.
+fn output_locations(module: &Module, entry: &EntryPoint) -> Vec<u32> {
+    let Some(result) = &entry.function.result else {
+        return Vec::new();
+    };
+    let mut locations: Vec<u32> = match (&result.binding, &module.types[result.ty].inner) {
+        (Some(binding), _) => location(binding).into_iter().collect(),
+        (None, TypeInner::Struct { members, .. }) => members
+            .iter()
+            .filter_map(|member| member.binding.as_ref().and_then(location))
+            .collect(),
+        (None, _) => Vec::new(),
+    };
+    locations.sort_unstable();
+    locations
+}
+
+fn location(binding: &Binding) -> Option<u32> {
+    match *binding {
+        Binding::Location { location, .. } => Some(location),
+        Binding::BuiltIn(_) => None,
+    }
+}
+
+/// Textures and samplers need to match exactly. Buffers only need to be of
+/// the same kind, as their layout is checked by wgpu against the size of the
+/// binding.
+fn same_binding_type(module: &Module, ty: Handle<Type>, builtin: &Module, builtin_ty: Handle<Type>) -> bool {
+    match (&module.types[ty].inner, &builtin.types[builtin_ty].inner) {
+        (TypeInner::BindingArray { base, .. }, TypeInner::BindingArray { base: builtin_base, .. }) => {
+            same_binding_type(module, *base, builtin, *builtin_base)
+        }
+        (inner @ (TypeInner::Image { .. } | TypeInner::Sampler { .. }), builtin_inner) => inner == builtin_inner,
+        (inner, builtin_inner) => mem::discriminant(inner) == mem::discriminant(builtin_inner),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
@@ -22,10 +202,18 @@ mod tests {
             termcolor::{ColorChoice, StandardStream},
         },
     };
+    use glam::{Mat4, Vec3, Vec4};
     use naga::WithSpan;
-    use rend3::{RendererProfile, ShaderConfig, ShaderPreProcessor, ShaderVertexBufferConfig};
+    use rend3::{
+        managers::ShaderObject, types::BoundingSphere, RendererProfile, ShaderConfig, ShaderPreProcessor,
+        ShaderVertexBufferConfig,
+    };
 
-    use crate::{pbr::PbrMaterial, shaders::Rend3RoutineShaderSources};
+    use crate::{
+        culling::object_offsets,
+        pbr::PbrMaterial,
+        shaders::{check_override, Rend3RoutineShaderSources, ShaderOverrideError},
+    };
 
     fn print_err(error: &dyn Error) {
         eprint!("{}", error);
@@ -121,4 +309,276 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn object_layout_matches_shader() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shaders_embed::<Rend3RoutineShaderSources>("rend3-routine");
+
+        let source = pp
+            .render_shader(
+                "rend3-routine/opaque.wgsl",
+                &ShaderConfig {
+                    profile: Some(RendererProfile::GpuDriven),
+                },
+                Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+            )
+            .unwrap();
+        let module = naga::front::wgsl::parse_str(&source).unwrap();
+        let (members, span) = module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match ty.inner {
+                naga::TypeInner::Struct { ref members, span } if ty.name.as_deref() == Some("Object") => {
+                    Some((members.clone(), span))
+                }
+                _ => None,
+            })
+            .expect("shader has no Object struct");
+
+        // Every field starts with a different value, so reading the first word
+        // at each of the shader's offsets tells if the field is where the
+        // shader expects it.
+        let object = ShaderObject::<PbrMaterial> {
+            transform: Mat4::from_cols_array(&[1.0; 16]),
+            bounding_sphere: BoundingSphere {
+                center: Vec3::splat(2.0),
+                radius: 3.0,
+            },
+            tint: Vec4::splat(4.0),
+            clip_planes: [Vec4::splat(5.0); 4],
+            dissolve: 6.0,
+            dissolve_scale: 7.0,
+            first_index: 8,
+            index_count: 9,
+            material_index: 10,
+            flags: 11,
+            vertex_attribute_start_offsets: [12; 6],
+            prev_transform: Mat4::from_cols_array(&[13.0; 16]),
+        };
+        let expected = [
+            ("transform", 1.0_f32.to_bits()),
+            ("bounding_sphere", 2.0_f32.to_bits()),
+            ("tint", 4.0_f32.to_bits()),
+            ("clip_planes", 5.0_f32.to_bits()),
+            ("dissolve", 6.0_f32.to_bits()),
+            ("dissolve_scale", 7.0_f32.to_bits()),
+            ("first_index", 8),
+            ("index_count", 9),
+            ("material_index", 10),
+            ("flags", 11),
+            ("vertex_attribute_start_offsets", 12),
+            ("prev_transform", 13.0_f32.to_bits()),
+        ];
+
+        let mut buffer = encase::StorageBuffer::new(Vec::new());
+        buffer.write(&object).unwrap();
+        let bytes = buffer.into_inner();
+        assert_eq!(bytes.len(), span as usize, "ShaderObject and Object differ in size");
+
+        assert_eq!(members.len(), expected.len());
+        for (member, (name, value)) in members.iter().zip(expected) {
+            assert_eq!(member.name.as_deref(), Some(name));
+            let offset = member.offset as usize;
+            let word = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+            assert_eq!(word, value, "{name} is not at offset {offset}");
+        }
+
+        // Culled objects are read back at fixed offsets.
+        let offset = |name: &str| {
+            members
+                .iter()
+                .find(|member| member.name.as_deref() == Some(name))
+                .unwrap()
+                .offset as usize
+        };
+        assert_eq!(offset("transform"), object_offsets::TRANSFORM);
+        assert_eq!(offset("bounding_sphere"), object_offsets::BOUNDING_SPHERE);
+        assert_eq!(offset("tint"), object_offsets::TINT);
+        assert_eq!(offset("clip_planes"), object_offsets::CLIP_PLANES);
+        assert_eq!(offset("first_index"), object_offsets::FIRST_INDEX);
+        assert_eq!(offset("index_count"), object_offsets::INDEX_COUNT);
+        assert_eq!(offset("material_index"), object_offsets::MATERIAL_INDEX);
+        assert_eq!(offset("flags"), object_offsets::FLAGS);
+    }
+
+    #[test]
+    fn validate_gbuffer_output() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shaders_embed::<Rend3RoutineShaderSources>("rend3-routine");
+
+        for profile in [RendererProfile::CpuDriven, RendererProfile::GpuDriven] {
+            for discard in [false, true] {
+                let config = serde_json::json!({
+                    "profile": profile,
+                    "discard": discard,
+                    "blend": false,
+                    "gbuffer": true,
+                });
+                println!("Testing gbuffer output with config {config:?}");
+                let source = pp
+                    .render_shader(
+                        "rend3-routine/opaque.wgsl",
+                        &config,
+                        Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+                    )
+                    .unwrap();
+                let module = naga::front::wgsl::parse_str(&source).unwrap();
+
+                let mut validator =
+                    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all());
+                if let Err(err) = validator.validate(&module) {
+                    emit_annotated_error(&err, "rend3-routine/opaque.wgsl", &source);
+                    print_err(&err);
+                    panic!()
+                }
+
+                // The color, then the three gbuffer targets.
+                assert_eq!(fragment_output_locations(&module), vec![0, 1, 2, 3]);
+            }
+        }
+    }
+
+    /// Locations of the outputs of the fragment entry point `fs_main`.
+    fn fragment_output_locations(module: &naga::Module) -> Vec<u32> {
+        let entry = module
+            .entry_points
+            .iter()
+            .find(|entry| entry.name == "fs_main")
+            .expect("shader has no fs_main");
+        let result = entry.function.result.as_ref().expect("fs_main has no output");
+        let location = |binding: &Option<naga::Binding>| match *binding {
+            Some(naga::Binding::Location { location, .. }) => Some(location),
+            _ => None,
+        };
+        match module.types[result.ty].inner {
+            naga::TypeInner::Struct { ref members, .. } => {
+                members.iter().filter_map(|member| location(&member.binding)).collect()
+            }
+            _ => location(&result.binding).into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn validate_velocity_output() {
+        let mut pp = ShaderPreProcessor::new();
+        pp.add_shaders_embed::<Rend3RoutineShaderSources>("rend3-routine");
+
+        for profile in [RendererProfile::CpuDriven, RendererProfile::GpuDriven] {
+            for discard in [false, true] {
+                for gbuffer in [false, true] {
+                    let config = serde_json::json!({
+                        "profile": profile,
+                        "discard": discard,
+                        "blend": false,
+                        "gbuffer": gbuffer,
+                        "velocity": true,
+                    });
+                    println!("Testing velocity output with config {config:?}");
+                    let source = pp
+                        .render_shader(
+                            "rend3-routine/opaque.wgsl",
+                            &config,
+                            Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
+                        )
+                        .unwrap();
+                    let module = naga::front::wgsl::parse_str(&source).unwrap();
+
+                    let mut validator = naga::valid::Validator::new(
+                        naga::valid::ValidationFlags::all(),
+                        naga::valid::Capabilities::all(),
+                    );
+                    if let Err(err) = validator.validate(&module) {
+                        emit_annotated_error(&err, "rend3-routine/opaque.wgsl", &source);
+                        print_err(&err);
+                        panic!()
+                    }
+
+                    // The color, the gbuffer if enabled, then the velocity.
+                    let targets = 1 + if gbuffer { 3 } else { 0 } + 1;
+                    assert_eq!(fragment_output_locations(&module), (0..targets).collect::<Vec<u32>>());
+                }
+            }
+        }
+    }
+
+    const BUILTIN: &str = "
+        @group(1) @binding(0)
+        var source: texture_2d<f32>;
+
+        @vertex
+        fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+            return vec4<f32>(f32(id), 0.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            return textureLoad(source, vec2<i32>(position.xy), 0);
+        }
+    ";
+
+    fn check(source: &str) -> Result<(), ShaderOverrideError> {
+        check_override("custom.wgsl", source, BUILTIN, &["vs_main", "fs_main"])
+    }
+
+    #[test]
+    fn override_matching_builtin() {
+        assert_eq!(check(BUILTIN), Ok(()));
+        // Leaving out bindings is fine.
+        let unbound = BUILTIN
+            .replace("@group(1) @binding(0)", "")
+            .replace("var source: texture_2d<f32>;", "")
+            .replace("textureLoad(source, vec2<i32>(position.xy), 0)", "position");
+        assert_eq!(check(&unbound), Ok(()));
+    }
+
+    #[test]
+    fn override_missing_entry_point() {
+        assert_eq!(
+            check(&BUILTIN.replace("fn fs_main", "fn fs_other")),
+            Err(ShaderOverrideError::MissingEntryPoint {
+                shader: "custom.wgsl".into(),
+                entry_point: "fs_main".into(),
+                stage: naga::ShaderStage::Fragment,
+            })
+        );
+    }
+
+    #[test]
+    fn override_mismatched_binding() {
+        let mismatched = [
+            BUILTIN.replace("@binding(0)", "@binding(1)"),
+            BUILTIN.replace("texture_2d<f32>", "texture_depth_2d").replace(
+                "textureLoad(source, vec2<i32>(position.xy), 0)",
+                "vec4<f32>(textureLoad(source, vec2<i32>(position.xy), 0))",
+            ),
+        ];
+        for source in mismatched {
+            assert!(
+                matches!(
+                    check(&source),
+                    Err(ShaderOverrideError::MismatchedBinding { group: 1, .. })
+                ),
+                "Expected a mismatched binding for {source}"
+            );
+        }
+    }
+
+    #[test]
+    fn override_mismatched_outputs() {
+        assert_eq!(
+            check(&BUILTIN.replace("@location(0) vec4", "@location(1) vec4")),
+            Err(ShaderOverrideError::MismatchedOutputs {
+                shader: "custom.wgsl".into(),
+                entry_point: "fs_main".into(),
+                expected: vec![0],
+                found: vec![1],
+            })
+        );
+    }
+
+    #[test]
+    fn override_invalid_wgsl() {
+        assert!(matches!(check("fn"), Err(ShaderOverrideError::Parse { .. })));
+    }
 }
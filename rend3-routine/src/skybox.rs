@@ -1,7 +1,8 @@
 //! Routine that renders a cubemap as a skybox.
 
-use std::borrow::Cow;
+use std::{borrow::Cow, mem};
 
+use glam::{Mat4, Quat};
 use rend3::{
     graph::{
         DataHandle, NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
@@ -12,49 +13,114 @@ use rend3::{
     Renderer, ShaderConfig, ShaderPreProcessor,
 };
 use wgpu::{
-    BindGroup, BindGroupLayout, BindingType, Color, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
-    DepthStencilState, Face, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode,
-    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
-    ShaderStages, StencilState, TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Color,
+    ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Face, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureSampleType,
+    TextureViewDimension, VertexState,
 };
 
 use crate::common::WholeFrameInterfaces;
 
+/// The actual structure passed to the shader.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct SkyboxUniforms {
+    inv_rotation: Mat4,
+    intensity: f32,
+    blend: f32,
+    _padding: [u32; 2],
+}
+
+unsafe impl bytemuck::Zeroable for SkyboxUniforms {}
+unsafe impl bytemuck::Pod for SkyboxUniforms {}
+
+/// When the skybox is drawn relative to the opaque geometry.
+///
+/// Either way the final image only shows the skybox where no geometry has been
+/// drawn, the orders only differ in how many pixels the skybox shades.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SkyboxOrder {
+    /// Draw the skybox before the opaque geometry. Every pixel is shaded
+    /// by the skybox, and geometry drawn on top of it overwrites it.
+    BeforeOpaque,
+    /// Draw the skybox after the opaque geometry, testing against its depth,
+    /// so no time is spent on sky pixels behind geometry. This is the
+    /// cheapest choice unless most of the screen is sky.
+    #[default]
+    AfterOpaque,
+}
+
 struct StoredSkybox {
     bg: Option<BindGroup>,
     handle: Option<TextureCubeHandle>,
+    blend_handle: Option<TextureCubeHandle>,
 }
 
 /// Skybox rendering routine.
 ///
+/// The skybox is opaque and writes an alpha of one, so leave it out when
+/// rendering with a transparent background.
+///
 /// See module for documentation.
 pub struct SkyboxRoutine {
     pipelines: SkyboxPipelines,
     bgl: BindGroupLayout,
+    uniform_buffer: Buffer,
     current_skybox: StoredSkybox,
+    /// Rotation applied to the skybox.
+    pub rotation: Quat,
+    /// Multiplier on the color of the skybox.
+    pub intensity: f32,
+    /// How much of the blend texture is mixed into the background texture,
+    /// from 0.0 to 1.0. Has no effect without a blend texture.
+    pub blend: f32,
+    /// When the skybox is drawn. Defaults to [`SkyboxOrder::AfterOpaque`].
+    pub order: SkyboxOrder,
 }
 
 impl SkyboxRoutine {
     /// Create the routine.
     pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        let cube_binding = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::Cube,
+            multisampled: false,
+        };
+
         let bgl = BindGroupLayoutBuilder::new()
-            .append(
+            .append(ShaderStages::FRAGMENT, cube_binding, None)
+            .append(ShaderStages::FRAGMENT, cube_binding, None)
+            .append_buffer(
                 ShaderStages::FRAGMENT,
-                BindingType::Texture {
-                    sample_type: TextureSampleType::Float { filterable: true },
-                    view_dimension: TextureViewDimension::Cube,
-                    multisampled: false,
-                },
-                None,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<SkyboxUniforms>() as _,
             )
             .build(&renderer.device, Some("skybox bgl"));
 
         let pipelines = SkyboxPipelines::new(renderer, spp, interfaces, &bgl);
 
+        let uniform_buffer = renderer.device.create_buffer(&BufferDescriptor {
+            label: Some("skybox uniforms"),
+            size: mem::size_of::<SkyboxUniforms>() as _,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         Self {
-            current_skybox: StoredSkybox { bg: None, handle: None },
+            current_skybox: StoredSkybox {
+                bg: None,
+                handle: None,
+                blend_handle: None,
+            },
             bgl,
+            uniform_buffer,
             pipelines,
+            rotation: Quat::IDENTITY,
+            intensity: 1.0,
+            blend: 0.0,
+            order: SkyboxOrder::default(),
         }
     }
 
@@ -65,22 +131,44 @@ impl SkyboxRoutine {
         self.current_skybox.bg = None;
     }
 
+    /// Set the texture which is crossfaded with the background texture by
+    /// [`SkyboxRoutine::blend`]. Bad things will happen if this isn't a cube
+    /// texture.
+    pub fn set_blend_texture(&mut self, texture: Option<TextureCubeHandle>) {
+        self.current_skybox.blend_handle = texture;
+        self.current_skybox.bg = None;
+    }
+
     /// Evaluate any changes that have happened to the skybox routine.
     pub fn evaluate(&mut self, renderer: &Renderer) {
-        let data_core = renderer.data_core.lock();
+        let data_core = renderer.data_core.read();
         let d2c_texture_manager = &data_core.d2c_texture_manager;
 
         profiling::scope!("Update Skybox");
 
         if let Some(ref handle) = self.current_skybox.handle {
             if self.current_skybox.bg.is_none() {
+                // Blending a texture with itself is a no-op, so use that when there is nothing to blend with.
+                let blend_handle = self.current_skybox.blend_handle.as_ref().unwrap_or(handle);
                 let bg = BindGroupBuilder::new()
                     .append_texture_view(d2c_texture_manager.get_view(handle.get_raw()))
+                    .append_texture_view(d2c_texture_manager.get_view(blend_handle.get_raw()))
+                    .append_buffer(&self.uniform_buffer)
                     .build(&renderer.device, Some("skybox"), &self.bgl);
 
                 self.current_skybox.bg = Some(bg)
             }
         }
+
+        let uniforms = SkyboxUniforms {
+            inv_rotation: Mat4::from_quat(self.rotation.inverse()),
+            intensity: self.intensity,
+            blend: self.blend.clamp(0.0, 1.0),
+            _padding: [0; 2],
+        };
+        renderer
+            .queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
     }
 
     /// Add rendering the skybox to the given rendergraph.
@@ -180,7 +268,7 @@ impl SkyboxPipelines {
                     conservative: false,
                 },
                 depth_stencil: Some(DepthStencilState {
-                    format: TextureFormat::Depth32Float,
+                    format: interfaces.depth_format,
                     depth_write_enabled: true,
                     depth_compare: CompareFunction::GreaterEqual,
                     stencil: StencilState::default(),
@@ -194,7 +282,7 @@ impl SkyboxPipelines {
                     module: &skybox_sm,
                     entry_point: "fs_main",
                     targets: &[Some(ColorTargetState {
-                        format: TextureFormat::Rgba16Float,
+                        format: interfaces.hdr_format,
                         blend: None,
                         write_mask: ColorWrites::all(),
                     })],
@@ -0,0 +1,376 @@
+//! Screen-space reflections which ray-march the depth buffer and add the
+//! reflected hdr color back into the image.
+//!
+//! SSR is opt-in. Enable it with
+//! [`BaseRenderGraphBuilder::ssr`](crate::base::BaseRenderGraphBuilder::ssr) to
+//! have [`BaseRenderGraph::add_to_graph`](crate::base::BaseRenderGraph::add_to_graph)
+//! run it. In a custom graph, add it after all forward rendering but before
+//! tonemapping with
+//! [`BaseRenderGraphIntermediateState::ssr`](crate::base::BaseRenderGraphIntermediateState::ssr).
+//!
+//! Rays are reflected off the normals in the gbuffer, when one is rendered
+//! and single sampled. Otherwise normals are reconstructed from the
+//! derivatives of the depth buffer, which is blocky along edges and ignores
+//! normal maps. Likewise, the blur is scaled per pixel by the perceptual
+//! roughness in the material target of the gbuffer. Without it every pixel is
+//! blurred by the full [`SsrSettings::blur_radius`]. SSR needs a single sampled depth
+//! buffer, so when multisampling, resolve it first with
+//! [`BaseRenderGraphIntermediateState::resolve_depth`](crate::base::BaseRenderGraphIntermediateState::resolve_depth).
+
+use std::{borrow::Cow, mem};
+
+use glam::UVec2;
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor,
+        RenderTargetHandle,
+    },
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState,
+    BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, Device, Extent3d, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+    VertexState,
+};
+
+use crate::common::WholeFrameInterfaces;
+
+/// Tweakables for screen-space reflections. This is passed directly to the
+/// shader.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+pub struct SsrSettings {
+    /// Maximum amount of steps to march each ray.
+    pub max_steps: u32,
+    /// Distance, in view space units, between each step of the ray.
+    pub step_size: f32,
+    /// How far behind the depth buffer a ray can be and still count as a hit.
+    pub thickness: f32,
+    /// Multiplier on the reflected color.
+    pub intensity: f32,
+    /// Fraction of the screen, from the edges, over which reflections fade out.
+    pub edge_fade: f32,
+    /// Radius, in pixels, of the blur applied to reflections off fully rough
+    /// surfaces. Smoother surfaces are blurred less.
+    pub blur_radius: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        Self {
+            max_steps: 64,
+            step_size: 0.1,
+            thickness: 0.5,
+            intensity: 1.0,
+            edge_fade: 0.1,
+            blur_radius: 1.0,
+        }
+    }
+}
+
+unsafe impl bytemuck::Zeroable for SsrSettings {}
+unsafe impl bytemuck::Pod for SsrSettings {}
+
+fn create_pipeline(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    interfaces: &WholeFrameInterfaces,
+    bgl: &BindGroupLayout,
+    shader: &str,
+    format: TextureFormat,
+    blend: Option<BlendState>,
+) -> RenderPipeline {
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(shader),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader(shader, &ShaderConfig::default(), None).unwrap(),
+        )),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(shader),
+        bind_group_layouts: &[&interfaces.forward_uniform_bgl, bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(shader),
+        layout: Some(&pll),
+        vertex: VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format,
+                blend,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+fn create_fallback(renderer: &Renderer, label: &str, texel: [u8; 4]) -> TextureView {
+    renderer
+        .device
+        .create_texture_with_data(
+            &renderer.queue,
+            &TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            &texel,
+        )
+        .create_view(&TextureViewDescriptor::default())
+}
+
+/// Screen-space reflection routine.
+///
+/// See module for documentation.
+pub struct SsrRoutine {
+    trace_bgl: BindGroupLayout,
+    trace_pipeline: RenderPipeline,
+    composite_bgl: BindGroupLayout,
+    composite_pipeline: RenderPipeline,
+    format: TextureFormat,
+    /// Bound in place of the gbuffer normal target, with a normal of zero
+    /// telling the shader to reconstruct normals from depth.
+    fallback_normal: TextureView,
+    /// Bound in place of the gbuffer material target, with a roughness of 1.
+    fallback_material: TextureView,
+    pub settings: SsrSettings,
+}
+
+impl SsrRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("SsrRoutine::new");
+
+        let color_binding = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let settings_size = mem::size_of::<SsrSettings>() as _;
+
+        let trace_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, color_binding, None)
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(ShaderStages::FRAGMENT, color_binding, None)
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, settings_size)
+            .build(&renderer.device, Some("ssr trace bgl"));
+
+        let composite_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, color_binding, None)
+            .append(ShaderStages::FRAGMENT, color_binding, None)
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, settings_size)
+            .build(&renderer.device, Some("ssr composite bgl"));
+
+        let trace_pipeline = create_pipeline(
+            &renderer.device,
+            spp,
+            interfaces,
+            &trace_bgl,
+            "rend3-routine/ssr.wgsl",
+            interfaces.hdr_format,
+            None,
+        );
+        let composite_pipeline = create_pipeline(
+            &renderer.device,
+            spp,
+            interfaces,
+            &composite_bgl,
+            "rend3-routine/ssr_composite.wgsl",
+            interfaces.hdr_format,
+            Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent {
+                    src_factor: BlendFactor::Zero,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+            }),
+        );
+
+        let fallback_normal = create_fallback(renderer, "ssr fallback normal", [0, 0, 0, 0]);
+        let fallback_material = create_fallback(renderer, "ssr fallback material", [0, 255, 0, 0]);
+
+        Self {
+            trace_bgl,
+            trace_pipeline,
+            composite_bgl,
+            composite_pipeline,
+            format: interfaces.hdr_format,
+            fallback_normal,
+            fallback_material,
+            settings: SsrSettings::default(),
+        }
+    }
+
+    /// Add reflections to the given graph, adding them into `color`.
+    ///
+    /// `normal` and `material` are the gbuffer targets holding the world space
+    /// normal and the perceptual roughness in green, see
+    /// [`GBUFFER_FORMATS`](crate::common::GBUFFER_FORMATS). Without `normal`,
+    /// normals are reconstructed from `depth`. Without `material`, all
+    /// reflections are blurred as if fully rough. All targets must be single
+    /// sampled.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        normal: Option<RenderTargetHandle>,
+        material: Option<RenderTargetHandle>,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        resolution: UVec2,
+    ) {
+        let reflections = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("ssr reflections".into()),
+            resolution,
+            depth: 1,
+            samples: SampleCount::One,
+            format: self.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        let settings_handle = graph.add_data::<wgpu::Buffer>();
+
+        let mut builder = graph.add_node("SSR Trace");
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::Input);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let normal_handle = normal.map(|normal| builder.add_render_target(normal, NodeResourceUsage::Input));
+        let reflections_handle = builder.add_render_target(reflections, NodeResourceUsage::Output);
+        let settings_output_handle = builder.add_data(settings_handle, NodeResourceUsage::Output);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: reflections_handle,
+                clear: Color::TRANSPARENT,
+                resolve: None,
+            }],
+            depth_stencil: None,
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let color = ctx.graph_data.get_render_target(color_handle);
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+            let normal = match normal_handle {
+                Some(handle) => ctx.graph_data.get_render_target(handle),
+                None => &self.fallback_normal,
+            };
+
+            profiling::scope!("ssr trace");
+
+            let settings_buffer = ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("ssr settings"),
+                contents: bytemuck::bytes_of(&self.settings),
+                usage: BufferUsages::UNIFORM,
+            });
+
+            let trace_bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(color)
+                    .append_texture_view(depth)
+                    .append_texture_view(normal)
+                    .append_buffer(&settings_buffer)
+                    .build(&ctx.renderer.device, Some("ssr trace bg"), &self.trace_bgl),
+            );
+
+            rpass.set_pipeline(&self.trace_pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, trace_bg, &[]);
+            rpass.draw(0..3, 0..1);
+
+            ctx.graph_data.set_data(settings_output_handle, Some(settings_buffer));
+        });
+
+        let mut builder = graph.add_node("SSR Composite");
+
+        let reflections_handle = builder.add_render_target(reflections, NodeResourceUsage::Input);
+        let material_handle = material.map(|material| builder.add_render_target(material, NodeResourceUsage::Input));
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::InputOutput);
+        let settings_input_handle = builder.add_data(settings_handle, NodeResourceUsage::Input);
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: color_handle,
+                clear: Color::BLACK,
+                resolve: None,
+            }],
+            depth_stencil: None,
+        });
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let settings_buffer = ctx.graph_data.get_data(ctx.temps, settings_input_handle).unwrap();
+            let reflections = ctx.graph_data.get_render_target(reflections_handle);
+            let material = match material_handle {
+                Some(handle) => ctx.graph_data.get_render_target(handle),
+                None => &self.fallback_material,
+            };
+
+            profiling::scope!("ssr composite");
+
+            let composite_bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(reflections)
+                    .append_texture_view(material)
+                    .append_buffer(settings_buffer)
+                    .build(&ctx.renderer.device, Some("ssr composite bg"), &self.composite_bgl),
+            );
+
+            rpass.set_pipeline(&self.composite_pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, composite_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
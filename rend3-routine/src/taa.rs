@@ -0,0 +1,373 @@
+//! Temporal anti-aliasing which resolves a jittered image against the
+//! accumulated history of previous frames.
+//!
+//! TAA is opt-in. Enable it with
+//! [`BaseRenderGraphBuilder::taa`](crate::base::BaseRenderGraphBuilder::taa) to
+//! have [`BaseRenderGraph::add_to_graph`] run it, calling
+//! [`BaseRenderGraph::evaluate`](crate::base::BaseRenderGraph::evaluate) every
+//! frame. In a custom graph, call [`TaaRoutine::evaluate`] every frame, set
+//! [`BaseRenderGraphIntermediateState::jitter`] to [`TaaRoutine::jitter`]
+//! before creating the frame uniforms, then feed the output of
+//! [`TaaRoutine::add_to_graph`] into tonemapping instead of the hdr color.
+//!
+//! History is reprojected with the velocity buffer, see
+//! [`BaseRenderGraphIntermediateState::velocity_view`](crate::base::BaseRenderGraphIntermediateState::velocity_view),
+//! which the builder enables along with TAA. Where nothing wrote a velocity,
+//! like the sky, or without a single sampled velocity buffer, history is
+//! reprojected with the camera movement alone, so anything that moves on its
+//! own relies on the neighborhood clamp to reject stale history. TAA needs a
+//! single sampled depth buffer, so when multisampling, resolve it first with
+//! [`BaseRenderGraphIntermediateState::resolve_depth`](crate::base::BaseRenderGraphIntermediateState::resolve_depth).
+//!
+//! [`BaseRenderGraph::add_to_graph`]: crate::base::BaseRenderGraph::add_to_graph
+//! [`BaseRenderGraphIntermediateState::jitter`]: crate::base::BaseRenderGraphIntermediateState::jitter
+
+use std::{borrow::Cow, mem};
+
+use glam::{UVec2, Vec2};
+use rend3::{
+    graph::{
+        DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle,
+        ViewportRect,
+    },
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites,
+    Device, Extent3d, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::common::{WholeFrameInterfaces, VELOCITY_FORMAT};
+
+/// Amount of frames in the jitter sequence before it repeats.
+const JITTER_SEQUENCE_LENGTH: u32 = 16;
+
+/// The actual structure passed to the shader.
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct TaaUniforms {
+    jitter: Vec2,
+    blend_factor: f32,
+    history_valid: u32,
+    velocity_valid: u32,
+    _padding: [u32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for TaaUniforms {}
+unsafe impl bytemuck::Pod for TaaUniforms {}
+
+struct TaaHistory {
+    textures: [Texture; 2],
+    resolution: UVec2,
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+fn create_history_texture(device: &Device, resolution: UVec2, format: TextureFormat) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("taa history"),
+        size: Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn create_pipeline(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    interfaces: &WholeFrameInterfaces,
+    bgl: &BindGroupLayout,
+) -> RenderPipeline {
+    profiling::scope!("TaaRoutine::create_pipeline");
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("taa"),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader("rend3-routine/taa.wgsl", &ShaderConfig::default(), None)
+                .unwrap(),
+        )),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("taa pass"),
+        bind_group_layouts: &[&interfaces.forward_uniform_bgl, bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("taa pass"),
+        layout: Some(&pll),
+        vertex: VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: interfaces.hdr_format,
+                blend: None,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// Temporal anti-aliasing routine.
+///
+/// See module for documentation.
+pub struct TaaRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+    /// Bound in place of the velocity buffer, with zero velocity.
+    fallback_velocity: TextureView,
+    format: TextureFormat,
+    history: Option<TaaHistory>,
+    /// Set when the history has to be thrown away before the next frame.
+    reset: bool,
+    /// Whether the history read this frame holds previous frames.
+    history_valid: bool,
+    frame_index: u32,
+    jitter: Vec2,
+    /// If false, the projection isn't jittered and the routine only
+    /// accumulates history.
+    pub jitter_enabled: bool,
+    /// Weight given to the current frame when blending with the history.
+    /// Lower values are smoother but take longer to converge.
+    pub blend_factor: f32,
+}
+
+impl TaaRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        let texture_binding = |sample_type| BindingType::Texture {
+            sample_type,
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                texture_binding(TextureSampleType::Float { filterable: true }),
+                None,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                texture_binding(TextureSampleType::Float { filterable: true }),
+                None,
+            )
+            .append(ShaderStages::FRAGMENT, texture_binding(TextureSampleType::Depth), None)
+            .append(
+                ShaderStages::FRAGMENT,
+                texture_binding(TextureSampleType::Float { filterable: false }),
+                None,
+            )
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<TaaUniforms>() as _,
+            )
+            .build(&renderer.device, Some("taa bgl"));
+
+        let pipeline = create_pipeline(&renderer.device, spp, interfaces, &bgl);
+
+        let fallback_velocity = renderer
+            .device
+            .create_texture_with_data(
+                &renderer.queue,
+                &TextureDescriptor {
+                    label: Some("taa fallback velocity"),
+                    size: Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: VELOCITY_FORMAT,
+                    usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                    view_formats: &[],
+                },
+                &[0; 4],
+            )
+            .create_view(&TextureViewDescriptor::default());
+
+        Self {
+            bgl,
+            pipeline,
+            fallback_velocity,
+            format: interfaces.hdr_format,
+            history: None,
+            reset: true,
+            history_valid: false,
+            frame_index: 0,
+            jitter: Vec2::ZERO,
+            jitter_enabled: true,
+            blend_factor: 0.1,
+        }
+    }
+
+    /// Jitter, in NDC, that should be applied to this frame's projection.
+    pub fn jitter(&self) -> Vec2 {
+        self.jitter
+    }
+
+    /// Throw away all accumulated history. Call this when the camera cuts to
+    /// an unrelated view.
+    pub fn reset_history(&mut self) {
+        self.reset = true;
+    }
+
+    /// Advance to the next frame. Must be called every frame before adding
+    /// to the graph.
+    pub fn evaluate(&mut self, renderer: &Renderer, resolution: UVec2) {
+        profiling::scope!("Update TAA");
+
+        if self.history.as_ref().map(|h| h.resolution) != Some(resolution) {
+            self.history = Some(TaaHistory {
+                textures: [
+                    create_history_texture(&renderer.device, resolution, self.format),
+                    create_history_texture(&renderer.device, resolution, self.format),
+                ],
+                resolution,
+            });
+            self.reset = true;
+        }
+        self.history_valid = !mem::take(&mut self.reset);
+
+        self.frame_index = self.frame_index.wrapping_add(1);
+
+        self.jitter = if self.jitter_enabled {
+            let index = self.frame_index % JITTER_SEQUENCE_LENGTH + 1;
+            let offset = Vec2::new(halton(index, 2), halton(index, 3)) - 0.5;
+            offset * 2.0 / resolution.as_vec2()
+        } else {
+            Vec2::ZERO
+        };
+    }
+
+    /// Add the TAA resolve to the graph, returning the anti-aliased image.
+    ///
+    /// `color`, `depth` and `velocity` must be single sampled, and `depth`
+    /// must have been rendered with the jitter from [`TaaRoutine::jitter`].
+    /// `velocity` is the target of
+    /// [`BaseRenderGraphIntermediateState::velocity_view`](crate::base::BaseRenderGraphIntermediateState::velocity_view).
+    /// Without it, history is reprojected with the camera movement alone.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        velocity: Option<RenderTargetHandle>,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) -> RenderTargetHandle {
+        let history = self
+            .history
+            .as_ref()
+            .expect("TaaRoutine::evaluate must be called before TaaRoutine::add_to_graph");
+
+        let viewport = ViewportRect::from_size(history.resolution);
+        let write_index = (self.frame_index % 2) as usize;
+        let history_read = graph.add_imported_render_target(&history.textures[1 - write_index], 0..1, viewport);
+        let history_write = graph.add_imported_render_target(&history.textures[write_index], 0..1, viewport);
+
+        let mut builder = graph.add_node("TAA Resolve");
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::Input);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let velocity_handle = velocity.map(|velocity| builder.add_render_target(velocity, NodeResourceUsage::Input));
+        let history_handle = builder.add_render_target(history_read, NodeResourceUsage::Input);
+        let output_handle = builder.add_render_target(history_write, NodeResourceUsage::Output);
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: output_handle,
+                clear: Color::BLACK,
+                resolve: None,
+            }],
+            depth_stencil: None,
+        });
+
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+            let color = ctx.graph_data.get_render_target(color_handle);
+            let depth = ctx.graph_data.get_render_target(depth_handle);
+            let history = ctx.graph_data.get_render_target(history_handle);
+            let velocity = match velocity_handle {
+                Some(handle) => ctx.graph_data.get_render_target(handle),
+                None => &self.fallback_velocity,
+            };
+
+            profiling::scope!("taa resolve");
+
+            let uniforms = TaaUniforms {
+                jitter: self.jitter,
+                blend_factor: self.blend_factor,
+                history_valid: self.history_valid as u32,
+                velocity_valid: velocity_handle.is_some() as u32,
+                _padding: [0; 3],
+            };
+            let uniform_buffer = ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("taa uniforms"),
+                contents: bytemuck::bytes_of(&uniforms),
+                usage: BufferUsages::UNIFORM,
+            });
+
+            let taa_bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(color)
+                    .append_texture_view(history)
+                    .append_texture_view(depth)
+                    .append_texture_view(velocity)
+                    .append_buffer(&uniform_buffer)
+                    .build(&ctx.renderer.device, Some("taa bg"), &self.bgl),
+            );
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, taa_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+
+        history_write
+    }
+}
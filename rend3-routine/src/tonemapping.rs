@@ -5,59 +5,269 @@
 //! and will offer a variety of tonemapping operators.
 //!
 //! When creating the tonemapping, ensure you use the correct format for the
-//! output. Each TonemappingRoutine instance only has a single pipeline, so if
-//! you need to render to two different formats potentially, use two different
-//! routines.
+//! output. Each TonemappingRoutine instance only renders to a single format,
+//! so if you need to render to two different formats potentially, use two
+//! different routines.
+//!
+//! Cheap final-stage effects, like vignette, chromatic aberration, and
+//! dithering, are applied in the same pass and are configured through
+//! [`PostSettings`].
+//! Color grading through a 3D LUT is also applied in this pass, see
+//! [`TonemappingRoutine::set_lut`].
+//!
+//! By default the alpha of the hdr buffer is passed through as is. For
+//! compositing the output over other content, enable
+//! [`PostSettings::premultiplied_alpha`].
 
-use std::borrow::Cow;
+use std::{borrow::Cow, mem};
 
+use glam::{Vec3, Vec4};
+use once_cell::sync::OnceCell;
 use rend3::{
     graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle},
     util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
     Renderer, ShaderConfig, ShaderPreProcessor,
 };
 use wgpu::{
-    BindGroup, BindGroupLayout, BindingType, Color, ColorTargetState, ColorWrites, Device, FragmentState, FrontFace,
-    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
-    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType,
-    TextureViewDimension, VertexState,
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, BlendState, BufferBindingType, BufferUsages, Color, ColorTargetState,
+    ColorWrites, Device, Extent3d, FragmentState, FrontFace, MultisampleState, PipelineLayout,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+    VertexState,
+};
+
+use crate::{
+    common::WholeFrameInterfaces,
+    shaders::{check_override, ShaderOverrideError},
 };
 
-use crate::common::WholeFrameInterfaces;
+/// Name of the builtin tonemapping shader.
+const BUILTIN_SHADER: &str = "rend3-routine/blit.wgsl";
+
+bitflags::bitflags! {
+    struct PostFlags : u32 {
+        const VIGNETTE = 0x0001;
+        const CHROMATIC_ABERRATION = 0x0002;
+        const COLOR_GRADING = 0x0004;
+        const DITHERING = 0x0008;
+        const PREMULTIPLIED_ALPHA = 0x0010;
+        const EDGE_UPSCALING = 0x0020;
+    }
+}
+
+/// A 3D lookup table used for color grading.
+///
+/// The LUT maps display-referred (sRGB encoded) colors to display-referred
+/// colors, which matches `.cube` files produced by most art tools.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorGradingLut {
+    /// Amount of entries along each axis. Usually 17, 33, or 65.
+    pub size: u32,
+    /// `size^3` output colors. Red changes the fastest, then green, then blue.
+    pub data: Vec<Vec3>,
+}
+
+impl ColorGradingLut {
+    /// A LUT which doesn't change the color.
+    pub fn identity(size: u32) -> Self {
+        assert!(size >= 2, "LUT size must be at least 2, got {size}");
+
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(Vec3::new(r as f32, g as f32, b as f32) / max);
+                }
+            }
+        }
+
+        Self { size, data }
+    }
+
+    fn create_texture(&self, renderer: &Renderer) -> TextureView {
+        assert!(self.size >= 2, "LUT size must be at least 2, got {}", self.size);
+        assert_eq!(
+            self.data.len(),
+            (self.size * self.size * self.size) as usize,
+            "LUT data must have size^3 entries"
+        );
+
+        let data: Vec<Vec4> = self.data.iter().map(|c| c.extend(1.0)).collect();
+
+        let texture = renderer.device.create_texture_with_data(
+            &renderer.queue,
+            &TextureDescriptor {
+                label: Some("color grading lut"),
+                size: Extent3d {
+                    width: self.size,
+                    height: self.size,
+                    depth_or_array_layers: self.size,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D3,
+                format: TextureFormat::Rgba32Float,
+                usage: TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            bytemuck::cast_slice(&data),
+        );
+
+        texture.create_view(&TextureViewDescriptor::default())
+    }
+}
+
+/// Optional effects applied while tonemapping.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PostSettings {
+    /// Darken the image with a radial falloff towards the edges.
+    pub vignette: bool,
+    /// Strength of the vignette. At 0.5 the corners are fully black.
+    pub vignette_strength: f32,
+    /// Offset the red and blue channels radially from the center.
+    pub chromatic_aberration: bool,
+    /// Fraction of the distance to the center the channels are offset by.
+    pub chromatic_aberration_strength: f32,
+    /// Apply ordered dithering before the output is quantized to reduce
+    /// banding on 8-bit outputs.
+    pub dithering: bool,
+    /// Output premultiplied alpha, with alpha being how much of each pixel is
+    /// covered by the scene, for compositing the output over other content.
+    ///
+    /// Opaque surfaces and the skybox fully cover a pixel and blended surfaces
+    /// add their alpha, so to get a meaningful alpha, don't render a skybox
+    /// and clear with a fully transparent color, usually `Vec4::ZERO`.
+    /// Surfaces with an additive or multiplicative
+    /// [`BlendMode`](crate::pbr::BlendMode) don't change coverage.
+    pub premultiplied_alpha: bool,
+    /// Upscale the scene with an edge adaptive filter, in the style of FSR1,
+    /// instead of bilinear filtering. Only has an effect when the scene is
+    /// rendered at a lower resolution than the output, like with
+    /// [`BaseRenderGraph::render_scale`](crate::base::BaseRenderGraph::render_scale).
+    pub edge_adaptive_upscaling: bool,
+    /// How much the upscaler sharpens away from edges, from 0.0 to 1.0.
+    /// Edges are always sharpened.
+    pub upscaling_sharpness: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            vignette: false,
+            vignette_strength: 0.25,
+            chromatic_aberration: false,
+            chromatic_aberration_strength: 0.005,
+            dithering: false,
+            premultiplied_alpha: false,
+            edge_adaptive_upscaling: false,
+            upscaling_sharpness: 0.2,
+        }
+    }
+}
+
+impl PostSettings {
+    fn to_shader(self, color_grading: bool) -> ShaderPostSettings {
+        let mut flags = PostFlags::empty();
+        flags.set(PostFlags::VIGNETTE, self.vignette);
+        flags.set(PostFlags::CHROMATIC_ABERRATION, self.chromatic_aberration);
+        flags.set(PostFlags::COLOR_GRADING, color_grading);
+        flags.set(PostFlags::DITHERING, self.dithering);
+        flags.set(PostFlags::PREMULTIPLIED_ALPHA, self.premultiplied_alpha);
+        flags.set(PostFlags::EDGE_UPSCALING, self.edge_adaptive_upscaling);
+
+        ShaderPostSettings {
+            flags: flags.bits(),
+            vignette_strength: self.vignette_strength,
+            chromatic_aberration_strength: self.chromatic_aberration_strength,
+            upscaling_sharpness: self.upscaling_sharpness.clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C, align(16))]
+struct ShaderPostSettings {
+    flags: u32,
+    vignette_strength: f32,
+    chromatic_aberration_strength: f32,
+    upscaling_sharpness: f32,
+}
+
+unsafe impl bytemuck::Zeroable for ShaderPostSettings {}
+unsafe impl bytemuck::Pod for ShaderPostSettings {}
+
+/// The shader module and pipeline layout both tonemapping pipelines are
+/// created from.
+struct TonemappingShader {
+    name: String,
+    module: ShaderModule,
+    pll: PipelineLayout,
+}
+
+impl TonemappingShader {
+    fn new(
+        device: &Device,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        bgl: &BindGroupLayout,
+        shader: &str,
+    ) -> Result<Self, ShaderOverrideError> {
+        profiling::scope!("TonemappingShader::new");
+        let render = |shader: &str| spp.render_shader(shader, &ShaderConfig::default(), None);
+        let source = render(shader).map_err(|e| ShaderOverrideError::Preprocess {
+            shader: shader.to_owned(),
+            message: e.to_string(),
+        })?;
+        if shader != BUILTIN_SHADER {
+            let builtin_source =
+                render(BUILTIN_SHADER).unwrap_or_else(|e| panic!("Failed to preprocess shader {BUILTIN_SHADER}: {e}"));
+            check_override(
+                shader,
+                &source,
+                &builtin_source,
+                &["vs_main", "fs_main_scene", "fs_main_monitor"],
+            )?;
+        }
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(&format!("tonemapping {shader}")),
+            source: ShaderSource::Wgsl(Cow::Owned(source)),
+        });
+
+        let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("tonemapping pass"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, bgl],
+            push_constant_ranges: &[],
+        });
+
+        Ok(Self {
+            name: shader.to_owned(),
+            module,
+            pll,
+        })
+    }
+}
 
 fn create_pipeline(
     device: &Device,
-    spp: &ShaderPreProcessor,
-    interfaces: &WholeFrameInterfaces,
-    bgl: &BindGroupLayout,
+    shader: &TonemappingShader,
     output_format: TextureFormat,
+    blend: Option<BlendState>,
 ) -> RenderPipeline {
     profiling::scope!("TonemappingPass::new");
-    let module = device.create_shader_module(ShaderModuleDescriptor {
-        label: Some("tonemapping"),
-        source: ShaderSource::Wgsl(Cow::Owned(
-            spp.render_shader("rend3-routine/blit.wgsl", &ShaderConfig::default(), None)
-                .unwrap(),
-        )),
-    });
-
     let fs_entry_point = if output_format.is_srgb() {
         "fs_main_scene"
     } else {
         "fs_main_monitor"
     };
 
-    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-        label: Some("tonemapping pass"),
-        bind_group_layouts: &[&interfaces.forward_uniform_bgl, bgl],
-        push_constant_ranges: &[],
-    });
-
     device.create_render_pipeline(&RenderPipelineDescriptor {
-        label: Some("tonemapping pass"),
-        layout: Some(&pll),
+        label: Some(&format!("tonemapping pass {}", shader.name)),
+        layout: Some(&shader.pll),
         vertex: VertexState {
-            module: &module,
+            module: &shader.module,
             entry_point: "vs_main",
             buffers: &[],
         },
@@ -73,11 +283,11 @@ fn create_pipeline(
         depth_stencil: None,
         multisample: MultisampleState::default(),
         fragment: Some(FragmentState {
-            module: &module,
+            module: &shader.module,
             entry_point: fs_entry_point,
             targets: &[Some(ColorTargetState {
                 format: output_format,
-                blend: None,
+                blend,
                 write_mask: ColorWrites::all(),
             })],
         }),
@@ -90,7 +300,15 @@ fn create_pipeline(
 /// See module for documentation.
 pub struct TonemappingRoutine {
     bgl: BindGroupLayout,
+    shader: TonemappingShader,
     pipeline: RenderPipeline,
+    /// Blends over the output, see [`Self::add_to_graph_over`]. Only created
+    /// once that is used.
+    over_pipeline: OnceCell<RenderPipeline>,
+    lut: TextureView,
+    lut_enabled: bool,
+    output_format: TextureFormat,
+    pub post_settings: PostSettings,
 }
 
 impl TonemappingRoutine {
@@ -100,6 +318,26 @@ impl TonemappingRoutine {
         interfaces: &WholeFrameInterfaces,
         output_format: TextureFormat,
     ) -> Self {
+        Self::new_with_shader(renderer, spp, interfaces, output_format, BUILTIN_SHADER)
+            .expect("Failed to create the builtin tonemapping routine")
+    }
+
+    /// Create the routine, using the given shader instead of the builtin
+    /// `rend3-routine/blit.wgsl`.
+    ///
+    /// The shader must be registered with the [`ShaderPreProcessor`] and have
+    /// the same bindings as the builtin shader, and a `vs_main`,
+    /// `fs_main_scene`, and `fs_main_monitor` entry point. These are checked
+    /// against the builtin shader like
+    /// [`ShaderOverrides`](crate::pbr::ShaderOverrides) are, nothing is
+    /// created if they don't match.
+    pub fn new_with_shader(
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+        output_format: TextureFormat,
+        shader: &str,
+    ) -> Result<Self, ShaderOverrideError> {
         let bgl = BindGroupLayoutBuilder::new()
             .append(
                 ShaderStages::FRAGMENT,
@@ -110,11 +348,61 @@ impl TonemappingRoutine {
                 },
                 None,
             )
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                mem::size_of::<ShaderPostSettings>() as _,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                None,
+            )
             .build(&renderer.device, Some("bind bgl"));
 
-        let pipeline = create_pipeline(&renderer.device, spp, interfaces, &bgl, output_format);
+        let shader = TonemappingShader::new(&renderer.device, spp, interfaces, &bgl, shader)?;
+        let pipeline = create_pipeline(&renderer.device, &shader, output_format, None);
+
+        Ok(Self {
+            bgl,
+            shader,
+            pipeline,
+            over_pipeline: OnceCell::new(),
+            lut: ColorGradingLut::identity(2).create_texture(renderer),
+            lut_enabled: false,
+            output_format,
+            post_settings: PostSettings::default(),
+        })
+    }
+
+    /// Rebuild the pipeline from the current contents of `spp`, keeping all
+    /// other state. See [`builtin_shaders`](crate::builtin_shaders) for
+    /// re-reading the builtin shaders from disk. If an edited replacement
+    /// shader no longer matches the builtin shader, the old pipeline is kept.
+    #[cfg(feature = "shader-reload")]
+    pub fn reload_shaders(
+        &mut self,
+        renderer: &Renderer,
+        spp: &ShaderPreProcessor,
+        interfaces: &WholeFrameInterfaces,
+    ) -> Result<(), ShaderOverrideError> {
+        profiling::scope!("TonemappingRoutine::reload_shaders");
+
+        self.shader = TonemappingShader::new(&renderer.device, spp, interfaces, &self.bgl, &self.shader.name)?;
+        self.pipeline = create_pipeline(&renderer.device, &self.shader, self.output_format, None);
+        self.over_pipeline = OnceCell::new();
+        Ok(())
+    }
 
-        Self { bgl, pipeline }
+    /// Set the LUT used for color grading. `None` disables color grading.
+    pub fn set_lut(&mut self, renderer: &Renderer, lut: Option<&ColorGradingLut>) {
+        self.lut_enabled = lut.is_some();
+        self.lut = lut.unwrap_or(&ColorGradingLut::identity(2)).create_texture(renderer);
     }
 
     pub fn add_to_graph<'node>(
@@ -123,11 +411,68 @@ impl TonemappingRoutine {
         src: RenderTargetHandle,
         dst: RenderTargetHandle,
         forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        self.add_to_graph_inner(graph, src, dst, forward_uniform_bg, NodeResourceUsage::Output, false);
+    }
+
+    /// Same as [`Self::add_to_graph`], but if `dst` is an imported target, it
+    /// is not cleared, so everything outside of its viewport is kept.
+    pub fn add_to_graph_preserving<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        self.add_to_graph_inner(
+            graph,
+            src,
+            dst,
+            forward_uniform_bg,
+            NodeResourceUsage::InputOutput,
+            false,
+        );
+    }
+
+    /// Same as [`Self::add_to_graph_preserving`], but the scene is blended
+    /// over the existing contents of `dst` instead of replacing them.
+    ///
+    /// The scene is output with premultiplied alpha regardless of
+    /// [`PostSettings::premultiplied_alpha`], so `src` should be cleared to
+    /// `Vec4::ZERO` for anything not covered by the scene to keep what is
+    /// already in `dst`.
+    ///
+    /// The blending pipeline is created the first time this is rendered.
+    pub fn add_to_graph_over<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        self.add_to_graph_inner(
+            graph,
+            src,
+            dst,
+            forward_uniform_bg,
+            NodeResourceUsage::InputOutput,
+            true,
+        );
+    }
+
+    fn add_to_graph_inner<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        dst_usage: NodeResourceUsage,
+        over: bool,
     ) {
         let mut builder = graph.add_node("Tonemapping");
 
         let input_handle = builder.add_render_target(src, NodeResourceUsage::Input);
-        let output_handle = builder.add_render_target(dst, NodeResourceUsage::Output);
+        let output_handle = builder.add_render_target(dst, dst_usage);
 
         let rpass_handle = builder.add_renderpass(RenderPassTargets {
             targets: vec![RenderPassTarget {
@@ -147,15 +492,36 @@ impl TonemappingRoutine {
 
             profiling::scope!("tonemapping");
 
-            let blit_src_bg = ctx
-                .temps
-                .add(BindGroupBuilder::new().append_texture_view(hdr_color).build(
-                    &ctx.renderer.device,
-                    Some("blit src bg"),
-                    &self.bgl,
-                ));
+            let post_settings = PostSettings {
+                premultiplied_alpha: self.post_settings.premultiplied_alpha || over,
+                ..self.post_settings
+            };
+            let post_settings_buffer = ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("post settings"),
+                contents: bytemuck::bytes_of(&post_settings.to_shader(self.lut_enabled)),
+                usage: BufferUsages::UNIFORM,
+            });
+
+            let blit_src_bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(hdr_color)
+                    .append_buffer(&post_settings_buffer)
+                    .append_texture_view(&self.lut)
+                    .build(&ctx.renderer.device, Some("blit src bg"), &self.bgl),
+            );
 
-            rpass.set_pipeline(&self.pipeline);
+            let pipeline = match over {
+                true => self.over_pipeline.get_or_init(|| {
+                    create_pipeline(
+                        &ctx.renderer.device,
+                        &self.shader,
+                        self.output_format,
+                        Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    )
+                }),
+                false => &self.pipeline,
+            };
+            rpass.set_pipeline(pipeline);
             rpass.set_bind_group(0, forward_uniform_bg, &[]);
             rpass.set_bind_group(1, blit_src_bg, &[]);
             rpass.draw(0..3, 0..1);
@@ -3,7 +3,7 @@
 
 use std::iter::once;
 
-use glam::{Mat4, UVec2, Vec4};
+use glam::{Mat4, UVec2, Vec2, Vec4};
 use rend3::{
     graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderTargetHandle},
     managers::CameraManager,
@@ -14,7 +14,7 @@ use wgpu::{
     BindGroup, BufferUsages,
 };
 
-use crate::common::{Samplers, WholeFrameInterfaces};
+use crate::common::{LtcTables, Samplers, WholeFrameInterfaces};
 
 /// The actual structure passed to the shader.
 #[derive(Debug, Copy, Clone)]
@@ -26,18 +26,31 @@ pub struct FrameUniforms {
     pub inv_view: Mat4,
     pub inv_view_proj: Mat4,
     pub inv_origin_view_proj: Mat4,
+    /// Used to convert depth into linear view depth, see
+    /// [`CameraManager::linearize_depth`].
+    pub inv_proj: Mat4,
+    /// View projection of the previous frame, without jitter. Used for
+    /// velocity, see [`CameraManager::prev_view_proj`].
+    pub prev_view_proj: Mat4,
     pub frustum: Frustum,
     pub ambient: Vec4,
     pub resolution: UVec2,
+    /// Jitter applied to `view_proj` and `origin_view_proj`, in NDC.
+    pub jitter: Vec2,
 }
 impl FrameUniforms {
     /// Use the given camera to generate these uniforms.
-    pub fn new(camera: &CameraManager, ambient: Vec4, resolution: UVec2) -> Self {
+    ///
+    /// `jitter` is an offset in NDC applied to the projection, used for
+    /// temporal anti-aliasing. Pass [`Vec2::ZERO`] to disable it.
+    pub fn new(camera: &CameraManager, ambient: Vec4, resolution: UVec2, jitter: Vec2) -> Self {
         profiling::scope!("create uniforms");
 
+        let jitter_matrix = Mat4::from_translation(jitter.extend(0.0));
+
         let view = camera.view();
-        let view_proj = camera.view_proj();
-        let origin_view_proj = camera.origin_view_proj();
+        let view_proj = jitter_matrix * camera.view_proj();
+        let origin_view_proj = jitter_matrix * camera.origin_view_proj();
 
         Self {
             view,
@@ -46,9 +59,12 @@ impl FrameUniforms {
             inv_view: view.inverse(),
             inv_view_proj: view_proj.inverse(),
             inv_origin_view_proj: origin_view_proj.inverse(),
+            inv_proj: (jitter_matrix * camera.proj()).inverse(),
+            prev_view_proj: camera.prev_view_proj(),
             frustum: Frustum::from_matrix(camera.proj()),
             ambient,
             resolution,
+            jitter,
         }
     }
 }
@@ -65,8 +81,10 @@ pub fn add_to_graph<'node>(
     shadow_target: RenderTargetHandle,
     interfaces: &'node WholeFrameInterfaces,
     samplers: &'node Samplers,
+    ltc: &'node LtcTables,
     ambient: Vec4,
     resolution: UVec2,
+    jitter: Vec2,
 ) {
     let mut builder = graph.add_node("build uniform data");
     let shadow_handle = builder.add_data(shadow_uniform_bg, NodeResourceUsage::Output);
@@ -83,7 +101,7 @@ pub fn add_to_graph<'node>(
 
         samplers.add_to_bg(&mut bgb);
 
-        let uniforms = FrameUniforms::new(&ctx.data_core.camera_manager, ambient, resolution);
+        let uniforms = FrameUniforms::new(&ctx.data_core.camera_manager, ambient, resolution, jitter);
         let uniform_buffer = ctx.renderer.device.create_buffer_init(&BufferInitDescriptor {
             label: Some("frame uniform"),
             contents: bytemuck::bytes_of(&uniforms),
@@ -101,6 +119,8 @@ pub fn add_to_graph<'node>(
         );
 
         bgb.append_texture_view(shadow_target);
+        ctx.data_core.area_light_manager.add_to_bg(&mut bgb);
+        ltc.add_to_bg(&mut bgb);
 
         let forward_uniform_bg = bgb.build(
             &ctx.renderer.device,
@@ -19,7 +19,7 @@ use std::{
 use bytemuck::Zeroable;
 /// Reexport of the glam version rend3 is using.
 pub use glam;
-use glam::{Mat4, UVec2, Vec2, Vec3, Vec3A, Vec4};
+use glam::{Mat4, Quat, UVec2, Vec2, Vec3, Vec3A, Vec4, Vec4Swizzles};
 use list_any::VecAny;
 use thiserror::Error;
 
@@ -187,6 +187,8 @@ pub type MaterialHandle = ResourceHandle<MaterialTag>;
 pub type ObjectHandle = ResourceHandle<Object>;
 /// Refcounted handle to a DirectionalLight
 pub type DirectionalLightHandle = ResourceHandle<DirectionalLight>;
+/// Refcounted handle to an AreaLight
+pub type AreaLightHandle = ResourceHandle<AreaLight>;
 /// Refcounted handle to a Skeleton
 pub type SkeletonHandle = ResourceHandle<Skeleton>;
 /// Refcounted handle to an instance of GraphData with the type erased
@@ -206,6 +208,8 @@ pub type RawMaterialHandle = RawResourceHandle<MaterialTag>;
 pub type RawObjectHandle = RawResourceHandle<Object>;
 /// Internal non-owning handle to a DirectionalLight
 pub type RawDirectionalLightHandle = RawResourceHandle<DirectionalLight>;
+/// Internal non-owning handle to an AreaLight
+pub type RawAreaLightHandle = RawResourceHandle<AreaLight>;
 /// Internal non-owning handle to a Skeleton
 pub type RawSkeletonHandle = RawResourceHandle<Skeleton>;
 /// Internal non-owning handle to an instance of GraphData with the type erased
@@ -345,6 +349,9 @@ pub struct MeshBuilder {
     handedness: Handedness,
     flip_winding_order: bool,
     double_sided: bool,
+    without_tangents: bool,
+
+    bounding_sphere: Option<BoundingSphere>,
 }
 impl MeshBuilder {
     /// Create a new [`MeshBuilder`] with a given set of positions.
@@ -457,6 +464,22 @@ impl MeshBuilder {
         self
     }
 
+    /// Don't calculate tangents if they weren't provided. Use this for meshes
+    /// which are never normal mapped.
+    pub fn without_tangents(mut self) -> Self {
+        self.without_tangents = true;
+        self
+    }
+
+    /// Use the given bounding sphere for culling instead of computing one from
+    /// the vertex positions.
+    ///
+    /// See [`Mesh::bounding_sphere`] for more information.
+    pub fn with_bounding_sphere(mut self, bounding_sphere: BoundingSphere) -> Self {
+        self.bounding_sphere = Some(bounding_sphere);
+        self
+    }
+
     /// Doesn't run validation on the mesh.
     ///
     /// # Safety
@@ -475,8 +498,10 @@ impl MeshBuilder {
 
     /// Build a mesh, adding whatever components weren't provided.
     ///
-    /// If normals weren't provided, they will be calculated. If mesh
-    /// is right handed, will be converted to left handed.
+    /// If normals weren't provided, they will be calculated. If tangents
+    /// weren't provided, they will be calculated from the normals and first
+    /// texture coordinates, unless [`MeshBuilder::without_tangents`] was
+    /// called. If mesh is right handed, will be converted to left handed.
     ///
     /// All others will be filled with defaults.
     pub fn build(self) -> Result<Mesh, MeshValidationError> {
@@ -484,6 +509,7 @@ impl MeshBuilder {
             attributes: self.vertex_attributes,
             vertex_count: self.vertex_count,
             indices: self.indices.unwrap_or_else(|| (0..self.vertex_count as u32).collect()),
+            bounding_sphere: self.bounding_sphere,
         };
 
         let has_normals = mesh.find_attribute_index(&VERTEX_ATTRIBUTE_NORMAL).is_some();
@@ -504,7 +530,7 @@ impl MeshBuilder {
             unsafe { mesh.calculate_normals(self.handedness, true) };
         }
 
-        if !has_tangents {
+        if !has_tangents && !self.without_tangents {
             // SAFETY: We've validated this mesh or had its validity unsafely asserted.
             unsafe { mesh.calculate_tangents(true) };
         }
@@ -513,6 +539,71 @@ impl MeshBuilder {
     }
 }
 
+/// Represents a point in space and a radius from that point.
+#[derive(Debug, Default, Clone, Copy, PartialEq, encase::ShaderType)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+impl BoundingSphere {
+    /// Sphere around the bounding box of the given positions.
+    pub fn from_mesh(mesh: &[Vec3]) -> Self {
+        let center = find_mesh_center(mesh);
+        let radius = find_mesh_bounding_sphere_radius(center, mesh);
+
+        Self {
+            center: Vec3::from(center),
+            radius,
+        }
+    }
+
+    pub fn apply_transform(self, matrix: Mat4) -> Self {
+        let max_scale = matrix
+            .x_axis
+            .xyz()
+            .length_squared()
+            .max(
+                matrix
+                    .y_axis
+                    .xyz()
+                    .length_squared()
+                    .max(matrix.z_axis.xyz().length_squared()),
+            )
+            .sqrt();
+        let center = matrix * self.center.extend(1.0);
+
+        Self {
+            center: center.truncate(),
+            radius: max_scale * self.radius,
+        }
+    }
+}
+
+fn find_mesh_center(mesh: &[Vec3]) -> Vec3A {
+    let first = if let Some(first) = mesh.first() {
+        *first
+    } else {
+        return Vec3A::ZERO;
+    };
+    // Bounding box time baby!
+    let mut max = Vec3A::from(first);
+    let mut min = max;
+
+    for pos in mesh.iter().skip(1) {
+        let pos = Vec3A::from(*pos);
+        max = max.max(pos);
+        min = min.min(pos);
+    }
+
+    (max + min) / 2.0
+}
+
+fn find_mesh_bounding_sphere_radius(mesh_center: Vec3A, mesh: &[Vec3]) -> f32 {
+    mesh.iter().fold(0.0, |distance, pos| {
+        distance.max((Vec3A::from(*pos) - mesh_center).length())
+    })
+}
+
 /// A mesh that may be used by many objects.
 ///
 /// Meshes are in Structure of Array format and must have all the vertex_*
@@ -521,12 +612,24 @@ impl MeshBuilder {
 ///
 /// These can be annoying to construct, so use the [`MeshBuilder`] to make it
 /// easier.
+///
+/// Meshes are always triangle lists, as culling works on individual
+/// triangles. Lines and points can be drawn with a routine of their own, like
+/// the `DebugLineRoutine` of rend3-routine.
 #[derive(Debug)]
 pub struct Mesh {
     pub attributes: Vec<StoredVertexAttributeData>,
     pub vertex_count: usize,
 
     pub indices: Vec<u32>,
+
+    /// Bounding sphere used for culling objects using this mesh. If `None`,
+    /// it is computed from the vertex positions with
+    /// [`Mesh::calculate_bounding_sphere`] when the mesh is added.
+    ///
+    /// Set this when the mesh is deformed beyond its rest pose, like when
+    /// skinned, as anything outside of the sphere may be culled.
+    pub bounding_sphere: Option<BoundingSphere>,
 }
 
 impl Mesh {
@@ -604,6 +707,17 @@ impl Mesh {
         )
     }
 
+    /// Calculate the bounding sphere of the vertex positions. This is the
+    /// sphere used for culling if [`Mesh::bounding_sphere`] is `None`.
+    pub fn calculate_bounding_sphere(&self) -> BoundingSphere {
+        let positions = self
+            .attributes
+            .iter()
+            .find_map(|attribute| attribute.typed_data(&VERTEX_ATTRIBUTE_POSITION))
+            .unwrap_or(&[]);
+        BoundingSphere::from_mesh(positions)
+    }
+
     /// Calculate normals for the given mesh, assuming smooth shading and
     /// per-vertex normals.
     ///
@@ -833,9 +947,16 @@ impl Mesh {
             let uv1 = tex2 - tex1;
             let uv2 = tex3 - tex1;
 
-            let r = 1.0 / (uv1.x * uv2.y - uv1.y * uv2.x);
+            // Triangles with degenerate uvs have no meaningful tangent, and would poison
+            // every vertex they touch with NaNs. The determinant scales with the uv edges, so
+            // compare relative to them to keep densely packed uvs.
+            let det = uv1.x * uv2.y - uv1.y * uv2.x;
+            if det.abs() <= f32::EPSILON * uv1.length() * uv2.length() {
+                continue;
+            }
+            let r = 1.0 / det;
 
-            let tangent = (edge1 * Vec3::splat(uv2.y)) - (edge2 * Vec3::splat(uv1.y)) * r;
+            let tangent = ((edge1 * Vec3::splat(uv2.y)) - (edge2 * Vec3::splat(uv1.y))) * r;
 
             // SAFETY: The conditions of this function assert all thes indices are in-bounds
             unsafe { *tangents.get_unchecked_mut(idx0 as usize) += tangent };
@@ -916,15 +1037,46 @@ impl MipmapCount {
 }
 
 /// How texture mipmaps get generated.
+///
+/// Textures which shouldn't be mipmapped, like UI or data textures, should use
+/// [`MipmapCount::ONE`] with [`MipmapSource::Uploaded`].
 #[derive(Debug, Clone)]
 pub enum MipmapSource {
     /// The user will provide all of the mipmaps in the data texture. Upload all
     /// mip levels.
     Uploaded,
-    /// rend3 will generate the mipmaps for you. Upload only mip level 0.
+    /// rend3 will generate the mipmaps for you, by repeatedly downsampling
+    /// mip level 0 into each of the levels given by the [`MipmapCount`].
+    /// Upload only mip level 0.
+    ///
+    /// The format must be renderable and filterable, so this does not work
+    /// with compressed formats.
     Generated,
 }
 
+/// Whether a texture's data is sRGB encoded color or linear data.
+///
+/// Colors, like albedo and emissive textures, are usually sRGB encoded, while
+/// data, like normal, roughness, metallic, and ambient occlusion textures, are
+/// linear. Sampling a texture with an sRGB format decodes it to linear, so
+/// using the wrong format will wash out colors or skew data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// The variant of `format` with this color space. Formats without both an
+    /// sRGB and linear variant are returned unchanged.
+    pub fn format(self, format: TextureFormat) -> TextureFormat {
+        match self {
+            Self::Srgb => format.add_srgb_suffix(),
+            Self::Linear => format.remove_srgb_suffix(),
+        }
+    }
+}
+
 /// A bitmap image used as a data source for a texture.
 #[derive(Debug, Clone)]
 pub struct Texture {
@@ -934,6 +1086,9 @@ pub struct Texture {
     pub size: UVec2,
     pub mip_count: MipmapCount,
     pub mip_source: MipmapSource,
+    /// If set, the texture is created with the variant of `format` in this
+    /// color space, so the same data can be uploaded as a color or as data.
+    pub color_space: Option<ColorSpace>,
 }
 
 /// Describes a texture made from the mipmaps of another texture.
@@ -1066,6 +1221,14 @@ pub trait Material: Send + Sync + 'static {
 
     /// Fill up the given slice with data. This can be whatever data the shader expects.
     fn to_data(&self) -> Self::DataType;
+
+    /// Which of the samplers provided by the render routine the textures are
+    /// read with on the CpuDriven profile. Objects whose materials use
+    /// different samplers are drawn separately. The GpuDriven profile ignores
+    /// this, as its shaders pick the sampler from the material data.
+    fn sampler_index(&self) -> u8 {
+        0
+    }
 }
 
 /// Source of a mesh for an object.
@@ -1078,9 +1241,71 @@ pub enum ObjectMeshKind {
 changeable_struct! {
     /// An object in the world that is composed of a [`Mesh`] and [`Material`].
     pub struct Object <- ObjectChange {
+        /// The object holds on to this handle, so the mesh or skeleton is only
+        /// deleted once every object using it is gone, even if all other
+        /// handles to it were dropped.
         pub mesh_kind: ObjectMeshKind,
+        /// Kept alive by the object, the same as [`Self::mesh_kind`].
         pub material: MaterialHandle,
         pub transform: Mat4,
+        /// If true, the object is drawn in a separate pass after all other
+        /// forward rendering, ignoring the depth buffer. Later overlay objects
+        /// are drawn over earlier ones as determined by the material's sorting.
+        ///
+        /// Overlay objects do not cast shadows.
+        pub overlay: bool,
+        /// Bitmask of the layers this object is in. The object is only
+        /// rendered if it shares at least one layer with the culler's layer
+        /// mask. Use `u32::MAX` to be in every layer.
+        pub layers: u32,
+        /// If false, the object is skipped when rendering shadow maps.
+        pub cast_shadows: bool,
+        /// If false, the object is shaded as if no shadows fall on it.
+        pub receive_shadows: bool,
+        /// Multiplied into the final color of the object when forward
+        /// rendering. Use [`Vec4::ONE`] for no tint. This can be changed every
+        /// frame with `Renderer::set_object_tint` without touching the material.
+        pub tint: Vec4,
+        /// Clip planes and dissolve applied to the object. This can be changed
+        /// every frame with `Renderer::set_object_clip`.
+        pub clip: ObjectClip,
+    }
+}
+
+/// Maximum amount of clip planes on a single object.
+pub const MAX_OBJECT_CLIP_PLANES: usize = 4;
+
+/// Per-object fragment clipping, applied consistently in the forward, depth
+/// prepass and shadow passes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ObjectClip {
+    /// World space planes stored as `(normal, distance)`. Fragments at
+    /// position `p` where `normal.dot(p) + distance < 0` are discarded, so
+    /// the normal points towards the kept half. Unused planes should be
+    /// [`Vec4::ZERO`], which never clips.
+    pub planes: [Vec4; MAX_OBJECT_CLIP_PLANES],
+    /// Fraction of the object, in `[0, 1]`, that is dissolved away. The
+    /// threshold is compared against a 3d noise in model space, so the
+    /// pattern moves with the object. Zero disables dissolving.
+    pub dissolve: f32,
+    /// Frequency of the dissolve noise, in cells per model space unit.
+    pub dissolve_scale: f32,
+}
+
+impl Default for ObjectClip {
+    fn default() -> Self {
+        Self {
+            planes: [Vec4::ZERO; MAX_OBJECT_CLIP_PLANES],
+            dissolve: 0.0,
+            dissolve_scale: 1.0,
+        }
+    }
+}
+
+impl ObjectClip {
+    /// Returns true if this clips anything at all.
+    pub fn is_active(&self) -> bool {
+        self.dissolve > 0.0 || self.planes.iter().any(|&plane| plane != Vec4::ZERO)
     }
 }
 
@@ -1120,13 +1345,108 @@ changeable_struct! {
         /// Color of the light.
         pub color: Vec3,
         /// Resolution of the shadow map cascades (in pix)
+        ///
+        /// Must be a power of two no larger than the device's max texture
+        /// dimension, otherwise it is clamped with a warning. Changing this
+        /// reallocates the shadow atlas the next frame.
         pub resolution: u16,
-        /// Constant multiplier for the light.
+        /// If false, the light casts no shadows and takes no space in the
+        /// shadow atlas, making additional fill lights cheap.
+        pub cast_shadows: bool,
+        /// Constant multiplier for the light. With [`LightUnits::Photometric`]
+        /// this is the illuminance of the light in lux.
         pub intensity: f32,
         /// Direction of the sun.
         pub direction: Vec3,
         /// Distance from the camera that shadows should be calculated.
         pub distance: f32,
+        /// Extra distance towards the light in which objects still cast
+        /// shadows into the shadowed area. Increase this if tall or distant
+        /// casters lose their shadows. Larger margins lower the depth
+        /// precision of the shadow map.
+        pub caster_margin: f32,
+    }
+}
+
+/// Shape of an [`AreaLight`], in the light's local XY plane.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AreaLightShape {
+    /// Rectangle with the given full width (local X) and height (local Y).
+    Rectangle { size: Vec2 },
+    /// Disk with the given radius.
+    Disk { radius: f32 },
+}
+
+changeable_struct! {
+    /// Describes a light emitted from the surface of a rectangle or a disk.
+    ///
+    /// Area lights are shaded with linearly transformed cosines, so they
+    /// produce soft highlights shaped like the light. They cast no shadows.
+    pub struct AreaLight <- AreaLightChange {
+        /// Color of the light.
+        pub color: Vec3,
+        /// Constant multiplier for the light. With [`LightUnits::Photometric`]
+        /// this is the luminance of the surface of the light in nits.
+        pub intensity: f32,
+        /// Center of the light in world space.
+        pub position: Vec3,
+        /// Rotation of the light. The light's surface lies in its local XY
+        /// plane and emits along local -Z.
+        pub rotation: Quat,
+        /// Shape and size of the light.
+        pub shape: AreaLightShape,
+        /// If true, the light also emits along local +Z.
+        pub two_sided: bool,
+    }
+}
+
+/// How light intensities are interpreted.
+///
+/// With [`LightUnits::Relative`], intensities are unitless multipliers on the
+/// light color and are written to the hdr buffer as is.
+///
+/// With [`LightUnits::Photometric`], [`DirectionalLight::intensity`] is the
+/// illuminance in lux arriving at a surface perpendicular to the light. The
+/// light color should then be normalized to a luminance of 1. As real world
+/// values (the sun is around 100,000 lux) do not fit in a half float hdr
+/// buffer, intensities are multiplied by the camera exposure before shading,
+/// so the hdr buffer holds exposed values ready for tonemapping. Only
+/// directional and area lights are exposed: give emissive surfaces, the
+/// ambient term and the skybox values which are already exposed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightUnits {
+    /// Intensities are unitless multipliers.
+    Relative,
+    /// Intensities are in lux.
+    Photometric {
+        /// Exposure value of the camera at ISO 100. See [`LightUnits::ev100`].
+        ev100: f32,
+    },
+}
+
+impl Default for LightUnits {
+    fn default() -> Self {
+        Self::Relative
+    }
+}
+
+impl LightUnits {
+    /// Exposure value at ISO 100 of a physical camera with the given
+    /// aperture (f-stops), shutter time (seconds) and sensitivity (ISO).
+    pub fn ev100(aperture: f32, shutter_time: f32, iso: f32) -> f32 {
+        ((aperture * aperture) / shutter_time * 100.0 / iso).log2()
+    }
+
+    /// Multiplier applied to light intensities before they are used in
+    /// shading. This is always 1 for [`LightUnits::Relative`].
+    ///
+    /// This uses the saturation based sensitivity method, where a luminance
+    /// of `1.2 * 2^ev100` maps to 1.
+    pub fn exposure(self) -> f32 {
+        match self {
+            Self::Relative => 1.0,
+            Self::Photometric { ev100 } => 1.0 / (1.2 * 2.0_f32.powf(ev100)),
+        }
     }
 }
 
@@ -1196,6 +1516,9 @@ pub struct Skeleton {
     /// The `Skeleton::form_joint_transforms` constructor can be used to create
     /// a Skeleton with the joint transform matrices instead.
     pub joint_matrices: Vec<Mat4>,
+    /// Must have joint weights and joint indices, and no more joints than
+    /// there are `joint_matrices`. Otherwise the skeleton, and every object
+    /// using it, is skipped with a warning.
     pub mesh: MeshHandle,
 }
 
@@ -1221,3 +1544,83 @@ impl Skeleton {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec2, Vec3};
+
+    use crate::{AstcBlock, AstcChannel, ColorSpace, Mesh, TextureFormat};
+
+    /// Tangents of a unit quad in the xy plane, with uvs following the
+    /// positions scaled by `uv_scale`.
+    fn quad_tangents(uv_scale: f32) -> Vec<Vec3> {
+        let positions = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let normals = [Vec3::NEG_Z; 4];
+        let uvs = positions.map(|position| position.truncate() * uv_scale);
+        let mut tangents = [Vec3::ZERO; 4];
+
+        // SAFETY: All buffers have the same length and all indices are in bounds.
+        unsafe {
+            Mesh::calculate_tangents_for_buffers(&mut tangents, &positions, &normals, &uvs, &[0, 1, 2, 2, 3, 0], true)
+        };
+        tangents.to_vec()
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        for uv_scale in [1.0, 1.0e-4] {
+            for tangent in quad_tangents(uv_scale) {
+                assert!(
+                    tangent.abs_diff_eq(Vec3::X, 1.0e-5),
+                    "Expected {:?} at uv scale {uv_scale}, got {tangent:?}",
+                    Vec3::X
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_have_no_tangent() {
+        let positions = [Vec3::ZERO, Vec3::X, Vec3::Y];
+        let normals = [Vec3::NEG_Z; 3];
+        let uvs = [Vec2::ZERO, Vec2::ONE, Vec2::splat(2.0)];
+        let mut tangents = [Vec3::ONE; 3];
+
+        // SAFETY: All buffers have the same length and all indices are in bounds.
+        unsafe { Mesh::calculate_tangents_for_buffers(&mut tangents, &positions, &normals, &uvs, &[0, 1, 2], false) };
+        assert_eq!(tangents, [Vec3::ZERO; 3]);
+    }
+
+    #[test]
+    fn color_space_round_trip() {
+        let formats = [
+            TextureFormat::Rgba8Unorm,
+            TextureFormat::Bgra8Unorm,
+            TextureFormat::Bc1RgbaUnorm,
+            TextureFormat::Bc7RgbaUnorm,
+            TextureFormat::Etc2Rgba8Unorm,
+            TextureFormat::Astc {
+                block: AstcBlock::B4x4,
+                channel: AstcChannel::Unorm,
+            },
+        ];
+        for linear in formats {
+            let srgb = ColorSpace::Srgb.format(linear);
+            assert_ne!(srgb, linear);
+            assert!(srgb.is_srgb(), "{srgb:?} isn't srgb");
+            assert_eq!(ColorSpace::Srgb.format(srgb), srgb);
+            assert_eq!(ColorSpace::Linear.format(srgb), linear);
+            assert_eq!(ColorSpace::Linear.format(linear), linear);
+        }
+
+        for format in [TextureFormat::Rgba16Float, TextureFormat::R8Unorm] {
+            assert_eq!(ColorSpace::Srgb.format(format), format);
+            assert_eq!(ColorSpace::Linear.format(format), format);
+        }
+    }
+}
@@ -8,14 +8,16 @@ use std::{
 };
 
 use glam::UVec2;
+use parking_lot::RwLockWriteGuard;
 use wgpu::{
     Buffer, CommandBuffer, CommandEncoder, CommandEncoderDescriptor, LoadOp, Operations, RenderPass,
     RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, SurfaceTexture, Texture,
     TextureView, TextureViewDescriptor,
 };
 
-use super::ViewportRect;
+use super::{profile::CpuProfileRecorder, ViewportRect};
 use crate::{
+    format_sso,
     graph::{
         DataHandle, GraphResource, GraphSubResource, NodeExecutionContext, RenderGraphDataStore,
         RenderGraphEncoderOrPass, RenderGraphEncoderOrPassInner, RenderGraphNode, RenderGraphNodeBuilder,
@@ -27,7 +29,15 @@ use crate::{
 };
 
 /// Result of evaluating all instructions.
+///
+/// This is the prepared state of a frame, which [`RenderGraph::execute`]
+/// encodes. See [`Renderer`](crate::Renderer#frames) for the phases of a frame.
 pub struct InstructionEvaluationOutput {
+    /// Command buffers uploading the changes from instruction evaluation.
+    ///
+    /// These are submitted first, before any work of the graph, by
+    /// [`RenderGraph::execute`]. Pre-recorded command buffers which need to
+    /// run before the graph can be pushed here.
     pub cmd_bufs: Vec<CommandBuffer>,
     pub d2_texture: TextureManagerEvaluateOutput,
     pub d2c_texture: TextureManagerEvaluateOutput,
@@ -71,6 +81,8 @@ impl DataContents {
 struct ResourceSpan {
     first_reference: usize,
     first_usage: Option<usize>,
+    /// If the first usage reads the resource.
+    first_usage_reads: bool,
     last_reference: Option<usize>,
 }
 
@@ -80,6 +92,7 @@ pub struct RenderGraph<'node> {
     pub(super) imported_targets: Vec<&'node dyn AsTextureReference>,
     pub(super) data: Vec<DataContents>,
     pub(super) nodes: Vec<RenderGraphNode<'node>>,
+    pub(super) trailing_cmd_bufs: Vec<CommandBuffer>,
 }
 impl<'node> RenderGraph<'node> {
     pub fn new() -> Self {
@@ -88,9 +101,20 @@ impl<'node> RenderGraph<'node> {
             imported_targets: Vec::with_capacity(32),
             data: Vec::with_capacity(32),
             nodes: Vec::with_capacity(64),
+            trailing_cmd_bufs: Vec::new(),
         }
     }
 
+    /// Submit the given pre-recorded command buffers right after all work of
+    /// the graph, in the same submit. See the [module docs](super#submission)
+    /// for the full order.
+    pub fn add_trailing_command_buffers<I>(&mut self, cmd_bufs: I)
+    where
+        I: IntoIterator<Item = CommandBuffer>,
+    {
+        self.trailing_cmd_bufs.extend(cmd_bufs);
+    }
+
     pub fn add_node<'a, S>(&'a mut self, label: S) -> RenderGraphNodeBuilder<'a, 'node>
     where
         SsoString: From<S>,
@@ -105,7 +129,12 @@ impl<'node> RenderGraph<'node> {
         }
     }
 
-    pub fn add_render_target(&mut self, desc: RenderTargetDescriptor) -> RenderTargetHandle {
+    /// Add a render target to the graph.
+    ///
+    /// Zero sized resolutions are clamped to 1x1, as wgpu does not allow
+    /// creating zero sized textures.
+    pub fn add_render_target(&mut self, mut desc: RenderTargetDescriptor) -> RenderTargetHandle {
+        desc.resolution = desc.resolution.max(UVec2::ONE);
         let idx = self.targets.len();
         let handle = RenderTargetHandle {
             resource: GraphSubResource::Texture(TextureRegion {
@@ -225,6 +254,7 @@ impl<'node> RenderGraph<'node> {
                         .or_insert(ResourceSpan {
                             first_reference: idx,
                             first_usage: None,
+                            first_usage_reads: false,
                             last_reference: Some(idx),
                         });
                 }
@@ -233,12 +263,16 @@ impl<'node> RenderGraph<'node> {
                     resource_spans
                         .entry(input.to_resource())
                         .and_modify(|span| {
-                            span.first_usage.get_or_insert(idx);
+                            if span.first_usage.is_none() {
+                                span.first_usage = Some(idx);
+                                span.first_usage_reads = true;
+                            }
                             span.last_reference = Some(idx)
                         })
                         .or_insert(ResourceSpan {
                             first_reference: idx,
                             first_usage: Some(idx),
+                            first_usage_reads: true,
                             last_reference: Some(idx),
                         });
                 }
@@ -256,6 +290,7 @@ impl<'node> RenderGraph<'node> {
                         .or_insert(ResourceSpan {
                             first_reference: idx,
                             first_usage: Some(idx),
+                            first_usage_reads: false,
                             last_reference: end,
                         });
                 }
@@ -275,8 +310,8 @@ impl<'node> RenderGraph<'node> {
             }
         }
 
-        let mut data_core = renderer.data_core.lock();
-        let data_core = &mut *data_core;
+        let mut data_core_guard = renderer.data_core.write();
+        let data_core = &mut *data_core_guard;
 
         // Iterate through every node, allocating and deallocating textures as we go.
 
@@ -397,6 +432,11 @@ impl<'node> RenderGraph<'node> {
             }
         }
 
+        // Nodes only read the managers, so let readers on other threads in while recording. The upgradable
+        // lock still keeps out instruction evaluation and other graphs.
+        let data_core_guard = RwLockWriteGuard::downgrade_to_upgradable(data_core_guard);
+        let data_core = &*data_core_guard;
+
         profiling::scope!("Run Nodes");
 
         let encoder_cell = UnsafeCell::new(
@@ -408,6 +448,7 @@ impl<'node> RenderGraph<'node> {
 
         let mut next_rpass_idx = 0;
         let mut rpass = None;
+        let mut cpu_profile = CpuProfileRecorder::new();
 
         // Iterate through all the nodes and actually execute them.
         for (idx, node) in pruned_node_list.into_iter().enumerate() {
@@ -416,7 +457,26 @@ impl<'node> RenderGraph<'node> {
                 // borrowing when we make the new renderpass.
                 rpass = None;
 
+                if cpu_profile.end_pass() {
+                    // SAFETY: The renderpass borrowing the encoder just died.
+                    data_core
+                        .profiler
+                        .try_lock()
+                        .unwrap()
+                        .end_scope(unsafe { &mut *encoder_cell.get() });
+                }
+
                 if let Some(ref desc) = node.rpass {
+                    // Nodes sharing a renderpass are nested in a scope for the whole renderpass.
+                    let label = format_sso!("Renderpass: {}", node.label);
+                    cpu_profile.begin_pass(&label);
+                    data_core.profiler.try_lock().unwrap().begin_scope(
+                        &label,
+                        // SAFETY: Same context as above.
+                        unsafe { &mut *encoder_cell.get() },
+                        &renderer.device,
+                    );
+
                     rpass = Some(Self::create_rpass_from_desc(
                         desc,
                         // SAFETY: There are two things which borrow this encoder: the renderpass and the node's
@@ -469,8 +529,10 @@ impl<'node> RenderGraph<'node> {
                     None => RenderGraphEncoderOrPassInner::Encoder(unsafe { &mut *encoder_cell.get() }),
                 };
 
-                profiling::scope!(&format!("Node: {}", node.label));
+                // The label is passed as data, as some profiler backends need a constant scope name.
+                profiling::scope!("Node", node.label.as_str());
 
+                let node_start = cpu_profile.now();
                 data_core.profiler.try_lock().unwrap().begin_scope(
                     &node.label,
                     &mut encoder_or_rpass,
@@ -499,6 +561,7 @@ impl<'node> RenderGraph<'node> {
                 };
 
                 data_core.profiler.try_lock().unwrap().end_scope(&mut encoder_or_rpass);
+                cpu_profile.node(&node.label, node_start);
             }
         }
 
@@ -506,6 +569,16 @@ impl<'node> RenderGraph<'node> {
         // and output_cell safely
         drop(rpass);
 
+        if cpu_profile.end_pass() {
+            // SAFETY: The renderpass borrowing the encoder died above.
+            data_core
+                .profiler
+                .try_lock()
+                .unwrap()
+                .end_scope(unsafe { &mut *encoder_cell.get() });
+        }
+        *data_core.cpu_profile.try_lock().unwrap() = cpu_profile.finish();
+
         // SAFETY: the renderpass has dropped, and so has all the uses of the data, and
         // the immutable borrows of the allocator.
         unsafe { (*rpass_temps_cell.get()).clear() }
@@ -514,6 +587,7 @@ impl<'node> RenderGraph<'node> {
         // SAFETY: this is safe as we've dropped all renderpasses that possibly borrowed
         // it
         eval_output.cmd_bufs.push(encoder_cell.into_inner().finish());
+        eval_output.cmd_bufs.append(&mut self.trailing_cmd_bufs);
 
         let mut resolve_encoder = renderer.device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("profile resolve encoder"),
@@ -553,7 +627,9 @@ impl<'node> RenderGraph<'node> {
 
                 let first_usage = view_span.first_usage.expect("internal rendergraph error: renderpass attachment counts as a usage, but no first usage registered on texture");
 
-                let load = if first_usage == node_idx {
+                // Imported textures have meaningful contents, so keep them if the first node reads them.
+                let imported = matches!(target.color.handle.resource, GraphSubResource::ImportedTexture(_));
+                let load = if first_usage == node_idx && !(imported && view_span.first_usage_reads) {
                     LoadOp::Clear(target.clear)
                 } else {
                     LoadOp::Load
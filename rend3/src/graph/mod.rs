@@ -12,9 +12,8 @@
 //! Each node is a pile of arbitrary code that can use various resources within
 //! the renderer to do work.
 //!
-//! Two submits happen during execute. First, all work that doesn't interact
-//! with the surface is submitted, then the surface is acquired, then all the
-//! following work is submitted.
+//! All work is submitted at the end of [`RenderGraph::execute`], see
+//! [Submission](#submission).
 //!
 //! # Nodes
 //!
@@ -42,6 +41,24 @@
 //! If, inside the node, you need to create a temporary, you can put that temporary on
 //! the pool, and it will automatically have lifetime `'rpass`. The temporary is
 //! destroyed right after the renderpass is.
+//!
+//! # Submission
+//!
+//! [`RenderGraph::execute`] records every node, in order, into a single
+//! encoder, then submits everything with a single call to
+//! [`Queue::submit`](wgpu::Queue::submit). The command buffers are submitted
+//! in this order:
+//!
+//! 1. [`InstructionEvaluationOutput::cmd_bufs`], which holds the uploads from
+//!    instruction evaluation and anything pushed onto it after evaluation.
+//! 2. The encoder holding all nodes.
+//! 3. Buffers added with [`RenderGraph::add_trailing_command_buffers`].
+//! 4. A small buffer resolving the gpu profiler's queries.
+//!
+//! This lets pre-recorded work run before or after the graph without a second
+//! submit. Work which needs to run between two nodes can't be a separate
+//! command buffer, record it in a node of its own with the encoder given to
+//! that node instead.
 
 use std::ops::Range;
 
@@ -56,6 +73,7 @@ mod encpass;
 #[allow(clippy::module_inception)] // lmao
 mod graph;
 mod node;
+mod profile;
 mod store;
 mod temp;
 mod texture_store;
@@ -64,6 +82,7 @@ pub use data_handle::*;
 pub use encpass::*;
 pub use graph::*;
 pub use node::*;
+pub use profile::CpuTimerScopeResult;
 pub use store::*;
 pub use temp::*;
 pub(crate) use texture_store::*;
@@ -249,6 +268,10 @@ pub struct RenderPassTarget {
     /// can be used.
     pub color: DeclaredDependency<RenderTargetHandle>,
     /// Color the attachment will be cleared with if this is the first use.
+    ///
+    /// Imported targets are not cleared if the first node using them declares
+    /// them as [`NodeResourceUsage::InputOutput`], so their previous contents
+    /// are kept.
     pub clear: Color,
     /// Resolve attachment. Can only be present if color attachment has > 1
     /// sample.
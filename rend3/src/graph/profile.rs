@@ -0,0 +1,103 @@
+use std::{ops::Range, time::Instant};
+
+/// Cpu time spent recording a scope of the graph, and the scopes nested in it.
+///
+/// The tree has the same shape as the gpu timings returned from
+/// [`RenderGraph::execute`](super::RenderGraph::execute): a scope for each
+/// renderpass with its nodes nested in it, and nodes outside of renderpasses at
+/// the top level. The most recent tree is kept in
+/// [`RendererDataCore::cpu_profile`](crate::RendererDataCore::cpu_profile).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuTimerScopeResult {
+    pub label: String,
+    /// Seconds since the graph started recording its nodes.
+    pub time: Range<f64>,
+    pub nested_scopes: Vec<CpuTimerScopeResult>,
+}
+
+/// Builds the tree of [`CpuTimerScopeResult`] while the graph runs its nodes.
+///
+/// There is no clock on the web, so all times are zero there.
+pub(super) struct CpuProfileRecorder {
+    // Instant::now panics on the web.
+    start: Option<Instant>,
+    scopes: Vec<CpuTimerScopeResult>,
+    pass: Option<CpuTimerScopeResult>,
+}
+
+impl CpuProfileRecorder {
+    pub fn new() -> Self {
+        Self {
+            start: (!cfg!(target_arch = "wasm32")).then(Instant::now),
+            scopes: Vec::new(),
+            pass: None,
+        }
+    }
+
+    pub fn now(&self) -> f64 {
+        self.start.map_or(0.0, |start| start.elapsed().as_secs_f64())
+    }
+
+    pub fn begin_pass(&mut self, label: &str) {
+        self.end_pass();
+        let now = self.now();
+        self.pass = Some(CpuTimerScopeResult {
+            label: label.to_owned(),
+            time: now..now,
+            nested_scopes: Vec::new(),
+        });
+    }
+
+    /// Ends the current renderpass scope, returns false if there wasn't one.
+    pub fn end_pass(&mut self) -> bool {
+        let Some(mut pass) = self.pass.take() else {
+            return false;
+        };
+        pass.time.end = self.now();
+        self.scopes.push(pass);
+        true
+    }
+
+    /// Records a node which started at `start`, in the current renderpass if
+    /// there is one.
+    pub fn node(&mut self, label: &str, start: f64) {
+        let scope = CpuTimerScopeResult {
+            label: label.to_owned(),
+            time: start..self.now(),
+            nested_scopes: Vec::new(),
+        };
+        match self.pass {
+            Some(ref mut pass) => pass.nested_scopes.push(scope),
+            None => self.scopes.push(scope),
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<CpuTimerScopeResult> {
+        self.end_pass();
+        self.scopes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CpuProfileRecorder;
+
+    #[test]
+    fn nodes_nest_in_renderpasses() {
+        let mut recorder = CpuProfileRecorder::new();
+        recorder.node("culling", recorder.now());
+        recorder.begin_pass("Renderpass: opaque");
+        recorder.node("opaque", recorder.now());
+        recorder.node("cutout", recorder.now());
+        recorder.begin_pass("Renderpass: tonemapping");
+        recorder.node("tonemapping", recorder.now());
+        let scopes = recorder.finish();
+
+        let labels: Vec<_> = scopes.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(labels, ["culling", "Renderpass: opaque", "Renderpass: tonemapping"]);
+        let nested: Vec<_> = scopes[1].nested_scopes.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(nested, ["opaque", "cutout"]);
+        assert_eq!(scopes[2].nested_scopes.len(), 1);
+        assert!(scopes.iter().all(|s| s.time.start <= s.time.end));
+    }
+}
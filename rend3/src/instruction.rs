@@ -1,17 +1,19 @@
 use std::{mem, panic::Location};
 
-use glam::Mat4;
+use glam::{Mat4, Vec4};
 use parking_lot::Mutex;
 use rend3_types::{
-    MaterialHandle, MeshHandle, ObjectChange, ObjectHandle, RawDirectionalLightHandle, RawGraphDataHandleUntyped,
-    RawMaterialHandle, RawMeshHandle, RawSkeletonHandle, RawTexture2DHandle, RawTextureCubeHandle, Skeleton,
-    SkeletonHandle, Texture2DHandle, TextureCubeHandle, TextureFromTexture,
+    AreaLight, AreaLightChange, AreaLightHandle, LightUnits, MaterialHandle, MeshHandle, ObjectChange, ObjectClip,
+    ObjectHandle, RawAreaLightHandle, RawDirectionalLightHandle, RawGraphDataHandleUntyped, RawMaterialHandle,
+    RawMeshHandle, RawSkeletonHandle, RawTexture2DHandle, RawTextureCubeHandle, Skeleton, SkeletonHandle,
+    StoredVertexAttributeData, Texture2DHandle, TextureCubeHandle, TextureFromTexture,
 };
 use wgpu::{CommandBuffer, Device};
 
 use crate::{
-    managers::{GraphStorage, InternalMesh, InternalTexture, MaterialManager, TextureManager},
+    managers::{GraphStorage, InternalMesh, InternalTexture, MaterialManager, ObjectManager, TextureManager},
     types::{Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, Object, RawObjectHandle},
+    util::buffer::BufferGrowth,
     RendererProfile,
 };
 
@@ -25,7 +27,6 @@ pub enum InstructionKind {
     AddMesh {
         handle: MeshHandle,
         internal_mesh: InternalMesh,
-        cmd_buf: CommandBuffer,
     },
     AddSkeleton {
         handle: SkeletonHandle,
@@ -66,9 +67,19 @@ pub enum InstructionKind {
         handle: DirectionalLightHandle,
         light: DirectionalLight,
     },
+    AddAreaLight {
+        handle: AreaLightHandle,
+        light: AreaLight,
+    },
     AddGraphData {
         add_invoke: Box<dyn FnOnce(&mut GraphStorage) + Send>,
     },
+    ReserveObjects {
+        reserve_invoke: Box<dyn FnOnce(&mut ObjectManager, &Device) + Send>,
+    },
+    SetObjectBufferGrowth {
+        growth: BufferGrowth,
+    },
     ChangeMaterial {
         handle: MaterialHandle,
         change_invoke: Box<
@@ -81,6 +92,10 @@ pub enum InstructionKind {
         handle: RawDirectionalLightHandle,
         change: DirectionalLightChange,
     },
+    ChangeAreaLight {
+        handle: RawAreaLightHandle,
+        change: AreaLightChange,
+    },
     DeleteMesh {
         handle: RawMeshHandle,
     },
@@ -102,13 +117,28 @@ pub enum InstructionKind {
     DeleteDirectionalLight {
         handle: RawDirectionalLightHandle,
     },
+    DeleteAreaLight {
+        handle: RawAreaLightHandle,
+    },
     DeleteGraphData {
         handle: RawGraphDataHandleUntyped,
     },
+    UpdateMeshVertices {
+        handle: RawMeshHandle,
+        attributes: Vec<StoredVertexAttributeData>,
+    },
     SetObjectTransform {
         handle: RawObjectHandle,
         transform: Mat4,
     },
+    SetObjectTint {
+        handle: RawObjectHandle,
+        tint: Vec4,
+    },
+    SetObjectClip {
+        handle: RawObjectHandle,
+        clip: ObjectClip,
+    },
     SetSkeletonJointDeltas {
         handle: RawSkeletonHandle,
         joint_matrices: Vec<Mat4>,
@@ -119,6 +149,9 @@ pub enum InstructionKind {
     SetCameraData {
         data: Camera,
     },
+    SetLightUnits {
+        light_units: LightUnits,
+    },
     DuplicateObject {
         src_handle: ObjectHandle,
         dst_handle: ObjectHandle,
@@ -139,8 +172,11 @@ impl InstructionStreamPair {
     }
 
     pub fn swap(&self) {
-        let mut produce = self.producer.lock();
+        // Evaluation holds the consumer for its whole duration and pushes into the
+        // producer whenever it drops the last reference to a handle, so the consumer
+        // must always be locked first.
         let mut consume = self.consumer.lock();
+        let mut produce = self.producer.lock();
 
         mem::swap(&mut *produce, &mut *consume);
     }
@@ -197,8 +233,75 @@ impl DeletableRawResourceHandle for RawDirectionalLightHandle {
     }
 }
 
+impl DeletableRawResourceHandle for RawAreaLightHandle {
+    fn into_delete_instruction_kind(self) -> InstructionKind {
+        InstructionKind::DeleteAreaLight { handle: self }
+    }
+}
+
 impl DeletableRawResourceHandle for RawGraphDataHandleUntyped {
     fn into_delete_instruction_kind(self) -> InstructionKind {
         InstructionKind::DeleteGraphData { handle: self }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{panic::Location, sync::Arc, thread, time::Duration};
+
+    use super::{InstructionKind, InstructionStreamPair};
+
+    fn push(pair: &InstructionStreamPair) {
+        pair.push(InstructionKind::SetAspectRatio { ratio: 1.0 }, *Location::caller());
+    }
+
+    #[test]
+    fn swap_during_evaluation() {
+        let pair = Arc::new(InstructionStreamPair::new());
+
+        // Evaluation holds the consumer and pushes whenever it drops a handle.
+        let consumer = pair.consumer.lock();
+        let swapper = {
+            let pair = Arc::clone(&pair);
+            thread::spawn(move || pair.swap())
+        };
+        thread::sleep(Duration::from_millis(50));
+        push(&pair);
+        drop(consumer);
+
+        swapper.join().unwrap();
+        assert_eq!(pair.consumer.lock().len(), 1);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        const WRITERS: usize = 4;
+        const PUSHES: usize = 1000;
+
+        let pair = Arc::new(InstructionStreamPair::new());
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let pair = Arc::clone(&pair);
+                thread::spawn(move || {
+                    for _ in 0..PUSHES {
+                        push(&pair);
+                    }
+                })
+            })
+            .collect();
+
+        let mut received = 0;
+        while writers.iter().any(|writer| !writer.is_finished()) {
+            pair.swap();
+            received += pair.consumer.lock().drain(..).count();
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        pair.swap();
+        received += pair.consumer.lock().drain(..).count();
+
+        assert_eq!(received, WRITERS * PUSHES);
+    }
+}
@@ -141,6 +141,7 @@
 mod renderer;
 /// Managers for various type of resources.
 pub mod managers {
+    mod area;
     mod camera;
     mod directional;
     mod graph_storage;
@@ -151,6 +152,7 @@ pub mod managers {
     mod skeleton;
     mod texture;
 
+    pub use area::*;
     pub use camera::*;
     pub use directional::*;
     pub use graph_storage::*;
@@ -184,7 +186,11 @@ pub mod util {
     pub mod math;
     pub mod mipmap;
     pub mod output;
+    pub mod parallel;
+    pub mod readback;
     pub mod scatter_copy;
+    #[cfg(feature = "testing")]
+    pub mod testing;
     pub mod typedefs;
 }
 
@@ -0,0 +1,127 @@
+use std::f32::consts::{PI, SQRT_2};
+
+use encase::{ArrayLength, ShaderType};
+use glam::{Vec2, Vec3};
+use rend3_types::{AreaLight, AreaLightChange, AreaLightHandle, AreaLightShape, LightUnits, RawAreaLightHandle};
+use wgpu::{BindingType, BufferBindingType, BufferUsages, Device, ShaderStages};
+
+use crate::{
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        buffer::WrappedPotBuffer,
+    },
+    Renderer,
+};
+
+/// Internal representation of an area light.
+pub struct InternalAreaLight {
+    pub inner: AreaLight,
+}
+
+#[derive(Debug, Clone, ShaderType)]
+struct ShaderAreaLightBuffer {
+    count: ArrayLength,
+    #[size(runtime)]
+    array: Vec<ShaderAreaLight>,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct ShaderAreaLight {
+    /// World space center of the light.
+    pub position: Vec3,
+    /// Shape of the light, 0 for rectangles and 1 for disks.
+    pub shape: u32,
+    /// Color/intensity of the light.
+    pub color: Vec3,
+    /// 1 if the light emits from both sides.
+    pub two_sided: u32,
+    /// World space half extent of the light along its local X. For disks,
+    /// this is the circumradius of an octagon with the same area as the disk.
+    pub axis_x: Vec3,
+    /// World space half extent of the light along its local Y.
+    pub axis_y: Vec3,
+}
+
+/// Manages area lights.
+pub struct AreaLightManager {
+    data: Vec<Option<InternalAreaLight>>,
+    data_buffer: WrappedPotBuffer<ShaderAreaLightBuffer>,
+}
+impl AreaLightManager {
+    pub fn new(device: &Device) -> Self {
+        profiling::scope!("AreaLightManager::new");
+
+        Self {
+            data: Vec::new(),
+            data_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "area light data buffer"),
+        }
+    }
+
+    pub fn add(&mut self, handle: &AreaLightHandle, light: AreaLight) {
+        if handle.idx >= self.data.len() {
+            self.data.resize_with(handle.idx + 1, || None);
+        }
+        self.data[handle.idx] = Some(InternalAreaLight { inner: light })
+    }
+
+    pub fn update(&mut self, handle: RawAreaLightHandle, change: AreaLightChange) {
+        self.data[handle.idx]
+            .as_mut()
+            .unwrap()
+            .inner
+            .update_from_changes(change);
+    }
+
+    pub fn remove(&mut self, handle: RawAreaLightHandle) {
+        self.data[handle.idx].take().unwrap();
+    }
+
+    pub fn evaluate(&mut self, renderer: &Renderer, light_units: LightUnits) {
+        profiling::scope!("AreaLightManager::evaluate");
+
+        let exposure = light_units.exposure();
+
+        let lights = self.data.iter().filter_map(Option::as_ref).map(|light| {
+            let light = &light.inner;
+
+            let (shape, half_extent) = match light.shape {
+                AreaLightShape::Rectangle { size } => (0, size * 0.5),
+                // Disks are shaded as octagons, scaled so they keep the disk's area.
+                AreaLightShape::Disk { radius } => (1, Vec2::splat(radius * (PI / (2.0 * SQRT_2)).sqrt())),
+            };
+
+            ShaderAreaLight {
+                position: light.position,
+                shape,
+                color: light.color * light.intensity * exposure,
+                two_sided: light.two_sided as u32,
+                axis_x: light.rotation * Vec3::X * half_extent.x,
+                axis_y: light.rotation * Vec3::Y * half_extent.y,
+            }
+        });
+
+        let buffer = ShaderAreaLightBuffer {
+            count: ArrayLength,
+            array: lights.collect(),
+        };
+
+        self.data_buffer
+            .write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+    }
+
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: Some(ShaderAreaLightBuffer::min_size()),
+            },
+            None,
+        );
+    }
+
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_buffer(&self.data_buffer);
+    }
+}
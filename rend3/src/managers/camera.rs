@@ -1,4 +1,4 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec3, Vec4};
 use rend3_types::Handedness;
 
 use crate::{
@@ -16,6 +16,9 @@ pub struct CameraManager {
     world_frustum: Frustum,
     data: Camera,
     aspect_ratio: f32,
+    /// View projection of the previous frame, None before the first frame.
+    prev_view_proj: Option<Mat4>,
+    evaluated: bool,
 }
 impl CameraManager {
     /// Builds a new camera, using the given aspect ratio. If no aspect ratio is
@@ -37,9 +40,19 @@ impl CameraManager {
             world_frustum: frustum,
             data,
             aspect_ratio,
+            prev_view_proj: None,
+            evaluated: false,
         }
     }
 
+    /// Remember the camera of the frame which just ended as the previous
+    /// camera. Called once per frame, before any camera changes of the new
+    /// frame are applied.
+    pub(crate) fn begin_frame(&mut self) {
+        self.prev_view_proj = self.evaluated.then(|| self.view_proj());
+        self.evaluated = true;
+    }
+
     /// Sets the camera data, rebuilding the using the given aspect ratio. If no
     /// aspect ratio is given it is assumed that no aspect ratio scaling
     /// should be done.
@@ -64,6 +77,10 @@ impl CameraManager {
         self.data
     }
 
+    pub fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
     pub fn handedness(&self) -> Handedness {
         self.handedness
     }
@@ -76,6 +93,12 @@ impl CameraManager {
         self.proj * self.data.view
     }
 
+    /// View projection matrix of the previous frame, for computing how far
+    /// things moved on screen. In the first frame this is [`Self::view_proj`].
+    pub fn prev_view_proj(&self) -> Mat4 {
+        self.prev_view_proj.unwrap_or_else(|| self.view_proj())
+    }
+
     pub fn origin_view_proj(&self) -> Mat4 {
         self.proj * self.orig_view
     }
@@ -84,6 +107,28 @@ impl CameraManager {
         self.proj
     }
 
+    /// Distance to the near plane, if the camera has a perspective
+    /// projection. Perspective projections always have an infinitely far
+    /// far plane.
+    pub fn near(&self) -> Option<f32> {
+        match self.data.projection {
+            CameraProjection::Perspective { near, .. } => Some(near),
+            _ => None,
+        }
+    }
+
+    /// Convert a value from the depth buffer into the distance from the
+    /// camera's plane, in view space units.
+    ///
+    /// Depth is reversed, so 1.0 is the near plane and 0.0 is infinitely far
+    /// away for perspective projections. This uses the inverse of the
+    /// projection, so it works for any projection. Shaders can do the same with
+    /// `linearize_depth` from `rend3-routine/math/depth.wgsl`.
+    pub fn linearize_depth(&self, depth: f32) -> f32 {
+        let view = self.proj().inverse() * Vec4::new(0.0, 0.0, depth, 1.0);
+        (view.z / view.w).abs()
+    }
+
     pub fn world_frustum(&self) -> Frustum {
         self.world_frustum
     }
@@ -120,3 +165,38 @@ fn compute_origin_matrix(data: Camera) -> Mat4 {
     view.w_axis = glam::Vec4::W;
     view
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+    use rend3_types::Handedness;
+
+    use super::CameraManager;
+    use crate::types::Camera;
+
+    #[test]
+    fn prev_view_proj_lags_one_frame() {
+        let camera_at = |x: f32| Camera {
+            view: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+            ..Camera::default()
+        };
+        let mut manager = CameraManager::new(camera_at(0.0), Handedness::Left, Some(1.0));
+
+        // There is no previous frame yet, so nothing moved.
+        manager.begin_frame();
+        manager.set_data(camera_at(1.0));
+        assert_eq!(manager.prev_view_proj(), manager.view_proj());
+        let first = manager.view_proj();
+
+        manager.begin_frame();
+        manager.set_data(camera_at(2.0));
+        assert_eq!(manager.prev_view_proj(), first);
+        let second = manager.view_proj();
+
+        // A frame without changes has the previous camera equal to the current one.
+        manager.begin_frame();
+        assert_eq!(manager.prev_view_proj(), second);
+        manager.begin_frame();
+        assert_eq!(manager.prev_view_proj(), manager.view_proj());
+    }
+}
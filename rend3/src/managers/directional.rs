@@ -1,6 +1,6 @@
 use encase::{ArrayLength, ShaderType};
 use glam::{Mat4, UVec2, Vec2, Vec3};
-use rend3_types::{DirectionalLightChange, RawDirectionalLightHandle};
+use rend3_types::{DirectionalLightChange, LightUnits, RawDirectionalLightHandle};
 use wgpu::{
     BindingType, BufferBindingType, BufferUsages, Device, Extent3d, ShaderStages, TextureDescriptor, TextureDimension,
     TextureUsages, TextureView, TextureViewDescriptor,
@@ -48,7 +48,8 @@ struct ShaderDirectionalLight {
     pub inv_resolution: Vec2,
     /// [0, 1] offset of the shadow map in the atlas.
     pub atlas_offset: Vec2,
-    /// [0, 1] size of the shadow map in the atlas.
+    /// [0, 1] size of the shadow map in the atlas. Zero if the light casts
+    /// no shadows.
     pub atlas_size: Vec2,
 }
 
@@ -65,9 +66,12 @@ pub struct DirectionalLightManager {
 
     texture_size: UVec2,
     texture_view: TextureView,
+    max_texture_dimension: u32,
+
+    light_units: LightUnits,
 }
 impl DirectionalLightManager {
-    pub fn new(device: &Device) -> Self {
+    pub fn new(device: &Device, max_texture_dimension: u32) -> Self {
         profiling::scope!("DirectionalLightManager::new");
 
         let texture_size = MINIMUM_SHADOW_MAP_SIZE;
@@ -78,22 +82,34 @@ impl DirectionalLightManager {
             data_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "shadow data buffer"),
             texture_size,
             texture_view,
+            max_texture_dimension,
+            light_units: LightUnits::default(),
         }
     }
 
-    pub fn add(&mut self, handle: &DirectionalLightHandle, light: DirectionalLight) {
+    pub fn set_light_units(&mut self, light_units: LightUnits) {
+        self.light_units = light_units;
+    }
+
+    pub fn light_units(&self) -> LightUnits {
+        self.light_units
+    }
+
+    pub fn add(&mut self, handle: &DirectionalLightHandle, mut light: DirectionalLight) {
         if handle.idx >= self.data.len() {
             self.data.resize_with(handle.idx + 1, || None);
         }
+        light.resolution = validate_resolution(light.resolution, self.max_texture_dimension);
         self.data[handle.idx] = Some(InternalDirectionalLight { inner: light })
     }
 
     pub fn update(&mut self, handle: RawDirectionalLightHandle, change: DirectionalLightChange) {
-        self.data[handle.idx]
-            .as_mut()
-            .unwrap()
-            .inner
-            .update_from_changes(change);
+        let light = &mut self.data[handle.idx].as_mut().unwrap().inner;
+        let resolution_changed = change.resolution.is_some();
+        light.update_from_changes(change);
+        if resolution_changed {
+            light.resolution = validate_resolution(light.resolution, self.max_texture_dimension);
+        }
     }
 
     pub fn remove(&mut self, handle: RawDirectionalLightHandle) {
@@ -107,7 +123,12 @@ impl DirectionalLightManager {
             .data
             .iter()
             .enumerate()
-            .filter_map(|(idx, light)| Some((RawDirectionalLightHandle::new(idx), light.as_ref()?.inner.resolution)))
+            .filter_map(|(idx, light)| {
+                let light = &light.as_ref()?.inner;
+                light
+                    .cast_shadows
+                    .then_some((RawDirectionalLightHandle::new(idx), light.resolution))
+            })
             .collect();
         let shadow_atlas = shadow_alloc::allocate_shadow_atlas(shadow_maps, renderer.limits.max_texture_dimension_2d);
 
@@ -122,10 +143,9 @@ impl DirectionalLightManager {
             self.texture_view = create_shadow_texture(&renderer.device, self.texture_size);
         }
 
-        let coordinates = match shadow_atlas {
-            Some(m) => m.maps,
-            None => return (new_shadow_map_size, Vec::new()),
-        };
+        let exposure = self.light_units.exposure();
+
+        let coordinates = shadow_atlas.map(|m| m.maps).unwrap_or_default();
 
         let shadow_data: Vec<_> = coordinates
             .into_iter()
@@ -136,23 +156,36 @@ impl DirectionalLightManager {
             })
             .collect();
 
+        let shadowed = shadow_data.iter().map(|desc| {
+            let light = &self.data[desc.map.handle.idx].as_ref().unwrap().inner;
+
+            ShaderDirectionalLight {
+                view_proj: desc.camera.view_proj(),
+                color: light.color * light.intensity * exposure,
+                direction: light.direction,
+                inv_resolution: 1.0 / new_shadow_map_size_f32,
+                atlas_offset: desc.map.offset.as_vec2() / new_shadow_map_size_f32,
+                atlas_size: desc.map.size as f32 / new_shadow_map_size_f32,
+            }
+        });
+        let unshadowed = self
+            .data
+            .iter()
+            .filter_map(Option::as_ref)
+            .filter(|light| !light.inner.cast_shadows)
+            .map(|light| ShaderDirectionalLight {
+                view_proj: Mat4::IDENTITY,
+                color: light.inner.color * light.inner.intensity * exposure,
+                direction: light.inner.direction,
+                inv_resolution: Vec2::ZERO,
+                atlas_offset: Vec2::ZERO,
+                atlas_size: Vec2::ZERO,
+            });
+
+        // Shadowed lights must come first, as shadow passes index this with their shadow index.
         let buffer = ShaderDirectionalLightBuffer {
             count: ArrayLength,
-            array: shadow_data
-                .iter()
-                .map(|desc| {
-                    let light = &self.data[desc.map.handle.idx].as_ref().unwrap().inner;
-
-                    ShaderDirectionalLight {
-                        view_proj: desc.camera.view_proj(),
-                        color: light.color * light.intensity,
-                        direction: light.direction,
-                        inv_resolution: 1.0 / new_shadow_map_size_f32,
-                        atlas_offset: desc.map.offset.as_vec2() / new_shadow_map_size_f32,
-                        atlas_size: desc.map.size as f32 / new_shadow_map_size_f32,
-                    }
-                })
-                .collect(),
+            array: shadowed.chain(unshadowed).collect(),
         };
 
         self.data_buffer
@@ -178,6 +211,30 @@ impl DirectionalLightManager {
     }
 }
 
+/// The shadow atlas needs power of two resolutions which fit inside a single
+/// texture, so fix up any resolution that doesn't.
+fn validate_resolution(resolution: u16, max_texture_dimension: u32) -> u16 {
+    let max_log2 = u32::BITS - 1 - max_texture_dimension.max(1).leading_zeros();
+    let max_resolution = 1_u16 << max_log2.min(u16::BITS - 1);
+    let min_resolution = MINIMUM_SHADOW_MAP_SIZE.x as u16;
+
+    let mut valid = resolution.clamp(min_resolution, max_resolution);
+    if !valid.is_power_of_two() {
+        valid = valid
+            .checked_next_power_of_two()
+            .unwrap_or(max_resolution)
+            .min(max_resolution);
+    }
+
+    if valid != resolution {
+        log::warn!(
+            "Directional light shadow resolution {resolution} is not a power of two between {min_resolution} and {max_resolution}, using {valid} instead"
+        );
+    }
+
+    valid
+}
+
 fn create_shadow_texture(device: &Device, size: UVec2) -> TextureView {
     profiling::scope!("shadow texture creation");
 
@@ -22,10 +22,15 @@ pub(super) fn shadow_camera(l: &InternalDirectionalLight, user_camera: &CameraMa
     let inv_origin_view = origin_view.inverse();
     let new_shadow_location = inv_origin_view.transform_point3(shadow_location);
 
+    // Pull the camera back towards the light and deepen the projection by the margin, so the far plane stays
+    // put while casters within the margin are in front of the near plane.
+    let margin = l.inner.caster_margin.max(0.0);
+    let new_shadow_location = new_shadow_location - l.inner.direction.normalize_or_zero() * (margin * 0.5);
+
     CameraManager::new(
         Camera {
             projection: CameraProjection::Orthographic {
-                size: Vec3A::splat(l.inner.distance),
+                size: Vec3A::new(l.inner.distance, l.inner.distance, l.inner.distance + margin),
             },
             view: look_at(new_shadow_location, new_shadow_location + l.inner.direction, Vec3::Y),
         },
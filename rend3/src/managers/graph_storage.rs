@@ -5,8 +5,9 @@ use rend3_types::{GraphDataHandle, RawGraphDataHandleUntyped};
 
 #[derive(Default)]
 pub struct GraphStorage {
-    // Type under any is Mutex<T>
-    data: Vec<Option<Box<dyn Any + Send>>>,
+    // Type under any is Mutex<T>, which is Sync for any T: Send, so the
+    // renderer stays Sync.
+    data: Vec<Option<Box<dyn Any + Send + Sync>>>,
 }
 
 impl GraphStorage {
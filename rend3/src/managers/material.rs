@@ -271,6 +271,11 @@ impl MaterialManager {
         );
     }
 
+    /// Whether the material exists.
+    pub fn contains(&self, handle: RawMaterialHandle) -> bool {
+        self.handle_to_typeid.contains_key(&handle)
+    }
+
     pub(super) fn call_object_add_callback(&self, handle: RawMaterialHandle, args: ObjectAddCallbackArgs) {
         let type_id = self.handle_to_typeid[&handle];
 
@@ -351,7 +356,7 @@ fn apply_buffer_gpu<M: Material>(
         GpuPoweredShaderWrapper::<M> {
             textures: material
                 .to_textures()
-                .map_to_u32(|handle_opt| handle_opt.map(translation_fn).map_or(0, NonZeroU32::get)),
+                .map_to_u32(|handle_opt| handle_opt.and_then(translation_fn).map_or(0, NonZeroU32::get)),
             data: material.to_data(),
         }
     });
@@ -1,4 +1,5 @@
 use std::{
+    mem,
     num::NonZeroU64,
     ops::{Index, Range},
     sync::Arc,
@@ -6,8 +7,14 @@ use std::{
 
 use parking_lot::{Mutex, MutexGuard, RwLock};
 use range_alloc::RangeAllocator;
-use rend3_types::{RawMeshHandle, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_POSITION};
-use wgpu::{Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoder, Device, Queue};
+use rend3_types::{
+    RawMeshHandle, StoredVertexAttributeData, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES,
+    VERTEX_ATTRIBUTE_POSITION,
+};
+use thiserror::Error;
+use wgpu::{
+    Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, IndexFormat, Queue,
+};
 
 use crate::{
     types::{Mesh, MeshHandle},
@@ -21,18 +28,45 @@ pub const VERTEX_OBJECT_INDEX_SLOT: u32 = 0;
 /// Pre-allocated mesh data. 32MB.
 pub const STARTING_MESH_DATA: u64 = 1 << 25;
 
+/// Reason why [`Renderer::update_mesh_vertices`](crate::Renderer::update_mesh_vertices)
+/// rejected an update.
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq)]
+pub enum MeshUpdateError {
+    #[error("Mesh does not have a {0} attribute to update")]
+    MissingAttribute(&'static str),
+    #[error("{name} attribute has {actual} vertices, but the other attributes have {expected}")]
+    VertexCountMismatch {
+        name: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Changing the vertex count of a mesh requires updating all of its attributes, but {0} is missing")]
+    IncompleteResize(&'static str),
+    #[error("Skinned meshes can't change their vertex count, add a new mesh to change it")]
+    SkinnedResize,
+}
+
 /// Internal representation of a mesh.
 pub struct InternalMesh {
-    /// Location in the vertex buffer for each vertex attribute
+    /// Location in the vertex buffer for each vertex attribute. This may be
+    /// padded past the last vertex, if the mesh shrank after it was added.
     pub vertex_attribute_ranges: Vec<(VertexAttributeId, Range<u64>)>,
     /// Vertex count
     pub vertex_count: u32,
     /// Range in the mesh data buffer where index data for this mesh resides.
+    /// This may be padded past the last index.
     pub index_range: Range<u64>,
+    /// Index count
+    pub index_count: u32,
+    /// Format of the indices in the mesh data buffer. Meshes with few enough
+    /// vertices use 16 bit indices.
+    pub index_format: IndexFormat,
     /// For skinned meshes, stores the maximum joint index present in the joint
     /// index buffer. None means it has no joint index buffer.
     pub required_joint_count: Option<u16>,
-    /// The bounding sphere of this mesh. Used for culling.
+    /// The bounding sphere of this mesh. Used for culling. Inspect this
+    /// through [`MeshManager::lock_internal_data`] to debug meshes which are
+    /// wrongly culled.
     pub bounding_sphere: BoundingSphere,
 }
 
@@ -43,6 +77,8 @@ impl InternalMesh {
             vertex_attribute_ranges: Vec::new(),
             vertex_count: 0,
             index_range: 0..0,
+            index_count: 0,
+            index_format: IndexFormat::Uint32,
             required_joint_count: None,
             bounding_sphere: BoundingSphere::from_mesh(&[]),
         }
@@ -63,6 +99,10 @@ pub struct MeshManager {
     allocator: Mutex<RangeAllocator<u64>>,
 
     data: Mutex<Vec<Option<InternalMesh>>>,
+    /// Size in bytes of each attribute of every mesh, recorded when the mesh
+    /// is added and when an update is validated, so updates can be validated
+    /// before the mesh is filled or earlier updates are applied.
+    attribute_sizes: Mutex<Vec<Vec<(VertexAttributeId, u64)>>>,
 }
 
 impl MeshManager {
@@ -84,11 +124,12 @@ impl MeshManager {
             buffer,
             allocator,
             data,
+            attribute_sizes: Mutex::new(Vec::new()),
         }
     }
 
     #[must_use]
-    pub fn add(&self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder, mesh: Mesh) -> InternalMesh {
+    pub fn add(&self, device: &Device, queue: &Queue, handle: RawMeshHandle, mesh: Mesh) -> InternalMesh {
         profiling::scope!("MeshManager::add");
 
         let index_count = mesh.indices.len();
@@ -96,6 +137,7 @@ impl MeshManager {
         // If vertex_count is 0, index_count _must_ also be 0, as all indices would be
         // out of range.
         if index_count == 0 {
+            self.record_attribute_sizes(handle, Vec::new());
             return InternalMesh::new_empty();
         }
 
@@ -113,12 +155,29 @@ impl MeshManager {
         let mut allocator_guard = self.allocator.lock();
         let mut vertex_attribute_ranges = Vec::with_capacity(mesh.attributes.len());
         for attribute in &mesh.attributes {
-            let range = self.allocate_range_impl(device, encoder, &mut allocator_guard, attribute.bytes());
+            let range = self.allocate_range_impl(device, queue, &mut allocator_guard, attribute.bytes());
             vertex_attribute_ranges.push((*attribute.id(), range));
         }
-        let index_range = self.allocate_range_impl(device, encoder, &mut allocator_guard, index_count as u64 * 4);
+        let index_format = match mesh.vertex_count <= (u16::MAX as usize + 1) {
+            true => IndexFormat::Uint16,
+            false => IndexFormat::Uint32,
+        };
+        // Keep allocations 4 byte aligned, as the buffer is also read as u32s.
+        let index_bytes = match index_format {
+            IndexFormat::Uint16 => (index_count as u64 * 2 + 3) & !3,
+            IndexFormat::Uint32 => index_count as u64 * 4,
+        };
+        let index_range = self.allocate_range_impl(device, queue, &mut allocator_guard, index_bytes);
         drop(allocator_guard);
 
+        self.record_attribute_sizes(
+            handle,
+            vertex_attribute_ranges
+                .iter()
+                .map(|(id, range)| (*id, range.end - range.start))
+                .collect(),
+        );
+
         let buffer_guard = self.buffer.read();
         for (attribute_data, (_, range)) in mesh.attributes.iter().zip(&vertex_attribute_ranges) {
             let mut mapping = queue
@@ -132,29 +191,27 @@ impl MeshManager {
         }
 
         let mut mapping = queue
-            .write_buffer_with(
-                &buffer_guard,
-                index_range.start,
-                NonZeroU64::new(mesh.indices.len() as u64 * 4).unwrap(),
-            )
+            .write_buffer_with(&buffer_guard, index_range.start, NonZeroU64::new(index_bytes).unwrap())
             .unwrap();
-        mapping.copy_from_slice(bytemuck::cast_slice(&mesh.indices));
+        match index_format {
+            IndexFormat::Uint16 => {
+                let mut indices: Vec<u16> = mesh.indices.iter().map(|&index| index as u16).collect();
+                indices.resize(index_bytes as usize / 2, 0);
+                mapping.copy_from_slice(bytemuck::cast_slice(&indices));
+            }
+            IndexFormat::Uint32 => mapping.copy_from_slice(bytemuck::cast_slice(&mesh.indices)),
+        }
         drop(mapping);
         drop(buffer_guard);
 
-        // We can cheat here as we know vertex positions are always the first attribute as they must exist.
-        let bounding_sphere = BoundingSphere::from_mesh(
-            mesh.attributes
-                .first()
-                .unwrap()
-                .typed_data(&VERTEX_ATTRIBUTE_POSITION)
-                .unwrap(),
-        );
+        let bounding_sphere = mesh.bounding_sphere.unwrap_or_else(|| mesh.calculate_bounding_sphere());
 
         InternalMesh {
             vertex_attribute_ranges,
             vertex_count: mesh.vertex_count as u32,
             index_range,
+            index_count: index_count as u32,
+            index_format,
             required_joint_count,
             bounding_sphere,
         }
@@ -171,6 +228,133 @@ impl MeshManager {
         drop(data_guard);
     }
 
+    fn record_attribute_sizes(&self, handle: RawMeshHandle, sizes: Vec<(VertexAttributeId, u64)>) {
+        let mut sizes_guard = self.attribute_sizes.lock();
+        if handle.idx >= sizes_guard.len() {
+            sizes_guard.resize_with(handle.idx + 1, Vec::new);
+        }
+        sizes_guard[handle.idx] = sizes;
+    }
+
+    /// Check that every attribute exists in the mesh, and that they all have
+    /// the same vertex count. If that count differs from the mesh's, every
+    /// attribute of the mesh must be updated, and the mesh must not be
+    /// skinned. Works from the moment the mesh was added, even before it is
+    /// filled.
+    ///
+    /// On success, the new sizes are recorded to validate later updates
+    /// against.
+    pub fn validate_update(
+        &self,
+        handle: RawMeshHandle,
+        attributes: &[StoredVertexAttributeData],
+    ) -> Result<(), MeshUpdateError> {
+        let mut sizes_guard = self.attribute_sizes.lock();
+        // Every handle has its sizes recorded when the mesh is added.
+        let sizes = &mut sizes_guard[handle.idx];
+
+        let mut vertex_count = None;
+        for attribute in attributes {
+            let name = attribute.id().name();
+            if !sizes.iter().any(|(id, _)| id == attribute.id()) {
+                return Err(MeshUpdateError::MissingAttribute(name));
+            }
+            let actual = attribute.bytes() / attribute.id().metadata().size as u64;
+            match vertex_count {
+                None => vertex_count = Some(actual),
+                Some(expected) if expected != actual => {
+                    return Err(MeshUpdateError::VertexCountMismatch { name, expected, actual });
+                }
+                Some(_) => {}
+            }
+        }
+
+        let resized = match vertex_count {
+            Some(count) => sizes
+                .iter()
+                .any(|(id, bytes)| bytes / id.metadata().size as u64 != count),
+            None => false,
+        };
+        if resized {
+            if sizes.iter().any(|(id, _)| id == VERTEX_ATTRIBUTE_JOINT_INDICES.id()) {
+                return Err(MeshUpdateError::SkinnedResize);
+            }
+            if let Some((id, _)) = sizes
+                .iter()
+                .find(|(id, _)| !attributes.iter().any(|attribute| attribute.id() == id))
+            {
+                return Err(MeshUpdateError::IncompleteResize(id.name()));
+            }
+        }
+
+        for (id, bytes) in sizes.iter_mut() {
+            if let Some(attribute) = attributes.iter().find(|attribute| attribute.id() == id) {
+                *bytes = attribute.bytes();
+            }
+        }
+        Ok(())
+    }
+
+    /// Overwrite existing vertex attributes of a mesh. Attributes which got
+    /// larger are moved to a new range of the mesh data buffer, smaller ones
+    /// keep their range. Returns true if the objects using the mesh need to be
+    /// updated from it, as its bounding sphere changed or attributes moved.
+    ///
+    /// The attributes must have passed [`Self::validate_update`].
+    pub fn update_vertices(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        handle: RawMeshHandle,
+        attributes: &[StoredVertexAttributeData],
+    ) -> bool {
+        profiling::scope!("MeshManager::update_vertices");
+
+        let mut data_guard = self.data.lock();
+        let mesh = data_guard[handle.idx].as_mut().unwrap();
+
+        // Allocate before taking the buffer lock, as growing the buffer needs to write to it.
+        let mut allocator_guard = self.allocator.lock();
+        let mut moved = false;
+        for attribute in attributes {
+            let Some((_, range)) = mesh
+                .vertex_attribute_ranges
+                .iter_mut()
+                .find(|(id, _)| id == attribute.id())
+            else {
+                continue;
+            };
+            if attribute.bytes() > range.end - range.start {
+                let new_range = self.allocate_range_impl(device, queue, &mut allocator_guard, attribute.bytes());
+                Self::free_range_impl(&mut allocator_guard, mem::replace(range, new_range));
+                moved = true;
+            }
+            mesh.vertex_count = (attribute.bytes() / attribute.id().metadata().size as u64) as u32;
+        }
+        drop(allocator_guard);
+
+        let buffer_guard = self.buffer.read();
+        let mut bounding_sphere = None;
+        for attribute in attributes {
+            let Some(range) = mesh.get_attribute(attribute.id()) else {
+                continue;
+            };
+            if attribute.bytes() == 0 {
+                continue;
+            }
+            queue.write_buffer(&buffer_guard, range.start, attribute.untyped_data());
+
+            if let Some(positions) = attribute.typed_data(&VERTEX_ATTRIBUTE_POSITION) {
+                bounding_sphere = Some(BoundingSphere::from_mesh(positions));
+            }
+        }
+
+        if let Some(bounding_sphere) = bounding_sphere {
+            mesh.bounding_sphere = bounding_sphere;
+        }
+        moved || bounding_sphere.is_some()
+    }
+
     pub fn remove(&self, object_id: RawMeshHandle) {
         let mesh = self.data.lock()[object_id.idx].take().unwrap();
 
@@ -192,21 +376,21 @@ impl MeshManager {
     }
 
     /// Duplicates a mesh's vertex data so that it can be skinned on the GPU.
-    pub fn allocate_range(&self, device: &Device, encoder: &mut CommandEncoder, bytes: u64) -> Range<u64> {
-        self.allocate_range_impl(device, encoder, &mut self.allocator.lock(), bytes)
+    pub fn allocate_range(&self, device: &Device, queue: &Queue, bytes: u64) -> Range<u64> {
+        self.allocate_range_impl(device, queue, &mut self.allocator.lock(), bytes)
     }
 
     fn allocate_range_impl(
         &self,
         device: &Device,
-        encoder: &mut CommandEncoder,
+        queue: &Queue,
         allocator: &mut RangeAllocator<u64>,
         bytes: u64,
     ) -> Range<u64> {
         match allocator.allocate_range(bytes) {
             Ok(range) => range,
             Err(..) => {
-                self.reallocate_buffers(device, encoder, allocator, bytes);
+                self.reallocate_buffers(device, queue, allocator, bytes);
                 allocator.allocate_range(bytes).unwrap()
             }
         }
@@ -230,7 +414,7 @@ impl MeshManager {
     fn reallocate_buffers(
         &self,
         device: &Device,
-        encoder: &mut CommandEncoder,
+        queue: &Queue,
         allocator: &mut RangeAllocator<u64>,
         needed_bytes: u64,
     ) {
@@ -250,8 +434,17 @@ impl MeshManager {
             mapped_at_creation: false,
         }));
 
+        // The copy is submitted right away, while we still hold the lock. Meshes can
+        // be added from any thread, so this orders the copy after all data already
+        // written into the old buffer and before any data written into the new one.
+        // It also keeps a frame from seeing the new buffer before the copy is
+        // submitted.
         let mut buffer_guard = self.buffer.write();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("mesh buffer reallocation"),
+        });
         encoder.copy_buffer_to_buffer(&buffer_guard, 0, &new_buffer, 0, allocator.initial_range().end);
+        queue.submit(Some(encoder.finish()));
 
         *buffer_guard = new_buffer;
         allocator.grow_to(new_bytes);
@@ -260,6 +453,13 @@ impl MeshManager {
 
 pub struct LockedInternalMeshDataArray<'a>(MutexGuard<'a, Vec<Option<InternalMesh>>>);
 
+impl<'a> LockedInternalMeshDataArray<'a> {
+    /// The mesh, if it exists.
+    pub fn get(&self, handle: RawMeshHandle) -> Option<&InternalMesh> {
+        self.0.get(handle.idx)?.as_ref()
+    }
+}
+
 impl<'a> Index<RawMeshHandle> for LockedInternalMeshDataArray<'a> {
     type Output = InternalMesh;
 
@@ -2,35 +2,75 @@ use std::{any::TypeId, ops::Range};
 
 use bytemuck::Zeroable;
 use encase::ShaderType;
-use glam::{Mat4, Vec3A};
+use glam::{Mat4, Vec3A, Vec4};
 use list_any::VecAny;
 use rend3_types::{
-    Material, MaterialArray, MaterialHandle, ObjectChange, ObjectMeshKind, RawObjectHandle, VertexAttributeId,
+    Material, MaterialArray, MaterialHandle, ObjectChange, ObjectClip, ObjectMeshKind, RawMeshHandle, RawObjectHandle,
+    VertexAttributeId,
 };
-use wgpu::{Buffer, CommandEncoder, Device};
+use wgpu::{Buffer, CommandEncoder, Device, IndexFormat};
 
 use super::SkeletonManager;
 use crate::{
     managers::{InternalMesh, MaterialManager, MeshManager},
     types::{Object, ObjectHandle},
     util::{
-        freelist::FreelistDerivedBuffer, frustum::BoundingSphere, iter::ExactSizerIterator, scatter_copy::ScatterCopy,
-        typedefs::FastHashMap,
+        buffer::BufferGrowth,
+        freelist::FreelistDerivedBuffer,
+        frustum::BoundingSphere,
+        iter::ExactSizerIterator,
+        scatter_copy::ScatterCopy,
+        typedefs::{FastHashMap, FastHashSet},
     },
 };
 
+/// Set in [`ShaderObject::flags`] if the object receives shadows.
+pub const OBJECT_FLAG_RECEIVE_SHADOWS: u32 = 1 << 0;
+/// Set in [`ShaderObject::flags`] if the object has any clip planes or
+/// dissolve active.
+pub const OBJECT_FLAG_CLIPPED: u32 = 1 << 1;
+/// Set in [`ShaderObject::flags`] if the mesh of the object has 16 bit
+/// indices. [`ShaderObject::first_index`] then counts 16 bit indices.
+pub const OBJECT_FLAG_U16_INDICES: u32 = 1 << 2;
+
 /// Cpu side input to gpu-based culling
+///
+/// This is written with encase, so it follows WGSL's layout rules and must
+/// match the `Object` struct in rend3-routine's `structures_object.wgsl` field
+/// by field. The vectors are 16 byte aligned: `bounding_sphere` packs its
+/// radius into the fourth component, and `tint` starts right after it. The
+/// scalars following `clip_planes` are packed without padding, and the whole
+/// struct is padded to a multiple of 16 bytes.
+///
+/// An object is only written to the gpu when it is added or one of its
+/// properties changes, so objects which never move cost no upload bandwidth.
+/// Nothing view dependent is stored here: the model-view matrices are
+/// computed from `transform` and the camera uniforms in the vertex shader.
+///
+/// `prev_transform` is last so the offsets of the other fields don't depend
+/// on the material's vertex attributes.
 #[derive(ShaderType)]
 pub struct ShaderObject<M: Material> {
     /// Model -> World matrix
     pub transform: Mat4,
     /// Boudning sphere in world space.
     pub bounding_sphere: BoundingSphere,
+    /// Multiplied into the final color of the object.
+    pub tint: Vec4,
+    /// World space clip planes, see [`ObjectClip::planes`].
+    pub clip_planes: [Vec4; 4],
+    pub dissolve: f32,
+    pub dissolve_scale: f32,
     pub first_index: u32,
     pub index_count: u32,
     pub material_index: u32,
+    /// Combination of the `OBJECT_FLAG_*` constants.
+    pub flags: u32,
     pub vertex_attribute_start_offsets:
         <M::SupportedAttributeArrayType as MaterialArray<&'static VertexAttributeId>>::U32Array,
+    /// Model -> World matrix of the previous frame, used for velocity. Equal
+    /// to `transform` if the object didn't move since the previous frame.
+    pub prev_transform: Mat4,
 }
 
 impl<M: Material> Default for ShaderObject<M> {
@@ -38,10 +78,16 @@ impl<M: Material> Default for ShaderObject<M> {
         Self {
             transform: Default::default(),
             bounding_sphere: Default::default(),
+            tint: Default::default(),
+            clip_planes: Default::default(),
+            dissolve: Default::default(),
+            dissolve_scale: Default::default(),
             first_index: Default::default(),
             index_count: Default::default(),
             material_index: Default::default(),
+            flags: Default::default(),
             vertex_attribute_start_offsets: Zeroable::zeroed(),
+            prev_transform: Default::default(),
         }
     }
 }
@@ -55,10 +101,16 @@ impl<M: Material> Clone for ShaderObject<M> {
         Self {
             transform: self.transform,
             bounding_sphere: self.bounding_sphere,
+            tint: self.tint,
+            clip_planes: self.clip_planes,
+            dissolve: self.dissolve,
+            dissolve_scale: self.dissolve_scale,
             first_index: self.first_index,
             index_count: self.index_count,
             material_index: self.material_index,
+            flags: self.flags,
             vertex_attribute_start_offsets: self.vertex_attribute_start_offsets,
+            prev_transform: self.prev_transform,
         }
     }
 }
@@ -69,6 +121,9 @@ pub struct InternalObject<M: Material> {
     pub material_handle: MaterialHandle,
     /// World space
     pub location: Vec3A,
+    pub overlay: bool,
+    pub layers: u32,
+    pub cast_shadows: bool,
     pub inner: ShaderObject<M>,
 }
 
@@ -79,6 +134,9 @@ impl<M: Material> Clone for InternalObject<M> {
             mesh_kind: self.mesh_kind.clone(),
             material_handle: self.material_handle.clone(),
             location: self.location,
+            overlay: self.overlay,
+            layers: self.layers,
+            cast_shadows: self.cast_shadows,
             inner: self.inner,
         }
     }
@@ -89,7 +147,15 @@ struct ObjectArchetype {
     data_vec: VecAny,
     object_count: usize,
     buffer: FreelistDerivedBuffer,
-    set_object_transform: fn(&mut VecAny, &mut FreelistDerivedBuffer, usize, Mat4),
+    /// Objects whose transform was set since the last evaluate.
+    moved: FastHashSet<usize>,
+    /// Objects which moved in the previous frame, so their previous
+    /// transform needs to catch up with the current one.
+    settling: Vec<usize>,
+    set_object_transform: fn(&mut VecAny, &mut FreelistDerivedBuffer, usize, Mat4, bool),
+    set_object_tint: fn(&mut VecAny, &mut FreelistDerivedBuffer, usize, Vec4),
+    set_object_clip: fn(&mut VecAny, &mut FreelistDerivedBuffer, usize, ObjectClip),
+    update_mesh: fn(&mut VecAny, &mut FreelistDerivedBuffer, RawMeshHandle, &InternalMesh),
     duplicate_object: fn(&VecAny, usize, ObjectChange) -> Object,
     remove: fn(&mut VecAny, usize),
     evaluate: fn(&mut ObjectArchetype, &Device, &mut CommandEncoder, &ScatterCopy),
@@ -99,6 +165,7 @@ struct ObjectArchetype {
 pub struct ObjectManager {
     archetype: FastHashMap<TypeId, ObjectArchetype>,
     handle_to_typeid: FastHashMap<RawObjectHandle, TypeId>,
+    buffer_growth: BufferGrowth,
 }
 impl ObjectManager {
     pub fn new() -> Self {
@@ -107,16 +174,27 @@ impl ObjectManager {
         Self {
             archetype: FastHashMap::default(),
             handle_to_typeid: FastHashMap::default(),
+            buffer_growth: BufferGrowth::Doubling,
         }
     }
 
     fn ensure_archetype<M: Material>(&mut self, device: &Device) -> &mut ObjectArchetype {
         let type_id = TypeId::of::<M>();
+        let buffer_growth = self.buffer_growth;
         self.archetype.entry(type_id).or_insert_with(|| ObjectArchetype {
             data_vec: VecAny::new::<Option<InternalObject<M>>>(),
             object_count: 0,
-            buffer: FreelistDerivedBuffer::new::<ShaderObject<M>>(device),
+            buffer: {
+                let mut buffer = FreelistDerivedBuffer::new::<ShaderObject<M>>(device);
+                buffer.set_growth(buffer_growth);
+                buffer
+            },
+            moved: FastHashSet::default(),
+            settling: Vec::new(),
             set_object_transform: set_object_transform::<M>,
+            set_object_tint: set_object_tint::<M>,
+            set_object_clip: set_object_clip::<M>,
+            update_mesh: update_mesh::<M>,
             duplicate_object: duplicate_object::<M>,
             remove: remove::<M>,
             evaluate: evaluate::<M>,
@@ -135,16 +213,46 @@ impl ObjectManager {
         let mesh_manager_guard = mesh_manager.lock_internal_data();
         let (internal_mesh, skeleton_ranges) = match &object.mesh_kind {
             ObjectMeshKind::Animated(skeleton) => {
-                let skeleton = skeleton_manager.internal_data(**skeleton);
-                let mesh = &mesh_manager_guard[*skeleton.mesh_handle];
+                let Some(skeleton) = skeleton_manager.get(**skeleton) else {
+                    log::warn!(
+                        "Object {} references skeleton {} which does not exist, skipping the object",
+                        handle.idx,
+                        skeleton.idx
+                    );
+                    return;
+                };
+                let Some(mesh) = mesh_manager_guard.get(*skeleton.mesh_handle) else {
+                    log::warn!(
+                        "Object {} references mesh {} which does not exist, skipping the object",
+                        handle.idx,
+                        skeleton.mesh_handle.idx
+                    );
+                    return;
+                };
                 (mesh, &*skeleton.overridden_attribute_ranges)
             }
-            ObjectMeshKind::Static(mesh) => {
-                let mesh = &mesh_manager_guard[**mesh];
+            ObjectMeshKind::Static(mesh_handle) => {
+                let Some(mesh) = mesh_manager_guard.get(**mesh_handle) else {
+                    log::warn!(
+                        "Object {} references mesh {} which does not exist, skipping the object",
+                        handle.idx,
+                        mesh_handle.idx
+                    );
+                    return;
+                };
                 (mesh, &[][..])
             }
         };
 
+        if !material_manager.contains(*object.material) {
+            log::warn!(
+                "Object {} references material {} which does not exist, skipping the object",
+                handle.idx,
+                object.material.idx
+            );
+            return;
+        }
+
         material_manager.call_object_add_callback(
             *object.material,
             ObjectAddCallbackArgs {
@@ -159,19 +267,61 @@ impl ObjectManager {
     }
 
     pub fn set_object_transform(&mut self, handle: RawObjectHandle, transform: Mat4) {
-        let type_id = self.handle_to_typeid[&handle];
+        // Objects skipped when they were added are ignored.
+        let Some(&type_id) = self.handle_to_typeid.get(&handle) else {
+            return;
+        };
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
-        (archetype.set_object_transform)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, transform);
+        // Only the first change in a frame keeps the transform of the previous frame.
+        let first_move = archetype.moved.insert(handle.idx);
+        (archetype.set_object_transform)(
+            &mut archetype.data_vec,
+            &mut archetype.buffer,
+            handle.idx,
+            transform,
+            first_move,
+        );
+    }
+
+    pub fn set_object_tint(&mut self, handle: RawObjectHandle, tint: Vec4) {
+        let Some(&type_id) = self.handle_to_typeid.get(&handle) else {
+            return;
+        };
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
+        (archetype.set_object_tint)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, tint);
+    }
+
+    pub fn set_object_clip(&mut self, handle: RawObjectHandle, clip: ObjectClip) {
+        let Some(&type_id) = self.handle_to_typeid.get(&handle) else {
+            return;
+        };
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
+        (archetype.set_object_clip)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, clip);
+    }
+
+    /// Update the bounding sphere and vertex offsets of all static objects
+    /// using the given mesh.
+    pub fn update_mesh(&mut self, mesh: RawMeshHandle, internal_mesh: &InternalMesh) {
+        for archetype in self.archetype.values_mut() {
+            (archetype.update_mesh)(&mut archetype.data_vec, &mut archetype.buffer, mesh, internal_mesh);
+        }
     }
 
     pub fn remove(&mut self, handle: RawObjectHandle) {
-        let type_id = self.handle_to_typeid[&handle];
+        let Some(type_id) = self.handle_to_typeid.remove(&handle) else {
+            return;
+        };
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
         (archetype.remove)(&mut archetype.data_vec, handle.idx);
+        archetype.moved.remove(&handle.idx);
 
         archetype.object_count -= 1;
     }
@@ -186,6 +336,39 @@ impl ObjectManager {
         Some(&self.archetype.get(&TypeId::of::<M>())?.buffer)
     }
 
+    /// Make space for at least `count` objects using the given material type,
+    /// so adding them doesn't reallocate the object buffer.
+    pub fn reserve<M: Material>(&mut self, device: &Device, count: usize) {
+        self.ensure_archetype::<M>(device).buffer.reserve(count);
+    }
+
+    /// How the object buffers grow when they run out of space. Defaults to
+    /// [`BufferGrowth::Doubling`].
+    pub fn set_buffer_growth(&mut self, growth: BufferGrowth) {
+        self.buffer_growth = growth;
+        for archetype in self.archetype.values_mut() {
+            archetype.buffer.set_growth(growth);
+        }
+    }
+
+    /// Amount of objects using the given material type.
+    pub fn object_count<M: Material>(&self) -> usize {
+        self.archetype
+            .get(&TypeId::of::<M>())
+            .map_or(0, |archetype| archetype.object_count)
+    }
+
+    /// Amount of objects using the given material type the object buffer has
+    /// space for before it needs to be reallocated.
+    pub fn object_capacity<M: Material>(&self) -> usize {
+        self.archetype
+            .get(&TypeId::of::<M>())
+            .map_or(0, |archetype| archetype.buffer.capacity())
+    }
+
+    /// Iterate over all objects of the given material, in increasing order of
+    /// handle index. The order only depends on the sequence of objects added
+    /// and removed, so it is the same between runs.
     pub fn enumerated_objects<M: Material>(
         &self,
     ) -> Option<impl ExactSizeIterator<Item = (RawObjectHandle, &InternalObject<M>)> + '_> {
@@ -215,7 +398,14 @@ impl ObjectManager {
         skeleton_manager: &SkeletonManager,
         material_manager: &mut MaterialManager,
     ) {
-        let type_id = self.handle_to_typeid[&*src_handle];
+        let Some(&type_id) = self.handle_to_typeid.get(&*src_handle) else {
+            log::warn!(
+                "Object {} is duplicated from object {} which was skipped, skipping the duplicate",
+                dst_handle.idx,
+                src_handle.idx
+            );
+            return;
+        };
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
@@ -247,31 +437,13 @@ pub(super) struct ObjectAddCallbackArgs<'a> {
     object: Object,
 }
 
-pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCallbackArgs<'_>) {
-    // Make sure all required attributes are in the mesh and the supported attribute list.
-    for &required_attribute in M::required_attributes().into_iter() {
-        // We can just directly use the internal mesh, as every attribute in the skeleton is also in the mesh.
-        let found_in_mesh = args
-            .internal_mesh
-            .vertex_attribute_ranges
-            .iter()
-            .any(|&(id, _)| id == required_attribute);
-
-        // Check that our required attributes are in the supported one.
-        let found_in_supported = args
-            .internal_mesh
-            .vertex_attribute_ranges
-            .iter()
-            .any(|&(id, _)| id == required_attribute);
-
-        assert!(found_in_mesh);
-        assert!(found_in_supported);
-    }
-
-    let vertex_attribute_start_offsets = M::supported_attributes().map_to_u32(|&supported_attribute| {
+fn vertex_attribute_start_offsets<M: Material>(
+    internal_mesh: &InternalMesh,
+    skeleton_ranges: &[(VertexAttributeId, Range<u64>)],
+) -> <M::SupportedAttributeArrayType as MaterialArray<&'static VertexAttributeId>>::U32Array {
+    M::supported_attributes().map_to_u32(|&supported_attribute| {
         // We first check the skeleton for the attribute's base offset.
-        let found_start_offset = args
-            .skeleton_ranges
+        let found_start_offset = skeleton_ranges
             .iter()
             .find_map(|(id, range)| (*id == supported_attribute).then_some(range.start));
 
@@ -280,12 +452,29 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
         }
 
         // After the skeleton, check the mesh for non-overriden attributes.
-        match args.internal_mesh.get_attribute(&supported_attribute) {
+        match internal_mesh.get_attribute(&supported_attribute) {
             Some(range) => range.start as u32,
             // If the attribute isn't there, push u32::MAX.
             None => u32::MAX,
         }
-    });
+    })
+}
+
+pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCallbackArgs<'_>) {
+    // Make sure all required attributes are in the mesh. We can just directly use the internal mesh, as every
+    // attribute in the skeleton is also in the mesh.
+    for &required_attribute in M::required_attributes().into_iter() {
+        if args.internal_mesh.get_attribute(&required_attribute).is_none() {
+            log::warn!(
+                "Object {} uses a mesh without the {} attribute its material requires, skipping the object",
+                args.handle.idx,
+                required_attribute.name()
+            );
+            return;
+        }
+    }
+
+    let vertex_attribute_start_offsets = vertex_attribute_start_offsets::<M>(args.internal_mesh, args.skeleton_ranges);
 
     // Transform the bounding sphere from model to world space.
     let bounding_sphere = args
@@ -293,20 +482,38 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
         .bounding_sphere
         .apply_transform(args.object.transform);
     let index_range = args.internal_mesh.index_range.clone();
+    let (first_index, index_flag) = match args.internal_mesh.index_format {
+        IndexFormat::Uint16 => (index_range.start / 2, OBJECT_FLAG_U16_INDICES),
+        IndexFormat::Uint32 => (index_range.start / 4, 0),
+    };
 
-    let internal_object = InternalObject::<M> {
+    let mut internal_object = InternalObject::<M> {
         location: bounding_sphere.center.into(),
         inner: ShaderObject {
             material_index: args.object.material.idx as u32,
+            flags: index_flag
+                | match args.object.receive_shadows {
+                    true => OBJECT_FLAG_RECEIVE_SHADOWS,
+                    false => 0,
+                },
             transform: args.object.transform,
+            prev_transform: args.object.transform,
             bounding_sphere,
-            first_index: (index_range.start / 4) as u32,
-            index_count: ((index_range.end - index_range.start) / 4) as u32,
+            tint: args.object.tint,
+            clip_planes: Default::default(),
+            dissolve: Default::default(),
+            dissolve_scale: Default::default(),
+            first_index: first_index as u32,
+            index_count: args.internal_mesh.index_count,
             vertex_attribute_start_offsets,
         },
         material_handle: args.object.material,
         mesh_kind: args.object.mesh_kind,
+        overlay: args.object.overlay,
+        layers: args.object.layers,
+        cast_shadows: args.object.cast_shadows,
     };
+    apply_clip(&mut internal_object.inner, args.object.clip);
 
     let type_id = TypeId::of::<M>();
 
@@ -327,16 +534,72 @@ fn set_object_transform<M: Material>(
     buffer: &mut FreelistDerivedBuffer,
     idx: usize,
     transform: Mat4,
+    first_move: bool,
 ) {
     let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
 
     let object = data_vec[idx].as_mut().unwrap();
+    if first_move {
+        object.inner.prev_transform = object.inner.transform;
+    }
     object.inner.transform = transform;
     object.location = transform.transform_point3a(Vec3A::ZERO);
 
     buffer.use_index(idx);
 }
 
+fn set_object_tint<M: Material>(data: &mut VecAny, buffer: &mut FreelistDerivedBuffer, idx: usize, tint: Vec4) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    let object = data_vec[idx].as_mut().unwrap();
+    object.inner.tint = tint;
+
+    buffer.use_index(idx);
+}
+
+fn set_object_clip<M: Material>(data: &mut VecAny, buffer: &mut FreelistDerivedBuffer, idx: usize, clip: ObjectClip) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    let object = data_vec[idx].as_mut().unwrap();
+    apply_clip(&mut object.inner, clip);
+
+    buffer.use_index(idx);
+}
+
+fn update_mesh<M: Material>(
+    data: &mut VecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    mesh: RawMeshHandle,
+    internal_mesh: &InternalMesh,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    for (idx, object) in data_vec.iter_mut().enumerate() {
+        let object = match object {
+            Some(object) => object,
+            None => continue,
+        };
+        match object.mesh_kind {
+            ObjectMeshKind::Static(ref handle) if handle.get_raw() == mesh => {}
+            _ => continue,
+        }
+        object.inner.bounding_sphere = internal_mesh.bounding_sphere.apply_transform(object.inner.transform);
+        object.inner.vertex_attribute_start_offsets = vertex_attribute_start_offsets::<M>(internal_mesh, &[]);
+
+        buffer.use_index(idx);
+    }
+}
+
+fn apply_clip<M: Material>(object: &mut ShaderObject<M>, clip: ObjectClip) {
+    object.clip_planes = clip.planes;
+    object.dissolve = clip.dissolve;
+    object.dissolve_scale = clip.dissolve_scale;
+    match clip.is_active() {
+        true => object.flags |= OBJECT_FLAG_CLIPPED,
+        false => object.flags &= !OBJECT_FLAG_CLIPPED,
+    }
+}
+
 fn duplicate_object<M: Material>(data: &VecAny, idx: usize, change: ObjectChange) -> Object {
     let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
 
@@ -346,6 +609,18 @@ fn duplicate_object<M: Material>(data: &VecAny, idx: usize, change: ObjectChange
         mesh_kind: change.mesh_kind.unwrap_or_else(|| src_obj.mesh_kind.clone()),
         material: change.material.unwrap_or_else(|| src_obj.material_handle.clone()),
         transform: change.transform.unwrap_or(src_obj.inner.transform),
+        overlay: change.overlay.unwrap_or(src_obj.overlay),
+        layers: change.layers.unwrap_or(src_obj.layers),
+        cast_shadows: change.cast_shadows.unwrap_or(src_obj.cast_shadows),
+        receive_shadows: change
+            .receive_shadows
+            .unwrap_or(src_obj.inner.flags & OBJECT_FLAG_RECEIVE_SHADOWS != 0),
+        tint: change.tint.unwrap_or(src_obj.inner.tint),
+        clip: change.clip.unwrap_or(ObjectClip {
+            planes: src_obj.inner.clip_planes,
+            dissolve: src_obj.inner.dissolve,
+            dissolve_scale: src_obj.inner.dissolve_scale,
+        }),
     }
 }
 
@@ -363,9 +638,21 @@ fn evaluate<M: Material>(
 ) {
     let data_vec = archetype
         .data_vec
-        .downcast_slice::<Option<InternalObject<M>>>()
+        .downcast_slice_mut::<Option<InternalObject<M>>>()
         .unwrap();
 
+    // Objects which moved last frame but not in this one are standing still again.
+    for idx in archetype.settling.drain(..) {
+        if archetype.moved.contains(&idx) {
+            continue;
+        }
+        if let Some(object) = data_vec[idx].as_mut() {
+            object.inner.prev_transform = object.inner.transform;
+            archetype.buffer.use_index(idx);
+        }
+    }
+    archetype.settling.extend(archetype.moved.drain());
+
     archetype.buffer.apply(device, encoder, scatter, |idx| {
         data_vec[idx].as_ref().map(|o| o.inner).unwrap_or_default()
     })
@@ -6,7 +6,7 @@ use rend3_types::{
     MeshHandle, RawSkeletonHandle, Skeleton, SkeletonHandle, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES,
     VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
 };
-use wgpu::{CommandEncoder, Device};
+use wgpu::{Device, Queue};
 
 use crate::{managers::MeshManager, util::iter::ExactSizerIterator};
 
@@ -53,30 +53,42 @@ impl SkeletonManager {
     pub fn add(
         &mut self,
         device: &Device,
-        encoder: &mut CommandEncoder,
+        queue: &Queue,
         mesh_manager: &MeshManager,
         handle: &SkeletonHandle,
         skeleton: Skeleton,
     ) {
-        let internal_mesh = &mesh_manager.lock_internal_data()[skeleton.mesh.get_raw()];
-        let required_joint_count = internal_mesh
-            .required_joint_count
-            .expect("Mesh must have joint weights and joint indices to be used in a skeleton");
-
-        let joint_weight_range = internal_mesh
-            .get_attribute(&VERTEX_ATTRIBUTE_JOINT_WEIGHTS)
-            .expect("Mesh must have joint weights and joint indices to be used in a skeleton");
-        let joint_indices_range = internal_mesh
-            .get_attribute(&VERTEX_ATTRIBUTE_JOINT_INDICES)
-            .expect("Mesh must have joint weights and joint indices to be used in a skeleton");
-
-        assert!(
-            required_joint_count as usize <= skeleton.joint_matrices.len(),
-            "Not enough joints to create this skeleton. The mesh has {} joints, \
-             but only {} joint matrices were provided.",
-            required_joint_count,
-            skeleton.joint_matrices.len(),
-        );
+        let mesh_guard = mesh_manager.lock_internal_data();
+        let Some(internal_mesh) = mesh_guard.get(skeleton.mesh.get_raw()) else {
+            log::warn!(
+                "Skeleton {} references mesh {} which does not exist, skipping the skeleton",
+                handle.idx,
+                skeleton.mesh.idx
+            );
+            return;
+        };
+        let (Some(required_joint_count), Some(joint_weight_range), Some(joint_indices_range)) = (
+            internal_mesh.required_joint_count,
+            internal_mesh.get_attribute(&VERTEX_ATTRIBUTE_JOINT_WEIGHTS),
+            internal_mesh.get_attribute(&VERTEX_ATTRIBUTE_JOINT_INDICES),
+        ) else {
+            log::warn!(
+                "Skeleton {} uses mesh {} which has no joint weights and joint indices, skipping the skeleton",
+                handle.idx,
+                skeleton.mesh.idx
+            );
+            return;
+        };
+
+        if (required_joint_count as usize) > skeleton.joint_matrices.len() {
+            log::warn!(
+                "Skeleton {} has {} joint matrices, but its mesh has {} joints, skipping the skeleton",
+                handle.idx,
+                skeleton.joint_matrices.len(),
+                required_joint_count
+            );
+            return;
+        }
 
         self.global_joint_count += required_joint_count as usize;
 
@@ -104,8 +116,7 @@ impl SkeletonManager {
         //
         // We skip the first two as those are always the joint* attributes.
         for (attribute_id, original_range) in &source_attribute_ranges[2..] {
-            let skeleton_range =
-                mesh_manager.allocate_range(device, encoder, original_range.end - original_range.start);
+            let skeleton_range = mesh_manager.allocate_range(device, queue, original_range.end - original_range.start);
             overridden_attribute_ranges.push((*attribute_id, skeleton_range));
         }
 
@@ -120,6 +131,7 @@ impl SkeletonManager {
             overridden_attribute_ranges,
             vertex_count: internal_mesh.vertex_count,
         };
+        drop(mesh_guard);
 
         if handle.idx >= self.data.len() {
             self.data.resize_with(handle.idx + 1, || None);
@@ -130,7 +142,10 @@ impl SkeletonManager {
     }
 
     pub fn remove(&mut self, mesh_manager: &MeshManager, handle: RawSkeletonHandle) {
-        let skeleton = self.data[handle.idx].take().unwrap();
+        // Skeletons skipped when they were added have nothing to remove.
+        let Some(skeleton) = self.data.get_mut(handle.idx).and_then(Option::take) else {
+            return;
+        };
         self.global_joint_count -= skeleton.joint_matrices.len();
 
         // Free the owned regions of the mesh data buffer
@@ -142,14 +157,18 @@ impl SkeletonManager {
     }
 
     pub fn set_joint_matrices(&mut self, handle: RawSkeletonHandle, mut joint_matrices: Vec<Mat4>) {
-        let skeleton = self.data[handle.idx].as_mut().unwrap();
-        assert!(
-            skeleton.joint_matrices.len() <= joint_matrices.len(),
-            "Not enough joints to update this skeleton. The mesh has {} joints, \
-            but only {} joint matrices were provided.",
-            skeleton.joint_matrices.len(),
-            joint_matrices.len(),
-        );
+        let Some(skeleton) = self.data.get_mut(handle.idx).and_then(Option::as_mut) else {
+            return;
+        };
+        if skeleton.joint_matrices.len() > joint_matrices.len() {
+            log::warn!(
+                "Skeleton {} has {} joints, but only {} joint matrices were provided, ignoring the update",
+                handle.idx,
+                skeleton.joint_matrices.len(),
+                joint_matrices.len()
+            );
+            return;
+        }
         // Truncate to avoid storing any extra joint matrices
         joint_matrices.truncate(skeleton.joint_matrices.len());
         skeleton.joint_matrices = joint_matrices;
@@ -159,6 +178,12 @@ impl SkeletonManager {
         self.data[handle.idx].as_ref().unwrap()
     }
 
+    /// The skeleton, if it exists. Skeletons with invalid data are skipped
+    /// when they are added.
+    pub fn get(&self, handle: RawSkeletonHandle) -> Option<&InternalSkeleton> {
+        self.data.get(handle.idx)?.as_ref()
+    }
+
     pub fn skeletons(&self) -> impl ExactSizeIterator<Item = &InternalSkeleton> {
         ExactSizerIterator::new(self.data.iter().filter_map(Option::as_ref), self.skeleton_count)
     }
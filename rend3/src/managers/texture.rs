@@ -1,10 +1,11 @@
 use std::{marker::PhantomData, num::NonZeroU32, sync::Arc};
 
 use rend3_types::{MipmapCount, MipmapSource, RawResourceHandle, TextureFormat, TextureFromTexture, TextureUsages};
+use thiserror::Error;
 use wgpu::{
     util::DeviceExt, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, CommandBuffer, CommandEncoder, CommandEncoderDescriptor,
-    Device, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, ShaderStages, Texture, TextureAspect,
+    Device, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, Queue, ShaderStages, Texture, TextureAspect,
     TextureDescriptor, TextureDimension, TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension,
 };
 
@@ -19,6 +20,16 @@ pub const STARTING_CUBE_TEXTURES: usize = 1 << 3;
 /// Largest amount of supported textures per type
 pub const MAX_TEXTURE_COUNT: u32 = 1 << 17;
 
+/// Texture array size which [`TextureManager::set_max_textures`] rejected.
+#[derive(Debug, Error, Copy, Clone, PartialEq, Eq)]
+#[error("{count} textures can't be used, the device supports 1 to {limit}")]
+pub struct MaxTexturesError {
+    /// Requested size.
+    pub count: u32,
+    /// Largest size the device supports.
+    pub limit: u32,
+}
+
 /// Internal representation of a Texture.
 pub struct InternalTexture {
     pub texture: Texture,
@@ -28,16 +39,28 @@ pub struct InternalTexture {
 
 /// Preallocation count of texture view array
 const TEXTURE_PREALLOCATION: usize = 1024;
-/// What we divide the texture limit by to get the count supplied in the BGL.
+/// What we divide the texture limit by to get the default count supplied in
+/// the BGL.
 const BGL_DIVISOR: u32 = 4;
 
 /// Manages textures and associated bindless bind groups
 pub struct TextureManager<T> {
     layout: ProfileData<(), Arc<BindGroupLayout>>,
+    /// Incremented every time the layout is recreated.
+    layout_generation: u64,
     group: ProfileData<(), Arc<BindGroup>>,
     group_dirty: ProfileData<(), bool>,
 
+    /// Size of the texture array in the layout.
+    max_textures: u32,
+    /// Largest texture array the device supports.
+    texture_limit: u32,
+
     null_view: TextureView,
+    /// Substituted for textures which are referenced but missing. White and
+    /// magenta.
+    missing_views: [TextureView; 2],
+    highlight_missing: bool,
 
     data: Vec<Option<InternalTexture>>,
 
@@ -46,12 +69,23 @@ pub struct TextureManager<T> {
     _phantom: PhantomData<T>,
 }
 impl<T: 'static> TextureManager<T> {
-    pub fn new(device: &Device, profile: RendererProfile, texture_limit: u32, dimension: TextureViewDimension) -> Self {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        profile: RendererProfile,
+        texture_limit: u32,
+        dimension: TextureViewDimension,
+    ) -> Self {
         profiling::scope!("TextureManager::new");
 
         let null_view = create_null_tex_view(device, dimension);
+        let missing_views = [
+            create_missing_tex_view(device, queue, dimension, [255, 255, 255, 255]),
+            create_missing_tex_view(device, queue, dimension, [255, 0, 255, 255]),
+        ];
 
-        let max_textures = (texture_limit / BGL_DIVISOR).min(MAX_TEXTURE_COUNT);
+        let texture_limit = texture_limit.min(MAX_TEXTURE_COUNT);
+        let max_textures = texture_limit / BGL_DIVISOR;
 
         let mut data = Vec::with_capacity(TEXTURE_PREALLOCATION);
         data.resize_with(TEXTURE_PREALLOCATION, || None);
@@ -59,14 +93,19 @@ impl<T: 'static> TextureManager<T> {
         let layout = profile.into_data(|| (), || create_bind_group_layout(device, max_textures, dimension));
         let group = profile.into_data(
             || (),
-            || create_bind_group(device, layout.as_gpu(), &null_view, &data, dimension),
+            || create_bind_group(device, layout.as_gpu(), &missing_views[0], &data, dimension),
         );
 
         Self {
             layout,
+            layout_generation: 0,
             group,
             group_dirty: profile.into_data(|| (), || false),
+            max_textures,
+            texture_limit,
             null_view,
+            missing_views,
+            highlight_missing: false,
             data,
             dimension,
             _phantom: PhantomData,
@@ -78,6 +117,10 @@ impl<T: 'static> TextureManager<T> {
         texture: crate::types::Texture,
         cube: bool,
     ) -> (Option<CommandBuffer>, InternalTexture) {
+        let mut texture = texture;
+        if let Some(color_space) = texture.color_space {
+            texture.format = color_space.format(texture.format);
+        }
         validate_texture_format(texture.format);
 
         let (block_x, block_y) = texture.format.block_dimensions();
@@ -252,6 +295,55 @@ impl<T: 'static> TextureManager<T> {
         self.data[handle.idx] = None;
     }
 
+    /// Size of the texture array used with the GpuDriven profile.
+    ///
+    /// Defaults to a quarter of the device's
+    /// `max_sampled_textures_per_shader_stage`, leaving the rest of the limit
+    /// to other bindings, and at most [`MAX_TEXTURE_COUNT`].
+    pub fn max_textures(&self) -> u32 {
+        self.max_textures
+    }
+
+    /// Counter which changes every time the layout of the texture array is
+    /// recreated, either by [`Self::set_max_textures`] or because the array
+    /// grew. Pipelines built against [`Self::gpu_bgl`] need to be rebuilt
+    /// when it changes.
+    pub fn layout_generation(&self) -> u64 {
+        self.layout_generation
+    }
+
+    /// Set the size of the texture array used with the GpuDriven profile.
+    ///
+    /// Do this before creating any routines, as they build their pipelines
+    /// against the layout, or rebuild them afterwards. Has no effect with the
+    /// CpuDriven profile.
+    ///
+    /// The array grows by itself when more textures than this are alive, up
+    /// to the device limit, so this is mostly useful to avoid rebuilding
+    /// pipelines when a large amount of textures is known up front.
+    ///
+    /// Fails, leaving the array untouched, if `count` is zero or larger than
+    /// what the device supports.
+    pub fn set_max_textures(&mut self, device: &Device, count: u32) -> Result<(), MaxTexturesError> {
+        if count == 0 || count > self.texture_limit {
+            return Err(MaxTexturesError {
+                count,
+                limit: self.texture_limit,
+            });
+        }
+        self.resize_layout(device, count);
+        Ok(())
+    }
+
+    fn resize_layout(&mut self, device: &Device, count: u32) {
+        if let ProfileData::Gpu(ref mut layout) = self.layout {
+            *layout = create_bind_group_layout(device, count, self.dimension);
+            self.layout_generation += 1;
+            *self.group_dirty.as_gpu_mut() = true;
+        }
+        self.max_textures = count;
+    }
+
     pub fn evaluate(&mut self, device: &Device) -> TextureManagerEvaluateOutput {
         profiling::scope!("TextureManager::evaluate");
 
@@ -259,11 +351,35 @@ impl<T: 'static> TextureManager<T> {
             profiling::scope!("Update GPU Texture Arrays");
 
             if group_dirty {
+                let needed = self
+                    .data
+                    .iter()
+                    .rposition(Option::is_some)
+                    .map_or(0, |idx| idx as u32 + 1);
+                if needed > self.max_textures {
+                    let grown = needed.next_power_of_two().min(self.texture_limit);
+                    if needed > grown {
+                        log::error!(
+                            "{needed} {:?} textures are alive, but the device only supports {grown}. Textures past \
+                             the limit are not bound.",
+                            self.dimension
+                        );
+                    }
+                    if grown > self.max_textures {
+                        log::debug!(
+                            "Growing the {:?} texture array from {} to {grown}",
+                            self.dimension,
+                            self.max_textures
+                        );
+                        self.resize_layout(device, grown);
+                    }
+                }
+
                 *self.group.as_gpu_mut() = create_bind_group(
                     device,
                     self.layout.as_gpu(),
-                    &self.null_view,
-                    &self.data,
+                    self.missing_view(),
+                    &self.data[..self.data.len().min(self.max_textures as usize)],
                     self.dimension,
                 );
                 *self.group_dirty.as_gpu_mut() = false;
@@ -279,12 +395,46 @@ impl<T: 'static> TextureManager<T> {
         }
     }
 
+    /// True if the texture has been uploaded and can be rendered with. Until
+    /// then, materials using it sample a placeholder.
+    pub fn is_ready(&self, handle: RawResourceHandle<T>) -> bool {
+        matches!(self.data.get(handle.idx), Some(Some(_)))
+    }
+
     pub fn get_internal(&self, handle: RawResourceHandle<T>) -> &InternalTexture {
         self.data[handle.idx].as_ref().unwrap()
     }
 
+    /// Get the view of the given texture. If the texture is missing, a warning
+    /// is logged and a placeholder is returned instead, see
+    /// [`TextureManager::set_highlight_missing`].
     pub fn get_view(&self, handle: RawResourceHandle<T>) -> &TextureView {
-        &self.data[handle.idx].as_ref().unwrap().view
+        match self.data.get(handle.idx) {
+            Some(Some(texture)) => &texture.view,
+            _ => {
+                log::warn!(
+                    "Texture {} is referenced but does not exist, using a placeholder",
+                    handle.idx
+                );
+                self.missing_view()
+            }
+        }
+    }
+
+    /// If true, missing textures are replaced by magenta instead of white,
+    /// to make them obvious in the scene.
+    ///
+    /// With the CpuDriven profile, this only applies to materials added after
+    /// the change.
+    pub fn set_highlight_missing(&mut self, highlight: bool) {
+        if self.highlight_missing != highlight {
+            self.highlight_missing = highlight;
+            self.group_dirty = self.group_dirty.map_gpu(|_| true);
+        }
+    }
+
+    fn missing_view(&self) -> &TextureView {
+        &self.missing_views[self.highlight_missing as usize]
     }
 
     pub fn get_null_view(&self) -> &TextureView {
@@ -295,8 +445,11 @@ impl<T: 'static> TextureManager<T> {
         self.layout.as_gpu()
     }
 
-    pub fn translation_fn(&self) -> impl Fn(RawResourceHandle<T>) -> NonZeroU32 + Copy + '_ {
-        move |v: RawResourceHandle<T>| NonZeroU32::new(v.idx as u32 + 1).unwrap()
+    /// Returns the index of a texture in the shader's texture array. `None`
+    /// if the texture doesn't fit into the array.
+    pub fn translation_fn(&self) -> impl Fn(RawResourceHandle<T>) -> Option<NonZeroU32> + Copy + '_ {
+        let max_textures = self.max_textures as usize;
+        move |v: RawResourceHandle<T>| (v.idx < max_textures).then(|| NonZeroU32::new(v.idx as u32 + 1).unwrap())
     }
 }
 
@@ -325,7 +478,7 @@ fn create_bind_group_layout(device: &Device, count: u32, view_dimension: Texture
 fn create_bind_group<'a>(
     device: &Device,
     layout: &BindGroupLayout,
-    null_view: &'a TextureView,
+    missing_view: &'a TextureView,
     data: &[Option<InternalTexture>],
     dimension: TextureViewDimension,
 ) -> Arc<BindGroup> {
@@ -333,7 +486,7 @@ fn create_bind_group<'a>(
     let mut view_array = Vec::with_capacity(count);
     view_array.extend(data.iter().map(|tex| match tex {
         Some(t) => &t.view,
-        None => null_view,
+        None => missing_view,
     }));
     Arc::new(device.create_bind_group(&BindGroupDescriptor {
         label: Some(&*format!("{:?} texture bg count {}", dimension, count)),
@@ -377,6 +530,41 @@ fn create_null_tex_view(device: &Device, dimension: TextureViewDimension) -> Tex
         })
 }
 
+fn create_missing_tex_view(
+    device: &Device,
+    queue: &Queue,
+    dimension: TextureViewDimension,
+    color: [u8; 4],
+) -> TextureView {
+    let layers = match dimension {
+        TextureViewDimension::Cube | TextureViewDimension::CubeArray => 6,
+        _ => 1,
+    };
+    device
+        .create_texture_with_data(
+            queue,
+            &TextureDescriptor {
+                label: Some(&*format!("missing {:?} texture", dimension)),
+                size: Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            &color.repeat(layers as usize),
+        )
+        .create_view(&TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..TextureViewDescriptor::default()
+        })
+}
+
 fn validate_texture_format(format: TextureFormat) {
     let sample_type = format.sample_type(None);
     if let Some(TextureSampleType::Float { filterable }) = sample_type {
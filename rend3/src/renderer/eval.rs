@@ -18,37 +18,28 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
         label: Some("primary encoder"),
     });
 
-    let mut data_core = renderer.data_core.lock();
+    let mut data_core = renderer.data_core.write();
     let data_core = &mut *data_core;
 
+    data_core.camera_manager.begin_frame();
+
     {
         profiling::scope!("Instruction Processing");
         for Instruction { kind, location: _ } in instructions.drain(..) {
             match kind {
-                InstructionKind::AddMesh {
-                    handle,
-                    internal_mesh,
-                    cmd_buf: buffer,
-                } => {
+                InstructionKind::AddMesh { handle, internal_mesh } => {
                     profiling::scope!("Add Mesh");
                     renderer.mesh_manager.fill(&handle, internal_mesh);
-                    cmd_bufs.push(buffer);
                 }
                 InstructionKind::AddSkeleton { handle, skeleton } => {
                     profiling::scope!("Add Skeleton");
-                    data_core
-                        .profiler
-                        .try_lock()
-                        .unwrap()
-                        .begin_scope("Add Skeleton", &mut encoder, &renderer.device);
                     data_core.skeleton_manager.add(
                         &renderer.device,
-                        &mut encoder,
+                        &renderer.queue,
                         &renderer.mesh_manager,
                         &handle,
                         skeleton,
                     );
-                    data_core.profiler.try_lock().unwrap().end_scope(&mut encoder);
                 }
                 InstructionKind::AddTexture2D {
                     handle,
@@ -82,6 +73,12 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 InstructionKind::AddGraphData { add_invoke } => {
                     add_invoke(&mut data_core.graph_storage);
                 }
+                InstructionKind::ReserveObjects { reserve_invoke } => {
+                    reserve_invoke(&mut data_core.object_manager, &renderer.device);
+                }
+                InstructionKind::SetObjectBufferGrowth { growth } => {
+                    data_core.object_manager.set_buffer_growth(growth);
+                }
                 InstructionKind::ChangeMaterial { handle, change_invoke } => {
                     profiling::scope!("Change Material");
 
@@ -102,9 +99,23 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                         &mut data_core.material_manager,
                     );
                 }
+                InstructionKind::UpdateMeshVertices { handle, attributes } => {
+                    profiling::scope!("Update Mesh Vertices");
+                    let mesh_manager = &renderer.mesh_manager;
+                    if mesh_manager.update_vertices(&renderer.device, &renderer.queue, handle, &attributes) {
+                        let mesh_guard = mesh_manager.lock_internal_data();
+                        data_core.object_manager.update_mesh(handle, &mesh_guard[handle]);
+                    }
+                }
                 InstructionKind::SetObjectTransform { handle, transform } => {
                     data_core.object_manager.set_object_transform(handle, transform);
                 }
+                InstructionKind::SetObjectTint { handle, tint } => {
+                    data_core.object_manager.set_object_tint(handle, tint);
+                }
+                InstructionKind::SetObjectClip { handle, clip } => {
+                    data_core.object_manager.set_object_clip(handle, clip);
+                }
                 InstructionKind::SetSkeletonJointDeltas { handle, joint_matrices } => {
                     data_core.skeleton_manager.set_joint_matrices(handle, joint_matrices);
                 }
@@ -114,10 +125,19 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 InstructionKind::ChangeDirectionalLight { handle, change } => {
                     data_core.directional_light_manager.update(handle, change);
                 }
+                InstructionKind::AddAreaLight { handle, light } => {
+                    data_core.area_light_manager.add(&handle, light);
+                }
+                InstructionKind::ChangeAreaLight { handle, change } => {
+                    data_core.area_light_manager.update(handle, change);
+                }
                 InstructionKind::SetAspectRatio { ratio } => data_core.camera_manager.set_aspect_ratio(Some(ratio)),
                 InstructionKind::SetCameraData { data } => {
                     data_core.camera_manager.set_data(data);
                 }
+                InstructionKind::SetLightUnits { light_units } => {
+                    data_core.directional_light_manager.set_light_units(light_units);
+                }
                 InstructionKind::DuplicateObject {
                     src_handle,
                     dst_handle,
@@ -161,6 +181,10 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                     renderer.resource_handle_allocators.directional_light.deallocate(handle);
                     data_core.directional_light_manager.remove(handle)
                 }
+                InstructionKind::DeleteAreaLight { handle } => {
+                    renderer.resource_handle_allocators.area_light.deallocate(handle);
+                    data_core.area_light_manager.remove(handle)
+                }
                 InstructionKind::DeleteGraphData { handle } => {
                     renderer.resource_handle_allocators.graph_storage.deallocate(handle);
                     data_core.graph_storage.remove(&handle);
@@ -194,6 +218,8 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
     let (shadow_target_size, shadows) = data_core
         .directional_light_manager
         .evaluate(renderer, &data_core.camera_manager);
+    let light_units = data_core.directional_light_manager.light_units();
+    data_core.area_light_manager.evaluate(renderer, light_units);
     let mesh_buffer = renderer.mesh_manager.evaluate();
 
     cmd_bufs.push(encoder.finish());
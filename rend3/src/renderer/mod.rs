@@ -1,26 +1,29 @@
 use std::{marker::PhantomData, panic::Location, sync::Arc};
 
-use glam::Mat4;
-use parking_lot::Mutex;
+use glam::{Mat4, Vec4};
+use parking_lot::{Mutex, RwLock};
 use rend3_types::{
-    GraphDataHandle, GraphDataTag, Handedness, Material, MaterialTag, ObjectChange, Skeleton, SkeletonHandle,
-    Texture2DTag, TextureCubeHandle, TextureCubeTag, TextureFromTexture,
+    GraphDataHandle, GraphDataTag, Handedness, LightUnits, Material, MaterialTag, ObjectChange, ObjectClip, Skeleton,
+    SkeletonHandle, StoredVertexAttributeData, Texture2DTag, TextureCubeHandle, TextureCubeTag, TextureFromTexture,
+};
+use wgpu::{
+    Device, DownlevelCapabilities, Features, Limits, Queue, TextureDescriptor, TextureViewDescriptor,
+    TextureViewDimension,
 };
-use wgpu::{CommandEncoderDescriptor, Device, DownlevelCapabilities, Features, Limits, Queue};
 use wgpu_profiler::GpuProfiler;
 
 use crate::{
-    graph::{GraphTextureStore, InstructionEvaluationOutput},
+    graph::{CpuTimerScopeResult, GraphTextureStore, InstructionEvaluationOutput},
     instruction::{InstructionKind, InstructionStreamPair},
     managers::{
-        CameraManager, DirectionalLightManager, GraphStorage, HandleAllocator, MaterialManager, MeshManager,
-        ObjectManager, SkeletonManager, TextureManager,
+        AreaLightManager, CameraManager, DirectionalLightManager, GraphStorage, HandleAllocator, InternalTexture,
+        MaterialManager, MeshManager, MeshUpdateError, ObjectManager, SkeletonManager, TextureManager,
     },
     types::{
-        Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, MaterialHandle, Mesh, MeshHandle,
-        Object, ObjectHandle, Texture, Texture2DHandle,
+        AreaLight, AreaLightChange, AreaLightHandle, Camera, DirectionalLight, DirectionalLightChange,
+        DirectionalLightHandle, MaterialHandle, Mesh, MeshHandle, Object, ObjectHandle, Texture, Texture2DHandle,
     },
-    util::{mipmap::MipmapGenerator, scatter_copy::ScatterCopy},
+    util::{buffer::BufferGrowth, mipmap::MipmapGenerator, scatter_copy::ScatterCopy},
     ExtendedAdapterInfo, InstanceAdapterDevice, RendererInitializationError, RendererProfile,
 };
 
@@ -30,23 +33,84 @@ mod setup;
 
 /// Core struct which contains the renderer world. Primary way to interact with
 /// the world.
+///
+/// # Frames
+///
+/// Every frame is rendered in two phases:
+///
+/// 1. Prepare: [`Self::swap_instruction_buffers`], then
+///    [`Self::evaluate_instructions`], which applies and uploads all world
+///    changes recorded since the last frame. This is the only time the
+///    managers in [`Self::data_core`] are mutated. The returned
+///    [`InstructionEvaluationOutput`] holds everything needed to encode the
+///    frame.
+/// 2. Encode: build a [`RenderGraph`](crate::graph::RenderGraph) and
+///    [`execute`](crate::graph::RenderGraph::execute) it with that output.
+///    Building the graph doesn't touch the managers, and nodes only get shared
+///    access to them while executing.
+///
+/// World-modifying functions, like [`Self::add_object`], only record
+/// instructions and never lock [`Self::data_core`], so other threads can keep
+/// calling them during both phases. Their changes are picked up by the next
+/// prepare after the instruction buffers are swapped.
+///
+/// # Threading
+///
+/// The renderer is `Send + Sync` and is meant to be shared through an `Arc`.
+/// It doesn't need to live on the main thread, as long as the surface it
+/// renders to may be used from the thread it lives on.
+///
+/// - World-modifying functions and dropping handles may happen on any number
+///   of threads at any time, including while a frame is being prepared or
+///   encoded.
+/// - Adding meshes and textures does its uploads on the calling thread, so
+///   it's a good idea to do those from a loading thread instead of the render
+///   thread.
+/// - Swapping the instruction buffers, evaluating instructions and executing
+///   the graph must happen in that order, from a single render thread at a
+///   time. Nothing deadlocks if they don't, but frames will see an arbitrary
+///   mix of changes.
+///
+/// # Device Loss
+///
+/// The device can be lost at any time, for example when the system switches
+/// between gpus or the driver resets. wgpu reports errors on a lost device to
+/// the device's uncaptured error handler, which panics by default, so install
+/// one with [`Device::on_uncaptured_error`] to keep running.
+///
+/// A lost device shows up as
+/// [`SurfaceAcquireError::Lost`](crate::SurfaceAcquireError::Lost) when getting the
+/// next frame with [`get_surface_texture`](crate::get_surface_texture). Nothing
+/// created on the device can be used after that, so recover by:
+///
+/// 1. Dropping the renderer, the routines and the graph data, and all
+///    resource handles.
+/// 2. Creating a new [`InstanceAdapterDevice`] and surface, and a new renderer
+///    from them.
+/// 3. Adding all meshes, textures, materials, objects, and lights again.
 pub struct Renderer {
     pub(crate) instructions: InstructionStreamPair,
 
-    /// The rendering profile used.
+    /// The rendering profile used. Unless a profile was asked for,
+    /// [`create_iad`](crate::create_iad) falls back to
+    /// [`RendererProfile::CpuDriven`] on devices which lack the bindless
+    /// texturing or other features the GpuDriven profile needs.
     pub profile: RendererProfile,
-    /// Information about the adapter.
+    /// Information about the adapter, including its name and backend.
     pub adapter_info: ExtendedAdapterInfo,
     /// Queue all command buffers will be submitted to.
     pub queue: Arc<Queue>,
     /// Device all objects will be created with.
     pub device: Arc<Device>,
 
-    /// Features of the device
+    /// Features the device was created with. Check these before turning on
+    /// anything which needs an optional feature, like wireframe rendering
+    /// with [`Features::POLYGON_MODE_LINE`], as wgpu panics when using a
+    /// feature which isn't enabled.
     pub features: Features,
-    /// Limits of the device
+    /// Limits the device was created with.
     pub limits: Limits,
-    /// Downlevel limits of the device
+    /// Downlevel capabilities of the device.
     pub downlevel: DownlevelCapabilities,
     /// Handedness of all parts of this renderer.
     pub handedness: Handedness,
@@ -55,8 +119,22 @@ pub struct Renderer {
     resource_handle_allocators: HandleAllocators,
     /// Manages all vertex and index data.
     pub mesh_manager: MeshManager,
-    /// All the lockable data
-    pub data_core: Mutex<RendererDataCore>,
+    /// All the lockable data.
+    ///
+    /// It is locked by rend3 in these places, for these durations:
+    ///
+    /// - [`Self::evaluate_instructions`] holds a write lock for all of
+    ///   instruction evaluation.
+    /// - [`RenderGraph::execute`](crate::graph::RenderGraph::execute) holds a
+    ///   write lock while allocating render targets, then an upgradable read
+    ///   lock while recording the nodes of the graph. Other readers can access
+    ///   the managers while the graph is recorded.
+    ///
+    /// Nothing else in rend3 locks it, world-modifying functions only record
+    /// instructions. Routines take short locks outside of the graph, like a
+    /// read lock in `SkyboxRoutine::evaluate`. Don't hold a lock from another
+    /// thread for longer than needed, as both phases of a frame wait on it.
+    pub data_core: RwLock<RendererDataCore>,
 
     /// Tool which generates mipmaps from a texture.
     pub mipmap_generator: MipmapGenerator,
@@ -74,10 +152,11 @@ struct HandleAllocators {
     pub material: HandleAllocator<MaterialTag>,
     pub object: HandleAllocator<Object>,
     pub directional_light: HandleAllocator<DirectionalLight>,
+    pub area_light: HandleAllocator<AreaLight>,
     pub graph_storage: HandleAllocator<GraphDataTag>,
 }
 
-/// All the mutex protected data within the renderer
+/// All the lock protected data within the renderer
 pub struct RendererDataCore {
     /// Position and settings of the camera.
     pub camera_manager: CameraManager,
@@ -91,6 +170,8 @@ pub struct RendererDataCore {
     pub object_manager: ObjectManager,
     /// Manages all directional lights, including their shadow maps.
     pub directional_light_manager: DirectionalLightManager,
+    /// Manages all area lights.
+    pub area_light_manager: AreaLightManager,
     /// Manages skeletons, and their owned portion of the MeshManager's buffers
     pub skeleton_manager: SkeletonManager,
     /// Managed long term storage of data for the graph and it's routines
@@ -98,11 +179,22 @@ pub struct RendererDataCore {
 
     /// Stores gpu timing and debug scopes.
     pub profiler: Mutex<GpuProfiler>,
+    /// Cpu timing of the nodes of the most recently executed graph, nested
+    /// the same way as the gpu timings.
+    pub cpu_profile: Mutex<Vec<CpuTimerScopeResult>>,
 
     /// Stores a cache of render targets between graph invocations.
     pub(crate) graph_texture_store: GraphTextureStore,
 }
 
+// The renderer is shared between threads through an `Arc`, and the data core
+// is behind a `RwLock`, so everything in it needs to be `Sync` as well.
+fn _assert_send_sync() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<Renderer>();
+    is_send_sync::<RendererDataCore>();
+}
+
 impl Renderer {
     /// Create a new renderer with the given IAD.
     ///
@@ -128,16 +220,12 @@ impl Renderer {
     pub fn add_mesh(self: &Arc<Self>, mesh: Mesh) -> MeshHandle {
         let handle = self.resource_handle_allocators.mesh.allocate(self);
 
-        // todo: move out of data core
-
-        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor::default());
-        let internal_mesh = self.mesh_manager.add(&self.device, &self.queue, &mut encoder, mesh);
+        let internal_mesh = self.mesh_manager.add(&self.device, &self.queue, handle.get_raw(), mesh);
 
         self.instructions.push(
             InstructionKind::AddMesh {
                 handle: handle.clone(),
                 internal_mesh,
-                cmd_buf: encoder.finish(),
             },
             *Location::caller(),
         );
@@ -145,6 +233,36 @@ impl Renderer {
         handle
     }
 
+    /// Overwrite the vertex data of an existing mesh, keeping its handle. This
+    /// is cheap enough to do every frame.
+    ///
+    /// Every attribute given must already exist in the mesh, and all of them
+    /// must have the same amount of vertices, otherwise nothing is updated and
+    /// an error is returned. To change the vertex count, give every attribute
+    /// of the mesh. Attributes are written in place unless they grew, in
+    /// which case they are moved to a larger allocation and the objects using
+    /// the mesh are updated to match. The indices are kept, so they must
+    /// stay in range of the new vertex count. Skinned meshes
+    /// can't change their vertex count. If the positions are updated, the
+    /// bounding sphere of the mesh is recomputed from them, replacing any
+    /// explicitly set sphere.
+    #[track_caller]
+    pub fn update_mesh_vertices(
+        &self,
+        handle: &MeshHandle,
+        attributes: Vec<StoredVertexAttributeData>,
+    ) -> Result<(), MeshUpdateError> {
+        self.mesh_manager.validate_update(handle.get_raw(), &attributes)?;
+        self.instructions.push(
+            InstructionKind::UpdateMeshVertices {
+                handle: handle.get_raw(),
+                attributes,
+            },
+            *Location::caller(),
+        );
+        Ok(())
+    }
+
     /// Adds a skeleton into the renderer. This combines a [`Mesh`] with a set
     /// of joints that can be used to animate that mesh.
     ///
@@ -168,6 +286,12 @@ impl Renderer {
     ///
     /// The handle will keep the texture alive. All materials created with this
     /// texture will also keep the texture alive.
+    ///
+    /// The texture is created and its data staged for upload on the calling
+    /// thread, so adding large textures from a loading thread keeps the cost
+    /// off the render thread. The texture becomes usable once the next
+    /// frame's instructions are evaluated, which can be checked with
+    /// [`TextureManager::is_ready`].
     #[track_caller]
     pub fn add_texture_2d(self: &Arc<Self>, texture: Texture) -> Texture2DHandle {
         profiling::scope!("Add Texture 2D");
@@ -230,6 +354,53 @@ impl Renderer {
         handle
     }
 
+    /// Adds a Cube texture which was already created and filled on the gpu,
+    /// such as one which was rendered into.
+    ///
+    /// The texture must be a 2D texture with 6 array layers and
+    /// [`TextureUsages::TEXTURE_BINDING`](wgpu::TextureUsages::TEXTURE_BINDING).
+    /// All work writing to it must be submitted before the next frame's
+    /// instructions are evaluated.
+    ///
+    /// The handle will keep the texture alive.
+    #[track_caller]
+    pub fn add_texture_cube_from_gpu(self: &Arc<Self>, texture: wgpu::Texture) -> TextureCubeHandle {
+        profiling::scope!("Add Texture Cube From Gpu");
+
+        assert_eq!(
+            texture.depth_or_array_layers(),
+            6,
+            "Cube textures must have 6 array layers"
+        );
+
+        let handle = self.resource_handle_allocators.d2c_texture.allocate(self);
+
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..TextureViewDescriptor::default()
+        });
+        let desc = TextureDescriptor {
+            label: None,
+            size: texture.size(),
+            mip_level_count: texture.mip_level_count(),
+            sample_count: texture.sample_count(),
+            dimension: texture.dimension(),
+            format: texture.format(),
+            usage: texture.usage(),
+            view_formats: &[],
+        };
+
+        self.instructions.push(
+            InstructionKind::AddTextureCube {
+                handle: handle.clone(),
+                internal_texture: InternalTexture { texture, view, desc },
+                cmd_buf: None,
+            },
+            *Location::caller(),
+        );
+        handle
+    }
+
     /// Adds a material to the renderer. This can be used in an [`Object`].
     ///
     /// The handle will keep the material alive. All objects created with this
@@ -314,6 +485,32 @@ impl Renderer {
         );
     }
 
+    /// Change the color the given object is multiplied by. This only updates
+    /// the object, so is cheap enough to do every frame.
+    #[track_caller]
+    pub fn set_object_tint(&self, handle: &ObjectHandle, tint: Vec4) {
+        self.instructions.push(
+            InstructionKind::SetObjectTint {
+                handle: handle.get_raw(),
+                tint,
+            },
+            *Location::caller(),
+        );
+    }
+
+    /// Change the clip planes and dissolve of the given object. Like
+    /// [`Renderer::set_object_tint`], this is cheap to do every frame.
+    #[track_caller]
+    pub fn set_object_clip(&self, handle: &ObjectHandle, clip: ObjectClip) {
+        self.instructions.push(
+            InstructionKind::SetObjectClip {
+                handle: handle.get_raw(),
+                clip,
+            },
+            *Location::caller(),
+        );
+    }
+
     /// Sets the joint positions for a skeleton. See
     /// [Renderer::set_skeleton_joint_matrices] to set the vertex
     /// transformations directly, without having to supply two separate
@@ -384,6 +581,36 @@ impl Renderer {
         )
     }
 
+    /// Add a light emitted from a rectangle or disk into the world.
+    ///
+    /// The handle will keep the light alive.
+    #[track_caller]
+    pub fn add_area_light(self: &Arc<Self>, light: AreaLight) -> AreaLightHandle {
+        let handle = self.resource_handle_allocators.area_light.allocate(self);
+
+        self.instructions.push(
+            InstructionKind::AddAreaLight {
+                handle: handle.clone(),
+                light,
+            },
+            *Location::caller(),
+        );
+
+        handle
+    }
+
+    /// Updates the settings for given area light.
+    #[track_caller]
+    pub fn update_area_light(&self, handle: &AreaLightHandle, change: AreaLightChange) {
+        self.instructions.push(
+            InstructionKind::ChangeAreaLight {
+                handle: handle.get_raw(),
+                change,
+            },
+            *Location::caller(),
+        )
+    }
+
     /// Adds a piece of data for long term storage and convienient use in the RenderGraph
     ///
     /// The handle will keep the data alive.
@@ -400,6 +627,28 @@ impl Renderer {
         GraphDataHandle(handle, PhantomData)
     }
 
+    /// Make space for at least `count` objects using material type `M`, so
+    /// a known amount of objects can be added without reallocating the object
+    /// buffer mid-stream.
+    #[track_caller]
+    pub fn reserve_objects<M: Material>(&self, count: usize) {
+        self.instructions.push(
+            InstructionKind::ReserveObjects {
+                reserve_invoke: Box::new(move |manager, device| manager.reserve::<M>(device, count)),
+            },
+            *Location::caller(),
+        );
+    }
+
+    /// Sets how object buffers grow when they run out of space. Defaults to
+    /// [`BufferGrowth::Doubling`]. Bind groups referencing the object buffers
+    /// are recreated every frame, so they always see the current buffer.
+    #[track_caller]
+    pub fn set_object_buffer_growth(&self, growth: BufferGrowth) {
+        self.instructions
+            .push(InstructionKind::SetObjectBufferGrowth { growth }, *Location::caller());
+    }
+
     /// Sets the aspect ratio of the camera. This should correspond with the
     /// aspect ratio of the user.
     #[track_caller]
@@ -415,6 +664,14 @@ impl Renderer {
             .push(InstructionKind::SetCameraData { data }, *Location::caller())
     }
 
+    /// Sets how light intensities are interpreted. See [`LightUnits`] for the
+    /// unit conventions.
+    #[track_caller]
+    pub fn set_light_units(&self, light_units: LightUnits) {
+        self.instructions
+            .push(InstructionKind::SetLightUnits { light_units }, *Location::caller())
+    }
+
     /// Swaps the front and back instruction buffer. Any world-modifiying functions
     /// called after this will be recorded for the next frame.
     ///
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use rend3_types::{Camera, Handedness, TextureFormat};
 use wgpu::TextureViewDimension;
 
@@ -8,8 +8,8 @@ use crate::{
     graph::GraphTextureStore,
     instruction::InstructionStreamPair,
     managers::{
-        CameraManager, DirectionalLightManager, GraphStorage, MaterialManager, MeshManager, ObjectManager,
-        SkeletonManager, TextureManager,
+        AreaLightManager, CameraManager, DirectionalLightManager, GraphStorage, MaterialManager, MeshManager,
+        ObjectManager, SkeletonManager, TextureManager,
     },
     renderer::{HandleAllocators, RendererDataCore},
     util::{mipmap::MipmapGenerator, scatter_copy::ScatterCopy},
@@ -31,12 +31,14 @@ pub fn create_renderer(
 
     let d2_texture_manager = TextureManager::new(
         &iad.device,
+        &iad.queue,
         iad.profile,
         limits.max_sampled_textures_per_shader_stage,
         TextureViewDimension::D2,
     );
     let d2c_texture_manager = TextureManager::new(
         &iad.device,
+        &iad.queue,
         iad.profile,
         limits.max_sampled_textures_per_shader_stage,
         TextureViewDimension::Cube,
@@ -44,7 +46,8 @@ pub fn create_renderer(
     let mesh_manager = MeshManager::new(&iad.device);
     let material_manager = MaterialManager::new(&iad.device);
     let object_manager = ObjectManager::new();
-    let directional_light_manager = DirectionalLightManager::new(&iad.device);
+    let directional_light_manager = DirectionalLightManager::new(&iad.device, limits.max_texture_dimension_2d);
+    let area_light_manager = AreaLightManager::new(&iad.device);
     let skeleton_manager = SkeletonManager::new();
     let graph_storage = GraphStorage::new();
 
@@ -82,16 +85,18 @@ pub fn create_renderer(
 
         resource_handle_allocators: HandleAllocators::default(),
         mesh_manager,
-        data_core: Mutex::new(RendererDataCore {
+        data_core: RwLock::new(RendererDataCore {
             camera_manager,
             d2_texture_manager,
             d2c_texture_manager,
             material_manager,
             object_manager,
             directional_light_manager,
+            area_light_manager,
             skeleton_manager,
             graph_storage,
             profiler,
+            cpu_profile: Mutex::new(Vec::new()),
             graph_texture_store: GraphTextureStore::new(),
         }),
 
@@ -42,7 +42,9 @@ pub const OPTIONAL_FEATURES: Features = Features::from_bits_truncate(
         | Features::TEXTURE_COMPRESSION_ETC2.bits()
         | Features::TEXTURE_COMPRESSION_ASTC.bits()
         | Features::TIMESTAMP_QUERY.bits()
-        | Features::TIMESTAMP_QUERY_INSIDE_PASSES.bits(),
+        | Features::TIMESTAMP_QUERY_INSIDE_PASSES.bits()
+        | Features::ADDRESS_MODE_CLAMP_TO_BORDER.bits()
+        | Features::CONSERVATIVE_RASTERIZATION.bits(),
 );
 
 /// Check that all required features for a given profile are present in the feature
@@ -343,6 +345,12 @@ impl<T> PotentialAdapter<T> {
         if (features.is_err() || limits.is_err() || desired_profile == Some(RendererProfile::CpuDriven))
             && desired_profile != Some(RendererProfile::GpuDriven)
         {
+            if desired_profile.is_none() {
+                log::info!(
+                    "Adapter {} can't run the GpuDriven profile, falling back to CpuDriven",
+                    info.name
+                );
+            }
             features = check_features(RendererProfile::CpuDriven, inner_features);
             limits = check_limits(RendererProfile::CpuDriven, &inner_limits);
             profile = RendererProfile::CpuDriven;
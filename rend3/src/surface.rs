@@ -1,6 +1,7 @@
 use glam::UVec2;
 use rend3_types::{TextureFormat, TextureUsages};
-use wgpu::{CompositeAlphaMode, Device, SurfaceConfiguration};
+use thiserror::Error;
+use wgpu::{CompositeAlphaMode, Device, SurfaceConfiguration, SurfaceError, SurfaceTexture};
 
 use crate::types::{PresentMode, Surface};
 
@@ -25,3 +26,47 @@ pub fn configure_surface(
         },
     )
 }
+
+/// Reason [`get_surface_texture`] has no texture to render to.
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SurfaceAcquireError {
+    /// No texture became available in time, or the surface is still out of
+    /// date after reconfiguring it, like in the middle of a resize. Skip
+    /// this frame and try again with the next one.
+    #[error("No surface texture is available right now")]
+    Skip,
+    /// The surface is still lost after reconfiguring it, or there is no
+    /// memory left for a new frame. This usually means the device was lost,
+    /// for example because the system switched gpus. See
+    /// [`Renderer`](crate::Renderer#device-loss) for how to recover.
+    #[error("The surface, and likely the device, was lost")]
+    Lost,
+}
+
+/// Get the next texture of the surface to render to, reconfiguring the surface
+/// with the given settings if it is outdated or lost.
+///
+/// Unlike [`Surface::get_current_texture`], this sorts the errors into ones
+/// which go away on their own and ones which need everything to be recreated.
+pub fn get_surface_texture(
+    surface: &Surface,
+    device: &Device,
+    format: TextureFormat,
+    size: UVec2,
+    present_mode: PresentMode,
+) -> Result<SurfaceTexture, SurfaceAcquireError> {
+    match surface.get_current_texture() {
+        Ok(frame) => return Ok(frame),
+        Err(SurfaceError::Timeout) => return Err(SurfaceAcquireError::Skip),
+        Err(SurfaceError::OutOfMemory) => return Err(SurfaceAcquireError::Lost),
+        Err(SurfaceError::Outdated | SurfaceError::Lost) => {}
+    }
+
+    configure_surface(surface, device, format, size, present_mode);
+
+    match surface.get_current_texture() {
+        Ok(frame) => Ok(frame),
+        Err(SurfaceError::Timeout | SurfaceError::Outdated) => Err(SurfaceAcquireError::Skip),
+        Err(SurfaceError::Lost | SurfaceError::OutOfMemory) => Err(SurfaceAcquireError::Lost),
+    }
+}
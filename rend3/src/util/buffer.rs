@@ -75,6 +75,30 @@ impl<T> Deref for WrappedPotBuffer<T> {
     }
 }
 
+/// How a buffer picks its new size when it needs to grow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BufferGrowth {
+    /// Grow to the next power of two that fits. Few reallocations, but up to
+    /// half of the buffer may be unused.
+    Doubling,
+    /// Grow to the next multiple of the given size that fits. Less wasted
+    /// space, but reallocates every time that size is crossed.
+    Fixed(u64),
+}
+
+impl BufferGrowth {
+    /// The size to allocate so at least `needed` fits.
+    pub fn grow(self, needed: u64) -> u64 {
+        match self {
+            Self::Doubling => needed.max(1).next_power_of_two(),
+            Self::Fixed(increment) => {
+                let increment = increment.max(1);
+                ((needed.max(1) + increment - 1) / increment) * increment
+            }
+        }
+    }
+}
+
 fn resize_po2(current: BufferAddress, desired: BufferAddress, minimum: BufferAddress) -> Option<BufferAddress> {
     assert!(current.is_power_of_two());
     if current == minimum && desired <= minimum {
@@ -90,7 +114,18 @@ fn resize_po2(current: BufferAddress, desired: BufferAddress, minimum: BufferAdd
 
 #[cfg(test)]
 mod test {
-    use super::resize_po2;
+    use super::{resize_po2, BufferGrowth};
+
+    #[test]
+    fn buffer_growth() {
+        assert_eq!(BufferGrowth::Doubling.grow(0), 1);
+        assert_eq!(BufferGrowth::Doubling.grow(64), 64);
+        assert_eq!(BufferGrowth::Doubling.grow(65), 128);
+
+        assert_eq!(BufferGrowth::Fixed(16).grow(0), 16);
+        assert_eq!(BufferGrowth::Fixed(16).grow(16), 16);
+        assert_eq!(BufferGrowth::Fixed(16).grow(17), 32);
+    }
 
     #[test]
     fn automated_buffer_resize() {
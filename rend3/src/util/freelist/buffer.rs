@@ -3,13 +3,21 @@ use std::{any::TypeId, ops::Deref};
 use encase::{private::WriteInto, ShaderSize};
 use wgpu::{Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device};
 
-use crate::util::scatter_copy::{ScatterCopy, ScatterData};
+use crate::util::{
+    buffer::BufferGrowth,
+    scatter_copy::{ScatterCopy, ScatterData},
+};
 
 const STARTING_SIZE: usize = 16;
 const NEEDED_USAGES: BufferUsages = BufferUsages::STORAGE
     .union(BufferUsages::COPY_DST)
     .union(BufferUsages::COPY_SRC);
 
+/// Gpu buffer of elements which are written individually.
+///
+/// Every index passed to [`Self::use_index`] is marked stale, and only stale
+/// elements are uploaded by [`Self::apply`], so elements which don't change cost
+/// no bandwidth.
 pub struct FreelistDerivedBuffer {
     inner: Buffer,
 
@@ -17,6 +25,7 @@ pub struct FreelistDerivedBuffer {
     reserved_count: usize,
     rounded_size: u64,
     stored_type: TypeId,
+    growth: BufferGrowth,
 
     stale: Vec<usize>,
 }
@@ -41,14 +50,31 @@ impl FreelistDerivedBuffer {
             reserved_count: STARTING_SIZE,
             rounded_size,
             stored_type: TypeId::of::<T>(),
+            growth: BufferGrowth::Doubling,
 
             stale: Vec::new(),
         }
     }
 
+    /// Amount of elements the buffer has space for once applied.
+    pub fn capacity(&self) -> usize {
+        self.reserved_count
+    }
+
+    /// How the buffer grows when an index past its capacity is used.
+    pub fn set_growth(&mut self, growth: BufferGrowth) {
+        self.growth = growth;
+    }
+
+    /// Make space for at least `count` elements. The buffer is reallocated
+    /// the next time it is applied.
+    pub fn reserve(&mut self, count: usize) {
+        self.reserved_count = self.reserved_count.max(count);
+    }
+
     pub fn use_index(&mut self, index: usize) {
-        if index > self.reserved_count {
-            self.reserved_count = index.next_power_of_two();
+        if index >= self.reserved_count {
+            self.reserved_count = self.growth.grow(index as u64 + 1) as usize;
         }
 
         self.stale.push(index);
@@ -90,6 +116,10 @@ impl FreelistDerivedBuffer {
             return;
         }
 
+        // An element changed multiple times since the last apply only needs to be written once.
+        self.stale.sort_unstable();
+        self.stale.dedup();
+
         let data = self.stale.drain(..).map(|idx| {
             let data = get_value(idx);
             ScatterData {
@@ -109,3 +139,54 @@ impl Deref for FreelistDerivedBuffer {
         &self.inner
     }
 }
+
+#[cfg(test)]
+mod test {
+    use wgpu::CommandEncoderDescriptor;
+
+    use super::FreelistDerivedBuffer;
+    use crate::util::scatter_copy::ScatterCopy;
+
+    fn device() -> Option<wgpu::Device> {
+        let backends = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all());
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+        });
+        let adapter = pollster::block_on(wgpu::util::initialize_adapter_from_env_or_default(
+            &instance, backends, None,
+        ))?;
+        let (device, _queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()?;
+        Some(device)
+    }
+
+    #[test]
+    fn repeated_index_is_written_once() {
+        let Some(device) = device() else {
+            return;
+        };
+        let scatter = ScatterCopy::new(&device);
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+
+        let mut buffer = FreelistDerivedBuffer::new::<u32>(&device);
+        buffer.use_index(3);
+        buffer.use_index(5);
+        buffer.use_index(3);
+
+        let mut written = Vec::new();
+        buffer.apply(&device, &mut encoder, &scatter, |idx| {
+            written.push(idx);
+            idx as u32
+        });
+        assert_eq!(written, [3, 5]);
+
+        // Nothing is left stale for the next apply.
+        written.clear();
+        buffer.apply(&device, &mut encoder, &scatter, |idx| {
+            written.push(idx);
+            idx as u32
+        });
+        assert!(written.is_empty());
+    }
+}
@@ -3,71 +3,9 @@
 //! This entire module only exists because of <https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf>.
 
 use encase::ShaderType;
-use glam::{Mat4, Vec3, Vec3A, Vec4Swizzles};
+use glam::{Mat4, Vec3};
 
-/// Represents a point in space and a radius from that point.
-#[derive(Debug, Default, Clone, Copy, ShaderType)]
-pub struct BoundingSphere {
-    pub center: Vec3,
-    pub radius: f32,
-}
-impl BoundingSphere {
-    pub fn from_mesh(mesh: &[Vec3]) -> Self {
-        let center = find_mesh_center(mesh);
-        let radius = find_mesh_bounding_sphere_radius(center, mesh);
-
-        Self {
-            center: Vec3::from(center),
-            radius,
-        }
-    }
-
-    pub fn apply_transform(self, matrix: Mat4) -> Self {
-        let max_scale = matrix
-            .x_axis
-            .xyz()
-            .length_squared()
-            .max(
-                matrix
-                    .y_axis
-                    .xyz()
-                    .length_squared()
-                    .max(matrix.z_axis.xyz().length_squared()),
-            )
-            .sqrt();
-        let center = matrix * self.center.extend(1.0);
-
-        Self {
-            center: center.truncate(),
-            radius: max_scale * self.radius,
-        }
-    }
-}
-
-fn find_mesh_center(mesh: &[Vec3]) -> Vec3A {
-    let first = if let Some(first) = mesh.first() {
-        *first
-    } else {
-        return Vec3A::ZERO;
-    };
-    // Bounding box time baby!
-    let mut max = Vec3A::from(first);
-    let mut min = max;
-
-    for pos in mesh.iter().skip(1) {
-        let pos = Vec3A::from(*pos);
-        max = max.max(pos);
-        min = min.min(pos);
-    }
-
-    (max + min) / 2.0
-}
-
-fn find_mesh_bounding_sphere_radius(mesh_center: Vec3A, mesh: &[Vec3]) -> f32 {
-    mesh.iter().fold(0.0, |distance, pos| {
-        distance.max((Vec3A::from(*pos) - mesh_center).length())
-    })
-}
+pub use crate::types::BoundingSphere;
 
 /// Represents a plane as a vec4 (or vec3 + f32)
 #[derive(Debug, Copy, Clone, ShaderType)]
@@ -100,6 +38,11 @@ impl Plane {
 
 /// A frustum composed of 5 different planes. Has no far plane as it assumes
 /// infinite.
+///
+/// This is what the culling in rend3-routine tests objects against, so use it
+/// for your own queries to get the same answer as the renderer. The frustum
+/// of the current camera in world space is
+/// [`CameraManager::world_frustum`](crate::managers::CameraManager::world_frustum).
 #[derive(Debug, Copy, Clone, ShaderType)]
 pub struct Frustum {
     left: Plane,
@@ -110,6 +53,9 @@ pub struct Frustum {
 }
 
 impl Frustum {
+    /// Extract the planes from a reverse-Z, infinite far plane projection
+    /// matrix. Pass `proj * view` for a frustum in world space, or only
+    /// `proj` for one in view space.
     pub fn from_matrix(matrix: Mat4) -> Self {
         let mat_arr = matrix.to_cols_array_2d();
 
@@ -161,13 +107,18 @@ impl Frustum {
         }
     }
 
+    /// The planes in the order left, right, top, bottom, near. Their normals
+    /// point into the frustum and are normalized, so
+    /// [`Plane::distance`] is the signed distance to the plane.
+    pub fn planes(&self) -> [Plane; 5] {
+        [self.left, self.right, self.top, self.bottom, self.near]
+    }
+
     /// Determins if the sphere is at all inside the frustum.
     pub fn contains_sphere(&self, sphere: BoundingSphere) -> bool {
         let neg_radius = -sphere.radius;
 
-        let array = [self.left, self.right, self.top, self.bottom, self.near];
-
-        for plane in &array {
+        for plane in &self.planes() {
             let inside = plane.distance(sphere.center) >= neg_radius;
             if !inside {
                 return false;
@@ -177,3 +128,33 @@ impl Frustum {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+
+    use super::{BoundingSphere, Frustum};
+
+    #[test]
+    fn culls_outside_spheres() {
+        let proj = Mat4::perspective_infinite_reverse_lh(std::f32::consts::FRAC_PI_2, 1.0, 0.1);
+        let frustum = Frustum::from_matrix(proj);
+        let sphere = |x, y, z| BoundingSphere {
+            center: Vec3::new(x, y, z),
+            radius: 1.0,
+        };
+
+        assert!(frustum.contains_sphere(sphere(0.0, 0.0, 10.0)));
+        assert!(frustum.contains_sphere(sphere(0.0, 0.0, 1000.0)));
+        // Touching the frustum counts as inside.
+        assert!(frustum.contains_sphere(sphere(10.5, 0.0, 10.0)));
+        assert!(!frustum.contains_sphere(sphere(0.0, 0.0, -10.0)));
+        assert!(!frustum.contains_sphere(sphere(20.0, 0.0, 10.0)));
+        assert!(!frustum.contains_sphere(sphere(0.0, -20.0, 10.0)));
+
+        for plane in frustum.planes() {
+            assert!((plane.abc.length() - 1.0).abs() < 1e-5);
+            assert!(plane.distance(Vec3::new(0.0, 0.0, 10.0)) > 0.0);
+        }
+    }
+}
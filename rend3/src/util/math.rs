@@ -12,3 +12,52 @@ pub fn round_up<T: PrimInt>(src: T, factor: T) -> T {
 pub fn round_up_div<T: PrimInt>(a: T, b: T) -> T {
     (a + (b - T::one())) / b
 }
+
+#[cfg(test)]
+mod test {
+    use glam::{Mat3A, Quat, Vec3, Vec3A};
+
+    /// Matrix which transforms normals by the given transform, a copy of
+    /// `mat3_normal_matrix` in rend3-routine's `math/matrix.wgsl`.
+    ///
+    /// This is the inverse transpose scaled by the absolute value of the
+    /// determinant, so transformed normals must be normalized.
+    fn normal_matrix(transform: Mat3A) -> Mat3A {
+        let cofactor = Mat3A::from_cols(
+            transform.y_axis.cross(transform.z_axis),
+            transform.z_axis.cross(transform.x_axis),
+            transform.x_axis.cross(transform.y_axis),
+        );
+        cofactor * transform.x_axis.dot(cofactor.x_axis).signum()
+    }
+
+    #[test]
+    fn non_uniform_scale_normals() {
+        // Non-uniform scale under a rotated parent, which introduces shear.
+        let child = Mat3A::from_quat(Quat::from_rotation_z(0.3)) * Mat3A::from_diagonal(Vec3::new(2.0, 1.0, 0.5));
+        let parent = Mat3A::from_quat(Quat::from_rotation_x(0.7)) * Mat3A::from_diagonal(Vec3::new(1.0, 3.0, 1.0));
+        let mirrored = Mat3A::from_diagonal(Vec3::new(-1.0, 1.0, 1.0)) * parent * child;
+
+        for transform in [child, parent * child, mirrored] {
+            let normal_transform = normal_matrix(transform);
+
+            for i in 0..64 {
+                let theta = i as f32 * 0.4;
+                let phi = i as f32 * 0.17;
+                // On a unit sphere, the position is the normal.
+                let normal = Vec3A::new(theta.cos() * phi.sin(), theta.sin() * phi.sin(), phi.cos());
+                let axis = if normal.x.abs() < 0.9 { Vec3A::X } else { Vec3A::Y };
+                let tangent = normal.cross(axis).normalize();
+
+                let transformed_normal = (normal_transform * normal).normalize();
+                let transformed_tangent = transform * tangent;
+
+                assert!((transformed_normal.length() - 1.0).abs() < 1e-5);
+                // Still perpendicular to the stretched surface.
+                assert!(transformed_normal.dot(transformed_tangent).abs() < 1e-4);
+                // Still facing outwards.
+                assert!(transformed_normal.dot(transform * normal) > 0.0);
+            }
+        }
+    }
+}
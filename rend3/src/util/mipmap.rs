@@ -145,7 +145,8 @@ impl MipmapGenerator {
         desc: &TextureDescriptor,
     ) {
         profiling::scope!("generating mipmaps");
-        let mips: ArrayVec<_, 14> = (0..desc.size.max_mips(desc.dimension))
+        // Only generate the mips the texture has, which may be fewer than the maximum.
+        let mips: ArrayVec<_, 16> = (0..desc.mip_level_count)
             .map(|mip_level| {
                 texture.create_view(&TextureViewDescriptor {
                     label: None,
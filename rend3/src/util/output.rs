@@ -2,7 +2,7 @@
 
 use std::sync::Arc;
 
-use wgpu::{SurfaceTexture, TextureView};
+use wgpu::{SurfaceTexture, Texture, TextureView, TextureViewDescriptor};
 
 /// Anything that resembles a surface to render to.
 pub enum OutputFrame {
@@ -13,14 +13,39 @@ pub enum OutputFrame {
     },
     // Arbitrary texture view.
     View(Arc<TextureView>),
+    // Owned offscreen texture, for headless rendering. Create it with
+    // OutputFrame::from_texture.
+    Texture {
+        view: TextureView,
+        texture: Arc<Texture>,
+    },
 }
 
 impl OutputFrame {
+    /// Render to the given offscreen texture. Use
+    /// [`create_readback_texture`](super::readback::create_readback_texture)
+    /// to get a texture which can be read back once rendered.
+    pub fn from_texture(texture: Arc<Texture>) -> Self {
+        Self::Texture {
+            view: texture.create_view(&TextureViewDescriptor::default()),
+            texture,
+        }
+    }
+
+    /// The texture rendered to, if this is an offscreen texture.
+    pub fn as_texture(&self) -> Option<&Arc<Texture>> {
+        match self {
+            Self::Texture { texture, .. } => Some(texture),
+            _ => None,
+        }
+    }
+
     /// Turn the given surface into a texture view, if it has one.
     pub fn as_view(&self) -> Option<&TextureView> {
         match self {
             Self::SurfaceAcquired { view, .. } => Some(view),
             Self::View(inner) => Some(&**inner),
+            Self::Texture { view, .. } => Some(view),
         }
     }
 
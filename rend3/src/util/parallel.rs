@@ -0,0 +1,31 @@
+//! Helpers for doing independent work, like pipeline compilation, in parallel.
+
+/// Runs `a` on the current thread while `b` runs on another thread, returning
+/// both results.
+///
+/// On wasm, where threads aren't available, both are run on the current thread
+/// one after the other.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB + Send,
+    RB: Send,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::thread::scope(|scope| {
+            let b = scope.spawn(b);
+            let a = a();
+            let b = match b.join() {
+                Ok(b) => b,
+                Err(panic) => std::panic::resume_unwind(panic),
+            };
+            (a, b)
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        (a(), b())
+    }
+}
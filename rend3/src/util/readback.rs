@@ -0,0 +1,166 @@
+//! Copying textures and buffers back to the cpu, for screenshots, tests, and
+//! debugging.
+
+use std::ops::Range;
+
+use glam::UVec2;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, Extent3d, ImageCopyBuffer,
+    ImageCopyTexture, ImageDataLayout, Maintain, MapMode, Origin3d, Queue, Texture, TextureAspect, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+/// Create a texture which can be rendered to, then read back with
+/// [`read_texture_rgba8`].
+pub fn create_readback_texture(device: &Device, resolution: UVec2) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("readback target"),
+        size: Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Read the first mip of the given texture back to the cpu as tightly packed
+/// RGBA8 rows, top row first.
+///
+/// The texture must have an RGBA8 format and have been created with
+/// [`TextureUsages::COPY_SRC`]. This blocks until all previously submitted
+/// work is done, so it is not suitable for use every frame. On the web, where
+/// the device cannot be waited on, the copy will not be finished in time and
+/// this panics.
+pub fn read_texture_rgba8(device: &Device, queue: &Queue, texture: &Texture) -> Vec<u8> {
+    profiling::scope!("read_texture_rgba8");
+
+    assert!(
+        matches!(
+            texture.format(),
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+        ),
+        "Only RGBA8 textures can be read back, got {:?}",
+        texture.format()
+    );
+
+    read_texture(device, queue, texture, 4)
+}
+
+/// Read the first mip of the given `R32Float` texture back to the cpu, top row
+/// first.
+///
+/// Has the same requirements and blocking behavior as [`read_texture_rgba8`].
+pub fn read_texture_r32float(device: &Device, queue: &Queue, texture: &Texture) -> Vec<f32> {
+    profiling::scope!("read_texture_r32float");
+
+    assert_eq!(
+        texture.format(),
+        TextureFormat::R32Float,
+        "Only R32Float textures can be read back as floats"
+    );
+
+    read_texture(device, queue, texture, 4)
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+fn read_texture(device: &Device, queue: &Queue, texture: &Texture, bytes_per_pixel: u32) -> Vec<u8> {
+    let width = texture.width();
+    let height = texture.height();
+    let unpadded_row = width * bytes_per_pixel;
+    let padded_row =
+        (unpadded_row + COPY_BYTES_PER_ROW_ALIGNMENT - 1) / COPY_BYTES_PER_ROW_ALIGNMENT * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("readback staging"),
+        size: padded_row as u64 * height as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("readback encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        ImageCopyBuffer {
+            buffer: &staging,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: Some(height),
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    map_staging(device, &staging);
+
+    let mapped = staging.slice(..).get_mapped_range();
+    let mut pixels = Vec::with_capacity((unpadded_row * height) as usize);
+    for row in mapped.chunks_exact(padded_row as usize) {
+        pixels.extend_from_slice(&row[..unpadded_row as usize]);
+    }
+    pixels
+}
+
+/// Read the given range of a buffer back to the cpu.
+///
+/// The buffer must have been created with [`BufferUsages::COPY_SRC`] and the
+/// range must be a multiple of 4 bytes. Has the same blocking behavior as
+/// [`read_texture_rgba8`].
+pub fn read_buffer(device: &Device, queue: &Queue, buffer: &Buffer, range: Range<u64>) -> Vec<u8> {
+    profiling::scope!("read_buffer");
+
+    let size = range.end - range.start;
+    if size == 0 {
+        return Vec::new();
+    }
+
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("readback staging"),
+        size,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("readback encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, range.start, &staging, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    map_staging(device, &staging);
+
+    let data = staging.slice(..).get_mapped_range().to_vec();
+    data
+}
+
+fn map_staging(device: &Device, staging: &Buffer) {
+    let (sender, receiver) = flume::bounded(1);
+    staging.slice(..).map_async(MapMode::Read, move |res| {
+        let _ = sender.send(res);
+    });
+    device.poll(Maintain::Wait);
+    receiver
+        .try_recv()
+        .expect("Readback did not finish after waiting on the device")
+        .expect("Failed to map readback buffer");
+}
@@ -0,0 +1,121 @@
+//! Comparing rendered images against reference images.
+//!
+//! Only available with the `testing` feature. Pair with
+//! [`read_texture_rgba8`](super::readback::read_texture_rgba8) to check
+//! rendering output against images stored alongside the tests. Decoding and
+//! encoding the images is left to the caller, everything here works on tightly
+//! packed RGBA8 rows.
+
+use glam::UVec2;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ImageComparisonError {
+    #[error("Image has {actual} bytes, but an RGBA8 image of size {resolution} needs {expected} bytes")]
+    SizeMismatch {
+        resolution: UVec2,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// Result of comparing two images with [`compare_rgba8`].
+#[derive(Debug, Clone)]
+pub struct ImageComparison {
+    pub resolution: UVec2,
+    /// Pixels where any channel differed by more than the tolerance.
+    pub mismatched_pixels: usize,
+    /// Largest difference in any channel of any pixel.
+    pub max_difference: u8,
+    /// RGBA8 image showing mismatched pixels in red over a dimmed grayscale
+    /// copy of the reference.
+    pub diff: Vec<u8>,
+}
+
+impl ImageComparison {
+    /// True if no pixels mismatched.
+    pub fn matches(&self) -> bool {
+        self.mismatched_pixels == 0
+    }
+
+    /// Fraction of all pixels which mismatched.
+    pub fn mismatched_fraction(&self) -> f32 {
+        self.mismatched_pixels as f32 / (self.resolution.x * self.resolution.y).max(1) as f32
+    }
+}
+
+/// Compare a rendered image against a reference, with both images being
+/// tightly packed RGBA8 rows of the given resolution.
+///
+/// A pixel mismatches if any of its channels differ by more than `tolerance`.
+/// Different gpus round differently, so a small tolerance is needed for
+/// reference images to be usable across vendors.
+pub fn compare_rgba8(
+    rendered: &[u8],
+    reference: &[u8],
+    resolution: UVec2,
+    tolerance: u8,
+) -> Result<ImageComparison, ImageComparisonError> {
+    let expected = resolution.x as usize * resolution.y as usize * 4;
+    for image in [rendered, reference] {
+        if image.len() != expected {
+            return Err(ImageComparisonError::SizeMismatch {
+                resolution,
+                expected,
+                actual: image.len(),
+            });
+        }
+    }
+
+    let mut mismatched_pixels = 0;
+    let mut max_difference = 0;
+    let mut diff = Vec::with_capacity(expected);
+    for (rendered, reference) in rendered.chunks_exact(4).zip(reference.chunks_exact(4)) {
+        let difference = rendered
+            .iter()
+            .zip(reference)
+            .map(|(&a, &b)| a.abs_diff(b))
+            .max()
+            .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+
+        if difference > tolerance {
+            mismatched_pixels += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let luma = (reference[0] as u32 * 54 + reference[1] as u32 * 183 + reference[2] as u32 * 19) >> 8;
+            let dimmed = (luma / 3) as u8;
+            diff.extend_from_slice(&[dimmed, dimmed, dimmed, 255]);
+        }
+    }
+
+    Ok(ImageComparison {
+        resolution,
+        mismatched_pixels,
+        max_difference,
+        diff,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use glam::UVec2;
+
+    use super::compare_rgba8;
+
+    #[test]
+    fn tolerance() {
+        let reference = [10, 10, 10, 255, 200, 200, 200, 255];
+        let rendered = [12, 10, 10, 255, 200, 190, 200, 255];
+
+        let loose = compare_rgba8(&rendered, &reference, UVec2::new(2, 1), 10).unwrap();
+        assert!(loose.matches());
+        assert_eq!(loose.max_difference, 10);
+
+        let strict = compare_rgba8(&rendered, &reference, UVec2::new(2, 1), 2).unwrap();
+        assert_eq!(strict.mismatched_pixels, 1);
+        assert_eq!(&strict.diff[4..], &[255, 0, 0, 255]);
+
+        assert!(compare_rgba8(&rendered, &reference, UVec2::new(2, 2), 2).is_err());
+    }
+}